@@ -1,70 +1,243 @@
-use core::f32::consts::PI;
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
-use dsp::{atan2, cossin};
-use dsp::{iir, iir_int};
-use dsp::{pll::PLL, rpll::RPLL};
+//! Per-sample throughput of the DSP kernels.
+//!
+//! Run with `cargo bench --features bench` on the host. Each kernel
+//! processes a block of `N` samples of white noise from `dsp::testing` and
+//! criterion reports the throughput in samples (elements) per second.
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion,
+    Throughput,
+};
+use dsp::{atan2, atan2_precise, cordic, cossin, macc_i16, Accu, Complex};
+use dsp::{iir, iir_int, lockin::Lockin, lowpass::Lowpass};
+use dsp::{pll::PLL, rpll::RPLL, testing::WhiteNoise};
 
-fn atan2_bench(c: &mut Criterion) {
-    let xi = (10 << 16) as i32;
-    let xf = xi as f32 / i32::MAX as f32;
+// Samples per iteration
+const N: usize = 1 << 10;
+
+fn noise_f32(seed: u64) -> Vec<f32> {
+    WhiteNoise::new(seed, 1.).take(N).collect()
+}
+
+fn noise_i32(seed: u64) -> Vec<i32> {
+    WhiteNoise::new(seed, 1.)
+        .take(N)
+        .map(|x| (x as f64 * i32::MAX as f64) as i32)
+        .collect()
+}
+
+fn noise_i16(seed: u64) -> Vec<i16> {
+    noise_i32(seed).iter().map(|&x| (x >> 16) as i16).collect()
+}
 
-    let yi = (-26_328 << 16) as i32;
-    let yf = yi as f32 / i32::MAX as f32;
+fn group<'a>(
+    c: &'a mut Criterion,
+    name: &str,
+) -> BenchmarkGroup<'a, criterion::measurement::WallTime> {
+    let mut group = c.benchmark_group(name);
+    group.throughput(Throughput::Elements(N as u64));
+    group
+}
 
-    c.bench_function("atan2(y, x)", |b| {
-        b.iter(|| atan2(black_box(yi), black_box(xi)))
+fn atan2_bench(c: &mut Criterion) {
+    let (yi, xi) = (noise_i32(1), noise_i32(2));
+    let yf: Vec<_> = yi.iter().map(|&y| y as f32).collect();
+    let xf: Vec<_> = xi.iter().map(|&x| x as f32).collect();
+    let mut g = group(c, "atan2");
+    g.bench_function("atan2(y, x)", |b| {
+        b.iter(|| {
+            for (&y, &x) in yi.iter().zip(xi.iter()) {
+                black_box(atan2(black_box(y), black_box(x)));
+            }
+        })
+    });
+    g.bench_function("atan2_precise(y, x)", |b| {
+        b.iter(|| {
+            for (&y, &x) in yi.iter().zip(xi.iter()) {
+                black_box(atan2_precise(black_box(y), black_box(x)));
+            }
+        })
     });
-    c.bench_function("y.atan2(x)", |b| {
-        b.iter(|| black_box(yf).atan2(black_box(xf)))
+    g.bench_function("y.atan2(x)", |b| {
+        b.iter(|| {
+            for (&y, &x) in yf.iter().zip(xf.iter()) {
+                black_box(black_box(y).atan2(black_box(x)));
+            }
+        })
     });
+    g.finish();
 }
 
 fn cossin_bench(c: &mut Criterion) {
-    let zi = -0x7304_2531_i32;
-    let zf = zi as f32 / i32::MAX as f32 * PI;
-    c.bench_function("cossin(zi)", |b| b.iter(|| cossin(black_box(zi))));
-    c.bench_function("zf.sin_cos()", |b| b.iter(|| black_box(zf).sin_cos()));
+    let zi = noise_i32(3);
+    let zf: Vec<_> = noise_f32(3)
+        .iter()
+        .map(|&z| z * core::f32::consts::PI)
+        .collect();
+    let mut g = group(c, "cossin");
+    g.bench_function("cossin(zi)", |b| {
+        b.iter(|| {
+            for &z in zi.iter() {
+                black_box(cossin(black_box(z)));
+            }
+        })
+    });
+    g.bench_function("zf.sin_cos()", |b| {
+        b.iter(|| {
+            for &z in zf.iter() {
+                black_box(black_box(z).sin_cos());
+            }
+        })
+    });
+    g.finish();
 }
 
-fn rpll_bench(c: &mut Criterion) {
-    let mut dut = RPLL::new(8);
-    c.bench_function("RPLL::update(Some(t), 21, 20)", |b| {
-        b.iter(|| dut.update(black_box(Some(0x241)), 21, 20))
+fn cordic_bench(c: &mut Criterion) {
+    let z = noise_i32(4);
+    let xy: Vec<_> = noise_i32(5)
+        .iter()
+        .zip(z.iter())
+        .map(|(&x, &y)| Complex(x >> 1, y >> 1))
+        .collect();
+    let mut g = group(c, "cordic");
+    g.bench_function("cordic::rotate(zi)", |b| {
+        b.iter(|| {
+            for &z in z.iter() {
+                black_box(cordic::rotate(black_box(z)));
+            }
+        })
     });
-    c.bench_function("RPLL::update(Some(t), sf, sp)", |b| {
+    g.bench_function("cordic::vector(xy)", |b| {
         b.iter(|| {
-            dut.update(black_box(Some(0x241)), black_box(21), black_box(20))
+            for &xy in xy.iter() {
+                black_box(cordic::vector(black_box(xy)));
+            }
         })
     });
+    g.finish();
 }
 
 fn pll_bench(c: &mut Criterion) {
-    let mut dut = PLL::default();
-    c.bench_function("PLL::update(t, 12, 11)", |b| {
-        b.iter(|| dut.update(black_box(0x1234), 12, 1))
+    // Monotonic timestamps with jitter
+    let t: Vec<_> = noise_i32(6)
+        .iter()
+        .enumerate()
+        .map(|(i, &j)| (i as i32).wrapping_mul(0x241) + (j >> 24))
+        .collect();
+    let mut g = group(c, "pll");
+    let mut rpll = RPLL::new(8);
+    g.bench_function("RPLL::update(Some(t), 21, 20)", |b| {
+        b.iter(|| {
+            for &t in t.iter() {
+                black_box(rpll.update(black_box(Some(t)), 21, 20));
+            }
+        })
+    });
+    let mut pll = PLL::default();
+    g.bench_function("PLL::update(t, 12, 11)", |b| {
+        b.iter(|| {
+            for &t in t.iter() {
+                black_box(pll.update(black_box(t), 12, 11));
+            }
+        })
+    });
+    g.finish();
+}
+
+fn iir_bench(c: &mut Criterion) {
+    let x = noise_f32(7);
+    let mut y = vec![0.; N];
+    let dut = iir::IIR::default();
+    let mut xy = iir::Vec5::default();
+    let mut g = group(c, "iir");
+    g.bench_function("iir::IIR::update(s, x)", |b| {
+        b.iter(|| {
+            for &x in x.iter() {
+                black_box(dut.update(&mut xy, black_box(x)));
+            }
+        })
     });
-    c.bench_function("PLL::update(t, sf, sp)", |b| {
-        b.iter(|| dut.update(black_box(0x241), black_box(21), black_box(20)))
+    g.bench_function("iir::IIR::update_block(s, x, y)", |b| {
+        b.iter(|| dut.update_block(&mut xy, black_box(&x), &mut y))
     });
+    g.finish();
 }
 
 fn iir_int_bench(c: &mut Criterion) {
+    let x: Vec<_> = noise_i32(8).iter().map(|&x| x >> 2).collect();
+    let mut y = vec![0; N];
     let dut = iir_int::IIR::default();
     let mut xy = iir_int::Vec5::default();
-    c.bench_function("int_iir::IIR::update(s, x)", |b| {
-        b.iter(|| dut.update(&mut xy, black_box(0x2832)))
+    let mut g = group(c, "iir_int");
+    g.bench_function("iir_int::IIR::update(s, x)", |b| {
+        b.iter(|| {
+            for &x in x.iter() {
+                black_box(dut.update(&mut xy, black_box(x)));
+            }
+        })
+    });
+    g.bench_function("iir_int::IIR::update_block(s, x, y)", |b| {
+        b.iter(|| dut.update_block(&mut xy, black_box(&x), &mut y))
+    });
+    let mut lowpass = Lowpass::<2>::default();
+    g.bench_function("Lowpass::<2>::update(x, 8)", |b| {
+        b.iter(|| {
+            for &x in x.iter() {
+                black_box(lowpass.update(black_box(x), 8));
+            }
+        })
     });
+    g.finish();
 }
 
-fn iir_bench(c: &mut Criterion) {
-    let dut = iir::IIR::default();
-    let mut xy = iir::Vec5::default();
-    c.bench_function("int::IIR::update(s, x)", |b| {
-        b.iter(|| dut.update(&mut xy, black_box(0.32241)))
+fn macc_bench(c: &mut Criterion) {
+    // N windows of 32 samples
+    let x: Vec<_> = noise_i16(9).iter().cycle().take(N + 31).copied().collect();
+    let a = [-0x2345i16; 32];
+    let mut g = group(c, "fir");
+    g.bench_function("macc_i16(y0, x[32], a[32])", |b| {
+        b.iter(|| {
+            for x in x.windows(32) {
+                black_box(macc_i16(black_box(0), black_box(x), &a));
+            }
+        })
+    });
+    let mut fir = dsp::fir_int::Fir16::new(a);
+    g.bench_function("Fir16::<32>::update(x)", |b| {
+        b.iter(|| {
+            for &x in x[..N].iter() {
+                black_box(fir.update(black_box(x)));
+            }
+        })
+    });
+    g.finish();
+}
+
+fn lockin_bench(c: &mut Criterion) {
+    // The lockin-internal processing path: demodulate a batch of 8
+    // samples with the reference phase, filter, and decimate.
+    let x = noise_i16(10);
+    let mut lockin = Lockin::default();
+    let mut g = group(c, "lockin");
+    g.bench_function("Lockin::update(x[8], phase, 8).last()", |b| {
+        b.iter(|| {
+            for batch in x.chunks_exact(8) {
+                let frequency = lockin.demodulation_frequency(1 << 29);
+                let phase = lockin.demodulation_phase(black_box(0));
+                let output = batch
+                    .iter()
+                    .zip(Accu::new(phase, frequency))
+                    .map(|(&x, phase)| lockin.update(x, phase, 8))
+                    .last();
+                black_box(output);
+            }
+        })
     });
+    g.finish();
 }
 
-criterion_group!(trig, atan2_bench, cossin_bench);
-criterion_group!(pll, rpll_bench, pll_bench);
-criterion_group!(iir, iir_int_bench, iir_bench);
-criterion_main!(trig, pll, iir);
+criterion_group!(trig, atan2_bench, cossin_bench, cordic_bench);
+criterion_group!(pll, pll_bench);
+criterion_group!(iir, iir_bench, iir_int_bench);
+criterion_group!(fir, macc_bench);
+criterion_group!(lockin, lockin_bench);
+criterion_main!(trig, pll, iir, fir, lockin);
@@ -5,13 +5,20 @@ use std::io::prelude::*;
 use std::path::Path;
 
 fn write_cossin_table() {
-    const DEPTH: usize = 7;
+    // Table depth (log2 of the number of entries) selected by feature.
+    let depth: usize = if env::var_os("CARGO_FEATURE_COSSIN_LARGE").is_some() {
+        9
+    } else if env::var_os("CARGO_FEATURE_COSSIN_SMALL").is_some() {
+        5
+    } else {
+        7
+    };
 
     let out_dir = env::var_os("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("cossin_table.rs");
     let mut file = File::create(dest_path).unwrap();
 
-    writeln!(file, "pub(crate) const COSSIN_DEPTH: usize = {};", DEPTH)
+    writeln!(file, "pub(crate) const COSSIN_DEPTH: usize = {};", depth)
         .unwrap();
     write!(
         file,
@@ -21,13 +28,13 @@ fn write_cossin_table() {
 
     // Treat sin and cos as unsigned values since the sign will always be
     // positive in the range [0, pi/4).
-    // No headroom for interpolation rounding error (this is needed for
-    // DEPTH = 6 for example).
+    // No headroom for interpolation rounding error. `cossin()` saturates
+    // instead (this is needed for depth 6 and below for example).
     const AMPLITUDE: f64 = u16::MAX as f64;
 
-    for i in 0..(1 << DEPTH) {
+    for i in 0..(1 << depth) {
         // use midpoint samples to save one entry in the LUT
-        let phase = (PI / 4. / (1 << DEPTH) as f64) * (i as f64 + 0.5);
+        let phase = (PI / 4. / (1 << depth) as f64) * (i as f64 + 0.5);
         // add one bit accuracy to cos due to 0.5 < cos(z) <= 1 for |z| < pi/4
         let cos = ((phase.cos() - 0.5) * 2. * AMPLITUDE).round() as u16;
         let sin = (phase.sin() * AMPLITUDE).round() as u16;
@@ -41,6 +48,47 @@ fn write_cossin_table() {
     println!("cargo:rerun-if-changed=build.rs");
 }
 
+fn write_cordic_table() {
+    const DEPTH: usize = 24;
+
+    let out_dir = env::var_os("OUT_DIR").unwrap();
+    let dest_path = Path::new(&out_dir).join("cordic_table.rs");
+    let mut file = File::create(dest_path).unwrap();
+
+    writeln!(file, "pub(crate) const CORDIC_DEPTH: usize = {};", DEPTH)
+        .unwrap();
+    write!(
+        file,
+        "pub(crate) const CORDIC_ATAN: [i32; CORDIC_DEPTH] = ["
+    )
+    .unwrap();
+
+    // Elementary rotation angles atan(2**-i) with pi == 1 << 31 and the
+    // total CORDIC gain prod(sqrt(1 + 2**(-2*i))).
+    let mut gain = 1f64;
+    for i in 0..DEPTH {
+        let r = 0.5f64.powi(i as i32);
+        let atan = (r.atan() / PI * (1i64 << 31) as f64).round() as i32;
+        gain *= (1. + r * r).sqrt();
+        if i % 4 == 0 {
+            write!(file, "\n   ").unwrap();
+        }
+        write!(file, " {},", atan).unwrap();
+    }
+    writeln!(file, "\n];").unwrap();
+
+    // Inverse gain as U0.31
+    writeln!(
+        file,
+        "pub(crate) const CORDIC_GAIN_INV: u32 = {};",
+        ((1i64 << 31) as f64 / gain).round() as u32
+    )
+    .unwrap();
+
+    println!("cargo:rerun-if-changed=build.rs");
+}
+
 fn main() {
     write_cossin_table();
+    write_cordic_table();
 }
@@ -1,13 +1,60 @@
+use super::Reset;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Wrapping phase accumulator.
+///
+/// As an `Iterator` it yields the current phase and then advances it by
+/// `step`. The phase wraps modulo `1 << 32`: full scale is a full turn, so
+/// crossing `i32::MAX` to `i32::MIN` is seamless. The iterator never ends.
 #[derive(Copy, Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Accu {
+    #[cfg_attr(feature = "serde", serde(skip))]
     state: i32,
     step: i32,
 }
 
 impl Accu {
-    pub fn new(state: i32, step: i32) -> Self {
+    pub const fn new(state: i32, step: i32) -> Self {
         Self { state, step }
     }
+
+    /// Phase of the next sample.
+    pub fn phase(&self) -> i32 {
+        self.state
+    }
+
+    /// Phase increment per sample.
+    pub fn step(&self) -> i32 {
+        self.step
+    }
+
+    /// Change the phase increment, keeping the current state.
+    pub fn set_step(&mut self, step: i32) {
+        self.step = step;
+    }
+
+    /// Change the phase increment by `delta` (wrapping), keeping the current
+    /// state.
+    pub fn add_to_step(&mut self, delta: i32) {
+        self.step = self.step.wrapping_add(delta);
+    }
+
+    /// The phases of the next `n` samples.
+    ///
+    /// The state is advanced by `n` steps immediately, independent of how
+    /// much of the returned iterator is consumed. The iterator uses the step
+    /// at the time of the call: a step changed while it is consumed takes
+    /// effect for the next batch.
+    ///
+    /// # Args
+    /// * `n`: Number of phases.
+    pub fn iter_n(&mut self, n: usize) -> core::iter::Take<Self> {
+        let batch = *self;
+        self.state = self.state.wrapping_add(self.step.wrapping_mul(n as i32));
+        batch.take(n)
+    }
 }
 
 impl Iterator for Accu {
@@ -17,4 +64,74 @@ impl Iterator for Accu {
         self.state = self.state.wrapping_add(self.step);
         Some(s)
     }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (usize::MAX, None)
+    }
+}
+
+impl Reset for Accu {
+    fn reset(&mut self) {
+        self.state = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    #[test]
+    fn iter_n() {
+        let mut rng = Xoshiro::new(1);
+        for n in [0, 1, 8, 16, 1000].iter().copied() {
+            let (state, step) = (rng.next_u32() as i32, rng.next_u32() as i32);
+            let mut accu = Accu::new(state, step);
+            let mut manual = accu;
+            let mut want = state;
+            for phase in accu.iter_n(n) {
+                assert_eq!(phase, want);
+                assert_eq!(manual.next(), Some(want));
+                want = want.wrapping_add(step);
+            }
+            assert_eq!(accu, manual);
+            assert_eq!(accu.phase(), want);
+            // The state is committed even if the batch is not consumed
+            let mut lazy = Accu::new(state, step);
+            drop(lazy.iter_n(n));
+            assert_eq!(lazy, accu);
+        }
+    }
+
+    #[test]
+    fn wrap() {
+        let mut accu = Accu::new(i32::MAX - 1, 1);
+        assert!(accu.iter_n(4).eq([
+            i32::MAX - 1,
+            i32::MAX,
+            i32::MIN,
+            i32::MIN + 1
+        ]
+        .iter()
+        .copied()));
+        assert_eq!(accu.phase(), i32::MIN + 2);
+        // A full turn is the identity
+        let mut accu = Accu::new(5, 1 << 30);
+        drop(accu.iter_n(4));
+        assert_eq!(accu.phase(), 5);
+        accu.set_step(-1 << 30);
+        accu.add_to_step(i32::MIN);
+        assert_eq!(accu.step(), 1 << 30);
+        assert_eq!(accu.nth(3), Some(5 + (3 << 30)));
+    }
+
+    #[test]
+    fn step_at_boundary() {
+        let mut accu = Accu::new(0, 10);
+        let batch = accu.iter_n(3);
+        accu.add_to_step(5);
+        assert!(batch.eq([0, 10, 20].iter().copied()));
+        assert!(accu.iter_n(3).eq([30, 45, 60].iter().copied()));
+        assert_eq!(accu.phase(), 75);
+    }
 }
@@ -0,0 +1,242 @@
+use super::{saturating_scale_i64, Reset, Rounding};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Unity gain, the gain is Q16.16.
+pub const AGC_UNITY: i32 = 1 << 16;
+
+/// Automatic gain control.
+///
+/// Feed-forward AGC: The input magnitude (mean absolute value) is estimated
+/// with a single pole lowpass with a time constant of `1 << rate_shift`
+/// samples. The gain is the ratio of the target level to that estimate,
+/// limited to `[gain_min, gain_max]`. Since there is no feedback loop there
+/// is no limit cycle. Level steps are followed exponentially with the
+/// lowpass time constant.
+///
+/// When the magnitude estimate falls below `squelch` the gain is frozen.
+/// This avoids amplifying noise to full scale when the signal is lost.
+///
+/// The mean absolute value of a sinusoid of amplitude `a` is `2*a/pi`.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Agc {
+    /// Target magnitude (mean absolute value) of the output.
+    pub target: i32,
+    /// Log2 of the magnitude estimate time constant in samples, 0..=32.
+    pub rate_shift: u32,
+    /// Input magnitude below which the gain is frozen. Positive.
+    pub squelch: i32,
+    /// Minimum gain, Q16.16.
+    pub gain_min: i32,
+    /// Maximum gain, Q16.16.
+    pub gain_max: i32,
+    // Input magnitude estimate, Q32.16
+    #[cfg_attr(feature = "serde", serde(skip))]
+    level: i64,
+    // Current gain, Q16.16
+    #[cfg_attr(feature = "serde", serde(skip, default = "unity"))]
+    gain: i32,
+}
+
+#[cfg(feature = "serde")]
+fn unity() -> i32 {
+    AGC_UNITY
+}
+
+impl Agc {
+    /// Create a new AGC with unity gain and a gain range of `[1/16, 1 << 15)`.
+    ///
+    /// # Args
+    /// * `target`: Target output magnitude.
+    /// * `rate_shift`: Log2 time constant in samples.
+    /// * `squelch`: Squelch input magnitude.
+    pub const fn new(target: i32, rate_shift: u32, squelch: i32) -> Self {
+        Self {
+            target,
+            rate_shift,
+            squelch,
+            gain_min: AGC_UNITY >> 4,
+            gain_max: i32::MAX,
+            level: 0,
+            gain: AGC_UNITY,
+        }
+    }
+
+    /// The currently applied gain, Q16.16.
+    pub fn gain(&self) -> i32 {
+        self.gain
+    }
+
+    /// The current input magnitude estimate.
+    pub fn level(&self) -> i32 {
+        (self.level >> 16) as i32
+    }
+
+    /// Whether the gain is frozen by the squelch.
+    pub fn squelched(&self) -> bool {
+        self.level() < self.squelch
+    }
+
+    /// Apply the gain to a sample and update the gain.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    ///
+    /// # Returns
+    /// Output sample, saturating.
+    pub fn update(&mut self, x: i32) -> i32 {
+        let a = (x.unsigned_abs() as i64) << 16;
+        self.level += (a - self.level) >> self.rate_shift;
+        if !self.squelched() {
+            // `level > 0` due to a positive squelch
+            let g = ((self.target as i64) << 32) / self.level;
+            self.gain =
+                g.max(self.gain_min as i64).min(self.gain_max as i64) as i32;
+        }
+        saturating_scale_i64(x as i64 * self.gain as i64, 16, Rounding::HalfUp)
+    }
+}
+
+impl Reset for Agc {
+    fn reset(&mut self) {
+        self.level = 0;
+        self.gain = AGC_UNITY;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Square wave: constant magnitude
+    fn square(a: i32, i: usize) -> i32 {
+        if i & 8 == 0 {
+            a
+        } else {
+            -a
+        }
+    }
+
+    #[test]
+    fn step() {
+        let target = 1 << 28;
+        let r = 8;
+        let mut agc = Agc::new(target, r, 1 << 12);
+        let (a0, a1) = (1 << 20, 1 << 25);
+        for i in 0..1 << 14 {
+            agc.update(square(a0, i));
+        }
+        assert_eq!(agc.gain(), (((target as i64) << 16) / a0 as i64) as i32);
+        assert!((agc.update(a0) - target).abs() < 1 << 12);
+        // 30 dB step, converge with the time constant, saturating initially
+        let mut level = a0 as f64;
+        let alpha = 1. / (1 << r) as f64;
+        for i in 0..1 << 12 {
+            let y = agc.update(square(a1, i));
+            level += (a1 as f64 - level) * alpha;
+            if i % (1 << (r - 1)) == 0 {
+                let want =
+                    (target as f64 / level * a1 as f64).min(i32::MAX as f64);
+                assert!(
+                    (y.abs() as f64 / want - 1.).abs() < 1e-3,
+                    "{}: {} {}",
+                    i,
+                    y,
+                    want
+                );
+            }
+        }
+        assert!((agc.update(a1) - target).abs() < 1 << 12);
+    }
+
+    #[test]
+    fn squelch() {
+        let mut agc = Agc::new(1 << 28, 4, 1 << 16);
+        for i in 0..1000 {
+            agc.update(square(1 << 22, i));
+        }
+        assert!(!agc.squelched());
+        // Signal lost: the gain rises until the squelch freezes it
+        for i in 0..1000 {
+            agc.update(square(1 << 10, i));
+        }
+        assert!(agc.squelched());
+        let g = agc.gain();
+        assert!(g <= (1 << 28) / (1 << 16) << 16);
+        for i in 0..1000 {
+            let y = agc.update(square(1 << 10, i));
+            assert_eq!(y.abs(), ((1i64 << 10) * g as i64 >> 16) as i32);
+        }
+        assert_eq!(agc.gain(), g);
+        // And recovers
+        for i in 0..1000 {
+            agc.update(square(1 << 24, i));
+        }
+        assert_eq!(agc.gain(), 1 << 20);
+    }
+
+    #[test]
+    fn limits() {
+        let mut agc = Agc::new(1 << 26, 4, 1);
+        agc.gain_max = 10 << 16;
+        for _ in 0..1000 {
+            agc.update(1 << 10);
+        }
+        assert_eq!(agc.gain(), 10 << 16);
+        for _ in 0..1000 {
+            agc.update(i32::MIN);
+        }
+        assert_eq!(agc.gain(), agc.gain_min);
+        assert_eq!(agc.update(i32::MIN), i32::MIN >> 4);
+    }
+
+    #[test]
+    fn no_limit_cycle() {
+        // Constant input: gain and output settle exactly
+        let mut agc = Agc::new(1 << 28, 6, 1 << 12);
+        let y: Vec<i32> = (0..2000).map(|_| agc.update(-3 << 24)).collect();
+        assert!(y[1000..].iter().all(|&yi| yi == y[1000]));
+        assert!((y[1000] + (1 << 28)).abs() < 1 << 10, "{}", y[1000]);
+        // Sinusoidal input: the gain settles and its ripple is bounded by
+        // the lowpass
+        let mut agc = Agc::new(1 << 28, 10, 1 << 12);
+        let a = 1e8;
+        let x = |i: usize| (a * (i as f64 * 0.1).sin()) as i32;
+        for i in 0..1 << 14 {
+            agc.update(x(i));
+        }
+        let (mut min, mut max) = (i32::MAX, i32::MIN);
+        for i in 1 << 14..1 << 16 {
+            agc.update(x(i));
+            min = min.min(agc.gain());
+            max = max.max(agc.gain());
+        }
+        let want = (1 << 28) as f64 / (2. * a / core::f64::consts::PI);
+        let g = (min as f64 + max as f64) / 2. / (1 << 16) as f64;
+        assert!((g / want - 1.).abs() < 1e-2, "{} {}", g, want);
+        assert!(
+            ((max - min) as f64 / (min as f64)) < 1e-2,
+            "{} {}",
+            min,
+            max
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let mut agc = Agc::new(1 << 24, 8, 1 << 10);
+        for i in 0..1000 {
+            agc.update(square(1 << 20, i));
+        }
+        let (json, de) = crate::testing::round_trip(&agc);
+        assert_eq!(
+            json,
+            r#"{"target":16777216,"rate_shift":8,"squelch":1024,"gain_min":4096,"gain_max":2147483647}"#
+        );
+        // The state is not serialized
+        assert_eq!((de.level(), de.gain()), (0, AGC_UNITY));
+        assert_ne!(agc.gain(), AGC_UNITY);
+    }
+}
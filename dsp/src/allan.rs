@@ -0,0 +1,200 @@
+use super::Reset;
+
+/// Allan variance estimate at one averaging time.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Estimate {
+    /// Averaging time in samples.
+    pub tau: u32,
+    /// Allan variance in units of `(phase LSB/sample)**2`.
+    pub variance: f64,
+    /// Number of second differences in the estimate.
+    pub count: u32,
+}
+
+impl Estimate {
+    /// Allan deviation in units of `phase LSB/sample`.
+    pub fn deviation(&self) -> f64 {
+        libm::sqrt(self.variance)
+    }
+}
+
+/// Allan variance accumulator for octave spaced averaging times.
+///
+/// Ingests phase samples (e.g. the extended phase from
+/// `unwrap::SaturatingUnwrapper`) at a constant rate. For each averaging time
+/// `tau = 1 << k` samples, `k` in `0..NTAU`, it decimates the phase by `tau`,
+/// keeps the last two decimated phase points and accumulates the square of
+/// the second difference `x[i + 2] - 2 x[i + 1] + x[i]` of each new
+/// point (three-point recursion). The Allan variance is then
+/// `sum / (2 tau**2 count)`.
+///
+/// The state is constant in size and does not store the samples. As a
+/// consequence the second differences at each `tau` do not overlap: the
+/// estimate is the non-overlapping Allan variance. Its confidence interval
+/// for `count` differences is somewhat wider than that of the overlapping
+/// estimator.
+///
+/// To convert to fractional frequency, scale the deviation by
+/// `1/(f0 * t0)` where `f0` is the nominal frequency in phase LSB per
+/// second and `t0` is the sample period in seconds.
+#[derive(Copy, Clone, Debug)]
+pub struct Accumulator<const NTAU: usize> {
+    // Last two decimated phase points per tau, most recent first
+    x: [[i64; 2]; NTAU],
+    // Sum of squared second differences per tau
+    sum: [f64; NTAU],
+    // Number of second differences per tau
+    count: [u32; NTAU],
+    // Input sample index
+    index: u64,
+}
+
+impl<const NTAU: usize> Default for Accumulator<NTAU> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const NTAU: usize> Accumulator<NTAU> {
+    /// Create a new, empty accumulator.
+    pub const fn new() -> Self {
+        Self {
+            x: [[0; 2]; NTAU],
+            sum: [0.; NTAU],
+            count: [0; NTAU],
+            index: 0,
+        }
+    }
+
+    /// Clear all sums and restart.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Ingest a phase sample.
+    ///
+    /// # Args
+    /// * `x`: Phase sample.
+    pub fn update(&mut self, x: i64) {
+        for k in 0..NTAU {
+            if self.index & ((1 << k) - 1) != 0 {
+                // Octave spaced: no higher tau is due either
+                break;
+            }
+            // Number of decimated points before this one
+            let points = self.index >> k;
+            let [x1, x0] = self.x[k];
+            if points >= 2 {
+                let d = x.wrapping_sub(x1.wrapping_mul(2)).wrapping_add(x0);
+                let d = d as f64;
+                self.sum[k] += d * d;
+                self.count[k] += 1;
+            }
+            self.x[k] = [x, x1];
+        }
+        self.index += 1;
+    }
+
+    /// Current estimates.
+    ///
+    /// # Returns
+    /// An `Estimate` for each `tau`, in order of increasing `tau`. The
+    /// variance is zero while `count` is zero.
+    pub fn read(&self) -> [Estimate; NTAU] {
+        let mut e = [Estimate::default(); NTAU];
+        for (k, e) in e.iter_mut().enumerate() {
+            let tau = 1u32 << k;
+            e.tau = tau;
+            e.count = self.count[k];
+            if e.count > 0 {
+                let t = tau as f64;
+                e.variance = self.sum[k] / (2. * t * t * e.count as f64);
+            }
+        }
+        e
+    }
+}
+
+impl<const NTAU: usize> Reset for Accumulator<NTAU> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    // Least squares slope of log(deviation) over log(tau)
+    fn slope<const N: usize>(e: &[Estimate; N]) -> f64 {
+        let p: Vec<(f64, f64)> = e
+            .iter()
+            .map(|e| ((e.tau as f64).ln(), e.deviation().ln()))
+            .collect();
+        let n = p.len() as f64;
+        let (mx, my) = p
+            .iter()
+            .fold((0., 0.), |(x, y), p| (x + p.0 / n, y + p.1 / n));
+        let sxy: f64 = p.iter().map(|p| (p.0 - mx) * (p.1 - my)).sum();
+        let sxx: f64 = p.iter().map(|p| (p.0 - mx).powi(2)).sum();
+        sxy / sxx
+    }
+
+    #[test]
+    fn white_pm() {
+        let mut rng = Xoshiro::new(1);
+        let mut a = Accumulator::<8>::new();
+        let sigma = 1 << 20;
+        for _ in 0..1 << 20 {
+            a.update(rng.gaussian_i32(sigma) as i64);
+        }
+        let e = a.read();
+        assert_eq!(e[7].count, (1 << 13) - 2);
+        // AVAR(1) = 6 sigma**2 / 2
+        let want = 3. * (sigma as f64).powi(2);
+        assert!((e[0].variance / want - 1.).abs() < 2e-2, "{:?}", e[0]);
+        let s = slope(&e);
+        assert!((s + 1.).abs() < 0.05, "{}", s);
+    }
+
+    #[test]
+    fn white_fm() {
+        let mut rng = Xoshiro::new(2);
+        let mut a = Accumulator::<8>::new();
+        let sigma = 1 << 20;
+        let mut x = 0i64;
+        for _ in 0..1 << 20 {
+            x += rng.gaussian_i32(sigma) as i64;
+            a.update(x);
+        }
+        let e = a.read();
+        // AVAR(tau) = sigma**2 / tau
+        for e in e.iter() {
+            let want = (sigma as f64).powi(2) / e.tau as f64;
+            let tol = 5. * (2. / e.count as f64).sqrt();
+            assert!((e.variance / want - 1.).abs() < tol, "{:?}", e);
+        }
+        let s = slope(&e);
+        assert!((s + 0.5).abs() < 0.05, "{}", s);
+    }
+
+    #[test]
+    fn deterministic() {
+        let mut a = Accumulator::<3>::new();
+        assert_eq!(a.read()[2].count, 0);
+        assert_eq!(a.read()[2].variance, 0.);
+        // Constant frequency drift: second differences are constant
+        for i in 0..100i64 {
+            a.update(1000 * i + 3 * i * i);
+        }
+        let e = a.read();
+        assert_eq!(e[0].count, 98);
+        // d = 6 tau**2
+        for e in e.iter() {
+            assert_eq!(e.variance, 18. * (e.tau as f64).powi(2));
+        }
+        a.reset();
+        assert_eq!(a.read()[0].count, 0);
+    }
+}
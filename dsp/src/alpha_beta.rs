@@ -0,0 +1,271 @@
+use super::Reset;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+// Position limit in Q32.16, the `i32` range
+const POSITION_LIMIT: i64 = 1 << 47;
+// Velocity limit in Q32.16, the full `i32` range per sample
+const VELOCITY_LIMIT: i64 = 1 << 48;
+
+// Critically damped gains for a tracking bandwidth (see `AlphaBeta`).
+fn critical_gains(bandwidth: f32) -> (f64, f64) {
+    let theta = libm::exp(-2. * core::f64::consts::PI * bandwidth as f64);
+    (1. - theta * theta, (1. - theta) * (1. - theta))
+}
+
+/// Alpha-beta (g-h) tracking filter.
+///
+/// Estimates position `x` and velocity `v` (per sample) from position
+/// measurements `z`:
+///
+/// ```text
+/// p = x + v
+/// x = p + alpha (z - p)
+/// v = v + beta (z - p)
+/// ```
+///
+/// This is the steady state Kalman filter for a constant velocity model.
+/// For `0 < alpha < 1` and `0 < beta < 4 - 2 alpha` it is stable and it
+/// tracks a constant velocity ramp without lag.
+///
+/// `alpha` and `beta` are Q1.31 fractions (`1 << 31` would be unity). The
+/// state is kept in `i64` Q32.16: the position saturates at the `i32` range
+/// and the velocity at the full `i32` range (`1 << 32`) per sample.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct AlphaBeta {
+    /// Position gain, Q1.31.
+    pub alpha: i32,
+    /// Velocity gain, Q1.31.
+    pub beta: i32,
+    // Position estimate, Q32.16
+    #[cfg_attr(feature = "serde", serde(skip))]
+    x: i64,
+    // Velocity estimate per sample, Q32.16
+    #[cfg_attr(feature = "serde", serde(skip))]
+    v: i64,
+}
+
+impl AlphaBeta {
+    /// Create a new tracker with zero state.
+    ///
+    /// # Args
+    /// * `alpha`: Position gain, Q1.31.
+    /// * `beta`: Velocity gain, Q1.31.
+    pub const fn new(alpha: i32, beta: i32) -> Self {
+        Self {
+            alpha,
+            beta,
+            x: 0,
+            v: 0,
+        }
+    }
+
+    /// Create a critically damped tracker for a bandwidth.
+    ///
+    /// Both closed loop poles are at `theta = exp(-2 pi bandwidth)`:
+    /// `alpha = 1 - theta**2` and `beta = (1 - theta)**2`.
+    ///
+    /// # Args
+    /// * `bandwidth`: Tracking bandwidth in units of the sample rate,
+    ///   positive.
+    pub fn from_bandwidth(bandwidth: f32) -> Self {
+        let (alpha, beta) = critical_gains(bandwidth);
+        let q = (1u64 << 31) as f64;
+        Self::new(
+            libm::round(alpha * q).min(i32::MAX as f64) as i32,
+            libm::round(beta * q).min(i32::MAX as f64) as i32,
+        )
+    }
+
+    /// Set the state.
+    ///
+    /// # Args
+    /// * `x`: Position.
+    /// * `v`: Velocity per sample, Q16.16.
+    pub fn set(&mut self, x: i32, v: i32) {
+        self.x = (x as i64) << 16;
+        self.v = v as i64;
+    }
+
+    /// Position estimate, Q32.16.
+    pub fn position(&self) -> i64 {
+        self.x
+    }
+
+    /// Velocity estimate per sample, Q32.16.
+    pub fn velocity(&self) -> i64 {
+        self.v
+    }
+
+    /// Update the tracker with a measurement.
+    ///
+    /// # Args
+    /// * `z`: Position measurement.
+    ///
+    /// # Returns
+    /// The position estimate (rounded down) and the velocity estimate per
+    /// sample (Q16.16), both saturating.
+    pub fn update(&mut self, z: i32) -> (i32, i32) {
+        // |p| <= 3 << 47, |r| <= 1 << 49
+        let p = self.x + self.v;
+        let r = ((z as i64) << 16) - p;
+        let mul = |g: i32| ((r as i128 * g as i128) >> 31) as i64;
+        self.x = (p + mul(self.alpha)).clamp(-POSITION_LIMIT, POSITION_LIMIT);
+        self.v =
+            (self.v + mul(self.beta)).clamp(-VELOCITY_LIMIT, VELOCITY_LIMIT);
+        let x = (self.x >> 16).clamp(i32::MIN as i64, i32::MAX as i64);
+        let v = self.v.clamp(i32::MIN as i64, i32::MAX as i64);
+        (x as i32, v as i32)
+    }
+}
+
+/// Floating point alpha-beta (g-h) tracking filter.
+///
+/// See `AlphaBeta`.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct AlphaBetaF32 {
+    /// Position gain.
+    pub alpha: f32,
+    /// Velocity gain.
+    pub beta: f32,
+    /// Position estimate.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub x: f32,
+    /// Velocity estimate per sample.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub v: f32,
+}
+
+impl AlphaBetaF32 {
+    /// Create a new tracker with zero state.
+    pub const fn new(alpha: f32, beta: f32) -> Self {
+        Self {
+            alpha,
+            beta,
+            x: 0.,
+            v: 0.,
+        }
+    }
+
+    /// Create a critically damped tracker for a bandwidth.
+    ///
+    /// See `AlphaBeta::from_bandwidth()`.
+    pub fn from_bandwidth(bandwidth: f32) -> Self {
+        let (alpha, beta) = critical_gains(bandwidth);
+        Self::new(alpha as f32, beta as f32)
+    }
+
+    /// Update the tracker with a measurement.
+    ///
+    /// # Args
+    /// * `z`: Position measurement.
+    ///
+    /// # Returns
+    /// The position and velocity (per sample) estimates.
+    pub fn update(&mut self, z: f32) -> (f32, f32) {
+        let p = self.x + self.v;
+        let r = z - p;
+        self.x = p + self.alpha * r;
+        self.v += self.beta * r;
+        (self.x, self.v)
+    }
+}
+
+impl Reset for AlphaBeta {
+    fn reset(&mut self) {
+        self.x = 0;
+        self.v = 0;
+    }
+}
+
+impl Reset for AlphaBetaF32 {
+    fn reset(&mut self) {
+        self.x = 0.;
+        self.v = 0.;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    #[test]
+    fn ramp() {
+        let mut ab = AlphaBeta::from_bandwidth(1e-2);
+        let mut abf = AlphaBetaF32::from_bandwidth(1e-2);
+        let (mut x, mut v) = (0, 0);
+        for i in 0..2000 {
+            let z = -(1 << 20) + 1000 * i + 3;
+            let (xi, vi) = ab.update(z);
+            x = xi - z;
+            v = vi;
+            abf.update(z as f32);
+        }
+        // No lag
+        assert!(x.abs() <= 1, "{}", x);
+        assert!((v - (1000 << 16)).abs() <= 1 << 6, "{}", v);
+        assert!((abf.v - 1000.).abs() < 1e-2, "{:?}", abf);
+    }
+
+    // Steady state variance reduction factors of position and velocity
+    fn vrf(alpha: f64, beta: f64) -> (f64, f64) {
+        let d = alpha * (4. - 2. * alpha - beta);
+        (
+            (2. * alpha * alpha + 2. * beta - 3. * alpha * beta) / d,
+            2. * beta * beta / d,
+        )
+    }
+
+    #[test]
+    fn noise() {
+        let bandwidth = 2e-2;
+        let mut ab = AlphaBeta::from_bandwidth(bandwidth);
+        let mut abf = AlphaBetaF32::from_bandwidth(bandwidth);
+        let (alpha, beta) = critical_gains(bandwidth);
+        let (want_x, want_v) = vrf(alpha, beta);
+        let mut rng = Xoshiro::new(1);
+        // The Q16.16 velocity output range is sufficient
+        let sigma = 1 << 14;
+        let n = 1 << 18;
+        let (mut sx, mut sv, mut sxf) = (0., 0., 0.);
+        for i in 0..n + 1000 {
+            let z = rng.gaussian_i32(sigma);
+            let (x, v) = ab.update(z);
+            let (xf, _) = abf.update(z as f32);
+            if i >= 1000 {
+                sx += (x as f64).powi(2);
+                sv += (v as f64 / (1 << 16) as f64).powi(2);
+                sxf += (xf as f64).powi(2);
+            }
+        }
+        let s2 = (sigma as f64).powi(2) * n as f64;
+        for &(got, want) in [(sx, want_x), (sv, want_v), (sxf, want_x)].iter() {
+            let r = got / s2 / want;
+            assert!((r - 1.).abs() < 5e-2, "{} {}", got / s2, want);
+        }
+        assert!(want_x < 0.2);
+    }
+
+    #[test]
+    fn limits() {
+        let mut ab = AlphaBeta::new(i32::MAX, i32::MAX);
+        for i in 0..1000 {
+            let z = if i & 1 == 0 { i32::MIN } else { i32::MAX };
+            ab.update(z);
+            assert!(ab.position().abs() <= POSITION_LIMIT);
+            assert!(ab.velocity().abs() <= VELOCITY_LIMIT);
+        }
+        let mut ab = AlphaBeta::from_bandwidth(1e-3);
+        ab.set(0, i32::MAX);
+        for _ in 0..1 << 16 {
+            ab.update(i32::MAX);
+        }
+        assert_eq!(ab.update(i32::MAX).0, i32::MAX);
+        ab.set(i32::MIN, 0);
+        let (x, v) = ab.update(i32::MIN);
+        assert_eq!((x, v), (i32::MIN, 0));
+    }
+}
@@ -1,3 +1,5 @@
+use core::f64::consts::PI;
+
 /// 2-argument arctangent function.
 ///
 /// This implementation uses all integer arithmetic for fast
@@ -16,23 +18,40 @@
 /// result range is from i32::MIN to i32::MAX, where i32::MIN
 /// represents -pi and, equivalently, +pi. i32::MAX represents one
 /// count less than +pi.
+///
+/// The special cases of `(y, x)` are defined as follows (for any
+/// positive `a`, including `-i32::MIN`):
+///
+/// * `(0, 0)`: 0
+/// * `(0, a)`: 0
+/// * `(a, 0)`: `1 << 30` (pi/2)
+/// * `(0, -a)`: `i32::MAX` (pi)
+/// * `(-a, 0)`: `-1 << 30` (-pi/2)
+/// * `(a, a)`: `1 << 29` (pi/4)
+/// * `(a, -a)`: `i32::MAX - (1 << 29)` (3pi/4)
+/// * `(-a, -a)`: `(1 << 29) - i32::MAX` (-3pi/4)
+/// * `(-a, a)`: `-1 << 29` (-pi/4)
 pub fn atan2(y: i32, x: i32) -> i32 {
     let sign = (x < 0, y < 0);
 
-    let mut y = y.wrapping_abs() as u32;
-    let mut x = x.wrapping_abs() as u32;
+    // `unsigned_abs()` maps `i32::MIN` to `1 << 31` without overflow.
+    let mut y = y.unsigned_abs();
+    let mut x = x.unsigned_abs();
 
     let y_greater = y > x;
     if y_greater {
         core::mem::swap(&mut y, &mut x);
     }
 
-    let z = (16 - y.leading_zeros() as i32).max(0);
-
-    x >>= z;
+    // The angle of (0, 0) is defined to be 0.
     if x == 0 {
         return 0;
     }
+
+    let z = (16 - y.leading_zeros() as i32).max(0);
+
+    // `y <= x` ensures `x >> z` is nonzero.
+    x >>= z;
     y >>= z;
     let r = (y << 16) / x;
     debug_assert!(r <= 1 << 16);
@@ -82,16 +101,176 @@ pub fn atan2(y: i32, x: i32) -> i32 {
     angle
 }
 
+/// 2-argument arctangent function with higher accuracy.
+///
+/// This uses the same octant folding as `atan2()` but computes the ratio
+/// with 31 bits of resolution and approximates the arctangent with the
+/// degree 9 minimax polynomial from Abramowitz and Stegun (4.4.49). The
+/// worst-case error is about 1e-5 rad (1.6e-6 turns) compared to about
+/// 5e-3 rad for `atan2()`.
+///
+/// The 64 bit division (a library call on the Cortex-M7) and the four
+/// additional multiply-accumulates make this several times slower than
+/// `atan2()`. Use it where phase ripple matters more than cycles.
+///
+/// # Arguments
+///
+/// * `y` - Y-axis component.
+/// * `x` - X-axis component.
+///
+/// # Returns
+///
+/// The angle between the x-axis and the ray to the point (x,y). The
+/// result range, normalization, and the special cases on the axes are the
+/// same as for `atan2()`. The angle of (0, 0) is 0.
+pub fn atan2_precise(y: i32, x: i32) -> i32 {
+    let sign = (x < 0, y < 0);
+
+    let mut y = y.unsigned_abs();
+    let mut x = x.unsigned_abs();
+
+    let y_greater = y > x;
+    if y_greater {
+        core::mem::swap(&mut y, &mut x);
+    }
+
+    if x == 0 {
+        return 0;
+    }
+
+    // `r` is unsigned Q1.31 and <= 1
+    let r = (((y as u64) << 31) / x as u64) as i64;
+
+    // Polynomial coefficients of atan(r)/pi for odd powers of r in Q1.31
+    const FP: f64 = (1i64 << 31) as f64;
+    const A: [i64; 5] = [
+        (0.999_866_0 / PI * FP) as i64,
+        (-0.330_299_5 / PI * FP) as i64,
+        (0.180_141_0 / PI * FP) as i64,
+        (-0.085_133_0 / PI * FP) as i64,
+        (0.020_835_1 / PI * FP) as i64,
+    ];
+    let r2 = (r * r) >> 31;
+    let p = A.iter().rev().fold(0, |p, &a| a + ((p * r2) >> 31));
+    // `angle` is signed Q1.31 with 1 << 31 == +- pi
+    let mut angle = ((r * p) >> 31) as i32;
+
+    if y_greater {
+        angle = (1 << 30) - angle;
+    }
+
+    if sign.0 {
+        angle = i32::MAX - angle;
+    }
+
+    if sign.1 {
+        angle = angle.wrapping_neg();
+    }
+
+    angle
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use core::f64::consts::PI;
 
     fn angle_to_axis(angle: f64) -> f64 {
         let angle = angle % (PI / 2.);
         (PI / 2. - angle).min(angle)
     }
 
+    #[test]
+    fn atan2_precise_error() {
+        let scale = (1i64 << 31) as f64;
+        const N: usize = 1 << 14;
+        let mut max_err = 0f64;
+        for i in 0..N {
+            let p = 2. * PI * i as f64 / N as f64;
+            for &r in [1e-6, 1e-3, 0.3, 1.].iter() {
+                let x = (r * (scale - 1.) * p.cos()).round() as i32;
+                let y = (r * (scale - 1.) * p.sin()).round() as i32;
+                let want = (y as f64).atan2(x as f64);
+                let have = atan2_precise(y, x) as f64 * PI / scale;
+                let mut err = (have - want).abs();
+                if err > PI {
+                    err = 2. * PI - err;
+                }
+                max_err = max_err.max(err);
+            }
+        }
+        let max_err = max_err / (2. * PI);
+        println!("max abs err: {:.2e} turns", max_err);
+        assert!(max_err < 1e-5);
+    }
+
+    // Magnitudes to check on the axes and the diagonals
+    const MAGNITUDES: [i32; 4] = [1, 0x1234, i32::MAX, i32::MIN];
+
+    #[test]
+    fn atan2_axes() {
+        assert_eq!(atan2(0, 0), 0);
+        for &a in MAGNITUDES.iter() {
+            // `-a` for `a = i32::MIN` is `i32::MIN` again. Circumvent.
+            let (p, n) = if a == i32::MIN {
+                (i32::MAX, a)
+            } else {
+                (a, -a)
+            };
+            assert_eq!(atan2(0, p), 0);
+            assert_eq!(atan2(p, 0), 1 << 30);
+            assert_eq!(atan2(0, n), i32::MAX);
+            assert_eq!(atan2(n, 0), -1 << 30);
+            assert_eq!(atan2(0, a), if a < 0 { i32::MAX } else { 0 });
+            assert_eq!(atan2(a, 0), if a < 0 { -1 << 30 } else { 1 << 30 });
+        }
+    }
+
+    #[test]
+    fn atan2_diagonals() {
+        for &a in MAGNITUDES.iter() {
+            let p = a.unsigned_abs();
+            let (p, n) = if p > i32::MAX as u32 {
+                (None, i32::MIN)
+            } else {
+                (Some(p as i32), -(p as i32))
+            };
+            if let Some(p) = p {
+                assert_eq!(atan2(p, p), 1 << 29);
+                assert_eq!(atan2(p, n), i32::MAX - (1 << 29));
+                assert_eq!(atan2(n, p), -1 << 29);
+            }
+            assert_eq!(atan2(n, n), (1 << 29) - i32::MAX);
+        }
+    }
+
+    #[test]
+    fn atan2_precise_axes() {
+        assert_eq!(atan2_precise(0, 0), 0);
+        for &a in MAGNITUDES.iter() {
+            let (p, n) = if a == i32::MIN {
+                (i32::MAX, a)
+            } else {
+                (a, -a)
+            };
+            assert_eq!(atan2_precise(0, p), 0);
+            assert_eq!(atan2_precise(p, 0), 1 << 30);
+            assert_eq!(atan2_precise(0, n), i32::MAX);
+            assert_eq!(atan2_precise(n, 0), -1 << 30);
+        }
+        for &(y, x, want) in [
+            (1, 1, 1 << 29),
+            (1, -1, i32::MAX - (1 << 29)),
+            (-1, -1, (1 << 29) - i32::MAX),
+            (-1, 1, -1 << 29),
+            (i32::MIN, i32::MIN, (1 << 29) - i32::MAX),
+        ]
+        .iter()
+        {
+            let have = atan2_precise(y, x);
+            assert!(have.wrapping_sub(want).abs() < 1 << 14, "{:#x}", have);
+        }
+    }
+
     #[test]
     fn atan2_absolute_error() {
         const N: usize = 321;
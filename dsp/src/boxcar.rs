@@ -0,0 +1,216 @@
+use super::{Filter, FilterF32, Reset};
+
+/// Output rounding of `MovingAverage`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rounding {
+    /// Round towards negative infinity (truncate).
+    Floor,
+    /// Round half up.
+    HalfUp,
+}
+
+/// Boxcar (moving average) filter of length `N`.
+///
+/// The running sum is `i64` and does not overflow for any input if
+/// `N <= 1 << 32`. The update is O(1). For power of two `N` the division is a
+/// shift.
+#[derive(Copy, Clone, Debug)]
+pub struct MovingAverage<const N: usize> {
+    // delay line (ring buffer)
+    x: [i32; N],
+    // index of the oldest sample
+    i: usize,
+    // running sum
+    sum: i64,
+    rounding: Rounding,
+}
+
+impl<const N: usize> MovingAverage<N> {
+    /// Create a new moving average filter with cleared state.
+    ///
+    /// # Args
+    /// * `rounding`: Output rounding.
+    pub fn new(rounding: Rounding) -> Self {
+        Self {
+            x: [0; N],
+            i: 0,
+            sum: 0,
+            rounding,
+        }
+    }
+
+    /// Feed a new input value into the filter and return the new output.
+    ///
+    /// # Args
+    /// * `x0`: New input.
+    pub fn update(&mut self, x0: i32) -> i32 {
+        self.sum += x0 as i64 - self.x[self.i] as i64;
+        self.x[self.i] = x0;
+        self.i += 1;
+        if self.i == N {
+            self.i = 0;
+        }
+        let sum = match self.rounding {
+            Rounding::Floor => self.sum,
+            Rounding::HalfUp => self.sum + (N / 2) as i64,
+        };
+        if N.is_power_of_two() {
+            (sum >> N.trailing_zeros()) as i32
+        } else {
+            sum.div_euclid(N as i64) as i32
+        }
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.x = [0; N];
+        self.i = 0;
+        self.sum = 0;
+    }
+}
+
+/// Floating point boxcar (moving average) filter of length `N`.
+///
+/// The running sum is `f64` to limit the drift from the O(1) update.
+#[derive(Copy, Clone, Debug)]
+pub struct MovingAverageF32<const N: usize> {
+    // delay line (ring buffer)
+    x: [f32; N],
+    // index of the oldest sample
+    i: usize,
+    // running sum
+    sum: f64,
+}
+
+impl<const N: usize> Default for MovingAverageF32<N> {
+    fn default() -> Self {
+        Self {
+            x: [0.; N],
+            i: 0,
+            sum: 0.,
+        }
+    }
+}
+
+impl<const N: usize> MovingAverageF32<N> {
+    /// Feed a new input value into the filter and return the new output.
+    ///
+    /// # Args
+    /// * `x0`: New input.
+    pub fn update(&mut self, x0: f32) -> f32 {
+        self.sum += x0 as f64 - self.x[self.i] as f64;
+        self.x[self.i] = x0;
+        self.i += 1;
+        if self.i == N {
+            self.i = 0;
+        }
+        (self.sum / N as f64) as f32
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl<const N: usize> Reset for MovingAverage<N> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize> Reset for MovingAverageF32<N> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize> Filter for MovingAverage<N> {
+    type Config = Rounding;
+    fn config(&self) -> Rounding {
+        self.rounding
+    }
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x)
+    }
+}
+
+impl<const N: usize> FilterF32 for MovingAverageF32<N> {
+    type Config = ();
+    fn config(&self) {}
+    fn update(&mut self, x: f32) -> f32 {
+        Self::update(self, x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn dc<const N: usize>(x: i32) {
+        for &rounding in [Rounding::Floor, Rounding::HalfUp].iter() {
+            let mut m = MovingAverage::<N>::new(rounding);
+            for _ in 0..N - 1 {
+                m.update(x);
+            }
+            for _ in 0..2 * N {
+                assert_eq!(m.update(x), x);
+            }
+        }
+    }
+
+    #[test]
+    fn dc_exact() {
+        dc::<1>(-7);
+        dc::<8>(12345);
+        dc::<10>(-12345);
+        dc::<7>(i32::MIN);
+        dc::<16>(i32::MAX);
+    }
+
+    #[test]
+    fn impulse() {
+        let mut m = MovingAverage::<5>::new(Rounding::HalfUp);
+        assert_eq!(m.update(1000), 200);
+        for _ in 0..4 {
+            assert_eq!(m.update(0), 200);
+        }
+        assert_eq!(m.update(0), 0);
+
+        let mut m = MovingAverage::<4>::new(Rounding::Floor);
+        for _ in 0..4 {
+            assert_eq!(m.update(-1), -1);
+            assert_eq!(m.update(0), -1);
+            m.reset();
+        }
+        let mut m = MovingAverage::<4>::new(Rounding::HalfUp);
+        assert_eq!(m.update(2), 1);
+        // -0.5 rounds to 0
+        assert_eq!(m.update(-4), 0);
+        assert_eq!(m.update(-6), -2);
+    }
+
+    #[test]
+    fn full_scale() {
+        let mut m = MovingAverage::<{ 1 << 16 }>::new(Rounding::HalfUp);
+        for _ in 0..2 << 16 {
+            m.update(i32::MAX);
+        }
+        assert_eq!(m.update(i32::MAX), i32::MAX);
+        for _ in 0..2 << 16 {
+            m.update(i32::MIN);
+        }
+        assert_eq!(m.update(i32::MIN), i32::MIN);
+    }
+
+    #[test]
+    fn float() {
+        let mut m = MovingAverageF32::<3>::default();
+        assert_eq!(m.update(3.), 1.);
+        assert_eq!(m.update(3.), 2.);
+        assert_eq!(m.update(3.), 3.);
+        assert_eq!(m.update(0.), 2.);
+        m.reset();
+        assert_eq!(m.update(0.), 0.);
+    }
+}
@@ -0,0 +1,215 @@
+use super::Reset;
+
+/// Cascaded integrator-comb (CIC) decimator.
+///
+/// `N` (1 to 4) integrator stages at the input rate are followed by
+/// decimation by `R` and `N` comb stages (differential delay 1) at the output
+/// rate. The DC gain is `R^N`. The accumulators are `i64` and use wrapping
+/// arithmetic which is exact as long as the output fits, i.e. for
+/// `R^N <= 1 << 32`.
+///
+/// The output is scaled by `1/(1 << shift)` where `shift = ceil(log2(R^N))`
+/// with "half up" rounding such that full scale input never overflows.
+///
+/// The magnitude response (as a function of the frequency `f` in units of
+/// the input sample rate) is `|sin(pi*R*f)/(R*sin(pi*f))|^N` (see
+/// `response()`). The passband droop at a quarter of the output Nyquist
+/// frequency (`f = 1/(8*R)`) is about `0.22*N` dB and at half the output
+/// Nyquist frequency about `0.91*N` dB (for large `R`). It can be compensated
+/// with a short FIR filter at the output rate, e.g. `fir::SymmetricFir`.
+#[derive(Copy, Clone, Debug)]
+pub struct Cic<const N: usize> {
+    // decimation ratio
+    r: u32,
+    // output shift
+    shift: u32,
+    // integrator states
+    integrators: [i64; N],
+    // previous comb inputs
+    combs: [i64; N],
+    // input samples until the next output
+    index: u32,
+}
+
+impl<const N: usize> Cic<N> {
+    /// Create a new CIC decimator with cleared state.
+    ///
+    /// # Args
+    /// * `r`: Decimation ratio. `r^N` must not exceed `1 << 32`.
+    ///
+    /// # Returns
+    /// The decimator or an error if the order or ratio is unsupported.
+    pub fn new(r: u32) -> Result<Self, &'static str> {
+        if !(1..=4).contains(&N) {
+            return Err("unsupported order");
+        }
+        let gain = match (r as u64).checked_pow(N as u32) {
+            Some(gain) if r > 0 && gain <= 1 << 32 => gain,
+            _ => return Err("unsupported ratio"),
+        };
+        Ok(Self {
+            r,
+            shift: 64 - (gain - 1).leading_zeros(),
+            integrators: [0; N],
+            combs: [0; N],
+            index: r,
+        })
+    }
+
+    /// The DC gain `R^N` before scaling.
+    pub fn gain(&self) -> u64 {
+        (self.r as u64).pow(N as u32)
+    }
+
+    /// The output scaling shift, `ceil(log2(R^N))`.
+    pub fn shift(&self) -> u32 {
+        self.shift
+    }
+
+    /// Feed a new input sample into the decimator.
+    ///
+    /// # Args
+    /// * `x`: New input.
+    ///
+    /// # Returns
+    /// The scaled output every `R` input samples, `None` otherwise.
+    pub fn update(&mut self, x: i32) -> Option<i32> {
+        let mut y = x as i64;
+        for i in self.integrators.iter_mut() {
+            *i = i.wrapping_add(y);
+            y = *i;
+        }
+        self.index -= 1;
+        if self.index > 0 {
+            return None;
+        }
+        self.index = self.r;
+        for c in self.combs.iter_mut() {
+            let d = y.wrapping_sub(*c);
+            *c = y;
+            y = d;
+        }
+        // Rounding bias, half up
+        let bias = (1i64 << self.shift) >> 1;
+        Some((y.wrapping_add(bias) >> self.shift) as i32)
+    }
+
+    /// Magnitude response (including the scaling).
+    ///
+    /// # Args
+    /// * `f`: Frequency in units of the input sample rate.
+    pub fn response(&self, f: f64) -> f64 {
+        let r = self.r as f64;
+        let h = if libm::fabs(libm::sin(core::f64::consts::PI * f)) < 1e-12 {
+            // Limit at multiples of the input sample rate
+            1.
+        } else {
+            libm::sin(core::f64::consts::PI * r * f)
+                / (r * libm::sin(core::f64::consts::PI * f))
+        };
+        libm::pow(libm::fabs(h), N as f64) * self.gain() as f64
+            / (1u64 << self.shift) as f64
+    }
+
+    /// Clear the state.
+    pub fn reset(&mut self) {
+        self.integrators = [0; N];
+        self.combs = [0; N];
+        self.index = self.r;
+    }
+}
+
+impl<const N: usize> Reset for Cic<N> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limits() {
+        assert!(Cic::<0>::new(2).is_err());
+        assert!(Cic::<5>::new(2).is_err());
+        assert!(Cic::<2>::new(0).is_err());
+        assert!(Cic::<4>::new(257).is_err());
+        assert!(Cic::<4>::new(256).is_ok());
+        assert!(Cic::<1>::new(u32::MAX).is_ok());
+        let c = Cic::<3>::new(10).unwrap();
+        assert_eq!(c.gain(), 1000);
+        assert_eq!(c.shift(), 10);
+        assert_eq!(Cic::<2>::new(16).unwrap().shift(), 8);
+        assert_eq!(Cic::<2>::new(1).unwrap().shift(), 0);
+    }
+
+    #[test]
+    fn dc_gain() {
+        let mut c = Cic::<3>::new(10).unwrap();
+        let mut y = None;
+        for _ in 0..10 * 10 {
+            y = c.update(1 << 20).or(y);
+        }
+        // 1000 * (1 << 20) / 1024
+        assert_eq!(y, Some(1_024_000));
+        assert!((c.response(0.) - 1000. / 1024.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn impulse() {
+        const R: usize = 4;
+        const N: usize = 3;
+        // N-fold convolution of a length R boxcar
+        let mut h = vec![1i64];
+        for _ in 0..N {
+            let mut g = vec![0; h.len() + R - 1];
+            for (i, h) in h.iter().enumerate() {
+                for g in g[i..i + R].iter_mut() {
+                    *g += h;
+                }
+            }
+            h = g;
+        }
+        assert_eq!(h.len(), N * (R - 1) + 1);
+        assert_eq!(h.iter().sum::<i64>(), (R as i64).pow(N as u32));
+
+        let mut c = Cic::<N>::new(R as u32).unwrap();
+        let mut y = vec![];
+        for i in 0..4 * R * N {
+            let x = if i == 0 { 1 << c.shift() } else { 0 };
+            if let Some(yi) = c.update(x) {
+                y.push(yi as i64);
+            }
+        }
+        // Output m is taken after input m*R + R - 1
+        for (m, y) in y.iter().enumerate() {
+            assert_eq!(*y, *h.get(m * R + R - 1).unwrap_or(&0), "{}", m);
+        }
+    }
+
+    #[test]
+    fn full_scale() {
+        fn check<const N: usize>(r: u32) {
+            let mut c = Cic::<N>::new(r).unwrap();
+            assert_eq!(c.gain(), 1 << 32);
+            for &x in [i32::MAX, i32::MIN, i32::MAX].iter() {
+                let mut y = None;
+                for _ in 0..(N as u32 + 1) * r {
+                    y = c.update(x).or(y);
+                }
+                assert_eq!(y, Some(x));
+            }
+        }
+        check::<4>(256);
+        check::<2>(1 << 16);
+    }
+
+    #[test]
+    fn droop() {
+        let c = Cic::<4>::new(64).unwrap();
+        let db = |f: f64| 20. * libm::log10(c.response(f));
+        assert!((db(1. / (8. * 64.)) + 0.22 * 4.).abs() < 0.05);
+        assert!((db(1. / (4. * 64.)) + 0.91 * 4.).abs() < 0.05);
+    }
+}
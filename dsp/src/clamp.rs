@@ -0,0 +1,147 @@
+use super::{max, min, Reset};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Output clamp with railed flags.
+///
+/// Limits a signal and records whether it hit the lower or upper limit.
+/// The railed state is set as soon as the signal reaches a limit and cleared
+/// only once the signal has returned at least `hysteresis` inside the limit.
+/// This prevents flag chatter on a signal riding the limit.
+///
+/// In addition sticky flags record whether the railed state was set at any
+/// time since they were last read and cleared with `take_railed()`.
+#[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Clamp {
+    /// Distance inside the limits the signal needs to return for the railed
+    /// state to clear. Non-negative.
+    pub hysteresis: f32,
+    // Current railed state (low, high)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    railed: (bool, bool),
+    // Sticky railed flags (low, high)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sticky: (bool, bool),
+}
+
+impl Clamp {
+    /// Create a new clamp.
+    ///
+    /// # Args
+    /// * `hysteresis`: Railed state hysteresis, see `Clamp`.
+    pub const fn new(hysteresis: f32) -> Self {
+        Self {
+            hysteresis,
+            railed: (false, false),
+            sticky: (false, false),
+        }
+    }
+
+    /// Limit a sample and update the railed state and flags.
+    ///
+    /// # Args
+    /// * `y`: Sample.
+    /// * `y_min`: Lower limit.
+    /// * `y_max`: Upper limit.
+    ///
+    /// # Returns
+    /// The limited sample.
+    pub fn update(&mut self, y: f32, y_min: f32, y_max: f32) -> f32 {
+        if y <= y_min {
+            self.railed.0 = true;
+        } else if y > y_min + self.hysteresis {
+            self.railed.0 = false;
+        }
+        if y >= y_max {
+            self.railed.1 = true;
+        } else if y < y_max - self.hysteresis {
+            self.railed.1 = false;
+        }
+        self.sticky.0 |= self.railed.0;
+        self.sticky.1 |= self.railed.1;
+        max(y_min, min(y_max, y))
+    }
+
+    /// Current railed state `(low, high)`.
+    pub fn railed(&self) -> (bool, bool) {
+        self.railed
+    }
+
+    /// Read and clear the sticky railed flags `(low, high)`.
+    ///
+    /// The flags are set again with the next `update()` if the signal is still
+    /// railed.
+    pub fn take_railed(&mut self) -> (bool, bool) {
+        let sticky = self.sticky;
+        self.sticky = (false, false);
+        sticky
+    }
+}
+
+impl Reset for Clamp {
+    fn reset(&mut self) {
+        self.railed = (false, false);
+        self.sticky = (false, false);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limit() {
+        let mut c = Clamp::default();
+        assert_eq!(c.update(0.5, -1., 1.), 0.5);
+        assert_eq!(c.take_railed(), (false, false));
+        assert_eq!(c.update(2., -1., 1.), 1.);
+        assert_eq!(c.update(-2., -1., 1.), -1.);
+        assert_eq!(c.railed(), (true, false));
+        assert_eq!(c.take_railed(), (true, true));
+    }
+
+    #[test]
+    fn sticky() {
+        let mut c = Clamp::new(0.);
+        // A single sample excursion
+        c.update(1., -1., 1.);
+        for _ in 0..10 {
+            c.update(0., -1., 1.);
+        }
+        assert_eq!(c.railed(), (false, false));
+        assert_eq!(c.take_railed(), (false, true));
+        // Cleared
+        assert_eq!(c.take_railed(), (false, false));
+        // Still railed: set again
+        c.update(-1., -1., 1.);
+        assert_eq!(c.take_railed(), (true, false));
+        c.update(-1., -1., 1.);
+        assert_eq!(c.take_railed(), (true, false));
+    }
+
+    #[test]
+    fn hysteresis() {
+        // Signal riding the upper limit with some noise
+        let y = [1., 0.99, 0.95, 1.02, 0.98, 0.91, 1., 0.97];
+        let toggles = |c: &mut Clamp| {
+            let mut n = 0;
+            let mut railed = false;
+            for &y in y.iter() {
+                c.update(y, -1., 1.);
+                if c.railed().1 != railed {
+                    railed = !railed;
+                    n += 1;
+                }
+            }
+            n
+        };
+        assert_eq!(toggles(&mut Clamp::new(0.)), 6);
+        let mut c = Clamp::new(0.1);
+        assert_eq!(toggles(&mut c), 1);
+        assert_eq!(c.railed(), (false, true));
+        // Clears once well inside
+        c.update(0.85, -1., 1.);
+        assert_eq!(c.railed(), (false, false));
+    }
+}
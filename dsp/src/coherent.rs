@@ -0,0 +1,153 @@
+//! Coherent (synchronous) averaging of a periodic signal.
+use super::Reset;
+
+/// Coherent averaging accumulator.
+///
+/// The reference period is divided into `N` bins of equal phase width. Each
+/// input sample is accumulated into the bin of its reference phase. The
+/// average of each bin is the average signal over that part of the period.
+/// Components not synchronous with the reference (noise, other tones)
+/// average out: the noise in each bin decreases as the square root of the
+/// number of samples in the bin.
+///
+/// The sums are `i64` and the counts `u32`: a bin stops accumulating once
+/// its count saturates.
+#[derive(Copy, Clone, Debug)]
+pub struct Averager<const N: usize> {
+    sum: [i64; N],
+    count: [u32; N],
+}
+
+impl<const N: usize> Default for Averager<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Averager<N> {
+    /// Create a new, empty averager.
+    pub const fn new() -> Self {
+        Self {
+            sum: [0; N],
+            count: [0; N],
+        }
+    }
+
+    /// Bin index of a phase.
+    ///
+    /// # Args
+    /// * `phase`: Reference phase, `1 << 32` is one turn.
+    ///
+    /// # Returns
+    /// Bin index in `0..N`. Bin `i` covers the phases from `i/N` to
+    /// `(i + 1)/N` turns (the phase taken as `u32`).
+    pub fn bin(phase: i32) -> usize {
+        ((phase as u32 as u64 * N as u64) >> 32) as usize
+    }
+
+    /// Accumulate a sample.
+    ///
+    /// # Args
+    /// * `phase`: Reference phase of the sample, `1 << 32` is one turn.
+    /// * `x`: Input sample.
+    pub fn update(&mut self, phase: i32, x: i32) {
+        let i = Self::bin(phase);
+        if self.count[i] < u32::MAX {
+            self.sum[i] += x as i64;
+            self.count[i] += 1;
+        }
+    }
+
+    /// Number of samples accumulated in each bin.
+    pub fn counts(&self) -> &[u32; N] {
+        &self.count
+    }
+
+    /// The average of each bin.
+    ///
+    /// # Returns
+    /// The bin averages (rounded towards zero), zero for empty bins.
+    pub fn read(&self) -> [i32; N] {
+        let mut avg = [0; N];
+        for (avg, (&sum, &count)) in
+            avg.iter_mut().zip(self.sum.iter().zip(self.count.iter()))
+        {
+            if count > 0 {
+                *avg = (sum / count as i64) as i32;
+            }
+        }
+        avg
+    }
+}
+
+impl<const N: usize> Reset for Averager<N> {
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    #[test]
+    fn bins() {
+        assert_eq!(Averager::<16>::bin(0), 0);
+        assert_eq!(Averager::<16>::bin(-1), 15);
+        assert_eq!(Averager::<16>::bin(i32::MIN), 8);
+        assert_eq!(Averager::<16>::bin((1 << 28) - 1), 0);
+        assert_eq!(Averager::<16>::bin(1 << 28), 1);
+        assert_eq!(Averager::<3>::bin(i32::MAX), 1);
+        let mut a = Averager::<4>::new();
+        a.update(0, 10);
+        a.update(1 << 20, 20);
+        a.update(-1, -7);
+        assert_eq!(a.read(), [15, 0, 0, -7]);
+        assert_eq!(a.counts(), &[2, 0, 0, 1]);
+        a.reset();
+        assert_eq!(a.read(), [0; 4]);
+    }
+
+    // Average `periods` periods of a tone buried in Gaussian noise and
+    // return the RMS deviation of the bins from the noiseless average.
+    fn residual(periods: usize) -> f64 {
+        const N: usize = 16;
+        let mut rng = Xoshiro::new(1);
+        let mut noisy = Averager::<N>::new();
+        let mut clean = Averager::<N>::new();
+        // 100 samples per period, not commensurate with the bins
+        let step = (1u64 << 32) / 100;
+        let mut phase = 0u32;
+        for _ in 0..periods * 100 {
+            let p = phase as f64 / (1u64 << 32) as f64;
+            let x = ((1 << 20) as f64 * (2. * core::f64::consts::PI * p).sin())
+                as i32;
+            noisy.update(phase as i32, x + rng.gaussian_i32(1 << 24));
+            clean.update(phase as i32, x);
+            phase = phase.wrapping_add(step as u32);
+        }
+        let (noisy, clean) = (noisy.read(), clean.read());
+        // The tone is visible in the clean average
+        assert!(clean.iter().any(|&y| y > 1 << 19));
+        let ss: f64 = noisy
+            .iter()
+            .zip(clean.iter())
+            .map(|(&a, &b)| ((a - b) as f64).powi(2))
+            .sum();
+        (ss / N as f64).sqrt()
+    }
+
+    #[test]
+    fn snr() {
+        let r1 = residual(1 << 6);
+        let r2 = residual(1 << 10);
+        let r3 = residual(1 << 14);
+        // The noise decreases with the square root of the averages
+        assert!((r1 / r2 / 4. - 1.).abs() < 0.3, "{} {}", r1, r2);
+        assert!((r2 / r3 / 4. - 1.).abs() < 0.3, "{} {}", r2, r3);
+        // The expected residual: sigma/sqrt(samples per bin)
+        let want = (1 << 24) as f64 / ((1 << 14) as f64 * 100. / 16.).sqrt();
+        assert!((r3 / want - 1.).abs() < 0.3, "{} {}", r3, want);
+    }
+}
@@ -1,8 +1,16 @@
-use core::ops::Mul;
+use core::ops::{Add, Mul, Neg, Sub};
 
-use super::{atan2, cossin};
+use super::{atan2, cossin_complex, isqrt, saturating_scale_i64, Rounding};
 
+/// Complex number `Complex(re, im)`.
+///
+/// For `Complex<i32>` the components are fixed point with full scale
+/// `1 << 31`, i.e. Q1.31. `from_angle()` returns (almost) full scale
+/// magnitude. The `Mul` operators scale the product by `2**-32`, i.e. they
+/// return half the Q1.31 product and do not overflow. `mul_shift()` gives
+/// control over the scaling and saturates.
 #[derive(Copy, Clone, Default, PartialEq, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Complex<T>(pub T, pub T);
 
 impl<T: Copy> Complex<T> {
@@ -14,6 +22,44 @@ impl<T: Copy> Complex<T> {
     }
 }
 
+impl<T: Copy + Neg<Output = T>> Complex<T> {
+    /// Return the complex conjugate.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use dsp::Complex;
+    /// assert_eq!(Complex(3, -4).conj(), Complex(3, 4));
+    /// ```
+    pub fn conj(&self) -> Self {
+        Complex(self.0, -self.1)
+    }
+}
+
+impl<T: Add<Output = T>> Add for Complex<T> {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Complex(self.0 + other.0, self.1 + other.1)
+    }
+}
+
+impl<T: Sub<Output = T>> Sub for Complex<T> {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Complex(self.0 - other.0, self.1 - other.1)
+    }
+}
+
+impl<T: Neg<Output = T>> Neg for Complex<T> {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Complex(-self.0, -self.1)
+    }
+}
+
 impl Complex<i32> {
     /// Return a Complex on the unit circle given an angle.
     ///
@@ -26,49 +72,70 @@ impl Complex<i32> {
     /// Complex::<i32>::from_angle(-1 << 30);  // -pi/2
     /// ```
     pub fn from_angle(angle: i32) -> Self {
-        let (c, s) = cossin(angle);
-        Self(c, s)
+        cossin_complex(angle)
     }
 
     /// Return the absolute square (the squared magnitude).
     ///
-    /// Note: Normalization is `1 << 32`, i.e. U0.32.
+    /// Note: Normalization is `1 << 62`, i.e. U2.62. The sum is computed in
+    /// `u64` and does not overflow. The maximum is `1 << 63` for
+    /// `Complex(i32::MIN, i32::MIN)`.
     ///
-    /// Note(panic): This will panic for `Complex(i32::MIN, i32::MIN)`
+    /// Example:
+    ///
+    /// ```
+    /// use dsp::Complex;
+    /// assert_eq!(Complex(i32::MIN, 0).abs_sqr(), 1 << 62);
+    /// assert_eq!(Complex(i32::MIN, i32::MIN).abs_sqr(), 1 << 63);
+    /// assert_eq!(Complex(3, -4).abs_sqr(), 25);
+    /// ```
+    pub fn abs_sqr(&self) -> u64 {
+        let re = self.0.unsigned_abs() as u64;
+        let im = self.1.unsigned_abs() as u64;
+        re * re + im * im
+    }
+
+    /// Return the absolute value (the magnitude).
+    ///
+    /// Note: Normalization is that of the components. The result is the floor
+    /// of the true magnitude. It saturates at `i32::MAX` for magnitudes of
+    /// `1 << 31` and above (e.g. `Complex(i32::MIN, 0)`).
     ///
     /// Example:
     ///
     /// ```
     /// use dsp::Complex;
-    /// assert_eq!(Complex(i32::MIN, 0).abs_sqr(), 1 << 31);
-    /// assert_eq!(Complex(i32::MAX, i32::MAX).abs_sqr(), u32::MAX - 3);
+    /// assert_eq!(Complex(3, -4).abs(), 5);
+    /// assert_eq!(Complex(i32::MAX, 0).abs(), i32::MAX);
+    /// assert_eq!(Complex(i32::MIN, 0).abs(), i32::MAX);
+    /// assert_eq!(Complex(i32::MIN, i32::MIN).abs(), i32::MAX);
+    /// assert_eq!(Complex(0, 0).abs(), 0);
     /// ```
-    pub fn abs_sqr(&self) -> u32 {
-        (((self.0 as i64) * (self.0 as i64)
-            + (self.1 as i64) * (self.1 as i64))
-            >> 31) as u32
+    pub fn abs(&self) -> i32 {
+        let r = isqrt(self.abs_sqr());
+        if r > i32::MAX as u32 {
+            i32::MAX
+        } else {
+            r as i32
+        }
     }
 
     /// log2(power) re full scale approximation
     ///
     /// TODO: scale up, interpolate
     ///
-    /// Panic:
-    /// This will panic for `Complex(i32::MIN, i32::MIN)`
-    ///
     /// Example:
     ///
     /// ```
     /// use dsp::Complex;
+    /// assert_eq!(Complex(i32::MIN, i32::MIN).log2(), 0);
     /// assert_eq!(Complex(i32::MAX, i32::MAX).log2(), -1);
     /// assert_eq!(Complex(i32::MAX, 0).log2(), -2);
     /// assert_eq!(Complex(1, 0).log2(), -63);
     /// assert_eq!(Complex(0, 0).log2(), -64);
     /// ```
     pub fn log2(&self) -> i32 {
-        let a = (self.0 as i64) * (self.0 as i64)
-            + (self.1 as i64) * (self.1 as i64);
-        -(a.leading_zeros() as i32)
+        -(self.abs_sqr().leading_zeros() as i32)
     }
 
     /// Return the angle.
@@ -89,6 +156,140 @@ impl Complex<i32> {
     pub fn arg(&self) -> i32 {
         atan2(self.1, self.0)
     }
+
+    /// Return the magnitude and the angle.
+    ///
+    /// This is a convenience for the lockin output, see `abs()` and `arg()`.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use dsp::Complex;
+    /// assert_eq!(Complex(0, 1 << 20).abs_arg(), (1 << 20, (i32::MAX >> 1) + 1));
+    /// ```
+    pub fn abs_arg(&self) -> (i32, i32) {
+        (self.abs(), self.arg())
+    }
+
+    /// Complex multiplication with explicit scaling.
+    ///
+    /// The products are accumulated in `i64`, then shifted right by `shift`,
+    /// rounded half up, and saturated.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use dsp::Complex;
+    /// let z = Complex(3 << 20, 4 << 20);
+    /// assert_eq!(z.mul_shift(Complex(1 << 30, 0), 31), z.map(|x| x >> 1));
+    /// assert_eq!(z.mul_shift(z.conj(), 40), Complex(25, 0));
+    /// assert_eq!(
+    ///     Complex(i32::MIN, 0).mul_shift(Complex(i32::MIN, 0), 31),
+    ///     Complex(i32::MAX, 0)
+    /// );
+    /// ```
+    ///
+    /// # Args
+    /// * `other`: Factor.
+    /// * `shift`: Number of bits to shift the product right, 0..=63.
+    pub fn mul_shift(self, other: Self, shift: u32) -> Self {
+        let a = self.0 as i64;
+        let b = self.1 as i64;
+        let c = other.0 as i64;
+        let d = other.1 as i64;
+        // The only overflow is `b*c + a*d == 1 << 63` for
+        // `Complex(i32::MIN, i32::MIN)` squared. Saturating to `i64::MAX`
+        // then results in the same rounded value.
+        Complex(
+            saturating_scale_i64(a * c - b * d, shift, Rounding::HalfUp),
+            saturating_scale_i64(
+                (b * c).saturating_add(a * d),
+                shift,
+                Rounding::HalfUp,
+            ),
+        )
+    }
+}
+
+impl Complex<i16> {
+    /// Convert from the `i32` domain for compact storage.
+    ///
+    /// The components are shifted right by 16 bits, rounded to nearest with
+    /// ties to even, and saturated. The rounding is symmetric
+    /// (`from_i32(-x) == -from_i32(x)` away from the rails) and unbiased
+    /// for ties, so a zero-mean signal stays zero-mean.
+    ///
+    /// Example:
+    ///
+    /// ```
+    /// use dsp::Complex;
+    /// let x = Complex((3 << 16) + (1 << 15), -1 << 15);
+    /// let z = Complex::<i16>::from_i32(x);
+    /// assert_eq!(z, Complex(4, 0));
+    /// assert_eq!(Complex::<i32>::from(z), Complex(4 << 16, 0));
+    /// assert_eq!(
+    ///     Complex::<i16>::from_i32(Complex(i32::MAX, i32::MIN)),
+    ///     Complex(i16::MAX, i16::MIN)
+    /// );
+    /// ```
+    pub fn from_i32(x: Complex<i32>) -> Self {
+        let round = |x: i32| {
+            let y = saturating_scale_i64(x as i64, 16, Rounding::NearestEven);
+            y.min(i16::MAX as i32) as i16
+        };
+        Complex(round(x.0), round(x.1))
+    }
+}
+
+impl From<Complex<i16>> for Complex<i32> {
+    /// Convert to the `i32` domain. This is exact.
+    fn from(x: Complex<i16>) -> Self {
+        Complex((x.0 as i32) << 16, (x.1 as i32) << 16)
+    }
+}
+
+impl Complex<f32> {
+    /// Return a Complex on the unit circle given an angle in radians.
+    ///
+    /// See `Complex::<i32>::from_angle()` for the fixed point version.
+    pub fn from_radians(angle: f32) -> Self {
+        let (s, c) = libm::sincosf(angle);
+        Self(c, s)
+    }
+
+    /// Return the absolute square (the squared magnitude).
+    pub fn abs_sqr(&self) -> f32 {
+        self.0 * self.0 + self.1 * self.1
+    }
+
+    /// Return the absolute value (the magnitude).
+    pub fn abs(&self) -> f32 {
+        libm::hypotf(self.0, self.1)
+    }
+
+    /// Return the angle in radians.
+    pub fn arg(&self) -> f32 {
+        libm::atan2f(self.1, self.0)
+    }
+}
+
+impl Mul for Complex<f32> {
+    type Output = Self;
+
+    fn mul(self, other: Self) -> Self {
+        Complex(
+            self.0 * other.0 - self.1 * other.1,
+            self.1 * other.0 + self.0 * other.1,
+        )
+    }
+}
+
+impl Mul<f32> for Complex<f32> {
+    type Output = Self;
+
+    fn mul(self, other: f32) -> Self {
+        Complex(self.0 * other, self.1 * other)
+    }
 }
 
 impl Mul for Complex<i32> {
@@ -127,3 +328,130 @@ impl Mul<i16> for Complex<i32> {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{prelude::*, rngs::StdRng};
+
+    #[test]
+    fn mul_shift_reference() {
+        let mut rng = StdRng::seed_from_u64(0xc0);
+        for _ in 0..10_000 {
+            let x = Complex(rng.gen::<i32>(), rng.gen::<i32>());
+            let y = Complex(rng.gen::<i32>(), rng.gen::<i32>());
+            let shift = rng.gen_range(31..=34);
+            let z = x.mul_shift(y, shift);
+            let s = (1u64 << shift) as f64;
+            let re = (x.0 as f64 * y.0 as f64 - x.1 as f64 * y.1 as f64) / s;
+            let im = (x.1 as f64 * y.0 as f64 + x.0 as f64 * y.1 as f64) / s;
+            let clip = |v: f64| v.max(i32::MIN as f64).min(i32::MAX as f64);
+            assert!((z.0 as f64 - clip(re)).abs() <= 1., "{:?} {}", z, re);
+            assert!((z.1 as f64 - clip(im)).abs() <= 1., "{:?} {}", z, im);
+        }
+    }
+
+    #[test]
+    fn conjugate() {
+        let mut rng = StdRng::seed_from_u64(0xc1);
+        for _ in 0..1000 {
+            let x = Complex(rng.gen::<i32>() >> 1, rng.gen::<i32>() >> 1);
+            let y = Complex(rng.gen::<i32>(), rng.gen::<i32>());
+            // conj(x*y) == conj(x)*conj(y)
+            assert_eq!(
+                x.mul_shift(y, 32).conj(),
+                x.conj().mul_shift(y.conj(), 32)
+            );
+            let p = x.mul_shift(x.conj(), 32);
+            assert_eq!(p.1, 0);
+            assert_eq!(p.0 as u64, (x.abs_sqr() + (1 << 31)) >> 32);
+            assert_eq!(x.conj().conj(), x);
+            assert_eq!(x + x.conj(), Complex(2 * x.0, 0));
+            assert_eq!(x - x.conj(), Complex(0, 2 * x.1));
+            assert_eq!(-x + x, Complex(0, 0));
+        }
+    }
+
+    #[test]
+    fn saturation() {
+        let min = Complex(i32::MIN, 0);
+        assert_eq!(min.mul_shift(min, 31), Complex(i32::MAX, 0));
+        let min = Complex(i32::MIN, i32::MIN);
+        assert_eq!(min.mul_shift(min, 31), Complex(0, i32::MAX));
+        assert_eq!(min.mul_shift(min, 32), Complex(0, i32::MAX));
+        // Within range with more shift
+        assert_eq!(min.mul_shift(min, 33), Complex(0, 1 << 30));
+        let max = Complex(i32::MAX, i32::MAX);
+        assert_eq!(max.mul_shift(max.conj(), 31), Complex(i32::MAX, 0));
+        assert_eq!(max.mul_shift(-max, 31), Complex(0, i32::MIN));
+    }
+
+    #[test]
+    fn i16_saturation() {
+        let sat = |x| Complex::<i16>::from_i32(Complex(x, 0)).0;
+        assert_eq!(sat(i32::MAX), i16::MAX);
+        assert_eq!(sat(i32::MIN), i16::MIN);
+        assert_eq!(sat(-i32::MAX), i16::MIN);
+        // Largest value that rounds down to i16::MAX
+        assert_eq!(sat((i16::MAX as i32) << 16 | 0x7fff), i16::MAX);
+        assert_eq!(sat(((i16::MAX as i32) << 16) + (1 << 15)), i16::MAX);
+        let z = Complex::<i16>::from_i32(Complex(i32::MIN, i32::MAX));
+        assert_eq!(z, Complex(i16::MIN, i16::MAX));
+    }
+
+    #[test]
+    fn i16_symmetry() {
+        let mut rng = StdRng::seed_from_u64(0xc2);
+        // Away from the rails
+        let lim = ((i16::MAX as i32) << 16) + (1 << 15) - 1;
+        for _ in 0..10_000 {
+            let x =
+                Complex(rng.gen_range(-lim..=lim), rng.gen_range(-lim..=lim));
+            assert_eq!(
+                Complex::<i16>::from_i32(-x),
+                -Complex::<i16>::from_i32(x)
+            );
+        }
+        // Ties do not introduce a DC offset, for a zero-mean (`re`) and a
+        // positive (`im`) signal. Rounding half up would yield
+        // `Complex(1000, 2_001_000)`.
+        let mut sum = Complex(0i64, 0i64);
+        for k in -1000..1000 {
+            let x = Complex((2 * k + 1) << 15, (2 * k + 2001) << 15);
+            let y = Complex::<i16>::from_i32(x);
+            sum = sum + Complex(y.0 as i64, y.1 as i64);
+        }
+        // The exact sums
+        assert_eq!(sum, Complex(0, 2_000_000));
+    }
+
+    #[test]
+    fn i16_round_trip() {
+        let mut rng = StdRng::seed_from_u64(0xc3);
+        let lim = ((i16::MAX as i32) << 16) + (1 << 15) - 1;
+        for _ in 0..10_000 {
+            let x =
+                Complex(rng.gen_range(i32::MIN..=lim), rng.gen::<i32>() >> 4);
+            let y = Complex::<i32>::from(Complex::<i16>::from_i32(x));
+            assert!((y.0 as i64 - x.0 as i64).abs() <= 1 << 15);
+            assert!((y.1 as i64 - x.1 as i64).abs() <= 1 << 15);
+        }
+        // Exact for i16 values
+        for &c in [i16::MIN, -1, 0, 1, i16::MAX].iter() {
+            let z = Complex(c, -(c >> 1));
+            assert_eq!(Complex::<i16>::from_i32(z.into()), z);
+        }
+    }
+
+    #[test]
+    fn float() {
+        let x = Complex(3f32, 4.);
+        assert_eq!(x * x.conj(), Complex(25., 0.));
+        assert_eq!(x * 2., Complex(6., 8.));
+        assert_eq!(x.abs(), 5.);
+        let y = Complex::<f32>::from_radians(core::f32::consts::FRAC_PI_2);
+        assert!((y - Complex(0., 1.)).abs() < 1e-7);
+        assert!((y.arg() - core::f32::consts::FRAC_PI_2).abs() < 1e-7);
+        assert_eq!((x * y).abs_sqr(), 25.);
+    }
+}
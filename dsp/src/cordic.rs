@@ -0,0 +1,203 @@
+use super::Complex;
+
+include!(concat!(env!("OUT_DIR"), "/cordic_table.rs"));
+
+// Guard bits for the internal 64 bit representation.
+const GUARD: usize = 28;
+
+/// Compute the cosine and sine of an angle using CORDIC in rotation mode.
+///
+/// The starting vector is pre-scaled by the inverse of the CORDIC gain
+/// (`CORDIC_GAIN_INV`, about 0.6073) so that the result is on the unit circle
+/// with full scale amplitude `1 << 31`. Components are saturated to the
+/// `i32` range.
+///
+/// With `CORDIC_DEPTH = 24` iterations there is about 1.2e-7 (in units of
+/// full scale) maximum error in each quadrature. Each iteration costs a few
+/// 64 bit additions and shifts. This is slower than the table based `cossin`
+/// but more accurate and it shares the kernel with `vector()`.
+///
+/// # Arguments
+/// * `phase` - 32-bit phase with `1 << 31 == pi`.
+///
+/// # Returns
+/// The cos and sin of the phase as a `Complex<i32>`.
+pub fn rotate(phase: i32) -> Complex<i32> {
+    // Fold into [-pi/2, pi/2) by rotating by pi (negating the result) if the
+    // two MSBs differ.
+    let fold = (phase ^ (phase << 1)) < 0;
+    let mut z = if fold {
+        phase.wrapping_add(i32::MIN)
+    } else {
+        phase
+    };
+
+    let mut x = (CORDIC_GAIN_INV as i64) << GUARD;
+    let mut y = 0i64;
+    for (i, &atan) in CORDIC_ATAN.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if z >= 0 {
+            x -= dx;
+            y += dy;
+            z -= atan;
+        } else {
+            x += dx;
+            y -= dy;
+            z += atan;
+        }
+    }
+
+    if fold {
+        x = -x;
+        y = -y;
+    }
+
+    let unscale = |v: i64| {
+        let v = (v + (1 << (GUARD - 1))) >> GUARD;
+        v.max(i32::MIN as i64).min(i32::MAX as i64) as i32
+    };
+    Complex(unscale(x), unscale(y))
+}
+
+/// Compute magnitude and angle of a vector using CORDIC in vectoring mode.
+///
+/// The CORDIC gain is compensated by multiplication with `CORDIC_GAIN_INV`
+/// after the iterations. The magnitude is saturated at `i32::MAX`
+/// (magnitudes of `1 << 31` and above).
+///
+/// With `CORDIC_DEPTH = 24` iterations the phase error is about 1.2e-7 rad
+/// and the magnitude error is one LSB plus about 1e-7 relative.
+///
+/// # Arguments
+/// * `c` - Input vector.
+///
+/// # Returns
+/// A tuple of the magnitude (same normalization as the input) and the angle
+/// (`1 << 31 == pi`).
+pub fn vector(c: Complex<i32>) -> (i32, i32) {
+    let mut x = (c.0 as i64) << GUARD;
+    let mut y = (c.1 as i64) << GUARD;
+    // Fold into the right half plane.
+    let mut z = if x < 0 {
+        x = -x;
+        y = -y;
+        i32::MIN
+    } else {
+        0
+    };
+
+    for (i, &atan) in CORDIC_ATAN.iter().enumerate() {
+        let (dx, dy) = (y >> i, x >> i);
+        if y < 0 {
+            x -= dx;
+            y += dy;
+            z = z.wrapping_sub(atan);
+        } else {
+            x += dx;
+            y -= dy;
+            z = z.wrapping_add(atan);
+        }
+    }
+
+    // `x` is now positive and at most about 2.33 * (1 << 31 + GUARD).
+    let x = (x >> GUARD) as u64;
+    let mag = (x * CORDIC_GAIN_INV as u64 + (1 << 30)) >> 31;
+    let mag = if mag > i32::MAX as u64 {
+        i32::MAX
+    } else {
+        mag as i32
+    };
+    (mag, z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{atan2, cossin, COSSIN_MAX_ERROR};
+    use core::f64::consts::PI;
+
+    const SCALE: f64 = (1i64 << 31) as f64;
+
+    #[test]
+    fn rotate_error() {
+        // log2 of the number of phase values to check
+        const PHASE_DEPTH: usize = 20;
+        let mut max_err = 0f64;
+        let mut max_err_cossin = 0f64;
+        for phase in 0..(1 << PHASE_DEPTH) {
+            let phase = (phase << (32 - PHASE_DEPTH)) as i32;
+            let have = rotate(phase);
+            let p = phase as f64 * PI / SCALE;
+            let want = (p.cos(), p.sin());
+            max_err = max_err
+                .max((have.0 as f64 / SCALE - want.0).abs())
+                .max((have.1 as f64 / SCALE - want.1).abs());
+            // Normalize the constant cossin() LUT amplitude
+            let (c, s) = cossin(phase);
+            let a = SCALE - (1 << 15) as f64;
+            max_err_cossin = max_err_cossin
+                .max((have.0 as f64 / SCALE - c as f64 / a).abs())
+                .max((have.1 as f64 / SCALE - s as f64 / a).abs());
+        }
+        println!("max: {:.2e}, re cossin: {:.2e}", max_err, max_err_cossin);
+        assert!(max_err < 2e-7);
+        assert!(max_err_cossin < COSSIN_MAX_ERROR + 2e-7);
+    }
+
+    #[test]
+    fn vector_error() {
+        const N: usize = 1 << 12;
+        let mut max_err = 0f64;
+        let mut max_err_atan2 = 0f64;
+        let mut max_mag_err = 0f64;
+        for i in 0..N {
+            let p = 2. * PI * i as f64 / N as f64;
+            for &r in [1e-5, 1e-3, 0.1, 0.5, 0.999].iter() {
+                let c = Complex(
+                    (r * SCALE * p.cos()).round() as i32,
+                    (r * SCALE * p.sin()).round() as i32,
+                );
+                let (mag, phase) = vector(c);
+                let want = (c.1 as f64).atan2(c.0 as f64);
+                let err = (phase.wrapping_sub((want / PI * SCALE) as i32)
+                    as f64
+                    / SCALE
+                    * PI)
+                    .abs();
+                max_err = max_err.max(err);
+                let err = (phase.wrapping_sub(atan2(c.1, c.0)) as f64 / SCALE
+                    * PI)
+                    .abs();
+                max_err_atan2 = max_err_atan2.max(err);
+                let want = (c.0 as f64).hypot(c.1 as f64);
+                // Relative error beyond one LSB
+                max_mag_err =
+                    max_mag_err.max(((mag as f64 - want).abs() - 1.) / want);
+            }
+        }
+        println!(
+            "max: {:.2e}, re atan2: {:.2e}, mag: {:.2e}",
+            max_err, max_err_atan2, max_mag_err
+        );
+        assert!(max_err < 2e-7);
+        assert!(max_err_atan2 < 5e-3);
+        assert!(max_mag_err < 2e-7);
+    }
+
+    #[test]
+    fn vector_limits() {
+        let close = |a: i32, b: i32| a.wrapping_sub(b).abs() < 1 << 8;
+        let (mag, phase) = vector(Complex(i32::MIN, i32::MIN));
+        assert_eq!(mag, i32::MAX);
+        assert!(close(phase, -3 << 29));
+        let (mag, phase) = vector(Complex(i32::MIN, 0));
+        assert_eq!(mag, i32::MAX);
+        assert!(close(phase, i32::MIN));
+        let (mag, phase) = vector(Complex(0, i32::MAX));
+        assert!(i32::MAX - mag < 8);
+        assert!(close(phase, 1 << 30));
+        assert_eq!(vector(Complex(0, 0)).0, 0);
+        assert_eq!(rotate(0).0, i32::MAX);
+        assert_eq!(rotate(i32::MIN).0, i32::MIN);
+    }
+}
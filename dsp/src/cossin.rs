@@ -1,7 +1,52 @@
+use super::{Complex, Q31};
 use core::f64::consts::PI;
 
 include!(concat!(env!("OUT_DIR"), "/cossin_table.rs"));
 
+// Error bounds of `cossin()` for the table depth selected by the
+// `cossin-small` (5 bit), default (7 bit), and `cossin-large` (9 bit)
+// features. The errors are relative to the constant LUT amplitude and
+// are verified over 20 bit phase.
+
+/// Maximum error in each quadrature.
+#[cfg(all(feature = "cossin-small", not(feature = "cossin-large")))]
+pub const COSSIN_MAX_ERROR: f64 = 8e-5;
+/// RMS error in each quadrature.
+#[cfg(all(feature = "cossin-small", not(feature = "cossin-large")))]
+pub const COSSIN_RMS_ERROR: f64 = 4e-7;
+/// Maximum relative amplitude error.
+#[cfg(all(feature = "cossin-small", not(feature = "cossin-large")))]
+pub const COSSIN_AMPLITUDE_ERROR: f64 = 8.2e-5;
+/// Maximum phase error in radians.
+#[cfg(all(feature = "cossin-small", not(feature = "cossin-large")))]
+pub const COSSIN_PHASE_ERROR: f64 = 9e-6;
+
+/// Maximum error in each quadrature.
+#[cfg(not(any(feature = "cossin-small", feature = "cossin-large")))]
+pub const COSSIN_MAX_ERROR: f64 = 1.1e-5;
+/// RMS error in each quadrature.
+#[cfg(not(any(feature = "cossin-small", feature = "cossin-large")))]
+pub const COSSIN_RMS_ERROR: f64 = 6e-8;
+/// Maximum relative amplitude error.
+#[cfg(not(any(feature = "cossin-small", feature = "cossin-large")))]
+pub const COSSIN_AMPLITUDE_ERROR: f64 = 1.15e-5;
+/// Maximum phase error in radians.
+#[cfg(not(any(feature = "cossin-small", feature = "cossin-large")))]
+pub const COSSIN_PHASE_ERROR: f64 = 8.5e-6;
+
+/// Maximum error in each quadrature.
+#[cfg(feature = "cossin-large")]
+pub const COSSIN_MAX_ERROR: f64 = 8e-6;
+/// RMS error in each quadrature.
+#[cfg(feature = "cossin-large")]
+pub const COSSIN_RMS_ERROR: f64 = 5.8e-8;
+/// Maximum relative amplitude error.
+#[cfg(feature = "cossin-large")]
+pub const COSSIN_AMPLITUDE_ERROR: f64 = 8.2e-6;
+/// Maximum phase error in radians.
+#[cfg(feature = "cossin-large")]
+pub const COSSIN_PHASE_ERROR: f64 = 8.5e-6;
+
 /// Compute the cosine and sine of an angle.
 /// This is ported from the MiSoC cossin core.
 /// (https://github.com/m-labs/misoc/blob/master/misoc/cores/cossin.py)
@@ -11,8 +56,9 @@ include!(concat!(env!("OUT_DIR"), "/cossin_table.rs"));
 ///
 /// # Returns
 /// The cos and sin values of the provided phase as a `(i32, i32)`
-/// tuple. With a 7-bit deep LUT there is 1e-5 max and 6e-8 RMS error
-/// in each quadrature over 20 bit phase.
+/// tuple. With the default 7-bit deep LUT there is 1.1e-5 max and 6e-8 RMS
+/// error in each quadrature over 20 bit phase. See `COSSIN_MAX_ERROR` and
+/// friends for the bounds of the active table size.
 pub fn cossin(phase: i32) -> (i32, i32) {
     // Phase bits excluding the three highes MSB
     const OCTANT_BITS: usize = 32 - 3;
@@ -52,7 +98,9 @@ pub fn cossin(phase: i32) -> (i32, i32) {
     let dcos = (sin * dphi) >> (COSSIN_DEPTH + 1);
     let dsin = (cos * dphi) >> (COSSIN_DEPTH + 1);
 
-    cos = (cos << (ALIGN_MSB - 1)) - dcos;
+    // The interpolation may overshoot full scale close to zero phase for
+    // small tables.
+    cos = (cos << (ALIGN_MSB - 1)).saturating_sub(dcos);
     sin = (sin << (ALIGN_MSB - 1)) + dsin;
 
     // Unmap using octant bits.
@@ -71,12 +119,77 @@ pub fn cossin(phase: i32) -> (i32, i32) {
     (cos, sin)
 }
 
+/// Compute the cosine and sine of an angle as a `Complex`.
+///
+/// See `cossin()`.
+///
+/// # Arguments
+/// * `phase` - 32-bit phase.
+///
+/// # Returns
+/// `Complex(cos, sin)` of the provided phase.
+pub fn cossin_complex(phase: i32) -> Complex<i32> {
+    let (c, s) = cossin(phase);
+    Complex(c, s)
+}
+
+/// Compute the cosine and sine of an angle as `Q31` fractions.
+///
+/// See `cossin()`.
+///
+/// # Arguments
+/// * `phase` - 32-bit phase.
+///
+/// # Returns
+/// `Complex(cos, sin)` of the provided phase.
+pub fn cossin_q31(phase: i32) -> Complex<Q31> {
+    let (c, s) = cossin(phase);
+    Complex(Q31(c), Q31(s))
+}
+
+/// Compute the cosine and sine of an angle in turns.
+///
+/// This is a floating point wrapper around `cossin()` for host-side code
+/// and float domain signal generation. It is not faster than `libm`.
+///
+/// # Arguments
+/// * `turns` - Angle in turns (`1.` is `2*pi`). Values outside `[0, 1)`
+///   are wrapped. Non-finite values are treated as zero.
+///
+/// # Returns
+/// The cos and sin values as a `(f32, f32)` tuple. The accuracy is that of
+/// the underlying table, see `COSSIN_MAX_ERROR`. The phase is
+/// quantized to 32 bit after wrapping. Large `turns` lose resolution
+/// due to the `f32` input.
+pub fn cossin_f32(turns: f32) -> (f32, f32) {
+    // Amplitude of the LUT data range
+    const AMPLITUDE: f64 = ((1i64 << 31) - (1i64 << 15)) as _;
+    let turns = turns as f64;
+    let frac = turns - libm::floor(turns);
+    // `frac` may round up to `1.` which wraps to zero phase.
+    let phase = libm::round(frac * (1u64 << 32) as f64) as u64 as u32 as i32;
+    let (c, s) = cossin(phase);
+    ((c as f64 / AMPLITUDE) as f32, (s as f64 / AMPLITUDE) as f32)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::Complex;
     use core::f64::consts::PI;
 
+    #[test]
+    fn q31() {
+        for &phase in [0, 1 << 30, -1 << 30, i32::MIN, 0x1234_5678].iter() {
+            let Complex(c, s) = cossin_q31(phase);
+            assert_eq!((c.0, s.0), cossin(phase));
+        }
+        let Complex(c, s) = cossin_q31(1 << 29);
+        let half = core::f32::consts::FRAC_1_SQRT_2;
+        assert!((c.to_f32() - half).abs() < 1e-4);
+        assert!((s.to_f32() - half).abs() < 1e-4);
+    }
+
     #[test]
     fn cossin_error_max_rms_all_phase() {
         // Constant amplitude error due to LUT data range.
@@ -85,6 +198,8 @@ mod tests {
         let mut rms_err = Complex(0f64, 0f64);
         let mut sum_err = Complex(0f64, 0f64);
         let mut max_err = Complex(0f64, 0f64);
+        let mut max_err_abs = 0f64;
+        let mut max_err_arg = 0f64;
         let mut sum = Complex(0f64, 0f64);
         let mut demod = Complex(0f64, 0f64);
 
@@ -121,6 +236,12 @@ mod tests {
 
             max_err.0 = max_err.0.max(err.0.abs());
             max_err.1 = max_err.1.max(err.1.abs());
+
+            let err_abs = (have.0 * have.0 + have.1 * have.1).sqrt() - 1.;
+            max_err_abs = max_err_abs.max(err_abs.abs());
+            let err_arg = (have.1 * want.0 - have.0 * want.1)
+                .atan2(have.0 * want.0 + have.1 * want.1);
+            max_err_arg = max_err_arg.max(err_arg.abs());
         }
         rms_err.0 /= MAX_PHASE;
         rms_err.1 /= MAX_PHASE;
@@ -130,6 +251,7 @@ mod tests {
         println!("sum_err: {:.2e} {:.2e}", sum_err.0, sum_err.1);
         println!("rms: {:.2e} {:.2e}", rms_err.0.sqrt(), rms_err.1.sqrt());
         println!("max: {:.2e} {:.2e}", max_err.0, max_err.1);
+        println!("abs: {:.2e} arg: {:.2e}", max_err_abs, max_err_arg);
 
         assert!(sum.0.abs() < 4e-10);
         assert!(sum.1.abs() < 4e-10);
@@ -140,10 +262,42 @@ mod tests {
         assert!(sum_err.0.abs() < 4e-10);
         assert!(sum_err.1.abs() < 4e-10);
 
-        assert!(rms_err.0.sqrt() < 6e-8);
-        assert!(rms_err.1.sqrt() < 6e-8);
+        assert!(rms_err.0.sqrt() < COSSIN_RMS_ERROR);
+        assert!(rms_err.1.sqrt() < COSSIN_RMS_ERROR);
 
-        assert!(max_err.0 < 1.1e-5);
-        assert!(max_err.1 < 1.1e-5);
+        assert!(max_err.0 < COSSIN_MAX_ERROR);
+        assert!(max_err.1 < COSSIN_MAX_ERROR);
+
+        assert!(max_err_abs < COSSIN_AMPLITUDE_ERROR);
+        assert!(max_err_arg < COSSIN_PHASE_ERROR);
+    }
+
+    #[test]
+    fn complex() {
+        for &phase in [0, 1 << 30, -1 << 30, 0x1234_5678, i32::MIN].iter() {
+            let (c, s) = cossin(phase);
+            assert_eq!(cossin_complex(phase), Complex(c, s));
+        }
+    }
+
+    #[test]
+    fn float() {
+        let mut max_err = 0f64;
+        for i in -5000..5000 {
+            let turns = i as f32 * 0.000_77;
+            let (c, s) = cossin_f32(turns);
+            let p = 2. * PI * turns as f64;
+            let err =
+                (c as f64 - p.cos()).abs().max((s as f64 - p.sin()).abs());
+            max_err = max_err.max(err);
+        }
+        println!("max: {:.2e}", max_err);
+        assert!(max_err < COSSIN_MAX_ERROR);
+        let zero = cossin_f32(0.);
+        assert_eq!(zero, cossin_f32(-3.));
+        assert_eq!(zero, cossin_f32(1. - 1e-9));
+        assert_eq!(zero, cossin_f32(f32::NAN));
+        assert_eq!(cossin_f32(1.25), cossin_f32(0.25));
+        assert_eq!(cossin_f32(-0.75), cossin_f32(0.25));
     }
 }
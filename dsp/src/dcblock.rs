@@ -0,0 +1,153 @@
+use super::{Filter, Reset};
+
+/// One-pole DC blocker (highpass).
+///
+/// `y[n] = x[n] - x[n-1] + a*y[n-1]` with the pole at
+/// `a = 1 - 2**-pole_shift`. The time constant is `1 << pole_shift` samples,
+/// the -3 dB corner is at about `2**-pole_shift/(2*pi)` of the sample rate.
+/// The gain at high frequencies is 1.
+///
+/// The filter state carries `pole_shift` fractional bits in an `i64` (fraction
+/// saving). Both the leak and the output are rounded half up. A constant input
+/// decays to exactly zero and quantization does not leave a residual offset.
+#[derive(Copy, Clone, Default)]
+pub struct DcBlock {
+    pole_shift: u32,
+    // Previous input
+    x: i32,
+    // Output with `pole_shift` fractional bits
+    y: i64,
+}
+
+impl DcBlock {
+    /// Create a new DC blocker.
+    ///
+    /// # Args
+    /// * `pole_shift`: Log2 time constant in samples, 1..=30.
+    pub fn new(pole_shift: u32) -> Self {
+        debug_assert!((1..=30).contains(&pole_shift));
+        Self {
+            pole_shift,
+            ..Default::default()
+        }
+    }
+
+    /// Log2 time constant.
+    pub fn pole_shift(&self) -> u32 {
+        self.pole_shift
+    }
+
+    /// Clear the filter state.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.pole_shift);
+    }
+
+    /// Update the filter with a new sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    ///
+    /// # Returns
+    /// Filtered output, saturating.
+    pub fn update(&mut self, x: i32) -> i32 {
+        let k = self.pole_shift;
+        // Rounding bias, half up
+        let bias = 1 << (k - 1);
+        self.y += ((x as i64 - self.x as i64) << k) - ((self.y + bias) >> k);
+        self.x = x;
+        ((self.y + bias) >> k)
+            .max(i32::MIN as i64)
+            .min(i32::MAX as i64) as i32
+    }
+}
+
+impl Reset for DcBlock {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl Filter for DcBlock {
+    type Config = u32;
+    fn config(&self) -> u32 {
+        self.pole_shift
+    }
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::f64::consts::PI;
+
+    #[test]
+    fn decay() {
+        for &x in [1 << 20, -1 << 20, i32::MAX, i32::MIN, 1, -1].iter() {
+            let k = 8;
+            let mut dc = DcBlock::new(k);
+            assert_eq!(dc.update(x), x);
+            // ln(2**32) ~ 22.2 time constants to below half an LSB
+            for _ in 0..23 << k {
+                dc.update(x);
+            }
+            for _ in 0..1 << k {
+                assert_eq!(dc.update(x), 0);
+            }
+        }
+    }
+
+    #[test]
+    fn no_offset() {
+        // Full scale tone on top of an offset. The mean of the output is zero.
+        let k = 6;
+        let mut dc = DcBlock::new(k);
+        let mut sum = 0i64;
+        let p = 7;
+        // Integer number of periods
+        let n = p << 13;
+        for i in 0..(1 << 12) + n {
+            let x = 12345
+                + (1000. * (2. * PI * (i % p) as f64 / p as f64).sin()) as i32;
+            let y = dc.update(x);
+            if i >= 1 << 12 {
+                sum += y as i64;
+            }
+        }
+        assert_eq!(sum, 0);
+    }
+
+    #[test]
+    fn ac() {
+        let k = 10;
+        let mut dc = DcBlock::new(k);
+        let a = (1 << 24) as f64;
+        // Far above the corner at about 1.6e-4.
+        let f = 1. / 64.;
+        let settle = 16 << k;
+        let m = 1 << 12;
+        let mut iq = (0f64, 0f64);
+        for i in 0..settle + m {
+            let p = 2. * PI * f * i as f64;
+            let y = dc.update((a * p.sin()).round() as i32) as f64;
+            if i >= settle {
+                iq.0 += y * p.cos();
+                iq.1 += y * p.sin();
+            }
+        }
+        let g = 2. * iq.0.hypot(iq.1) / m as f64 / a;
+        assert!((20. * g.log10()).abs() < 0.01, "{}", g);
+    }
+
+    #[test]
+    fn full_scale() {
+        let mut dc = DcBlock::new(30);
+        for i in 0..1000 {
+            let x = if i & 1 == 0 { i32::MAX } else { i32::MIN };
+            dc.update(x);
+        }
+        dc.reset();
+        assert_eq!(dc.update(5), 5);
+    }
+}
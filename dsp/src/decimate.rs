@@ -0,0 +1,155 @@
+use super::{cic::Cic, Complex, Reset};
+
+/// Anti-aliased decimation of IQ data.
+///
+/// Both components are filtered and decimated by a `Cic<N>` with a power of
+/// two ratio `R = 1 << log2_r`. The DC gain is exactly one. Tones close to
+/// multiples of the output rate, which would alias close to DC, are
+/// suppressed by the zeros of the CIC response, see `Cic::response()`. A
+/// tone at 1.25 times the output Nyquist frequency is attenuated by about
+/// `6.6*N` dB.
+///
+/// The ratio can be changed at runtime, this clears the filter state.
+#[derive(Copy, Clone, Debug)]
+pub struct Decimator<const N: usize> {
+    cic: [Cic<N>; 2],
+    log2_r: u8,
+}
+
+impl<const N: usize> Decimator<N> {
+    /// Create a new decimator.
+    ///
+    /// # Args
+    /// * `log2_r`: Log2 of the decimation ratio. `R^N` must not exceed
+    ///   `1 << 32`, i.e. `log2_r*N <= 32`.
+    ///
+    /// # Returns
+    /// The decimator or an error if the order or ratio is unsupported.
+    pub fn new(log2_r: u8) -> Result<Self, &'static str> {
+        if log2_r >= 32 {
+            return Err("unsupported ratio");
+        }
+        let cic = Cic::new(1 << log2_r)?;
+        Ok(Self {
+            cic: [cic; 2],
+            log2_r,
+        })
+    }
+
+    /// Log2 of the decimation ratio.
+    pub fn log2_ratio(&self) -> u8 {
+        self.log2_r
+    }
+
+    /// Change the decimation ratio and clear the state.
+    ///
+    /// # Args
+    /// * `log2_r`: Log2 of the decimation ratio, see `new()`.
+    ///
+    /// # Returns
+    /// An error if the ratio is unsupported. The decimator is unchanged
+    /// then.
+    pub fn set_log2_ratio(&mut self, log2_r: u8) -> Result<(), &'static str> {
+        *self = Self::new(log2_r)?;
+        Ok(())
+    }
+
+    /// Filter a new IQ sample.
+    ///
+    /// # Args
+    /// * `iq`: Input sample.
+    ///
+    /// # Returns
+    /// The filtered output every `R` inputs, `None` otherwise.
+    pub fn update(&mut self, iq: Complex<i32>) -> Option<Complex<i32>> {
+        let i = self.cic[0].update(iq.0);
+        let q = self.cic[1].update(iq.1);
+        Some(Complex(i?, q?))
+    }
+}
+
+impl<const N: usize> Reset for Decimator<N> {
+    fn reset(&mut self) {
+        self.cic.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Decimate a complex tone at frequency `f` (units of the input rate) and
+    // return the output amplitude relative to the input amplitude and the
+    // number of outputs.
+    fn tone(log2_r: u8, f: f64, n: usize) -> (f64, usize) {
+        let mut d = Decimator::<4>::new(log2_r).unwrap();
+        let amplitude = (1 << 30) as f64;
+        let mut max = 0f64;
+        let mut outputs = 0;
+        for i in 0..n {
+            let p = 2. * core::f64::consts::PI * f * i as f64;
+            let x = Complex(
+                (amplitude * p.cos()) as i32,
+                (amplitude * p.sin()) as i32,
+            );
+            if let Some(y) = d.update(x) {
+                outputs += 1;
+                // Skip the transient
+                if outputs > 8 {
+                    max = max.max((y.0 as f64).hypot(y.1 as f64));
+                }
+            }
+        }
+        (max / amplitude, outputs)
+    }
+
+    #[test]
+    fn rate() {
+        for log2_r in 0..=8 {
+            let mut d = Decimator::<4>::new(log2_r).unwrap();
+            let mut n = 0;
+            for i in 0..1 << 12 {
+                if d.update(Complex(1 << 20, -1 << 20)).is_some() {
+                    n += 1;
+                    // Regular output every R inputs
+                    assert_eq!((i + 1) % (1 << log2_r), 0);
+                }
+            }
+            assert_eq!(n, 1 << (12 - log2_r));
+        }
+        assert!(Decimator::<4>::new(9).is_err());
+        assert!(Decimator::<2>::new(16).is_ok());
+        assert!(Decimator::<1>::new(32).is_err());
+        let mut d = Decimator::<4>::new(3).unwrap();
+        assert!(d.set_log2_ratio(10).is_err());
+        assert_eq!(d.log2_ratio(), 3);
+        d.set_log2_ratio(5).unwrap();
+        assert_eq!(d.log2_ratio(), 5);
+    }
+
+    #[test]
+    fn dc() {
+        let mut d = Decimator::<4>::new(6).unwrap();
+        let mut y = None;
+        for _ in 0..64 * 5 {
+            y = d.update(Complex(123_456, i32::MIN)).or(y);
+        }
+        assert_eq!(y, Some(Complex(123_456, i32::MIN)));
+    }
+
+    #[test]
+    fn alias() {
+        let r = 1 << 5;
+        let nyquist = 0.5 / r as f64;
+        // Passband
+        let (pass, n) = tone(5, 0.1 * nyquist, r * 1000);
+        assert_eq!(n, 1000);
+        assert!(pass > 0.98, "{}", pass);
+        // Just above the output Nyquist frequency: at least 24 dB
+        let (stop, _) = tone(5, 1.25 * nyquist, r * 1000);
+        assert!(stop < 0.063, "{}", stop);
+        // Close to the output rate, aliasing close to DC: at least 80 dB
+        let (alias, _) = tone(5, 2. * nyquist * 1.01, r * 1000);
+        assert!(alias < 1e-4, "{}", alias);
+    }
+}
@@ -0,0 +1,34 @@
+/// Invalid DSP configuration.
+///
+/// Returned by the checked constructors and validation functions. The
+/// corresponding hot path functions (e.g. `update()`) do not check their
+/// arguments in release builds.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// A shift (gain or time constant exponent) is out of range.
+    ShiftRange,
+    /// The PLL frequency shift does not exceed the phase shift.
+    ShiftOrder,
+    /// A bandwidth is NaN, not positive, or out of range.
+    BandwidthRange,
+    /// The loop order is not supported.
+    Order,
+}
+
+impl Error {
+    /// Return a description of the error.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Error::ShiftRange => "shift out of range",
+            Error::ShiftOrder => "frequency shift must exceed phase shift",
+            Error::BandwidthRange => "bandwidth out of range",
+            Error::Order => "unsupported loop order",
+        }
+    }
+}
+
+impl From<Error> for &'static str {
+    fn from(e: Error) -> Self {
+        e.as_str()
+    }
+}
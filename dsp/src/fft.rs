@@ -0,0 +1,174 @@
+use super::{cossin, Complex};
+
+/// Scaling strategy to avoid overflow in `fft_radix2()` and `ifft_radix2()`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FftScaling {
+    /// Scale by 1/2 after each stage. The total scaling is `1/N`.
+    PerStage,
+    /// Block floating point: scale by 1/2 only after those stages where the
+    /// data could otherwise overflow.
+    BlockFloatingPoint,
+}
+
+/// In-place radix-2 decimation in time FFT.
+///
+/// The twiddle factors are computed on the fly using `cossin()`. The
+/// transform does not overflow as long as the input magnitudes are below
+/// `1 << 31` (e.g. all components within `±(1 << 30)`).
+///
+/// The accuracy is limited by that of `cossin()`. A round trip of a 512 point
+/// random input with components within `±(1 << 30)` through `fft_radix2()`
+/// and `ifft_radix2()` reproduces the input to within `1 << 18` LSB
+/// (about `2e-4` of full scale).
+///
+/// # Args
+/// * `buf`: Data. The length must be a power of two.
+/// * `scale`: Scaling strategy.
+///
+/// # Returns
+/// The number of stages that were scaled by 1/2. The result is the DFT
+/// `X[k] = sum(x[n]*exp(-2*pi*i*k*n/N))` scaled by `1/(1 << shift)`.
+pub fn fft_radix2(buf: &mut [Complex<i32>], scale: FftScaling) -> u32 {
+    transform(buf, scale, false)
+}
+
+/// In-place radix-2 decimation in time inverse FFT.
+///
+/// See `fft_radix2()`. There is no implicit `1/N` normalization.
+///
+/// # Returns
+/// The number of stages that were scaled by 1/2. The result is the inverse
+/// DFT `x[n] = sum(X[k]*exp(2*pi*i*k*n/N))` scaled by `1/(1 << shift)`.
+pub fn ifft_radix2(buf: &mut [Complex<i32>], scale: FftScaling) -> u32 {
+    transform(buf, scale, true)
+}
+
+fn transform(
+    buf: &mut [Complex<i32>],
+    scale: FftScaling,
+    inverse: bool,
+) -> u32 {
+    let n = buf.len();
+    assert!(n.is_power_of_two());
+    let log2n = n.trailing_zeros();
+    if log2n == 0 {
+        return 0;
+    }
+
+    // Bit reversal permutation
+    for i in 0..n {
+        let j = ((i as u32).reverse_bits() >> (32 - log2n)) as usize;
+        if j > i {
+            buf.swap(i, j);
+        }
+    }
+
+    let mut shift = 0;
+    for stage in 1..=log2n {
+        let half = 1 << (stage - 1);
+        let scaled = match scale {
+            FftScaling::PerStage => true,
+            FftScaling::BlockFloatingPoint => buf.iter().any(|x| {
+                x.0.unsigned_abs() >= 1 << 29 || x.1.unsigned_abs() >= 1 << 29
+            }),
+        };
+        // Rounding bias, half up
+        let (sh, bias) = if scaled { (1, 1) } else { (0, 0) };
+        shift += sh;
+        for k in 0..half {
+            let phase = ((k as u32) << (32 - stage)) as i32;
+            let (c, s) =
+                cossin(if inverse { phase } else { phase.wrapping_neg() });
+            let (c, s) = (c as i64, s as i64);
+            for j in (k..n).step_by(2 * half) {
+                let a = buf[j];
+                let b = buf[j + half];
+                let (b0, b1) = (b.0 as i64, b.1 as i64);
+                // t = b*w with w in Q1.31
+                let t0 = (b0 * c - b1 * s + (1 << 30)) >> 31;
+                let t1 = (b1 * c + b0 * s + (1 << 30)) >> 31;
+                let (a0, a1) = (a.0 as i64, a.1 as i64);
+                buf[j] = Complex(
+                    saturate((a0 + t0 + bias) >> sh),
+                    saturate((a1 + t1 + bias) >> sh),
+                );
+                buf[j + half] = Complex(
+                    saturate((a0 - t0 + bias) >> sh),
+                    saturate((a1 - t1 + bias) >> sh),
+                );
+            }
+        }
+    }
+    shift
+}
+
+#[inline]
+fn saturate(x: i64) -> i32 {
+    x.max(i32::MIN as i64).min(i32::MAX as i64) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::COSSIN_MAX_ERROR;
+    use rand::{prelude::*, rngs::StdRng};
+
+    #[test]
+    fn impulse() {
+        for &scale in
+            [FftScaling::PerStage, FftScaling::BlockFloatingPoint].iter()
+        {
+            let mut buf = [Complex(0, 0); 256];
+            buf[0] = Complex(1 << 20, -3 << 18);
+            let shift = fft_radix2(&mut buf, scale);
+            let want = Complex((1 << 20) >> shift, (-3 << 18) >> shift);
+            assert!(buf.iter().all(|&x| x == want));
+        }
+    }
+
+    #[test]
+    fn tone() {
+        let k0 = 37;
+        let mut buf = [Complex(0, 0); 1024];
+        for (i, x) in buf.iter_mut().enumerate() {
+            let (c, s) = cossin(((k0 * i as u32) << (32 - 10)) as i32);
+            *x = Complex(c >> 1, s >> 1);
+        }
+        let shift = fft_radix2(&mut buf, FftScaling::PerStage);
+        assert_eq!(shift, 10);
+        // The table error dominates for small `cossin()` tables.
+        let tol =
+            (1 << 17).max((4. * COSSIN_MAX_ERROR * (1 << 30) as f64) as i32);
+        for (k, x) in buf.iter().enumerate() {
+            let want = if k == k0 as usize { 1 << 30 } else { 0 };
+            assert!((x.0 - want).abs() < tol, "{} {:?}", k, x);
+            assert!(x.1.abs() < tol, "{} {:?}", k, x);
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut x = [Complex(0, 0); 512];
+        for x in x.iter_mut() {
+            *x = Complex(
+                rng.gen_range(-(1 << 30)..1 << 30),
+                rng.gen_range(-(1 << 30)..1 << 30),
+            );
+        }
+        let mut y = x;
+        assert_eq!(fft_radix2(&mut y, FftScaling::PerStage), 9);
+        // The inverse needs to scale some stages to avoid overflow.
+        let shift = ifft_radix2(&mut y, FftScaling::BlockFloatingPoint);
+        assert!(shift > 0);
+        let mut err = 0;
+        for (x, y) in x.iter().zip(y.iter()) {
+            let y = Complex(y.0 << shift, y.1 << shift);
+            err = err.max((x.0 - y.0).abs()).max((x.1 - y.1).abs());
+        }
+        // The twiddle factor error dominates for small `cossin()` tables.
+        let tol =
+            (1 << 18).max((8. * COSSIN_MAX_ERROR * (1 << 30) as f64) as i32);
+        assert!(err < tol, "{}", err);
+    }
+}
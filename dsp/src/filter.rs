@@ -0,0 +1,288 @@
+//! Composable single input, single output processing blocks.
+//!
+//! `Filter` (`i32`) and `FilterF32` (`f32`) give the blocks a common update
+//! signature. `Chain` threads a sample through a tuple of blocks with static
+//! dispatch: a chain inlines to the same code as the sequence of `update()`
+//! calls. Blocks that keep their configuration apart from their state (e.g.
+//! `iir::IIR` and `iir::Vec5`) are filters when bound to their state with
+//! `Stateful`.
+//!
+//! ```
+//! use dsp::{dcblock::DcBlock, requantize, slew::SlewLimiter, Chain, Filter};
+//! let mut chain = Chain((
+//!     DcBlock::new(8),
+//!     requantize::Requantizer::new(requantize::NoiseShaping::First),
+//!     SlewLimiter::new(1 << 8),
+//! ));
+//! assert_eq!(chain.update(0), 0);
+//! ```
+use super::Reset;
+
+/// A block processing `i32` samples.
+pub trait Filter {
+    /// The configuration of the block (gains, coefficients, limits), see
+    /// `Reset`.
+    type Config;
+
+    /// The current configuration.
+    fn config(&self) -> Self::Config;
+
+    /// Process a sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    ///
+    /// # Returns
+    /// Output sample.
+    fn update(&mut self, x: i32) -> i32;
+}
+
+/// A block processing `f32` samples.
+///
+/// See `Filter`.
+pub trait FilterF32 {
+    /// The configuration of the block.
+    type Config;
+
+    /// The current configuration.
+    fn config(&self) -> Self::Config;
+
+    /// Process a sample.
+    fn update(&mut self, x: f32) -> f32;
+}
+
+/// Pass samples through unchanged.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Identity;
+
+impl Filter for Identity {
+    type Config = ();
+    fn config(&self) {}
+    fn update(&mut self, x: i32) -> i32 {
+        x
+    }
+}
+
+impl FilterF32 for Identity {
+    type Config = ();
+    fn config(&self) {}
+    fn update(&mut self, x: f32) -> f32 {
+        x
+    }
+}
+
+impl Reset for Identity {
+    fn reset(&mut self) {}
+}
+
+impl<T: Filter + ?Sized> Filter for &mut T {
+    type Config = T::Config;
+    fn config(&self) -> T::Config {
+        (**self).config()
+    }
+    fn update(&mut self, x: i32) -> i32 {
+        (**self).update(x)
+    }
+}
+
+impl<T: FilterF32 + ?Sized> FilterF32 for &mut T {
+    type Config = T::Config;
+    fn config(&self) -> T::Config {
+        (**self).config()
+    }
+    fn update(&mut self, x: f32) -> f32 {
+        (**self).update(x)
+    }
+}
+
+/// A configuration bound to a separate state.
+///
+/// Both can be owned or borrowed. E.g. `Stateful { config: &iir, state:
+/// &mut xy }` is a `FilterF32` for an `iir::IIR` shared with other contexts
+/// and its state `iir::Vec5`.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Stateful<C, S> {
+    /// Configuration.
+    pub config: C,
+    /// State.
+    pub state: S,
+}
+
+impl<C, S: Reset> Reset for Stateful<C, S> {
+    fn reset(&mut self) {
+        self.state.reset();
+    }
+}
+
+macro_rules! impl_stateful {
+    ($Filter:ident, $T:ty, $C:ty, $S:ty) => {
+        impl $Filter for Stateful<$C, $S> {
+            type Config = $C;
+            fn config(&self) -> $C {
+                self.config
+            }
+            fn update(&mut self, x: $T) -> $T {
+                self.config.update(&mut self.state, x)
+            }
+        }
+
+        impl<'a> $Filter for Stateful<&'a $C, &'a mut $S> {
+            type Config = $C;
+            fn config(&self) -> $C {
+                *self.config
+            }
+            fn update(&mut self, x: $T) -> $T {
+                self.config.update(self.state, x)
+            }
+        }
+    };
+}
+
+impl_stateful!(FilterF32, f32, super::iir::IIR, super::iir::Vec5);
+impl_stateful!(Filter, i32, super::iir_int::IIR, super::iir_int::Vec5);
+
+/// A sequence of blocks.
+///
+/// Implemented for tuples of up to six blocks. The sample is processed by
+/// the first block, then the second, and so on. The configuration is the
+/// tuple of the block configurations.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Chain<T>(pub T);
+
+macro_rules! impl_chain {
+    ($($T:ident $i:tt),+) => {
+        impl<$($T: Filter),+> Filter for Chain<($($T,)+)> {
+            type Config = ($($T::Config,)+);
+            fn config(&self) -> Self::Config {
+                ($((self.0).$i.config(),)+)
+            }
+            #[inline]
+            fn update(&mut self, x: i32) -> i32 {
+                $(let x = (self.0).$i.update(x);)+
+                x
+            }
+        }
+
+        impl<$($T: FilterF32),+> FilterF32 for Chain<($($T,)+)> {
+            type Config = ($($T::Config,)+);
+            fn config(&self) -> Self::Config {
+                ($((self.0).$i.config(),)+)
+            }
+            #[inline]
+            fn update(&mut self, x: f32) -> f32 {
+                $(let x = (self.0).$i.update(x);)+
+                x
+            }
+        }
+
+        impl<$($T: Reset),+> Reset for Chain<($($T,)+)> {
+            fn reset(&mut self) {
+                $((self.0).$i.reset();)+
+            }
+        }
+    };
+}
+
+impl_chain!(A 0);
+impl_chain!(A 0, B 1);
+impl_chain!(A 0, B 1, C 2);
+impl_chain!(A 0, B 1, C 2, D 3);
+impl_chain!(A 0, B 1, C 2, D 3, E 4);
+impl_chain!(A 0, B 1, C 2, D 3, E 4, F 5);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        dcblock::DcBlock, iir, iir_int, noise::Xoshiro, requantize,
+        slew::SlewLimiter,
+    };
+    use core::mem::size_of;
+
+    #[test]
+    fn identity() {
+        let mut chain = Chain((Identity, Identity, Identity));
+        let mut rng = Xoshiro::new(1);
+        for x in [0, 1, -1, i32::MIN, i32::MAX].iter().copied() {
+            assert_eq!(Filter::update(&mut chain, x), x);
+        }
+        for _ in 0..1000 {
+            let x = rng.next_u32() as i32;
+            assert_eq!(Filter::update(&mut chain, x), x);
+            let x = x as f32;
+            assert_eq!(FilterF32::update(&mut chain, x), x);
+        }
+        assert_eq!(size_of::<Chain<(Identity, Identity, Identity)>>(), 0);
+    }
+
+    #[test]
+    fn sequence() {
+        // A chain is the sequence of its stages
+        let order = requantize::NoiseShaping::Second;
+        let (dc, q, slew) = (
+            DcBlock::new(6),
+            requantize::Requantizer::new(order),
+            SlewLimiter::new(1 << 6),
+        );
+        let mut stages = (dc, q, slew);
+        let mut chain = Chain((dc, q, slew));
+        // No overhead beyond the stages
+        assert_eq!(
+            size_of::<Chain<(DcBlock, requantize::Requantizer, SlewLimiter)>>(),
+            size_of::<(DcBlock, requantize::Requantizer, SlewLimiter)>()
+        );
+        assert_eq!(chain.config(), (6, order, 1 << 6));
+        // Saturating at the extremes, no overflow
+        let mut rng = Xoshiro::new(2);
+        for i in 0..1000 {
+            let x = match i % 4 {
+                0 => i32::MIN,
+                1 => i32::MAX,
+                _ => rng.next_u32() as i32,
+            };
+            let y = stages.2.update(stages.1.update(stages.0.update(x)) as i32);
+            assert_eq!(chain.update(x), y);
+        }
+        chain.reset();
+        stages.0.reset();
+        stages.1.reset();
+        stages.2.reset();
+        assert_eq!(chain.update(1 << 20), stages.0.update(1 << 20) >> 16);
+    }
+
+    #[test]
+    fn borrowed() {
+        let mut biquad = iir::IIR::new(1., -1e9, 1e9);
+        biquad.ba = iir::Vec5([0.05, 0.1, 0.05, 1.2, -0.4]);
+        let mut xy = iir::Vec5::default();
+        let mut xy_ref = iir::Vec5::default();
+        let mut slew = SlewLimiter::new(u32::MAX);
+        {
+            let mut chain = Chain((
+                Stateful {
+                    config: &biquad,
+                    state: &mut xy,
+                },
+                Identity,
+            ));
+            for _ in 0..10 {
+                assert_eq!(chain.update(1.), biquad.update(&mut xy_ref, 1.));
+            }
+        }
+        assert_eq!(xy.0, xy_ref.0);
+
+        let mut biquad = iir_int::IIR::default();
+        biquad.ba = iir_int::Vec5::lowpass(1e-2, 0.7, 1.);
+        let mut stage = Stateful {
+            config: biquad,
+            state: iir_int::Vec5::default(),
+        };
+        let mut xy = iir_int::Vec5::default();
+        let mut chain = Chain((&mut stage, &mut slew));
+        for _ in 0..10 {
+            let y = biquad.update(&mut xy, 1 << 20);
+            assert_eq!(chain.update(1 << 20), y);
+        }
+        assert_eq!(stage.state.0, xy.0);
+    }
+}
@@ -0,0 +1,179 @@
+use super::{macc, FilterF32, Reset};
+
+/// Finite impulse response (FIR) filter.
+///
+/// Direct form with `N` taps. The delay line holds the `N` most recent
+/// inputs. Lower indices correspond to more recent samples.
+#[derive(Copy, Clone, Debug)]
+pub struct Fir<const N: usize> {
+    /// Filter taps, i.e. the impulse response.
+    pub taps: [f32; N],
+    // delay line
+    x: [f32; N],
+}
+
+impl<const N: usize> Fir<N> {
+    /// Create a new FIR filter with cleared state.
+    ///
+    /// # Arguments
+    /// * `taps` - Filter taps.
+    pub fn new(taps: [f32; N]) -> Self {
+        Self { taps, x: [0.; N] }
+    }
+
+    /// Feed a new input value into the filter, update the delay line, and
+    /// return the new output.
+    ///
+    /// # Arguments
+    /// * `x0` - New input.
+    pub fn update(&mut self, x0: f32) -> f32 {
+        // Shift the delay line and store x0
+        self.x.copy_within(0..N - 1, 1);
+        self.x[0] = x0;
+        macc(0., &self.x, &self.taps)
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.x = [0.; N];
+    }
+}
+
+/// Linear phase FIR filter with symmetric taps.
+///
+/// Only the first `M = (N + 1) / 2` taps are stored (including the center tap
+/// for odd `N`). The remaining taps are implied by symmetry
+/// (`h[N - 1 - k] == h[k]`). The delay line is folded before the
+/// multiply-accumulate which halves the number of multiplications.
+#[derive(Copy, Clone, Debug)]
+pub struct SymmetricFir<const N: usize, const M: usize> {
+    /// First half of the filter taps.
+    pub taps: [f32; M],
+    // delay line
+    x: [f32; N],
+}
+
+impl<const N: usize, const M: usize> SymmetricFir<N, M> {
+    // Fails to evaluate unless `N > 0` and `M == (N + 1) / 2`.
+    const TAPS_OK: () = [()][(N == 0 || M != N - N / 2) as usize];
+
+    /// Create a new symmetric FIR filter with cleared state.
+    ///
+    /// # Arguments
+    /// * `taps` - First half of the filter taps. `N` must be positive and `M`
+    ///   must be `(N + 1) / 2`, this is checked at compile time.
+    ///
+    /// ```compile_fail
+    /// dsp::fir::SymmetricFir::<4, 3>::new([0.; 3]);
+    /// ```
+    #[allow(clippy::let_unit_value)]
+    pub fn new(taps: [f32; M]) -> Self {
+        let _ = Self::TAPS_OK;
+        Self { taps, x: [0.; N] }
+    }
+
+    /// Feed a new input value into the filter, update the delay line, and
+    /// return the new output.
+    ///
+    /// # Arguments
+    /// * `x0` - New input.
+    pub fn update(&mut self, x0: f32) -> f32 {
+        self.x.copy_within(0..N - 1, 1);
+        self.x[0] = x0;
+        // Fold the delay line onto the first half
+        let mut xf = [0.; M];
+        for (k, xf) in xf.iter_mut().enumerate().take(N / 2) {
+            *xf = self.x[k] + self.x[N - 1 - k];
+        }
+        if N & 1 != 0 {
+            // Center tap
+            xf[M - 1] = self.x[N / 2];
+        }
+        macc(0., &xf, &self.taps)
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.x = [0.; N];
+    }
+}
+
+impl<const N: usize> Reset for Fir<N> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize, const M: usize> Reset for SymmetricFir<N, M> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize> FilterF32 for Fir<N> {
+    type Config = [f32; N];
+    fn config(&self) -> [f32; N] {
+        self.taps
+    }
+    fn update(&mut self, x: f32) -> f32 {
+        Self::update(self, x)
+    }
+}
+
+impl<const N: usize, const M: usize> FilterF32 for SymmetricFir<N, M> {
+    type Config = [f32; M];
+    fn config(&self) -> [f32; M] {
+        self.taps
+    }
+    fn update(&mut self, x: f32) -> f32 {
+        Self::update(self, x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn impulse_response() {
+        let taps = [0.5, -0.25, 1., 0.125, 3.];
+        let mut fir = Fir::new(taps);
+        for (i, h) in taps.iter().enumerate() {
+            assert_eq!(fir.update(if i == 0 { 1. } else { 0. }), *h);
+        }
+        assert_eq!(fir.update(0.), 0.);
+    }
+
+    #[test]
+    fn reset() {
+        let mut fir = Fir::new([1.; 3]);
+        fir.update(1.);
+        fir.update(2.);
+        fir.reset();
+        assert_eq!(fir.update(0.), 0.);
+    }
+
+    fn check_symmetric<const N: usize, const M: usize>(half: [f32; M]) {
+        let mut taps = [0.; N];
+        for k in 0..N {
+            taps[k] = half[k.min(N - 1 - k)];
+        }
+        let mut naive = Fir::new(taps);
+        let mut folded = SymmetricFir::<N, M>::new(half);
+        // Dyadic rationals keep f32 arithmetic exact.
+        for i in 0..4 * N {
+            let x = ((i * 7 % 11) as f32 - 5.) / 8.;
+            assert_eq!(naive.update(x), folded.update(x));
+        }
+    }
+
+    #[test]
+    fn symmetric_odd() {
+        check_symmetric::<7, 4>([0.25, -0.5, 0.125, 1.]);
+    }
+
+    #[test]
+    fn symmetric_even() {
+        check_symmetric::<6, 3>([0.25, -0.5, 1.5]);
+    }
+}
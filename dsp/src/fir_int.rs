@@ -0,0 +1,265 @@
+use super::{
+    macc_i16, macc_i32, saturating_scale_i64, Filter, Reset, Rounding,
+};
+
+/// Coefficient fixed point format: signed Q2.30.
+pub const SHIFT: u32 = 30;
+
+/// Integer FIR filter.
+///
+/// See `dsp::fir::Fir` for general implementation details.
+/// The taps are signed Q2.30 (see `SHIFT`) and the accumulator is 64 bit.
+#[derive(Copy, Clone, Debug)]
+pub struct Fir<const N: usize> {
+    /// Filter taps, i.e. the impulse response.
+    pub taps: [i32; N],
+    // delay line
+    x: [i32; N],
+}
+
+impl<const N: usize> Fir<N> {
+    /// Create a new FIR filter with cleared state.
+    ///
+    /// # Arguments
+    /// * `taps` - Filter taps in Q2.30.
+    pub fn new(taps: [i32; N]) -> Self {
+        Self { taps, x: [0; N] }
+    }
+
+    /// Feed a new input value into the filter, update the delay line, and
+    /// return the new output.
+    ///
+    /// # Arguments
+    /// * `x0` - New input.
+    pub fn update(&mut self, x0: i32) -> i32 {
+        // Shift the delay line and store x0
+        self.x.copy_within(0..N - 1, 1);
+        self.x[0] = x0;
+        macc_i32(0, &self.x, &self.taps, SHIFT)
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.x = [0; N];
+    }
+}
+
+/// Integer FIR filter for 16 bit samples.
+///
+/// The samples and taps are `i16`, the taps are signed Q1.15. The
+/// accumulator is 64 bit and the dot product uses the dual 16 bit
+/// multiply-accumulate of `macc_i16()`.
+#[derive(Copy, Clone, Debug)]
+pub struct Fir16<const N: usize> {
+    /// Filter taps in Q1.15, i.e. the impulse response.
+    pub taps: [i16; N],
+    // delay line
+    x: [i16; N],
+}
+
+impl<const N: usize> Fir16<N> {
+    /// Create a new FIR filter with cleared state.
+    ///
+    /// # Arguments
+    /// * `taps` - Filter taps in Q1.15.
+    pub fn new(taps: [i16; N]) -> Self {
+        Self { taps, x: [0; N] }
+    }
+
+    /// Feed a new input value into the filter, update the delay line, and
+    /// return the new output.
+    ///
+    /// Rounding is half up and the output saturates.
+    ///
+    /// # Arguments
+    /// * `x0` - New input.
+    pub fn update(&mut self, x0: i16) -> i16 {
+        self.x.copy_within(0..N - 1, 1);
+        self.x[0] = x0;
+        let y = macc_i16(0, &self.x, &self.taps);
+        let y = saturating_scale_i64(y, 15, Rounding::HalfUp);
+        y.max(i16::MIN as i32).min(i16::MAX as i32) as i16
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.x = [0; N];
+    }
+}
+
+/// Integer linear phase FIR filter with symmetric taps.
+///
+/// See `dsp::fir::SymmetricFir` for details. The folded delay line is summed
+/// in 64 bit before the multiplication so the result is identical to
+/// that of `Fir` with the full set of taps.
+#[derive(Copy, Clone, Debug)]
+pub struct SymmetricFir<const N: usize, const M: usize> {
+    /// First half of the filter taps in Q2.30.
+    pub taps: [i32; M],
+    // delay line
+    x: [i32; N],
+}
+
+impl<const N: usize, const M: usize> SymmetricFir<N, M> {
+    // Fails to evaluate unless `N > 0` and `M == (N + 1) / 2`.
+    const TAPS_OK: () = [()][(N == 0 || M != N - N / 2) as usize];
+
+    /// Create a new symmetric FIR filter with cleared state.
+    ///
+    /// # Arguments
+    /// * `taps` - First half of the filter taps. `N` must be positive and `M`
+    ///   must be `(N + 1) / 2`, this is checked at compile time.
+    #[allow(clippy::let_unit_value)]
+    pub fn new(taps: [i32; M]) -> Self {
+        let _ = Self::TAPS_OK;
+        Self { taps, x: [0; N] }
+    }
+
+    /// Feed a new input value into the filter, update the delay line, and
+    /// return the new output.
+    ///
+    /// # Arguments
+    /// * `x0` - New input.
+    pub fn update(&mut self, x0: i32) -> i32 {
+        self.x.copy_within(0..N - 1, 1);
+        self.x[0] = x0;
+        // Rounding bias, half up
+        let mut y = 1i64 << (SHIFT - 1);
+        // Fold the delay line onto the first half
+        for k in 0..N / 2 {
+            y += (self.x[k] as i64 + self.x[N - 1 - k] as i64)
+                * self.taps[k] as i64;
+        }
+        if N & 1 != 0 {
+            // Center tap
+            y += self.x[N / 2] as i64 * self.taps[M - 1] as i64;
+        }
+        (y >> SHIFT) as i32
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.x = [0; N];
+    }
+}
+
+impl<const N: usize> Reset for Fir<N> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize> Reset for Fir16<N> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize, const M: usize> Reset for SymmetricFir<N, M> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+impl<const N: usize> Filter for Fir<N> {
+    type Config = [i32; N];
+    fn config(&self) -> [i32; N] {
+        self.taps
+    }
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x)
+    }
+}
+
+impl<const N: usize, const M: usize> Filter for SymmetricFir<N, M> {
+    type Config = [i32; M];
+    fn config(&self) -> [i32; M] {
+        self.taps
+    }
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fir16() {
+        let taps = [1 << 14, -(1 << 13), i16::MAX, 1234, i16::MIN];
+        let mut fir = Fir16::new(taps);
+        // Impulse response
+        for (i, h) in taps.iter().enumerate() {
+            assert_eq!(fir.update(if i == 0 { i16::MAX } else { 0 }), {
+                ((*h as i32 * i16::MAX as i32 + (1 << 14)) >> 15) as i16
+            });
+        }
+        assert_eq!(fir.update(0), 0);
+        // Saturation
+        let mut fir = Fir16::new([i16::MAX; 3]);
+        for _ in 0..3 {
+            fir.update(i16::MAX);
+        }
+        assert_eq!(fir.update(i16::MAX), i16::MAX);
+        fir.reset();
+        for _ in 0..3 {
+            fir.update(i16::MIN);
+        }
+        assert_eq!(fir.update(i16::MIN), i16::MIN);
+    }
+
+    #[test]
+    fn impulse_response() {
+        let taps = [1 << 29, -(1 << 28), 1 << 30, 12345, i32::MIN];
+        let mut fir = Fir::new(taps);
+        for (i, h) in taps.iter().enumerate() {
+            assert_eq!(fir.update(if i == 0 { 1 << SHIFT } else { 0 }), *h);
+        }
+        assert_eq!(fir.update(0), 0);
+    }
+
+    #[test]
+    fn reset() {
+        let mut fir = SymmetricFir::<3, 2>::new([1 << 30; 2]);
+        fir.update(1 << 20);
+        fir.update(-7);
+        fir.reset();
+        assert_eq!(fir.update(0), 0);
+    }
+
+    fn check_symmetric<const N: usize, const M: usize>(half: [i32; M]) {
+        let mut taps = [0; N];
+        for k in 0..N {
+            taps[k] = half[k.min(N - 1 - k)];
+        }
+        let mut naive = Fir::new(taps);
+        let mut folded = SymmetricFir::<N, M>::new(half);
+        let mut x = 0x1234_5678i32;
+        for _ in 0..8 * N {
+            // Pseudo-random full range input
+            x = x.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            assert_eq!(naive.update(x), folded.update(x));
+        }
+    }
+
+    #[test]
+    fn symmetric_odd() {
+        check_symmetric::<7, 4>([
+            0x0123_4567,
+            -0x0765_4321,
+            0x1357_9bdf,
+            0x3fff_ffff,
+        ]);
+    }
+
+    #[test]
+    fn symmetric_even() {
+        check_symmetric::<8, 4>([
+            -0x0012_3456,
+            0x0234_5678,
+            -0x1111_1111,
+            0x2aaa_aaaa,
+        ]);
+    }
+}
@@ -0,0 +1,247 @@
+//! Reciprocal frequency counter.
+//!
+//! The counter ingests the timestamps of input edges from a free running,
+//! wrapping timer. Within a gate of `gate` updates it measures the time
+//! between the first and the last timestamped edge and the number of edge
+//! intervals in between. The frequency is the ratio, scaled by the timer
+//! clock. The resolution is one timer tick over the measured time,
+//! independent of the input frequency.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Result of a frequency counter gate.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Measurement {
+    /// Frequency in Hz with 32 fractional bits (`1 << 32` is 1 Hz).
+    /// Zero if not `valid`.
+    pub frequency: u64,
+    /// Number of edges in the gate.
+    pub edges: u32,
+    /// At least two edges were timestamped in the gate.
+    pub valid: bool,
+}
+
+impl Measurement {
+    /// Frequency in Hz.
+    pub fn to_hz(&self) -> f32 {
+        self.frequency as f32 * (1. / (1u64 << 32) as f32)
+    }
+}
+
+/// Reciprocal frequency counter.
+///
+/// The gates are independent: there is no timing information shared
+/// between gates. The gate (`gate` times the update interval) must be
+/// shorter than the timer period (`1 << 32` ticks).
+#[derive(Copy, Clone, Debug)]
+pub struct Counter {
+    clock: u32,
+    gate: u32,
+    updates: u32,
+    edges: u32,
+    intervals: u32,
+    first: Option<u32>,
+    last: u32,
+    output: Measurement,
+}
+
+impl Counter {
+    /// Create a new frequency counter.
+    ///
+    /// # Args
+    /// * `clock`: Timer clock frequency in Hz.
+    /// * `gate`: Number of updates per gate, at least one.
+    pub const fn new(clock: u32, gate: u32) -> Self {
+        Self {
+            clock,
+            gate,
+            updates: 0,
+            edges: 0,
+            intervals: 0,
+            first: None,
+            last: 0,
+            output: Measurement {
+                frequency: 0,
+                edges: 0,
+                valid: false,
+            },
+        }
+    }
+
+    /// Number of updates per gate.
+    pub fn gate(&self) -> u32 {
+        self.gate
+    }
+
+    /// Change the number of updates per gate and restart the current gate.
+    pub fn set_gate(&mut self, gate: u32) {
+        debug_assert!(gate > 0);
+        self.gate = gate;
+        self.restart();
+    }
+
+    /// The result of the last complete gate.
+    pub fn output(&self) -> Measurement {
+        self.output
+    }
+
+    fn restart(&mut self) {
+        self.updates = 0;
+        self.edges = 0;
+        self.intervals = 0;
+        self.first = None;
+    }
+
+    /// Ingest the edges since the last update.
+    ///
+    /// # Args
+    /// * `timestamp`: Timer value (wrapping) of the last edge. Ignored if
+    ///   there were no edges.
+    /// * `edges`: Number of edges since the last update. Only the last of
+    ///   them is timestamped.
+    ///
+    /// # Returns
+    /// The measurement at the end of each gate.
+    pub fn update(
+        &mut self,
+        timestamp: u32,
+        edges: u32,
+    ) -> Option<Measurement> {
+        if edges > 0 {
+            self.edges = self.edges.saturating_add(edges);
+            if self.first.is_none() {
+                self.first = Some(timestamp);
+            } else {
+                self.intervals = self.intervals.saturating_add(edges);
+            }
+            self.last = timestamp;
+        }
+        self.updates += 1;
+        if self.updates < self.gate {
+            return None;
+        }
+        let mut output = Measurement {
+            edges: self.edges,
+            ..Default::default()
+        };
+        if let Some(first) = self.first {
+            let ticks = self.last.wrapping_sub(first);
+            if self.intervals > 0 && ticks > 0 {
+                let f = ((self.intervals as u128 * self.clock as u128) << 32)
+                    / ticks as u128;
+                output.frequency = if f > u64::MAX as u128 {
+                    u64::MAX
+                } else {
+                    f as u64
+                };
+                output.valid = true;
+            }
+        }
+        self.restart();
+        self.output = output;
+        Some(output)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Edges of a `clock/period` Hz input, one update per `step` ticks,
+    // starting at `t0`. Returns the last measurement.
+    fn run(
+        counter: &mut Counter,
+        t0: u32,
+        period: u64,
+        step: u64,
+        updates: u64,
+    ) -> Option<Measurement> {
+        let mut last = None;
+        let mut edge = 0u64;
+        for i in 1..=updates {
+            let mut edges = 0;
+            while (edge + 1) * period <= i * step {
+                edge += 1;
+                edges += 1;
+            }
+            let timestamp = t0.wrapping_add((edge * period) as u32);
+            if let Some(m) = counter.update(timestamp, edges) {
+                last = Some(m);
+            }
+        }
+        last
+    }
+
+    #[test]
+    fn frequency() {
+        let clock = 100_000_000;
+        let mut counter = Counter::new(clock, 100);
+        // 100 kHz, 1000 ticks per update
+        let m = run(&mut counter, 0, 1000, 1000, 1000).unwrap();
+        assert!(m.valid);
+        assert_eq!(m.edges, 100);
+        assert_eq!(m.frequency, 100_000 << 32);
+        assert_eq!(m.to_hz(), 100_000.);
+        assert_eq!(counter.output(), m);
+        // Resolution better than one tick over the gate
+        let mut counter = Counter::new(clock, 100);
+        let m = run(&mut counter, 12345, 977, 1024, 100).unwrap();
+        let want = clock as f64 / 977.;
+        assert!((m.to_hz() as f64 - want).abs() < want / (977. * 99.));
+    }
+
+    #[test]
+    fn wrap() {
+        let mut counter = Counter::new(1_000_000, 10);
+        // The gate spans the 32 bit timer boundary
+        let m = run(&mut counter, u32::MAX - 4000, 1000, 1000, 10).unwrap();
+        assert!(m.valid);
+        assert_eq!(m.frequency, 1000 << 32);
+        let m = run(&mut counter, u32::MAX - 10, 7, 100, 10).unwrap();
+        assert_eq!(m.frequency, ((1_000_000u64 << 32) / 7));
+    }
+
+    #[test]
+    fn few_edges() {
+        let mut counter = Counter::new(1_000_000, 4);
+        // No edges
+        for _ in 0..3 {
+            assert_eq!(counter.update(5, 0), None);
+        }
+        let m = counter.update(5, 0).unwrap();
+        assert_eq!((m.valid, m.edges, m.frequency), (false, 0, 0));
+        // One edge
+        assert_eq!(counter.update(10, 1), None);
+        for _ in 0..2 {
+            assert_eq!(counter.update(20, 0), None);
+        }
+        let m = counter.update(30, 0).unwrap();
+        assert_eq!((m.valid, m.edges, m.frequency), (false, 1, 0));
+        // Several edges but only one timestamp
+        for _ in 0..3 {
+            counter.update(0, 0);
+        }
+        let m = counter.update(50, 3).unwrap();
+        assert_eq!((m.valid, m.edges), (false, 3));
+        // Two edges
+        counter.update(100, 1);
+        counter.update(0, 0);
+        counter.update(0, 0);
+        let m = counter.update(600, 1).unwrap();
+        assert_eq!((m.valid, m.edges), (true, 2));
+        assert_eq!(m.frequency, 2000 << 32);
+    }
+
+    #[test]
+    fn set_gate() {
+        let mut counter = Counter::new(1000, 4);
+        counter.update(0, 1);
+        counter.set_gate(2);
+        assert_eq!(counter.gate(), 2);
+        // The partial gate is discarded
+        assert_eq!(counter.update(10, 1), None);
+        let m = counter.update(20, 1).unwrap();
+        assert_eq!((m.edges, m.frequency), (2, 100 << 32));
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Complex, Reset};
+use core::f64::consts::PI;
+
+/// Goertzel single bin discrete Fourier transform.
+///
+/// Computes the DFT `X = sum(x[n]*exp(-2*pi*i*f*n))` of a block of samples
+/// `x[n]` at a single frequency `f` using one real multiply-accumulate per
+/// sample.
+///
+/// The block length is determined by the number of `update()` calls before
+/// `finish()` and does not need to be a power of two. `finish()`
+/// returns the result and restarts the block.
+///
+/// The frequency does not need to be an integer multiple of the inverse
+/// block length. But for a tone at a frequency `f0` different from `f`, the
+/// magnitude response is the Dirichlet kernel
+/// `|sin(pi*N*(f - f0))/(N*sin(pi*(f - f0)))|` ("scalloping"). With `f`
+/// on a DFT bin (`k/N` for integer `k`) and a tone half way between two bins,
+/// the loss is about 3.9 dB. Tones on other DFT bins do not leak into the
+/// result, tones between bins do.
+///
+/// The state is single precision. The usual numerical limitations of the
+/// Goertzel algorithm for long blocks at frequencies close to DC or Nyquist
+/// apply.
+#[derive(Copy, Clone, Debug)]
+pub struct Goertzel {
+    // frequency (in units of the sample rate)
+    f: f32,
+    // 2*cos(2*pi*f)
+    coeff: f32,
+    // filter state
+    s: [f32; 2],
+    // samples in the current block
+    n: u32,
+}
+
+impl Goertzel {
+    /// Create a new Goertzel DFT.
+    ///
+    /// # Args
+    /// * `k_over_n`: Frequency in units of the sample rate, i.e. `k/N` for
+    ///   bin `k` of an `N` point DFT.
+    pub fn new(k_over_n: f32) -> Self {
+        Self {
+            f: k_over_n,
+            coeff: (2. * libm::cos(2. * PI * k_over_n as f64)) as f32,
+            s: [0.; 2],
+            n: 0,
+        }
+    }
+
+    /// Feed a new sample into the filter.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    pub fn update(&mut self, x: f32) {
+        let s0 = x + self.coeff * self.s[0] - self.s[1];
+        self.s = [s0, self.s[0]];
+        self.n += 1;
+    }
+
+    /// Number of samples in the current block.
+    pub fn len(&self) -> u32 {
+        self.n
+    }
+
+    /// Whether the current block is empty.
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    /// Compute the DFT of the current block and restart.
+    ///
+    /// # Returns
+    /// The DFT value at the configured frequency, with the phase referenced to
+    /// the first sample of the block.
+    pub fn finish(&mut self) -> Complex<f32> {
+        let w = 2. * PI * self.f as f64;
+        // y = s[0] - exp(-i*w)*s[1]
+        let (s0, s1) = (self.s[0] as f64, self.s[1] as f64);
+        let y = Complex(s0 - libm::cos(w) * s1, libm::sin(w) * s1);
+        // Reference the phase to the first sample: multiply by
+        // exp(-i*w*(N - 1)). Reduce modulo one turn in f64.
+        let turns = self.f as f64 * self.n.saturating_sub(1) as f64;
+        let p = 2. * PI * (turns - libm::floor(turns));
+        let (c, s) = (libm::cos(p), libm::sin(p));
+        self.s = [0.; 2];
+        self.n = 0;
+        Complex((y.0 * c + y.1 * s) as f32, (y.1 * c - y.0 * s) as f32)
+    }
+}
+
+impl Reset for Goertzel {
+    fn reset(&mut self) {
+        self.s = [0.; 2];
+        self.n = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Compare with a reference DFT of a synthesized tone.
+    fn check(f: f32, f0: f64, n: usize) {
+        let mut g = Goertzel::new(f);
+        let x: Vec<f64> = (0..n)
+            .map(|i| 0.7 * libm::cos(2. * PI * f0 * i as f64 + 0.3))
+            .collect();
+        let mut want = Complex(0f64, 0f64);
+        for (i, &x) in x.iter().enumerate() {
+            let p = 2. * PI * f as f64 * i as f64;
+            want.0 += x * libm::cos(p);
+            want.1 -= x * libm::sin(p);
+            g.update(x as f32);
+        }
+        assert_eq!(g.len(), n as u32);
+        // Do it twice to check the restart.
+        for _ in 0..2 {
+            let have = g.finish();
+            assert!(g.is_empty());
+            let tol = 1e-4 * n as f64;
+            assert!(
+                (have.0 as f64 - want.0).abs() < tol
+                    && (have.1 as f64 - want.1).abs() < tol,
+                "{:?} {:?}",
+                have,
+                want
+            );
+            for &x in x.iter() {
+                g.update(x as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn on_bin() {
+        // Not a power of two
+        check(7. / 100., 7. / 100., 100);
+        check(0.25, 0.25, 1000);
+    }
+
+    #[test]
+    fn off_bin() {
+        // Tone between bins: scalloping
+        check(7. / 100., 7.5 / 100., 100);
+        // Other tone on a different bin: no leakage
+        check(7. / 100., 9. / 100., 100);
+        // Non-integer bin
+        check(0.073, 0.073, 100);
+        check(0.1234, 0.1, 333);
+    }
+
+    #[test]
+    fn scalloping() {
+        let n = 1024;
+        let mut g = Goertzel::new(100. / n as f32);
+        for i in 0..n {
+            g.update(libm::cos(2. * PI * 100.5 / n as f64 * i as f64) as f32);
+        }
+        let y = g.finish();
+        let db = 20. * libm::log10((y.0.hypot(y.1) / (n as f32 / 2.)) as f64);
+        assert!((db + 3.9).abs() < 0.1, "{}", db);
+    }
+}
@@ -0,0 +1,211 @@
+use super::Reset;
+
+/// Half-band lowpass decimate-by-2 filter.
+///
+/// The filter has `4*TAPS - 1` taps. All even offsets from the center tap are
+/// zero and the center tap is exactly 0.5. Only the `TAPS` unique non-zero
+/// odd-offset coefficients are stored and each one is applied to the sum of
+/// the two symmetric samples. The center tap is a shift.
+///
+/// Coefficients are Q1.31 (`1 << 31 == 1`), ordered from the center outwards.
+/// For unity DC gain they sum to `1 << 29`.
+///
+/// The input is consumed in pairs, the output is at half the input rate.
+/// The group delay is `2*TAPS - 1` input samples.
+#[derive(Copy, Clone)]
+pub struct HalfBand<const TAPS: usize> {
+    taps: [i32; TAPS],
+    // Delay line of the phase with the symmetric taps, newest first.
+    // Split into the newer and the older half.
+    even: [[i32; TAPS]; 2],
+    // Delay line of the phase with the center tap, newest first
+    odd: [i32; TAPS],
+    // Number of input pairs consumed while filling the delay line
+    fill: usize,
+}
+
+/// 19 taps: passband to 0.1, stopband from 0.4 of the input sample rate.
+///
+/// Kaiser window, beta = 8.5. Stopband attenuation is more than 84 dB,
+/// passband ripple is less than 0.001 dB.
+pub const HALF_BAND_19: [i32; 5] =
+    [650605840, -144468450, 35917099, -5294754, 111177];
+
+/// 55 taps: passband to 0.2, stopband from 0.3 of the input sample rate.
+///
+/// Kaiser window, beta = 8.5. Stopband attenuation is more than 84 dB,
+/// passband ripple is less than 0.001 dB.
+pub const HALF_BAND_55: [i32; 14] = [
+    679827037, -216867938, 119090824, -74349155, 48156150, -31156180, 19699480,
+    -11972366, 6882031, -3669695, 1764918, -729157, 232022, -37059,
+];
+
+impl<const TAPS: usize> HalfBand<TAPS> {
+    /// Create a new filter.
+    ///
+    /// # Args
+    /// * `taps`: Unique non-zero coefficients, Q1.31, from the center outwards,
+    ///   see e.g. `HALF_BAND_19` and `HALF_BAND_55`.
+    pub fn new(taps: [i32; TAPS]) -> Self {
+        Self {
+            taps,
+            even: [[0; TAPS]; 2],
+            odd: [0; TAPS],
+            fill: 0,
+        }
+    }
+
+    /// Clear the delay line.
+    pub fn reset(&mut self) {
+        self.even = [[0; TAPS]; 2];
+        self.odd = [0; TAPS];
+        self.fill = 0;
+    }
+
+    /// Consume two input samples and compute one output sample.
+    ///
+    /// # Args
+    /// * `x0`: Older input sample.
+    /// * `x1`: Newer input sample.
+    ///
+    /// # Returns
+    /// The filtered and decimated output, `None` until the delay line has
+    /// been filled (`2*TAPS` input pairs). The output saturates.
+    pub fn update2(&mut self, x0: i32, x1: i32) -> Option<i32> {
+        let [new, old] = &mut self.even;
+        old.copy_within(..TAPS - 1, 1);
+        old[0] = new[TAPS - 1];
+        new.copy_within(..TAPS - 1, 1);
+        new[0] = x1;
+        self.odd.copy_within(..TAPS - 1, 1);
+        self.odd[0] = x0;
+
+        // Center tap 0.5 and rounding bias, half up
+        let y0 = ((self.odd[TAPS - 1] as i64) << 30) + (1 << 30);
+        let y = self
+            .taps
+            .iter()
+            .zip(new.iter().rev())
+            .zip(old.iter())
+            .map(|((a, x), y)| *a as i64 * (*x as i64 + *y as i64))
+            .fold(y0, |y, xa| y + xa);
+        let y = (y >> 31).max(i32::MIN as i64).min(i32::MAX as i64) as i32;
+
+        if self.fill < 2 * TAPS {
+            self.fill += 1;
+        }
+        if self.fill == 2 * TAPS {
+            Some(y)
+        } else {
+            None
+        }
+    }
+
+    /// Amplitude response at a frequency given in units of the input sample
+    /// rate.
+    pub fn response(&self, f: f64) -> f64 {
+        let w = 2. * core::f64::consts::PI * f;
+        self.taps.iter().enumerate().fold(0.5, |h, (j, a)| {
+            h + 2. * *a as f64 / (1u64 << 31) as f64
+                * libm::cos(w * (2 * j + 1) as f64)
+        })
+    }
+}
+
+impl<const TAPS: usize> Reset for HalfBand<TAPS> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::f64::consts::PI;
+
+    // Output amplitude for a unit tone of frequency `f` (in units of the input
+    // sample rate).
+    fn gain<const TAPS: usize>(taps: [i32; TAPS], f: f64) -> f64 {
+        let mut hb = HalfBand::new(taps);
+        let a = (1 << 30) as f64;
+        let m = 1 << 12;
+        let x = |i: usize| (a * (2. * PI * f * i as f64).sin()).round() as i32;
+        let mut n = 0;
+        let mut iq = (0f64, 0f64);
+        for i in 0.. {
+            if let Some(y) = hb.update2(x(2 * i), x(2 * i + 1)) {
+                let p = 2. * PI * 2. * f * n as f64;
+                iq.0 += y as f64 * p.cos();
+                iq.1 += y as f64 * p.sin();
+                n += 1;
+                if n == m {
+                    break;
+                }
+            }
+        }
+        2. * iq.0.hypot(iq.1) / m as f64 / a
+    }
+
+    #[test]
+    fn dc() {
+        let mut hb = HalfBand::new(HALF_BAND_19);
+        let mut y = None;
+        for _ in 0..20 {
+            y = hb.update2(1 << 30, 1 << 30).or(y);
+        }
+        assert_eq!(y, Some(1 << 30));
+        assert!((hb.response(0.) - 1.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn fill() {
+        let mut hb = HalfBand::new(HALF_BAND_19);
+        for _ in 0..9 {
+            assert_eq!(hb.update2(1, 1), None);
+        }
+        assert!(hb.update2(1, 1).is_some());
+        hb.reset();
+        assert_eq!(hb.update2(1, 1), None);
+    }
+
+    #[test]
+    fn full_scale() {
+        let mut hb = HalfBand::new(HALF_BAND_55);
+        for i in 0..100 {
+            // Worst case sign pattern
+            let x = |j: usize| {
+                if (i + j) & 2 == 0 {
+                    i32::MAX
+                } else {
+                    i32::MIN
+                }
+            };
+            hb.update2(x(0), x(1));
+        }
+    }
+
+    #[test]
+    fn alias_rejection() {
+        // Tones near Nyquist that alias into the passband.
+        for &f in [0.45, 0.48].iter() {
+            let g = gain(HALF_BAND_19, f);
+            assert!(g < 10f64.powf(-80. / 20.), "{}: {}", f, g);
+        }
+        for &f in [0.31, 0.35, 0.45, 0.48].iter() {
+            let g = gain(HALF_BAND_55, f);
+            assert!(g < 10f64.powf(-80. / 20.), "{}: {}", f, g);
+        }
+    }
+
+    #[test]
+    fn droop() {
+        for &f in [0.02, 0.05, 0.1].iter() {
+            let g = gain(HALF_BAND_19, f);
+            assert!(20. * g.log10() > -0.1, "{}: {}", f, g);
+        }
+        for &f in [0.05, 0.15, 0.2].iter() {
+            let g = gain(HALF_BAND_55, f);
+            assert!(20. * g.log10() > -0.1, "{}: {}", f, g);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use super::Reset;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Histogram bin counts.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Counts<const BINS: usize> {
+    /// In-range bins, in order of increasing value.
+    pub bins: [u32; BINS],
+    /// Samples below the lowest bin.
+    pub underflow: u32,
+    /// Samples above the highest bin.
+    pub overflow: u32,
+}
+
+impl<const BINS: usize> Counts<BINS> {
+    const fn new() -> Self {
+        Self {
+            bins: [0; BINS],
+            underflow: 0,
+            overflow: 0,
+        }
+    }
+
+    /// Total number of samples, saturating.
+    pub fn total(&self) -> u32 {
+        self.bins
+            .iter()
+            .fold(self.underflow.saturating_add(self.overflow), |t, b| {
+                t.saturating_add(*b)
+            })
+    }
+}
+
+impl<const BINS: usize> Default for Counts<BINS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Histogram accumulator.
+///
+/// The `BINS` bins are `1 << shift` wide and centered on `center`: bin `i`
+/// counts the samples in `[x0, x0 + (1 << shift))` with
+/// `x0 = center + ((i - BINS/2) << shift)`.
+/// Samples outside the bins are counted in the underflow and overflow bins.
+/// The counters saturate at `u32::MAX`.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Histogram<const BINS: usize> {
+    /// Center of the bins.
+    pub center: i32,
+    /// Log2 of the bin width.
+    pub shift: u32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    counts: Counts<BINS>,
+}
+
+impl<const BINS: usize> Histogram<BINS> {
+    /// Create a new, empty histogram.
+    ///
+    /// # Args
+    /// * `center`: Center of the bins.
+    /// * `shift`: Log2 of the bin width, `0..=32`.
+    pub const fn new(center: i32, shift: u32) -> Self {
+        Self {
+            center,
+            shift,
+            counts: Counts::new(),
+        }
+    }
+
+    /// Count a sample.
+    ///
+    /// # Args
+    /// * `x`: Sample.
+    pub fn update(&mut self, x: i32) {
+        let i =
+            ((x as i64 - self.center as i64) >> self.shift) + (BINS / 2) as i64;
+        let c = &mut self.counts;
+        let counter = if i < 0 {
+            &mut c.underflow
+        } else if i >= BINS as i64 {
+            &mut c.overflow
+        } else {
+            &mut c.bins[i as usize]
+        };
+        *counter = counter.saturating_add(1);
+    }
+
+    /// The counts since the last reset.
+    pub fn counts(&self) -> &Counts<BINS> {
+        &self.counts
+    }
+
+    /// Read the counts and reset them to zero.
+    pub fn read_and_reset(&mut self) -> Counts<BINS> {
+        core::mem::take(&mut self.counts)
+    }
+}
+
+impl<const BINS: usize> Reset for Histogram<BINS> {
+    fn reset(&mut self) {
+        self.counts = Counts::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    #[test]
+    fn bins() {
+        let mut h = Histogram::<4>::new(100, 2);
+        for x in 92..108 {
+            h.update(x);
+        }
+        assert_eq!(h.counts().bins, [4; 4]);
+        assert_eq!(h.counts().total(), 16);
+        // Edges
+        h.update(91);
+        h.update(i32::MIN);
+        h.update(108);
+        h.update(i32::MAX);
+        let c = h.read_and_reset();
+        assert_eq!((c.underflow, c.overflow), (2, 2));
+        assert_eq!(c.total(), 20);
+        assert_eq!(*h.counts(), Counts::new());
+        // Full range
+        let mut h = Histogram::<2>::new(0, 31);
+        for &x in [i32::MIN, -1, 0, i32::MAX].iter() {
+            h.update(x);
+        }
+        assert_eq!(h.counts().bins, [2, 2]);
+        assert_eq!(h.counts().total(), 4);
+    }
+
+    #[test]
+    fn uniform() {
+        // Uniform noise fills the bins evenly
+        let mut rng = Xoshiro::new(1);
+        let mut h = Histogram::<16>::new(1 << 20, 16);
+        let n = 1 << 20;
+        for _ in 0..n {
+            h.update((1 << 20) + rng.uniform_i32(1 << 19));
+        }
+        let c = h.read_and_reset();
+        assert_eq!((c.underflow, c.overflow), (0, 0));
+        let want = n as f64 / 16.;
+        for &b in c.bins.iter() {
+            assert!((b as f64 - want).abs() < 5. * want.sqrt(), "{:?}", c);
+        }
+    }
+
+    #[test]
+    fn gaussian() {
+        // One standard deviation per bin
+        let mut rng = Xoshiro::new(2);
+        let mut h = Histogram::<8>::new(0, 16);
+        let n = 1 << 20;
+        for _ in 0..n {
+            h.update(rng.gaussian_i32(1 << 16));
+        }
+        let c = h.read_and_reset();
+        // Irwin-Hall (n = 4) probability mass per bin
+        let p = [0.00021, 0.02106, 0.14409, 0.33464];
+        for i in 0..4 {
+            for &b in [c.bins[3 - i], c.bins[4 + i]].iter() {
+                let want = n as f64 * p[3 - i];
+                let tol = 5. * want.sqrt();
+                assert!((b as f64 - want).abs() < tol, "{} {:?}", i, c);
+            }
+        }
+        // Truncated tails of the Irwin-Hall approximation
+        assert_eq!((c.underflow, c.overflow), (0, 0));
+    }
+
+    #[test]
+    fn saturation() {
+        let mut h = Histogram::<2>::new(0, 0);
+        h.counts.bins[1] = u32::MAX - 1;
+        h.counts.overflow = u32::MAX;
+        for _ in 0..3 {
+            h.update(0);
+            h.update(10);
+        }
+        assert_eq!(h.counts().bins[1], u32::MAX);
+        assert_eq!(h.counts().overflow, u32::MAX);
+        assert_eq!(h.counts().total(), u32::MAX);
+    }
+}
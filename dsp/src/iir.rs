@@ -1,8 +1,15 @@
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
-use super::{abs, copysign, macc, max, min};
+use super::{
+    macc,
+    math::{abs, copysign, copysign_f64},
+    max, min, Complex, Reset,
+};
 use core::f32;
 
+pub mod design;
+
 /// IIR state and coefficients type.
 ///
 /// To represent the IIR state (input and output memory) during the filter update
@@ -11,9 +18,188 @@ use core::f32;
 /// To represent the IIR coefficients, this contains the feed-forward
 /// coefficients (b0, b1, b2) followd by the negated feed-back coefficients
 /// (-a1, -a2), all five normalized such that a0 = 1.
-#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Vec5(pub [f32; 5]);
 
+/// Frequency response `H(exp(j*2*pi*f))` of `[b0, b1, b2, -a1, -a2]`
+/// (`a0 = 1`), evaluated in double precision.
+pub(crate) fn response(ba: &[f64; 5], f: f32) -> Complex<f32> {
+    let w = 2. * core::f64::consts::PI * f as f64;
+    // exp(-j*w) and exp(-2j*w)
+    let (c1, s1) = (libm::cos(w), -libm::sin(w));
+    let (c2, s2) = (libm::cos(2. * w), -libm::sin(2. * w));
+    let (br, bi) = (ba[0] + ba[1] * c1 + ba[2] * c2, ba[1] * s1 + ba[2] * s2);
+    let (ar, ai) = (1. - ba[3] * c1 - ba[4] * c2, -ba[3] * s1 - ba[4] * s2);
+    let d = ar * ar + ai * ai;
+    Complex(
+        ((br * ar + bi * ai) / d) as f32,
+        ((bi * ar - br * ai) / d) as f32,
+    )
+}
+
+/// Largest magnitude of the roots of `z**2 - c1*z - c2`, i.e. of the poles
+/// of a biquad with negated feed-back coefficients `c1 = -a1`, `c2 = -a2`.
+pub(crate) fn pole_radius(c1: f64, c2: f64) -> f32 {
+    let d = c1 * c1 + 4. * c2;
+    let r = if d < 0. {
+        // Complex conjugate pair: |p|**2 = p*conj(p) = -c2
+        libm::sqrt(-c2)
+    } else {
+        (libm::fabs(c1) + libm::sqrt(d)) / 2.
+    };
+    r as f32
+}
+
+impl Vec5 {
+    /// Frequency response of the coefficients `H(exp(j*2*pi*f))`.
+    ///
+    /// # Args
+    /// * `f` - Frequency in units of the sample rate.
+    pub fn response(&self, f: f32) -> Complex<f32> {
+        let mut ba = [0f64; 5];
+        for (d, s) in ba.iter_mut().zip(self.0.iter()) {
+            *d = *s as f64;
+        }
+        response(&ba, f)
+    }
+
+    /// PID controller coefficients.
+    ///
+    /// The continuous time transfer function
+    /// `kp + ki/s + kd*s/(1 + s/(2*pi*f_d))` is discretized using the
+    /// bilinear transform. The integrator pole is exactly at `z = 1` and there
+    /// is no steady-state error.
+    ///
+    /// For `ki = 0` the result is a first-order PD and for `kd = 0` it is a
+    /// PI (`f_d` is ignored then).
+    ///
+    /// # Args
+    /// * `kp` - Proportional gain.
+    /// * `ki` - Integral gain (in units of the inverse of `t`).
+    /// * `kd` - Derivative gain (in units of `t`).
+    /// * `f_d` - Derivative rolloff frequency (in units of the inverse of `t`).
+    /// * `t` - Sample period.
+    ///
+    /// # Returns
+    /// 2nd-order IIR filter coefficients in the form [b0,b1,b2,-a1,-a2].
+    pub fn pid(
+        kp: f32,
+        ki: f32,
+        kd: f32,
+        f_d: f32,
+        t: f32,
+    ) -> Result<Self, &'static str> {
+        if t.is_nan() || t <= 0. {
+            return Err("sample period must be positive");
+        }
+        let (kp, ki, kd) = (kp as f64, ki as f64, kd as f64);
+        // Bilinear transform s = k*(1 - z^-1)/(1 + z^-1)
+        let k = 2. / t as f64;
+        // First order: Multiply through by (1 + z^-1)
+        let z1 = |p1: f64, p0: f64| [p1 * k + p0, p0 - p1 * k, 0.];
+        // Second order: Multiply through by (1 + z^-1)^2
+        let z2 = |p2: f64, p1: f64, p0: f64| {
+            let (p2, p1) = (p2 * k * k, p1 * k);
+            [p2 + p1 + p0, 2. * (p0 - p2), p2 - p1 + p0]
+        };
+        let (b, a) = if kd == 0. {
+            if ki == 0. {
+                ([kp, 0., 0.], [1., 0., 0.])
+            } else {
+                (z1(kp, ki), z1(1., 0.))
+            }
+        } else {
+            if f_d.is_nan() || f_d <= 0. {
+                return Err("derivative rolloff must be positive");
+            }
+            let tau = 1. / (2. * core::f64::consts::PI * f_d as f64);
+            if ki == 0. {
+                (z1(kp * tau + kd, kp), z1(tau, 1.))
+            } else {
+                (z2(kp * tau + kd, kp + ki * tau, ki), z2(tau, 1., 0.))
+            }
+        };
+        let mut ba = [
+            (b[0] / a[0]) as f32,
+            (b[1] / a[0]) as f32,
+            (b[2] / a[0]) as f32,
+            (-a[1] / a[0]) as f32,
+            (-a[2] / a[0]) as f32,
+        ];
+        if ki != 0. {
+            // Place the integrator pole exactly at z = 1 despite rounding.
+            ba[4] = 1. - ba[3];
+        }
+        if ba.iter().all(|c| c.is_finite()) {
+            Ok(Self(ba))
+        } else {
+            Err("non-finite coefficients")
+        }
+    }
+}
+
+/// IIR configuration validation failure.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ValidationError {
+    /// A coefficient, offset, or limit is NaN or infinite.
+    NonFinite,
+    /// The feed-back coefficients place poles outside the unit circle.
+    Unstable,
+    /// The lower output limit exceeds the upper output limit.
+    InvertedLimits,
+}
+
+impl ValidationError {
+    /// Return a description of the failure.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ValidationError::NonFinite => "non-finite coefficient or limit",
+            ValidationError::Unstable => "unstable poles",
+            ValidationError::InvertedLimits => "y_min exceeds y_max",
+        }
+    }
+}
+
+/// Pole location classification of a biquad.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stability {
+    /// All poles strictly inside the unit circle.
+    Stable,
+    /// No pole outside and at least one pole on the unit circle, e.g. an
+    /// integrator. Legitimate for servos where the output limits bound the
+    /// state.
+    Marginal,
+    /// At least one pole outside the unit circle.
+    Unstable,
+}
+
+/// Anti-windup mode of the output limits.
+///
+/// In both modes the stored outputs are the limited outputs. This already
+/// prevents windup of the feed-back state.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum AntiWindup {
+    /// Only clamp the output and the stored output state to the limits.
+    Clamp,
+    /// Additionally back-calculate the stored input (conditioning) such that
+    /// the filter equation holds exactly with the limited output. No excess
+    /// is retained in the input history and the filter resumes from the
+    /// limit. This mainly reduces overshoot for filters with large
+    /// high-frequency gain (e.g. derivative action). Requires `b0 != 0`.
+    BackCalculate,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for AntiWindup {
+    fn default() -> Self {
+        AntiWindup::Clamp
+    }
+}
+
 /// IIR configuration.
 ///
 /// Contains the coeeficients `ba`, the output offset `y_offset`, and the
@@ -39,12 +225,16 @@ pub struct Vec5(pub [f32; 5]);
 ///   Therefore it can trivially implement bump-less transfer.
 /// * Cascading multiple IIR filters allows stable and robust
 ///   implementation of transfer functions beyond bequadratic terms.
-#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct IIR {
     pub ba: Vec5,
     pub y_offset: f32,
     pub y_min: f32,
     pub y_max: f32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub anti_windup: AntiWindup,
 }
 
 impl IIR {
@@ -54,6 +244,7 @@ impl IIR {
             y_offset: 0.,
             y_min,
             y_max,
+            anti_windup: AntiWindup::Clamp,
         }
     }
 
@@ -88,6 +279,86 @@ impl IIR {
         Ok(())
     }
 
+    /// Check the configuration for sanity before applying it.
+    ///
+    /// Marginally stable filters (e.g. integrators) are accepted since the
+    /// output limits bound the state, see `stability()`.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if !(self.ba.0.iter().all(|c| c.is_finite())
+            && self.y_offset.is_finite()
+            && self.y_min.is_finite()
+            && self.y_max.is_finite())
+        {
+            return Err(ValidationError::NonFinite);
+        }
+        if self.stability() == Stability::Unstable {
+            return Err(ValidationError::Unstable);
+        }
+        if self.y_min > self.y_max {
+            return Err(ValidationError::InvertedLimits);
+        }
+        Ok(())
+    }
+
+    /// Classify the pole locations.
+    ///
+    /// Uses the stability triangle criterion `|a2| < 1` and `|a1| < 1 + a2`
+    /// on the exact coefficients. Filters on the boundary of the triangle
+    /// have poles on the unit circle and are `Stability::Marginal`.
+    pub fn stability(&self) -> Stability {
+        // `ba` contains the negated feed-back coefficients.
+        let (a1, a2) = (-self.ba.0[3], -self.ba.0[4]);
+        if abs(a2) > 1. || abs(a1) > 1. + a2 {
+            Stability::Unstable
+        } else if abs(a2) == 1. || abs(a1) == 1. + a2 {
+            Stability::Marginal
+        } else {
+            Stability::Stable
+        }
+    }
+
+    /// Whether all poles are strictly inside the unit circle.
+    ///
+    /// Marginally stable filters are not, see `stability()`.
+    pub fn is_stable(&self) -> bool {
+        self.stability() == Stability::Stable
+    }
+
+    /// Largest pole magnitude.
+    ///
+    /// `1 - pole_radius()` is the stability margin.
+    pub fn pole_radius(&self) -> f32 {
+        pole_radius(self.ba.0[3] as f64, self.ba.0[4] as f64)
+    }
+
+    /// Frequency response `H(exp(j*2*pi*f))` of the coefficients `ba`.
+    ///
+    /// Offset and limits are not taken into account.
+    ///
+    /// # Arguments
+    /// * `f` - Frequency in units of the sample rate.
+    pub fn response(&self, f: f32) -> Complex<f32> {
+        self.ba.response(f)
+    }
+
+    /// Magnitude of the frequency response in dB.
+    ///
+    /// # Arguments
+    /// * `f` - Frequency in units of the sample rate.
+    pub fn gain_db(&self, f: f32) -> f32 {
+        let h = self.response(f);
+        10. * libm::log10f(h.0 * h.0 + h.1 * h.1)
+    }
+
+    /// Phase of the frequency response in degrees, -180 to 180.
+    ///
+    /// # Arguments
+    /// * `f` - Frequency in units of the sample rate.
+    pub fn phase_deg(&self, f: f32) -> f32 {
+        let h = self.response(f);
+        libm::atan2f(h.1, h.0) * (180. / f32::consts::PI)
+    }
+
     /// Compute the overall (DC feed-forward) gain.
     pub fn get_k(&self) -> f32 {
         self.ba.0[..3].iter().sum()
@@ -118,6 +389,85 @@ impl IIR {
     /// * `xy` - Current filter state.
     /// * `x0` - New input.
     pub fn update(&self, xy: &mut Vec5, x0: f32) -> f32 {
+        self.update_limited(xy, x0, self.y_min, self.y_max)
+    }
+
+    /// Filter a batch of samples.
+    ///
+    /// The coefficients, offset, and limits are loaded once and the state is
+    /// kept in locals for the duration of the batch. The outputs and the final
+    /// state are bit-identical to repeated calls to `update()`.
+    ///
+    /// # Arguments
+    /// * `xy` - Current filter state.
+    /// * `x` - Input samples.
+    /// * `y` - Output samples. Must have the same length as `x`.
+    pub fn update_block(&self, xy: &mut Vec5, x: &[f32], y: &mut [f32]) {
+        debug_assert_eq!(x.len(), y.len());
+        let [b0, b1, b2, a1, a2] = self.ba.0;
+        let (y_offset, y_min, y_max) = (self.y_offset, self.y_min, self.y_max);
+        let back = self.anti_windup == AntiWindup::BackCalculate && b0 != 0.;
+        let [mut x1, mut x2, mut y1, mut y2, mut y3] = xy.0;
+        for (x0, y0) in x.iter().zip(y.iter_mut()) {
+            let mut x0 = *x0;
+            // Same summation order as `macc()`
+            let yu = y_offset + x0 * b0 + x1 * b1 + x2 * b2 + y1 * a1 + y2 * a2;
+            let yl = max(y_min, min(y_max, yu));
+            if back && yl != yu {
+                x0 -= (yu - yl) / b0;
+            }
+            x2 = x1;
+            x1 = x0;
+            y3 = y2;
+            y2 = y1;
+            y1 = yl;
+            *y0 = yl;
+        }
+        xy.0 = [x1, x2, y1, y2, y3];
+    }
+
+    /// Prepare the state for a switch from the `old` configuration to this one
+    /// (bumpless transfer).
+    ///
+    /// The stored output history is shifted such that, for a constant input,
+    /// the next output of this filter equals the next output the `old` filter
+    /// would have produced. The output is continuous across the coefficient
+    /// change and then settles according to the new configuration.
+    ///
+    /// A filter without feed-back (`a1 + a2 == 0`) has no output history to
+    /// adjust and the state is left unchanged.
+    ///
+    /// # Arguments
+    /// * `old` - The configuration that produced the current state.
+    /// * `xy` - Current filter state.
+    pub fn transfer_state(&self, old: &IIR, xy: &mut Vec5) {
+        // State for the next update assuming the input is held.
+        let next = [xy.0[0], xy.0[0], xy.0[1], xy.0[2], xy.0[3]];
+        let y_old = max(
+            old.y_min,
+            min(old.y_max, macc(old.y_offset, &next, &old.ba.0)),
+        );
+        let y_old = max(self.y_min, min(self.y_max, y_old));
+        let y_new = macc(self.y_offset, &next, &self.ba.0);
+        let a = self.ba.0[3] + self.ba.0[4];
+        if abs(a) < f32::EPSILON {
+            return;
+        }
+        let dy = (y_old - y_new) / a;
+        xy.0[2] += dy;
+        xy.0[3] += dy;
+    }
+
+    /// Filter update with explicit output limits `y_min` and `y_max`
+    /// instead of those from the configuration.
+    #[inline]
+    fn update_limited(
+        &self,
+        xy: &mut Vec5,
+        x0: f32,
+        y_min: f32,
+        y_max: f32,
+    ) -> f32 {
         let n = self.ba.0.len();
         debug_assert!(xy.0.len() == n);
         // `xy` contains       x0 x1 y0 y1 y2
@@ -130,9 +480,778 @@ impl IIR {
         // Compute y0 by multiply-accumulate
         let y0 = macc(self.y_offset, &xy.0, &self.ba.0);
         // Limit y0
-        let y0 = max(self.y_min, min(self.y_max, y0));
+        let y = max(y_min, min(y_max, y0));
+        if self.anti_windup == AntiWindup::BackCalculate
+            && y != y0
+            && self.ba.0[0] != 0.
+        {
+            // Input that would have resulted in the limited output
+            xy.0[0] -= (y0 - y) / self.ba.0[0];
+        }
+        let y0 = y;
         // Store y0            x0 x1 y0 y1 y2
         xy.0[n / 2] = y0;
         y0
     }
 }
+
+/// Zeros, poles, and gain of a biquad.
+pub type Zpk = ([Complex<f32>; 2], [Complex<f32>; 2], f32);
+
+/// Convert zeros, poles, and gain to biquad coefficients.
+///
+/// The transfer function is `k*(z - z0)*(z - z1)/((z - p0)*(z - p1))`.
+/// The zeros and the poles must each be real or a complex conjugate pair.
+/// A first-order section is padded with a zero and a pole at the origin.
+///
+/// # Arguments
+/// * `zeros` - The two zeros.
+/// * `poles` - The two poles.
+/// * `k` - Gain, equal to `b0`.
+///
+/// # Returns
+/// Coefficients in the form [b0,b1,b2,-a1,-a2].
+pub fn zpk_to_ba(
+    zeros: &[Complex<f32>; 2],
+    poles: &[Complex<f32>; 2],
+    k: f32,
+) -> [f32; 5] {
+    // Real coefficients of the monic polynomial `(x - r0)*(x - r1)`
+    let poly = |r: &[Complex<f32>; 2]| {
+        let (r0, r1) = (r[0], r[1]);
+        let (a, b, c, d) = (r0.0 as f64, r0.1 as f64, r1.0 as f64, r1.1 as f64);
+        (-(a + c), a * c - b * d)
+    };
+    let (b1, b2) = poly(zeros);
+    let (a1, a2) = poly(poles);
+    let k = k as f64;
+    [
+        k as f32,
+        (k * b1) as f32,
+        (k * b2) as f32,
+        -a1 as f32,
+        -a2 as f32,
+    ]
+}
+
+/// Convert biquad coefficients to zeros, poles, and gain.
+///
+/// This is the inverse of `zpk_to_ba()`. Real roots are returned in
+/// ascending order, complex roots with the positive imaginary part first.
+///
+/// # Arguments
+/// * `ba` - Coefficients in the form [b0,b1,b2,-a1,-a2].
+///
+/// # Returns
+/// Zeros, poles, and gain `k`.
+pub fn ba_to_zpk(ba: &[f32; 5]) -> Result<Zpk, &'static str> {
+    let k = ba[0];
+    if k == 0. {
+        return Err("zero at infinity (b0 = 0)");
+    }
+    let roots = |p, q| {
+        let r = quadratic_roots(p, q);
+        [
+            Complex(r[0].0 as f32, r[0].1 as f32),
+            Complex(r[1].0 as f32, r[1].1 as f32),
+        ]
+    };
+    let b0 = k as f64;
+    let zeros = roots(ba[1] as f64 / b0, ba[2] as f64 / b0);
+    let poles = roots(-ba[3] as f64, -ba[4] as f64);
+    Ok((zeros, poles, k))
+}
+
+/// Roots of `x**2 + p*x + q`.
+///
+/// Real roots are ordered ascending, complex roots with the positive imaginary
+/// part first.
+pub(crate) fn quadratic_roots(p: f64, q: f64) -> [Complex<f64>; 2] {
+    let h = -p / 2.;
+    let d = h * h - q;
+    if d >= 0. {
+        // Avoid cancellation
+        let r0 = h + copysign_f64(libm::sqrt(d), h);
+        let r1 = if r0 == 0. { 0. } else { q / r0 };
+        let (r0, r1) = if r0 < r1 { (r0, r1) } else { (r1, r0) };
+        [Complex(r0, 0.), Complex(r1, 0.)]
+    } else {
+        let i = libm::sqrt(-d);
+        [Complex(h, i), Complex(h, -i)]
+    }
+}
+
+/// Gain scheduling interpolator between two IIR configurations.
+///
+/// Yields one configuration per call to `next()`, moving from `from` to `to`
+/// in `steps` steps. The coefficients, the offset, and the limits are
+/// interpolated linearly. The first configuration is exactly `from` and
+/// from step `steps` on the configuration is exactly `to`.
+///
+/// Linear interpolation in `ba` space is simple but its intermediate
+/// filters are not "between" the endpoints in any physical sense: poles,
+/// zeros, and gains move non-linearly and intermediate responses can show
+/// features (e.g. a resonance) that neither endpoint has. The set of stable
+/// feed-back coefficients (stability triangle) is convex, so intermediate
+/// filters of two stable endpoints are stable.
+#[derive(Copy, Clone)]
+pub struct Interpolator {
+    pub from: IIR,
+    pub to: IIR,
+    pub steps: u32,
+    n: u32,
+}
+
+impl Interpolator {
+    /// Create a new interpolator.
+    ///
+    /// # Arguments
+    /// * `from` - Initial configuration.
+    /// * `to` - Final configuration. Its anti-windup mode is used throughout.
+    /// * `steps` - Number of steps.
+    pub const fn new(from: IIR, to: IIR, steps: u32) -> Self {
+        Self {
+            from,
+            to,
+            steps,
+            n: 0,
+        }
+    }
+
+    /// Whether the final configuration has been reached.
+    pub fn done(&self) -> bool {
+        self.n >= self.steps
+    }
+
+    /// The current configuration.
+    pub fn get(&self) -> IIR {
+        if self.done() {
+            return self.to;
+        }
+        let t = self.n as f32 / self.steps as f32;
+        let lerp = |a: f32, b: f32| a + (b - a) * t;
+        let mut iir = self.to;
+        for (c, (a, b)) in iir
+            .ba
+            .0
+            .iter_mut()
+            .zip(self.from.ba.0.iter().zip(self.to.ba.0.iter()))
+        {
+            *c = lerp(*a, *b);
+        }
+        iir.y_offset = lerp(self.from.y_offset, self.to.y_offset);
+        iir.y_min = lerp(self.from.y_min, self.to.y_min);
+        iir.y_max = lerp(self.from.y_max, self.to.y_max);
+        iir
+    }
+
+    /// Return the current configuration and advance by one step.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> IIR {
+        let iir = self.get();
+        if !self.done() {
+            self.n += 1;
+        }
+        iir
+    }
+}
+
+/// First-order IIR section.
+///
+/// Contains the coefficients `[b0, b1, -a1]` (`a0 = 1`, the feed-back
+/// coefficient negated as in `Vec5`). The state is `[x1, y1]`, the last input
+/// and output. This is cheaper than a biquad for lead/lag trims.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct FirstOrder {
+    pub ba: [f32; 3],
+}
+
+impl Default for FirstOrder {
+    fn default() -> Self {
+        Self::new(1.)
+    }
+}
+
+impl FirstOrder {
+    /// Create a pure gain section.
+    pub const fn new(gain: f32) -> Self {
+        Self { ba: [gain, 0., 0.] }
+    }
+
+    // Bilinear transform of `g*(1 + s/wz)/(1 + s/wp)` with the zero and pole
+    // frequencies prewarped.
+    fn zero_pole(fz: f32, fp: f32, g: f64) -> Result<Self, &'static str> {
+        if !(fz > 0. && fz < 0.5 && fp > 0. && fp < 0.5) {
+            return Err("frequencies must be within (0, 0.5)");
+        }
+        let wz = libm::tan(core::f64::consts::PI * fz as f64);
+        let wp = libm::tan(core::f64::consts::PI * fp as f64);
+        let a0 = 1. + 1. / wp;
+        Ok(Self {
+            ba: [
+                (g * (1. + 1. / wz) / a0) as f32,
+                (g * (1. - 1. / wz) / a0) as f32,
+                (-(1. - 1. / wp) / a0) as f32,
+            ],
+        })
+    }
+
+    /// Lead compensator with unity DC gain.
+    ///
+    /// The high frequency gain is `wp/wz` with the prewarped frequencies
+    /// `w = tan(pi*f)`. The maximum phase lead `asin((wp - wz)/(wp + wz))`
+    /// is at `f = atan(sqrt(wz*wp))/pi`, close to the geometric mean of `fz`
+    /// and `fp` for frequencies well below Nyquist.
+    ///
+    /// # Args
+    /// * `fz` - Zero frequency in units of the sample rate.
+    /// * `fp` - Pole frequency in units of the sample rate, above `fz`.
+    pub fn lead(fz: f32, fp: f32) -> Result<Self, &'static str> {
+        if fz.is_nan() || fp.is_nan() || fz >= fp {
+            return Err("lead zero must be below the pole");
+        }
+        Self::zero_pole(fz, fp, 1.)
+    }
+
+    /// Lag compensator with unity high frequency gain.
+    ///
+    /// The DC gain is `wz/wp` with the prewarped frequencies
+    /// `w = tan(pi*f)`. The maximum phase lag `asin((wz - wp)/(wz + wp))` is
+    /// at `f = atan(sqrt(wz*wp))/pi`.
+    ///
+    /// # Args
+    /// * `fz` - Zero frequency in units of the sample rate.
+    /// * `fp` - Pole frequency in units of the sample rate, below `fz`.
+    pub fn lag(fz: f32, fp: f32) -> Result<Self, &'static str> {
+        if fz.is_nan() || fp.is_nan() || fz <= fp {
+            return Err("lag pole must be below the zero");
+        }
+        let wz = libm::tan(core::f64::consts::PI * fz as f64);
+        let wp = libm::tan(core::f64::consts::PI * fp as f64);
+        Self::zero_pole(fz, fp, wz / wp)
+    }
+
+    /// Check the coefficients for finiteness and stability.
+    pub fn validate(&self) -> Result<(), ValidationError> {
+        if !self.ba.iter().all(|c| c.is_finite()) {
+            return Err(ValidationError::NonFinite);
+        }
+        if abs(self.ba[2]) > 1. {
+            return Err(ValidationError::Unstable);
+        }
+        Ok(())
+    }
+
+    /// Frequency response `H(exp(j*2*pi*f))`.
+    ///
+    /// # Args
+    /// * `f` - Frequency in units of the sample rate.
+    pub fn response(&self, f: f32) -> Complex<f32> {
+        let [b0, b1, a1] = self.ba;
+        response(&[b0 as f64, b1 as f64, 0., a1 as f64, 0.], f)
+    }
+
+    /// Feed a new input value into the section, update the state, and
+    /// return the new output.
+    ///
+    /// # Arguments
+    /// * `xy` - Current state `[x1, y1]`.
+    /// * `x0` - New input.
+    pub fn update(&self, xy: &mut [f32; 2], x0: f32) -> f32 {
+        let [b0, b1, a1] = self.ba;
+        let y0 = b0 * x0 + b1 * xy[0] + a1 * xy[1];
+        *xy = [x0, y0];
+        y0
+    }
+}
+
+/// Cascade of `N` second-order sections.
+///
+/// The output of each stage is the input to the next one. Offsets are applied
+/// for each stage. The output limits `y_min` and `y_max` of the last stage
+/// apply to the cascade output. The intermediate stages are not limited.
+#[derive(Copy, Clone)]
+pub struct Cascade<const N: usize> {
+    pub stages: [IIR; N],
+}
+
+impl<const N: usize> Cascade<N> {
+    pub const fn new(stages: [IIR; N]) -> Self {
+        Self { stages }
+    }
+
+    /// Feed a new input value into the cascade, update the filter states, and
+    /// return the new output. Only the states are modified.
+    ///
+    /// # Arguments
+    /// * `states` - Current filter states, one per stage.
+    /// * `x0` - New input.
+    pub fn update(&self, states: &mut [Vec5; N], x0: f32) -> f32 {
+        let (last, stages) = match self.stages.split_last() {
+            Some(split) => split,
+            None => return x0,
+        };
+        let y =
+            stages
+                .iter()
+                .zip(states.iter_mut())
+                .fold(x0, |x, (iir, xy)| {
+                    iir.update_limited(xy, x, f32::NEG_INFINITY, f32::INFINITY)
+                });
+        last.update(&mut states[N - 1], y)
+    }
+}
+
+impl Reset for Vec5 {
+    fn reset(&mut self) {
+        self.0 = [0.; 5];
+    }
+}
+
+impl Reset for Interpolator {
+    /// Restart the ramp at the initial configuration.
+    fn reset(&mut self) {
+        self.n = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::f64::consts::PI;
+    use rand::{prelude::*, rngs::StdRng};
+
+    // Magnitude of the frequency response at `f` (in units of the sample
+    // rate).
+    fn response(ba: &Vec5, f: f64) -> f64 {
+        let ba = ba.0;
+        let (c1, s1) = ((2. * PI * f).cos(), -(2. * PI * f).sin());
+        let (c2, s2) = ((4. * PI * f).cos(), -(4. * PI * f).sin());
+        let b = (
+            ba[0] as f64 + ba[1] as f64 * c1 + ba[2] as f64 * c2,
+            ba[1] as f64 * s1 + ba[2] as f64 * s2,
+        );
+        let a = (
+            1. - ba[3] as f64 * c1 - ba[4] as f64 * c2,
+            -ba[3] as f64 * s1 - ba[4] as f64 * s2,
+        );
+        (b.0.hypot(b.1)) / (a.0.hypot(a.1))
+    }
+
+    #[test]
+    fn pid_response() {
+        let t = 1e-6;
+        let (kp, ki, kd, f_d) = (1., 1e3, 1e-8, 2e5);
+        let ba = Vec5::pid(kp, ki, kd, f_d, t).unwrap();
+        // Integrator slope at low frequency
+        let f = 1.;
+        let h = response(&ba, f * t as f64);
+        let want = ki as f64 / (2. * PI * f);
+        assert!((h / want - 1.).abs() < 1e-2, "{} {}", h, want);
+        let h2 = response(&ba, 2. * f * t as f64);
+        assert!((h / h2 / 2. - 1.).abs() < 1e-2);
+        // Proportional plateau
+        let h = response(&ba, 5e4 * t as f64);
+        assert!((h / kp as f64 - 1.).abs() < 1e-2, "{}", h);
+    }
+
+    #[test]
+    fn pid_degenerate() {
+        // P
+        let ba = Vec5::pid(2., 0., 0., 0., 1.).unwrap();
+        assert_eq!(ba.0, [2., 0., 0., 0., 0.]);
+        // PI, rolloff ignored
+        let ba = Vec5::pid(1., 0.5, 0., f32::NAN, 1.).unwrap();
+        assert_eq!(ba.0, [1.25, -0.75, 0., 1., 0.]);
+        // PD
+        let ba = Vec5::pid(1., 0., 1e-3, 1e2, 1e-5).unwrap();
+        assert!(ba.0.iter().all(|c| c.is_finite()));
+        assert_eq!(ba.0[2], 0.);
+        assert_eq!(ba.0[4], 0.);
+        assert!((response(&ba, 1e-9) - 1.).abs() < 1e-3);
+        assert!(Vec5::pid(1., 0., 1e-3, 0., 1e-5).is_err());
+        assert!(Vec5::pid(1., 1., 0., 0., 0.).is_err());
+    }
+
+    #[test]
+    fn lead_lag() {
+        let (fz, fp) = (1e-3, 1e-2);
+        let warp = |f: f32| (core::f32::consts::PI * f).tan();
+        let (wz, wp) = (warp(fz), warp(fp));
+        let fm = (wz * wp).sqrt().atan() / core::f32::consts::PI;
+        let lead = FirstOrder::lead(fz, fp).unwrap();
+        assert_eq!(lead.validate(), Ok(()));
+        // Maximum phase lead at the (prewarped) geometric mean
+        let phase = lead.response(fm).arg();
+        let want = ((wp - wz) / (wp + wz)).asin();
+        assert!((phase - want).abs() < 1e-4, "{} {}", phase, want);
+        for &f in [fm * 0.9, fm * 1.1].iter() {
+            assert!(lead.response(f).arg() < phase);
+        }
+        // Close to the plain geometric mean
+        let phase_gm = lead.response((fz * fp).sqrt()).arg();
+        assert!((phase_gm - want).abs() < 1e-3);
+        // Unity DC gain, high frequency gain wp/wz at Nyquist
+        assert!((lead.response(0.).abs() - 1.).abs() < 1e-5);
+        assert!((lead.response(0.5).abs() / (wp / wz) - 1.).abs() < 1e-4);
+
+        let lag = FirstOrder::lag(fp, fz).unwrap();
+        let phase = lag.response(fm).arg();
+        assert!((phase + want).abs() < 1e-4, "{} {}", phase, want);
+        assert!((lag.response(0.5).abs() - 1.).abs() < 1e-5);
+        assert!((lag.response(0.).abs() / (wp / wz) - 1.).abs() < 1e-4);
+
+        assert!(FirstOrder::lead(fp, fz).is_err());
+        assert!(FirstOrder::lag(fz, fp).is_err());
+        assert!(FirstOrder::lead(0., fp).is_err());
+        assert!(FirstOrder::lead(fz, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn interpolator_endpoints() {
+        let mut from = IIR::new(1., -2., 2.);
+        from.set_pi(0.1, 1e-3, 0.).unwrap();
+        let mut to = IIR::new(1., -1., 3.);
+        to.set_pi(1., 1e-2, 0.).unwrap();
+        to.y_offset = 0.5;
+        let mut interp = Interpolator::new(from, to, 10);
+        assert_eq!(interp.next().ba.0, from.ba.0);
+        for _ in 1..10 {
+            assert!(!interp.done());
+            let iir = interp.next();
+            assert!(iir.y_min > -2. && iir.y_min < -1.);
+        }
+        assert!(interp.done());
+        for _ in 0..3 {
+            let iir = interp.next();
+            assert_eq!(iir.ba.0, to.ba.0);
+            assert_eq!(
+                (iir.y_offset, iir.y_min, iir.y_max),
+                (to.y_offset, to.y_min, to.y_max)
+            );
+        }
+        // Zero steps: immediately the final configuration
+        assert_eq!(Interpolator::new(from, to, 0).next().ba.0, to.ba.0);
+    }
+
+    #[test]
+    fn interpolator_stable() {
+        let mut rng = StdRng::seed_from_u64(0x42);
+        let stable = |rng: &mut StdRng| loop {
+            let mut iir = IIR::new(1., -1., 1.);
+            iir.ba.0 = [
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-2.0..2.0),
+                rng.gen_range(-1.0..1.0),
+            ];
+            if iir.stability() == Stability::Stable {
+                return iir;
+            }
+        };
+        for _ in 0..100 {
+            let (from, to) = (stable(&mut rng), stable(&mut rng));
+            let mut interp = Interpolator::new(from, to, 50);
+            while !interp.done() {
+                assert_eq!(interp.next().stability(), Stability::Stable);
+            }
+        }
+    }
+
+    #[test]
+    fn interpolator_no_step() {
+        let mut iir = IIR::new(1., -10., 10.);
+        iir.ba = Vec5::pid(1., 0.1, 0., 0., 1.).unwrap();
+        let mut interp = Interpolator::new(iir, iir, 100);
+        let (mut xy0, mut xy1) = (Vec5::default(), Vec5::default());
+        for i in 0..200 {
+            let x = (i as f32 * 0.1).sin();
+            assert_eq!(
+                interp.next().update(&mut xy0, x),
+                iir.update(&mut xy1, x)
+            );
+        }
+    }
+
+    #[test]
+    fn first_order_update() {
+        let lead = FirstOrder::lead(1e-3, 1e-2).unwrap();
+        let mut xy = [0.; 2];
+        // Settles to the DC gain
+        let y = (0..10_000).fold(0., |_, _| lead.update(&mut xy, 1.));
+        assert!((y - 1.).abs() < 1e-4, "{}", y);
+        // Matches the transfer function
+        let mut xy = [0.; 2];
+        let y0 = lead.update(&mut xy, 1.);
+        assert_eq!(y0, lead.ba[0]);
+        let y1 = lead.update(&mut xy, 0.);
+        assert_eq!(y1, lead.ba[1] + lead.ba[2] * y0);
+        let mut unstable = lead;
+        unstable.ba[2] = 1.1;
+        assert_eq!(unstable.validate(), Err(ValidationError::Unstable));
+        assert_eq!(FirstOrder::default().update(&mut xy, 3.), 3.);
+    }
+
+    #[test]
+    fn validate() {
+        let mut iir = IIR::new(1., -1., 1.);
+        // Second order Butterworth lowpass at 0.1 of the sample rate
+        iir.ba = Vec5([0.0675, 0.1349, 0.0675, 1.1430, -0.4128]);
+        assert_eq!(iir.validate(), Ok(()));
+        // PI with integrator pole at z = 1
+        iir.set_pi(1., 0.1, 0.).unwrap();
+        assert_eq!(iir.validate(), Ok(()));
+
+        let mut bad = iir;
+        bad.ba.0[1] = f32::NAN;
+        assert_eq!(bad.validate(), Err(ValidationError::NonFinite));
+        let mut bad = iir;
+        bad.y_max = f32::INFINITY;
+        assert_eq!(bad.validate(), Err(ValidationError::NonFinite));
+        let mut bad = iir;
+        bad.ba.0[3] = 0.;
+        bad.ba.0[4] = 1.5;
+        assert_eq!(bad.validate(), Err(ValidationError::Unstable));
+        let mut bad = iir;
+        bad.ba.0[3] = 1.5;
+        bad.ba.0[4] = 0.;
+        assert_eq!(bad.validate(), Err(ValidationError::Unstable));
+        let mut bad = iir;
+        bad.y_min = 2.;
+        assert_eq!(bad.validate(), Err(ValidationError::InvertedLimits));
+    }
+
+    // Peak output of a first-order lowpass plant in closed loop after a
+    // set-point step that drives the controller into its limit.
+    fn step_peak(iir: &IIR) -> f32 {
+        let mut xy = Vec5::default();
+        let (r, mut y, mut peak) = (0.8, 0., 0f32);
+        for _ in 0..5000 {
+            let u = iir.update(&mut xy, r - y);
+            y += 0.01 * (u - y);
+            peak = peak.max(y);
+        }
+        assert!((y - r).abs() < 1e-3);
+        peak
+    }
+
+    #[test]
+    fn anti_windup() {
+        let mut iir = IIR::new(1., -1., 1.);
+        iir.ba = Vec5::pid(0.5, 0.01, 30., 0.05, 1.).unwrap();
+        let clamp = step_peak(&iir) - 0.8;
+        iir.anti_windup = AntiWindup::BackCalculate;
+        let back = step_peak(&iir) - 0.8;
+        assert!(back > 0. && back < 0.7 * clamp, "{} {}", back, clamp);
+    }
+
+    #[test]
+    fn transfer() {
+        let lowpass = |a1: f32, k: f32| {
+            let mut iir = IIR::new(1., -1e4, 1e4);
+            iir.ba = Vec5([k * (1. - a1), 0., 0., a1, 0.]);
+            iir
+        };
+        let old = lowpass(0.99, 1.);
+        let new = lowpass(0.5, 2.);
+        let (mut xy, mut xy_t) = (Vec5::default(), Vec5::default());
+        let x = |i: usize| 1000. + 10. * (i as f32 * 1e-2).sin();
+        let mut y = 0.;
+        for i in 0..1000 {
+            y = old.update(&mut xy, x(i));
+            old.update(&mut xy_t, x(i));
+        }
+        new.transfer_state(&old, &mut xy_t);
+        let step = (new.update(&mut xy, x(1000)) - y).abs();
+        let step_t = (new.update(&mut xy_t, x(1000)) - y).abs();
+        assert!(step > 400., "{}", step);
+        assert!(step_t < 2., "{}", step_t);
+        // Settles to the new DC gain
+        for i in 1001..2000 {
+            y = new.update(&mut xy_t, x(i));
+        }
+        assert!((y - 2. * x(1999)).abs() < 2., "{}", y);
+    }
+
+    fn assert_zpk(
+        ba: [f32; 5],
+        zeros: [Complex<f32>; 2],
+        poles: [Complex<f32>; 2],
+    ) {
+        let (z, p, k) = ba_to_zpk(&ba).unwrap();
+        assert_eq!(k, ba[0]);
+        for (a, b) in
+            z.iter().zip(zeros.iter()).chain(p.iter().zip(poles.iter()))
+        {
+            assert!(
+                (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6,
+                "{:?} {:?}",
+                a,
+                b
+            );
+        }
+        let ba2 = zpk_to_ba(&z, &p, k);
+        for f in [1e-3, 0.01, 0.1, 0.25, 0.5].iter() {
+            let (h, h2) = (response(&Vec5(ba), *f), response(&Vec5(ba2), *f));
+            assert!((h - h2).abs() <= 1e-5 * h.max(1.), "{}: {} {}", f, h, h2);
+        }
+    }
+
+    #[test]
+    fn zpk() {
+        let r = 0.9f32;
+        let (c, s) = ((0.3f32).cos() * r, (0.3f32).sin() * r);
+        // Complex pole pair, real zeros
+        let ba = zpk_to_ba(
+            &[Complex(-1., 0.), Complex(0.5, 0.)],
+            &[Complex(c, s), Complex(c, -s)],
+            0.1,
+        );
+        assert!((ba[4] + r * r).abs() < 1e-6);
+        assert_zpk(
+            ba,
+            [Complex(-1., 0.), Complex(0.5, 0.)],
+            [Complex(c, s), Complex(c, -s)],
+        );
+        // Two real poles, complex zeros
+        let ba = zpk_to_ba(
+            &[Complex(0., 1.), Complex(0., -1.)],
+            &[Complex(0.2, 0.), Complex(0.7, 0.)],
+            2.,
+        );
+        assert_eq!(ba, [2., 0., 2., 0.9, -0.14]);
+        assert_zpk(
+            ba,
+            [Complex(0., 1.), Complex(0., -1.)],
+            [Complex(0.2, 0.), Complex(0.7, 0.)],
+        );
+        // PI: pure integrator with a pole at z = 1, padded at the origin
+        let mut iir = IIR::default();
+        iir.set_pi(1., 0.1, 0.).unwrap();
+        let b = iir.ba.0;
+        assert_zpk(
+            b,
+            [Complex(0., 0.), Complex(-b[1] / b[0], 0.)],
+            [Complex(0., 0.), Complex(1., 0.)],
+        );
+        assert!(ba_to_zpk(&[0., 1., 0., 0., 0.]).is_err());
+    }
+
+    #[test]
+    fn update_block() {
+        let mut rng = StdRng::seed_from_u64(0x4f21);
+        for i in 0..100 {
+            let mut iir = IIR::new(1., -0.8, 0.9);
+            iir.ba = Vec5(design::lowpass(
+                rng.gen_range(1e-3..0.4),
+                rng.gen_range(0.1..10.),
+            ));
+            iir.y_offset = rng.gen_range(-0.1..0.1);
+            if i & 1 != 0 {
+                iir.anti_windup = AntiWindup::BackCalculate;
+            }
+            let x: Vec<f32> = (0..rng.gen_range(0..100))
+                .map(|_| rng.gen_range(-2.0..2.))
+                .collect();
+            let mut y = vec![0.; x.len()];
+            let mut xy0 = Vec5([0.1, -0.2, 0.3, 0.4, -0.5]);
+            let mut xy1 = xy0;
+            iir.update_block(&mut xy0, &x, &mut y);
+            for (x, y) in x.iter().zip(y.iter()) {
+                assert_eq!(iir.update(&mut xy1, *x).to_bits(), y.to_bits());
+            }
+            for (a, b) in xy0.0.iter().zip(xy1.0.iter()) {
+                assert_eq!(a.to_bits(), b.to_bits());
+            }
+        }
+    }
+
+    #[test]
+    fn stability() {
+        let mut iir = IIR::new(1., -1., 1.);
+        let mut check = |a: [f32; 2], s: Stability, r: f32| {
+            iir.ba = Vec5([1., 0., 0., a[0], a[1]]);
+            assert_eq!(iir.stability(), s, "{:?}", a);
+            assert_eq!(iir.is_stable(), s == Stability::Stable);
+            assert!((iir.pole_radius() - r).abs() < 1e-4, "{:?}", a);
+        };
+        // Butterworth lowpass, complex pair
+        check([1.1430, -0.4128], Stability::Stable, 0.4128f32.sqrt());
+        // Real poles at 0.5 and -0.25
+        check([0.25, 0.125], Stability::Stable, 0.5);
+        // Integrator, double integrator, pole at -1
+        check([1., 0.], Stability::Marginal, 1.);
+        check([2., -1.], Stability::Marginal, 1.);
+        check([-1., 0.], Stability::Marginal, 1.);
+        // Complex pair on the unit circle (oscillator)
+        check([1., -1.], Stability::Marginal, 1.);
+        // Poles at 1 and -1
+        check([0., 1.], Stability::Marginal, 1.);
+        // Just inside and just outside the triangle edges
+        check([0.999, 0.], Stability::Stable, 0.999);
+        check([1.001, 0.], Stability::Unstable, 1.001);
+        check([1., -0.999], Stability::Stable, 0.999f32.sqrt());
+        check([1., -1.001], Stability::Unstable, 1.001f32.sqrt());
+        check([1.5, -0.501], Stability::Stable, 0.998);
+        check([1.5, -0.499], Stability::Unstable, 1.002);
+    }
+
+    #[test]
+    fn response_integrator() {
+        // Bilinear integrator: k*(1 + z^-1)/(1 - z^-1) = -j*k*cot(w/2)
+        let k = 0.01;
+        let mut iir = IIR::new(k, -1., 1.);
+        iir.ba = Vec5([k, k, 0., 1., 0.]);
+        for &f in [1e-4f32, 1e-3, 0.1, 0.3].iter() {
+            let h = iir.response(f);
+            let want = k as f64 / (PI * f as f64).tan();
+            assert!(h.0.abs() < 1e-6, "{:?}", h);
+            assert!((h.1 as f64 / -want - 1.).abs() < 1e-5, "{:?} {}", h, want);
+            assert!((iir.phase_deg(f) + 90.).abs() < 1e-3);
+            let db = 20. * want.log10();
+            assert!((iir.gain_db(f) as f64 - db).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn response_notch() {
+        let mut iir = IIR::new(1., -1., 1.);
+        iir.ba = Vec5(design::notch(0.1, 2.));
+        assert!(iir.gain_db(0.1) < -80.);
+        for &f in [0f32, 0.5].iter() {
+            assert!(iir.gain_db(f).abs() < 1e-4);
+        }
+        assert!(iir.phase_deg(0.).abs() < 1e-4);
+        // -45 degrees at the lower -3 dB frequency, +45 at the upper
+        let bw = 0.1 / 2.;
+        let h = iir.response(0.1 - bw / 2.);
+        assert!(h.1 < 0. && h.0 > 0.);
+        let h = iir.response(0.1 + bw / 2.);
+        assert!(h.1 > 0. && h.0 > 0.);
+    }
+
+    #[test]
+    fn cascade_identity() {
+        let cascade = Cascade::new([IIR::new(1., -10., 10.); 2]);
+        let mut states = [Vec5::default(); 2];
+        for &x in [0., 1., -3.5, 7.25, 0.125].iter() {
+            assert_eq!(cascade.update(&mut states, x), x);
+        }
+    }
+
+    #[test]
+    fn cascade_intermediate_unlimited() {
+        // Gain 4 then 1/4 with tight limits on the first stage.
+        let mut cascade =
+            Cascade::new([IIR::new(4., -1., 1.), IIR::new(0.25, -2., 2.)]);
+        let mut states = [Vec5::default(); 2];
+        assert_eq!(cascade.update(&mut states, 1.), 1.);
+        assert_eq!(states[0].0[2], 4.);
+        // The last stage limits apply.
+        cascade.stages[1].ba.0[0] = 1.;
+        assert_eq!(cascade.update(&mut states, 1.), 2.);
+        assert_eq!(cascade.update(&mut states, -1.), -2.);
+    }
+}
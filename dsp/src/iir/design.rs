@@ -0,0 +1,247 @@
+//! Biquad coefficient design.
+//!
+//! Implements the bilinear transform designs from the "Cookbook formulae for
+//! audio EQ biquad filter coefficients" (R. Bristow-Johnson).
+//!
+//! Frequencies `f0` are in units of the sample rate and must be in
+//! `(0, 0.5)`. The quality factor `q` must be positive. The coefficients are
+//! returned as `[b0, b1, b2, -a1, -a2]` normalized to `a0 = 1`, matching
+//! `Vec5`.
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use core::f64::consts::PI;
+
+/// Biquad filter type.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Filter {
+    Lowpass,
+    Highpass,
+    Bandpass,
+    Notch,
+    Allpass,
+    LowShelf,
+    HighShelf,
+}
+
+// Normalize and negate the feed-back coefficients
+fn normalize(b: [f64; 3], a: [f64; 3]) -> [f32; 5] {
+    [
+        (b[0] / a[0]) as f32,
+        (b[1] / a[0]) as f32,
+        (b[2] / a[0]) as f32,
+        (-a[1] / a[0]) as f32,
+        (-a[2] / a[0]) as f32,
+    ]
+}
+
+// Return `(cos(w0), alpha)`.
+fn prewarp(f0: f32, q: f32) -> (f64, f64) {
+    let w0 = 2. * PI * f0 as f64;
+    (libm::cos(w0), libm::sin(w0) / (2. * q as f64))
+}
+
+/// Second order lowpass, DC gain 1, gain `q` at `f0`.
+pub fn lowpass(f0: f32, q: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    let b = (1. - c) / 2.;
+    normalize([b, 2. * b, b], [1. + alpha, -2. * c, 1. - alpha])
+}
+
+/// Second order highpass, Nyquist gain 1, gain `q` at `f0`.
+pub fn highpass(f0: f32, q: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    let b = (1. + c) / 2.;
+    normalize([b, -2. * b, b], [1. + alpha, -2. * c, 1. - alpha])
+}
+
+/// Second order bandpass, peak gain 1 at `f0`.
+pub fn bandpass(f0: f32, q: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    normalize([alpha, 0., -alpha], [1. + alpha, -2. * c, 1. - alpha])
+}
+
+/// Notch at `f0`, DC and Nyquist gain 1.
+pub fn notch(f0: f32, q: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    normalize([1., -2. * c, 1.], [1. + alpha, -2. * c, 1. - alpha])
+}
+
+/// Allpass with a phase shift of `-pi` at `f0`.
+pub fn allpass(f0: f32, q: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    normalize(
+        [1. - alpha, -2. * c, 1. + alpha],
+        [1. + alpha, -2. * c, 1. - alpha],
+    )
+}
+
+/// Low shelf with `gain_db` at DC and unity gain at Nyquist.
+///
+/// The gain at `f0` is half of `gain_db`.
+pub fn low_shelf(f0: f32, q: f32, gain_db: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    let a = libm::pow(10., gain_db as f64 / 40.);
+    let s = 2. * libm::sqrt(a) * alpha;
+    normalize(
+        [
+            a * ((a + 1.) - (a - 1.) * c + s),
+            2. * a * ((a - 1.) - (a + 1.) * c),
+            a * ((a + 1.) - (a - 1.) * c - s),
+        ],
+        [
+            (a + 1.) + (a - 1.) * c + s,
+            -2. * ((a - 1.) + (a + 1.) * c),
+            (a + 1.) + (a - 1.) * c - s,
+        ],
+    )
+}
+
+/// High shelf with `gain_db` at Nyquist and unity gain at DC.
+///
+/// The gain at `f0` is half of `gain_db`.
+pub fn high_shelf(f0: f32, q: f32, gain_db: f32) -> [f32; 5] {
+    let (c, alpha) = prewarp(f0, q);
+    let a = libm::pow(10., gain_db as f64 / 40.);
+    let s = 2. * libm::sqrt(a) * alpha;
+    normalize(
+        [
+            a * ((a + 1.) + (a - 1.) * c + s),
+            -2. * a * ((a - 1.) + (a + 1.) * c),
+            a * ((a + 1.) + (a - 1.) * c - s),
+        ],
+        [
+            (a + 1.) - (a - 1.) * c + s,
+            2. * ((a - 1.) - (a + 1.) * c),
+            (a + 1.) - (a - 1.) * c - s,
+        ],
+    )
+}
+
+/// Design a filter of the given type after checking the parameters.
+///
+/// # Args
+/// * `filter`: Filter type.
+/// * `f0`: Characteristic frequency in units of the sample rate.
+/// * `q`: Quality factor.
+/// * `gain_db`: Shelf gain in dB, ignored for other types.
+pub fn design(
+    filter: Filter,
+    f0: f32,
+    q: f32,
+    gain_db: f32,
+) -> Result<[f32; 5], &'static str> {
+    if f0.is_nan() || f0 <= 0. || f0 >= 0.5 {
+        return Err("frequency out of range");
+    }
+    if q.is_nan() || q <= 0. {
+        return Err("quality factor must be positive");
+    }
+    if !gain_db.is_finite() {
+        return Err("non-finite gain");
+    }
+    let ba = match filter {
+        Filter::Lowpass => lowpass(f0, q),
+        Filter::Highpass => highpass(f0, q),
+        Filter::Bandpass => bandpass(f0, q),
+        Filter::Notch => notch(f0, q),
+        Filter::Allpass => allpass(f0, q),
+        Filter::LowShelf => low_shelf(f0, q, gain_db),
+        Filter::HighShelf => high_shelf(f0, q, gain_db),
+    };
+    if ba.iter().all(|c| c.is_finite()) {
+        Ok(ba)
+    } else {
+        Err("non-finite coefficients")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Complex frequency response at `f` (in units of the sample rate).
+    fn response(ba: &[f32; 5], f: f64) -> (f64, f64) {
+        let (c1, s1) = ((2. * PI * f).cos(), -(2. * PI * f).sin());
+        let (c2, s2) = ((4. * PI * f).cos(), -(4. * PI * f).sin());
+        let ba: Vec<f64> = ba.iter().map(|c| *c as f64).collect();
+        let b = (ba[0] + ba[1] * c1 + ba[2] * c2, ba[1] * s1 + ba[2] * s2);
+        let a = (1. - ba[3] * c1 - ba[4] * c2, -ba[3] * s1 - ba[4] * s2);
+        let d = a.0 * a.0 + a.1 * a.1;
+        ((b.0 * a.0 + b.1 * a.1) / d, (b.1 * a.0 - b.0 * a.1) / d)
+    }
+
+    fn gain(ba: &[f32; 5], f: f64) -> f64 {
+        let h = response(ba, f);
+        h.0.hypot(h.1)
+    }
+
+    fn assert_gain(ba: &[f32; 5], f: f64, g: f64) {
+        let h = gain(ba, f);
+        assert!((h - g).abs() < 1e-4 * g.max(1.), "{}: {} {}", f, h, g);
+    }
+
+    const F0: f32 = 0.01;
+    const Q: f32 = 3.;
+    const NYQUIST: f64 = 0.5;
+
+    #[test]
+    fn lowpass_highpass() {
+        let ba = lowpass(F0, Q);
+        assert_gain(&ba, 0., 1.);
+        assert_gain(&ba, F0 as f64, Q as f64);
+        assert_gain(&ba, NYQUIST, 0.);
+        let ba = highpass(F0, Q);
+        assert_gain(&ba, 0., 0.);
+        assert_gain(&ba, F0 as f64, Q as f64);
+        assert_gain(&ba, NYQUIST, 1.);
+    }
+
+    #[test]
+    fn bandpass_notch() {
+        let ba = bandpass(F0, Q);
+        assert_gain(&ba, 0., 0.);
+        assert_gain(&ba, F0 as f64, 1.);
+        assert_gain(&ba, NYQUIST, 0.);
+        let ba = notch(F0, 10.);
+        assert_gain(&ba, 0., 1.);
+        // Limited by f32 coefficient quantization
+        assert!(gain(&ba, F0 as f64) < 1e-3);
+        assert_gain(&ba, NYQUIST, 1.);
+    }
+
+    #[test]
+    fn allpass_phase() {
+        let ba = allpass(F0, Q);
+        for &f in [0., 1e-3, F0 as f64, 0.1, NYQUIST].iter() {
+            assert_gain(&ba, f, 1.);
+        }
+        let h = response(&ba, F0 as f64);
+        assert!((h.0 + 1.).abs() < 1e-4 && h.1.abs() < 1e-3, "{:?}", h);
+    }
+
+    #[test]
+    fn shelves() {
+        let g = 10f64.powf(12. / 20.);
+        let ba = low_shelf(F0, 0.7, 12.);
+        assert_gain(&ba, 0., g);
+        assert_gain(&ba, F0 as f64, g.sqrt());
+        assert_gain(&ba, NYQUIST, 1.);
+        let ba = high_shelf(F0, 0.7, 12.);
+        assert_gain(&ba, 0., 1.);
+        assert_gain(&ba, F0 as f64, g.sqrt());
+        assert_gain(&ba, NYQUIST, g);
+    }
+
+    #[test]
+    fn checks() {
+        assert!(design(Filter::Notch, 0.1, 10., 0.).is_ok());
+        assert!(design(Filter::Notch, 0., 10., 0.).is_err());
+        assert!(design(Filter::Notch, 0.5, 10., 0.).is_err());
+        assert!(design(Filter::Notch, f32::NAN, 10., 0.).is_err());
+        assert!(design(Filter::Lowpass, 0.1, 0., 0.).is_err());
+        assert!(design(Filter::LowShelf, 0.1, 1., f32::INFINITY).is_err());
+    }
+}
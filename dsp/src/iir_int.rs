@@ -1,10 +1,14 @@
+use super::{iir, macc_i32, round_shift, Complex, Reset, Rounding};
 use core::f64::consts::PI;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 /// Generic vector for integer IIR filter.
 /// This struct is used to hold the x/y input/output data vector or the b/a coefficient
 /// vector.
-#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Vec5(pub [i32; 5]);
 
 impl Vec5 {
@@ -36,38 +40,181 @@ impl Vec5 {
     }
 }
 
-fn macc(y0: i32, x: &[i32], a: &[i32], shift: u32) -> i32 {
-    // Rounding bias, half up
-    let y0 = ((y0 as i64) << shift) + (1 << (shift - 1));
-    let y = x
-        .iter()
-        .zip(a)
-        .map(|(x, a)| *x as i64 * *a as i64)
-        .fold(y0, |y, xa| y + xa);
-    (y >> shift) as i32
+/// Quantization error of a floating point to fixed point coefficient
+/// conversion, see `IIR::from_float()`.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct QuantReport {
+    /// Largest absolute coefficient error.
+    pub max_error: f32,
+    /// Largest distance between a pole of the floating point filter and the
+    /// corresponding pole of the fixed point filter.
+    pub pole_displacement: f32,
 }
 
 /// Integer biquad IIR
 ///
 /// See `dsp::iir::IIR` for general implementation details.
 /// Offset and limiting disabled to suit lowpass applications.
-/// Coefficient scaling fixed and optimized.
-#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct IIR {
     pub ba: Vec5,
+    /// Number of fractional bits of the coefficients, 1 to 31.
+    #[cfg_attr(feature = "serde", serde(default = "default_shift"))]
+    pub shift: u32,
+    // Number of saturated outputs of `update_saturating()`
+    #[cfg_attr(feature = "serde", serde(skip))]
+    sat_count: u16,
     // pub y_offset: i32,
     // pub y_min: i32,
     // pub y_max: i32,
 }
 
+#[cfg(feature = "serde")]
+fn default_shift() -> u32 {
+    IIR::SHIFT
+}
+
+impl Default for IIR {
+    fn default() -> Self {
+        Self::new(Vec5::default())
+    }
+}
+
 impl IIR {
-    /// Coefficient fixed point format: signed Q2.30.
+    /// Default coefficient fixed point format: signed Q2.30.
     /// Tailored to low-passes, PI, II etc.
     pub const SHIFT: u32 = 30;
 
+    /// Create a new filter with coefficients in the default format `SHIFT`.
+    pub const fn new(ba: Vec5) -> Self {
+        Self {
+            ba,
+            shift: Self::SHIFT,
+            sat_count: 0,
+        }
+    }
+
+    /// Quantize floating point coefficients.
+    ///
+    /// The largest shift (up to 31) is chosen for which all coefficients fit.
+    /// Coefficients are rounded to nearest.
+    ///
+    /// # Arguments
+    /// * `ba` - Coefficients in the form [b0,b1,b2,-a1,-a2], see
+    ///   `dsp::iir::Vec5`.
+    ///
+    /// # Returns
+    /// The filter and the quantization error report.
+    pub fn from_float(
+        ba: &[f32; 5],
+    ) -> Result<(Self, QuantReport), &'static str> {
+        if !ba.iter().all(|c| c.is_finite()) {
+            return Err("non-finite coefficient");
+        }
+        let quantize =
+            |c: f32, shift: u32| libm::round(c as f64 * (1u64 << shift) as f64);
+        let fits = |shift| {
+            ba.iter().all(|c| {
+                let q = quantize(*c, shift);
+                q >= i32::MIN as f64 && q <= i32::MAX as f64
+            })
+        };
+        let mut shift = 31;
+        while !fits(shift) {
+            if shift == 1 {
+                return Err("coefficient overflow");
+            }
+            shift -= 1;
+        }
+        let scale = (1u64 << shift) as f64;
+        let mut iir = Self {
+            ba: Vec5::default(),
+            shift,
+            sat_count: 0,
+        };
+        let mut report = QuantReport::default();
+        let mut max_error = 0f64;
+        for (q, c) in iir.ba.0.iter_mut().zip(ba.iter()) {
+            *q = quantize(*c, shift) as i32;
+            max_error =
+                max_error.max(libm::fabs(*q as f64 / scale - *c as f64));
+        }
+        report.max_error = max_error as f32;
+        // `ba` contains the negated feed-back coefficients.
+        let p = iir::quadratic_roots(-ba[3] as f64, -ba[4] as f64);
+        let q = iir::quadratic_roots(
+            -iir.ba.0[3] as f64 / scale,
+            -iir.ba.0[4] as f64 / scale,
+        );
+        let d = |a: &Complex<f64>, b: &Complex<f64>| {
+            libm::hypot(a.0 - b.0, a.1 - b.1)
+        };
+        // Match the poles pairwise
+        let straight = d(&p[0], &q[0]).max(d(&p[1], &q[1]));
+        let crossed = d(&p[0], &q[1]).max(d(&p[1], &q[0]));
+        report.pole_displacement = straight.min(crossed) as f32;
+        Ok((iir, report))
+    }
+
+    /// Convert the coefficients to floating point, see `dsp::iir::Vec5`.
+    pub fn to_float(&self) -> iir::Vec5 {
+        let mut ba = iir::Vec5::default();
+        for (f, i) in ba.0.iter_mut().zip(self.ba.0.iter()) {
+            *f = (*i as f64 / (1u64 << self.shift) as f64) as f32;
+        }
+        ba
+    }
+
+    /// Classify the pole locations, see `dsp::iir::IIR::stability()`.
+    ///
+    /// The check is exact on the fixed point coefficients.
+    pub fn stability(&self) -> iir::Stability {
+        let one = 1i64 << self.shift;
+        // `ba` contains the negated feed-back coefficients.
+        let (a1, a2) = (-(self.ba.0[3] as i64), -(self.ba.0[4] as i64));
+        if a2.abs() > one || a1.abs() > one + a2 {
+            iir::Stability::Unstable
+        } else if a2.abs() == one || a1.abs() == one + a2 {
+            iir::Stability::Marginal
+        } else {
+            iir::Stability::Stable
+        }
+    }
+
+    /// Whether all poles are strictly inside the unit circle.
+    pub fn is_stable(&self) -> bool {
+        self.stability() == iir::Stability::Stable
+    }
+
+    /// Largest pole magnitude, see `dsp::iir::IIR::pole_radius()`.
+    pub fn pole_radius(&self) -> f32 {
+        let scale = (1u64 << self.shift) as f64;
+        iir::pole_radius(
+            self.ba.0[3] as f64 / scale,
+            self.ba.0[4] as f64 / scale,
+        )
+    }
+
+    /// Frequency response `H(exp(j*2*pi*f))`, see `dsp::iir::IIR::response()`.
+    ///
+    /// # Arguments
+    /// * `f` - Frequency in units of the sample rate.
+    pub fn response(&self, f: f32) -> Complex<f32> {
+        // Avoid the f32 coefficient rounding of `to_float()`
+        let mut ba = [0f64; 5];
+        for (d, s) in ba.iter_mut().zip(self.ba.0.iter()) {
+            *d = *s as f64 / (1u64 << self.shift) as f64;
+        }
+        iir::response(&ba, f)
+    }
+
     /// Feed a new input value into the filter, update the filter state, and
     /// return the new output. Only the state `xy` is modified.
     ///
+    /// Rounding is half up. The output wraps, see `update_saturating()`.
+    ///
     /// # Arguments
     /// * `xy` - Current filter state.
     /// * `x0` - New input.
@@ -82,22 +229,281 @@ impl IIR {
         // Store x0            x0 x1 x2 y1 y2
         xy.0[0] = x0;
         // Compute y0 by multiply-accumulate
-        let y0 = macc(0, &xy.0, &self.ba.0, IIR::SHIFT);
+        let y0 = macc_i32(0, &xy.0, &self.ba.0, self.shift);
         // Limit y0
         // let y0 = y0.max(self.y_min).min(self.y_max);
         // Store y0            x0 x1 y0 y1 y2
         xy.0[n / 2] = y0;
         y0
     }
+
+    /// Filter update like `update()` that also counts saturated outputs,
+    /// see `take_sat_count()`.
+    ///
+    /// # Arguments
+    /// * `xy` - Current filter state.
+    /// * `x0` - New input.
+    pub fn update_saturating(&mut self, xy: &mut Vec5, x0: i32) -> i32 {
+        xy.0.copy_within(0..4, 1);
+        xy.0[0] = x0;
+        let acc =
+            xy.0.iter().zip(self.ba.0.iter()).fold(0i64, |y, (x, a)| {
+                y.saturating_add(*x as i64 * *a as i64)
+            });
+        let y0 = round_shift(acc, self.shift, Rounding::HalfUp);
+        let y0 = if y0 > i32::MAX as i64 {
+            self.sat_count = self.sat_count.saturating_add(1);
+            i32::MAX
+        } else if y0 < i32::MIN as i64 {
+            self.sat_count = self.sat_count.saturating_add(1);
+            i32::MIN
+        } else {
+            y0 as i32
+        };
+        xy.0[2] = y0;
+        y0
+    }
+
+    /// Read and clear the saturation counter.
+    ///
+    /// The counter itself saturates at `u16::MAX`.
+    pub fn take_sat_count(&mut self) -> u16 {
+        let count = self.sat_count;
+        self.sat_count = 0;
+        count
+    }
+
+    /// Filter a batch of samples.
+    ///
+    /// The state is kept in locals for the duration of the batch. The outputs
+    /// and the final state are identical to repeated calls to `update()`.
+    ///
+    /// # Arguments
+    /// * `xy` - Current filter state.
+    /// * `x` - Input samples.
+    /// * `y` - Output samples. Must have the same length as `x`.
+    pub fn update_block(&self, xy: &mut Vec5, x: &[i32], y: &mut [i32]) {
+        debug_assert_eq!(x.len(), y.len());
+        let [b0, b1, b2, a1, a2] = self.ba.0;
+        let shift = self.shift;
+        let [mut x1, mut x2, mut y1, mut y2, mut y3] = xy.0;
+        for (x0, y0) in x.iter().zip(y.iter_mut()) {
+            let x0 = *x0;
+            // Rounding bias, half up, see `macc_i32()`
+            let acc = (1i64 << (shift - 1))
+                + x0 as i64 * b0 as i64
+                + x1 as i64 * b1 as i64
+                + x2 as i64 * b2 as i64
+                + y1 as i64 * a1 as i64
+                + y2 as i64 * a2 as i64;
+            let yi = (acc >> shift) as i32;
+            x2 = x1;
+            x1 = x0;
+            y3 = y2;
+            y2 = y1;
+            y1 = yi;
+            *y0 = yi;
+        }
+        xy.0 = [x1, x2, y1, y2, y3];
+    }
+}
+
+impl Reset for Vec5 {
+    fn reset(&mut self) {
+        self.0 = [0; 5];
+    }
+}
+
+impl Reset for IIR {
+    /// Clear the saturation count.
+    fn reset(&mut self) {
+        self.sat_count = 0;
+    }
 }
 
 #[cfg(test)]
 mod test {
-    use super::Vec5;
+    use super::{iir, iir::Stability, Vec5, IIR};
+    use rand::{prelude::*, rngs::StdRng};
 
     #[test]
     fn lowpass_gen() {
         let ba = Vec5::lowpass(1e-5, 1. / 2f64.sqrt(), 2.);
         println!("{:?}", ba.0);
     }
+
+    #[test]
+    fn response() {
+        let iir = IIR::new(Vec5::lowpass(1e-3, 1. / 2f64.sqrt(), 2.));
+        // Exact DC gain of the quantized coefficients
+        let ba = iir.ba.0;
+        let dc = (ba[0] + ba[1] + ba[2]) as f64
+            / ((1 << IIR::SHIFT) - ba[3] - ba[4]) as f64;
+        assert!((dc - 2.).abs() < 1e-2);
+        let h = iir.response(0.);
+        assert!(
+            (h.0 as f64 / dc - 1.).abs() < 1e-6 && h.1.abs() < 1e-6,
+            "{:?}",
+            h
+        );
+        // -3 dB and -90 degrees at the corner
+        let h = iir.response(1e-3);
+        assert!(
+            h.0.abs() < 1e-2 && (h.1 + 2f32.sqrt()).abs() < 1e-2,
+            "{:?}",
+            h
+        );
+    }
+
+    #[test]
+    fn stability() {
+        let one = 1 << IIR::SHIFT;
+        let iir = IIR::new(Vec5::lowpass(1e-3, 1. / 2f64.sqrt(), 2.));
+        assert_eq!(iir.stability(), Stability::Stable);
+        assert!(iir.is_stable());
+        assert!(iir.pole_radius() > 0.99 && iir.pole_radius() < 1.);
+        // Integrator
+        let iir = IIR::new(Vec5([1, 1, 0, one, 0]));
+        assert_eq!(iir.stability(), Stability::Marginal);
+        assert!(!iir.is_stable());
+        assert_eq!(iir.pole_radius(), 1.);
+        // One LSB on either side of the integrator
+        let iir = IIR::new(Vec5([1, 1, 0, one - 1, 0]));
+        assert_eq!(iir.stability(), Stability::Stable);
+        let iir = IIR::new(Vec5([1, 1, 0, one + 1, 0]));
+        assert_eq!(iir.stability(), Stability::Unstable);
+        // Closest to a double integrator within the Q2.30 range: a complex
+        // pair on the unit circle
+        let iir = IIR::new(Vec5([1, 2, 1, i32::MAX, -one]));
+        assert_eq!(iir.stability(), Stability::Marginal);
+        let iir = IIR::new(Vec5([1, 0, 0, 0, -one - 1]));
+        assert_eq!(iir.stability(), Stability::Unstable);
+    }
+
+    #[test]
+    fn update_block() {
+        let mut rng = StdRng::seed_from_u64(0x2b9e);
+        for _ in 0..100 {
+            let iir = IIR::new(Vec5::lowpass(
+                rng.gen_range(1e-5..1e-2),
+                rng.gen_range(0.3..3.),
+                rng.gen_range(0.1..4.),
+            ));
+            let x: Vec<i32> = (0..rng.gen_range(0..100))
+                .map(|_| rng.gen::<i32>() >> 4)
+                .collect();
+            let mut y = vec![0; x.len()];
+            let mut xy0 = Vec5([0x1234, -0x5678, 0x9abc, 0xdef0, -0x1357]);
+            let mut xy1 = xy0;
+            iir.update_block(&mut xy0, &x, &mut y);
+            for (x, y) in x.iter().zip(y.iter()) {
+                assert_eq!(iir.update(&mut xy1, *x), *y);
+            }
+            assert_eq!(xy0.0, xy1.0);
+        }
+    }
+
+    #[test]
+    fn from_float_lowpass() {
+        let ba = iir::design::lowpass(0.1, 0.7);
+        let (iir, report) = IIR::from_float(&ba).unwrap();
+        assert_eq!(iir.shift, 30);
+        assert!(report.max_error <= 0.5 / (1 << 30) as f32, "{:?}", report);
+        assert!(report.pole_displacement < 1e-8, "{:?}", report);
+        let h = iir.response(0.05).0 - iir::Vec5(ba).response(0.05).0;
+        assert!(h.abs() < 1e-6);
+    }
+
+    #[test]
+    fn from_float_notch() {
+        // Sharp low frequency notch with 60 dB gain
+        let mut ba = iir::design::notch(1e-4, 10.);
+        for b in ba[..3].iter_mut() {
+            *b *= 1000.;
+        }
+        let (iir, report) = IIR::from_float(&ba).unwrap();
+        // |b1| = 2000 needs twelve integer bits
+        assert_eq!(iir.shift, 20);
+        assert!(report.max_error > 1e-7, "{:?}", report);
+        // Poles close to z = 1 are sensitive to the coefficients
+        assert!(report.pole_displacement > 1e-4, "{:?}", report);
+        // and the quantized ones end up on the unit circle.
+        assert!(iir::IIR {
+            ba: iir::Vec5(ba),
+            ..Default::default()
+        }
+        .is_stable());
+        assert!(!iir.is_stable());
+    }
+
+    #[test]
+    fn from_float_checks() {
+        assert!(IIR::from_float(&[1., 0., 0., f32::NAN, 0.]).is_err());
+        assert!(
+            IIR::from_float(&[1.1 * (1 << 30) as f32, 0., 0., 0., 0.]).is_err()
+        );
+        let (iir, _) = IIR::from_float(&[0.5, 0., 0., -0.25, 0.]).unwrap();
+        assert_eq!(iir.shift, 31);
+        assert_eq!(iir.ba.0, [1 << 30, 0, 0, -(1 << 29), 0]);
+        let mut xy = Vec5::default();
+        assert_eq!(iir.update(&mut xy, 1000), 500);
+    }
+
+    #[test]
+    fn wrapping() {
+        // Reference outputs of the wrapping update
+        let iir = IIR::new(Vec5([i32::MAX, i32::MAX, 0, 1 << 29, 0]));
+        let vectors = [
+            (1 << 28, 536870912),
+            (1 << 28, 1342177280),
+            (1 << 29, -2013265921),
+            (1 << 30, -2080374786),
+            (-1 << 30, -1040187393),
+            (-1 << 30, -520093694),
+            (0, 1887436802),
+            (123456789, 1190631979),
+        ];
+        let mut xy = Vec5::default();
+        for (x, y) in vectors.iter() {
+            assert_eq!(iir.update(&mut xy, *x), *y);
+        }
+        let x: Vec<i32> = vectors.iter().map(|v| v.0).collect();
+        let mut y = vec![0; x.len()];
+        iir.update_block(&mut Vec5::default(), &x, &mut y);
+        assert!(y.iter().zip(vectors.iter()).all(|(y, v)| *y == v.1));
+    }
+
+    #[test]
+    fn saturation() {
+        let (mut iir, _) = IIR::from_float(&[1e3, 0., 0., 0., 0.]).unwrap();
+        let mut xy = Vec5::default();
+        for _ in 0..10 {
+            assert_eq!(iir.update_saturating(&mut xy, 1 << 22), i32::MAX);
+        }
+        assert_eq!(iir.update_saturating(&mut xy, -1 << 22), i32::MIN);
+        assert_eq!(iir.update_saturating(&mut xy, 1 << 10), 1_024_000);
+        assert_eq!(iir.take_sat_count(), 11);
+        assert_eq!(iir.take_sat_count(), 0);
+        // Feed-back of a railed output
+        let mut iir = IIR::new(Vec5([1 << 30, 0, 0, 1 << 30, 0]));
+        let mut xy = Vec5::default();
+        for _ in 0..(1 << 16) + 10 {
+            iir.update_saturating(&mut xy, i32::MAX);
+        }
+        assert_eq!(iir.take_sat_count(), u16::MAX);
+    }
+
+    #[test]
+    fn saturation_in_range() {
+        let mut iir = IIR::new(Vec5::lowpass(1e-3, 1. / 2f64.sqrt(), 1.));
+        let mut xy0 = Vec5::default();
+        let mut xy1 = Vec5::default();
+        let mut rng = StdRng::seed_from_u64(0x7a3c);
+        for _ in 0..1000 {
+            let x = rng.gen::<i32>() >> 2;
+            let y = iir.update(&mut xy1, x);
+            assert_eq!(iir.update_saturating(&mut xy0, x), y);
+        }
+        assert_eq!(iir.take_sat_count(), 0);
+    }
 }
@@ -0,0 +1,164 @@
+use super::Reset;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Integrator with optional leak and hold.
+///
+/// `y[n] = y[n-1] + x[n]*2**-gain_shift - y[n-1]*2**-leak_shift`
+///
+/// The state carries `gain_shift` fractional bits in an `i64`. It saturates at
+/// the `i32` output range so that the output comes off the rail as soon as the
+/// input changes sign (no windup). While `hold` is asserted the output is
+/// frozen and neither the input nor the leak are applied.
+#[derive(Copy, Clone, Default, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Integrator {
+    /// Log2 of the integration time constant in samples, 0..=31.
+    pub gain_shift: u32,
+    /// Log2 of the leak time constant in samples, 1..=31, `None` for no leak.
+    pub leak_shift: Option<u32>,
+    // Output with `gain_shift` fractional bits
+    #[cfg_attr(feature = "serde", serde(skip))]
+    y: i64,
+}
+
+impl Integrator {
+    /// Create a new integrator.
+    ///
+    /// # Args
+    /// * `gain_shift`: Log2 integration time constant.
+    /// * `leak_shift`: Log2 leak time constant or `None`.
+    pub fn new(gain_shift: u32, leak_shift: Option<u32>) -> Self {
+        debug_assert!(gain_shift <= 31);
+        debug_assert!((1..=31).contains(&leak_shift.unwrap_or(1)));
+        Self {
+            gain_shift,
+            leak_shift,
+            y: 0,
+        }
+    }
+
+    /// Current output.
+    pub fn get(&self) -> i32 {
+        let k = self.gain_shift;
+        if k == 0 {
+            self.y as i32
+        } else {
+            // Rounding half up does not exceed `i32::MAX` since the state is
+            // limited to `i32::MAX << k`.
+            ((self.y + (1 << (k - 1))) >> k) as i32
+        }
+    }
+
+    /// Preset the output.
+    ///
+    /// This allows bumpless changes to the loop configuration.
+    pub fn set(&mut self, y: i32) {
+        self.y = (y as i64) << self.gain_shift;
+    }
+
+    /// Update the integrator with a new sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    /// * `hold`: Freeze the output.
+    ///
+    /// # Returns
+    /// Integrator output, saturating.
+    pub fn update(&mut self, x: i32, hold: bool) -> i32 {
+        if !hold {
+            let mut y = self.y + x as i64;
+            if let Some(k) = self.leak_shift {
+                // Rounding bias, half up
+                y -= (self.y + (1 << (k - 1))) >> k;
+            }
+            let k = self.gain_shift;
+            self.y = y.max((i32::MIN as i64) << k).min((i32::MAX as i64) << k);
+        }
+        self.get()
+    }
+}
+
+impl Reset for Integrator {
+    fn reset(&mut self) {
+        self.y = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ramp() {
+        let mut i = Integrator::new(4, None);
+        for n in 1..100 {
+            assert_eq!(i.update(16, false), n);
+        }
+        i.set(-5);
+        assert_eq!(i.get(), -5);
+        assert_eq!(i.update(-8, false), -5);
+        assert_eq!(i.update(-8, false), -6);
+    }
+
+    #[test]
+    fn hold() {
+        let mut i = Integrator::new(3, Some(4));
+        for _ in 0..10 {
+            i.update(12345, false);
+        }
+        let y = i.get();
+        for &x in [i32::MAX, 0, i32::MIN].iter() {
+            for _ in 0..100 {
+                assert_eq!(i.update(x, true), y);
+            }
+        }
+        assert_ne!(i.update(1 << 10, false), y);
+    }
+
+    #[test]
+    fn leak() {
+        let k = 8;
+        let mut i = Integrator::new(0, Some(k));
+        let y0 = 1 << 30;
+        i.set(y0);
+        for _ in 0..1 << k {
+            i.update(0, false);
+        }
+        // (1 - 2**-k)**(2**k) ~ 1/e
+        let e = (1. - 1. / (1 << k) as f64).powi(1 << k);
+        let g = i.get() as f64 / y0 as f64;
+        assert!((g - e).abs() < 1e-6, "{} {}", g, e);
+        // Half up rounding of the leak leaves a dead band
+        for _ in 0..40 << k {
+            i.update(0, false);
+        }
+        assert!(i.get().abs() <= 1 << (k - 1));
+        // Leak and input balance at `x << leak_shift`.
+        for _ in 0..40 << k {
+            i.update(1 << 10, false);
+        }
+        assert!((i.get() - (1 << (10 + k))).abs() <= 1 << (k - 1));
+    }
+
+    #[test]
+    fn saturate() {
+        for &k in [0, 1, 16, 31].iter() {
+            let mut i = Integrator::new(k, None);
+            for _ in 0..10 {
+                i.set(i32::MAX - 1);
+                assert_eq!(i.update(i32::MAX, false), i32::MAX);
+            }
+            for _ in 0..3 {
+                assert_eq!(i.update(i32::MAX, false), i32::MAX);
+            }
+            // Comes off the rail immediately
+            assert!(i.update(-1 << k, false) < i32::MAX);
+            i.set(i32::MIN);
+            for _ in 0..3 {
+                assert_eq!(i.update(i32::MIN, false), i32::MIN);
+            }
+            assert!(i.update(1 << k.min(30), false) > i32::MIN);
+        }
+    }
+}
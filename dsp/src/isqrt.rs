@@ -0,0 +1,80 @@
+/// Integer square root.
+///
+/// This is the bitwise (digit-by-digit) method. It uses no division and the
+/// inner loop is branch-free. There are at most 32 iterations.
+///
+/// # Arguments
+///
+/// * `x` - Radicand.
+///
+/// # Returns
+///
+/// The floor of the square root of `x`. This is exact for all inputs.
+pub fn isqrt(x: u64) -> u32 {
+    if x == 0 {
+        return 0;
+    }
+    // Highest power of four not larger than x
+    let mut b = 1u64 << ((63 - x.leading_zeros()) & !1);
+    let mut x = x;
+    let mut r = 0u64;
+    while b != 0 {
+        let t = r + b;
+        // All ones if the next root bit is set
+        let m = ((x >= t) as u64).wrapping_neg();
+        x -= t & m;
+        r = (r >> 1) + (b & m);
+        b >>= 2;
+    }
+    r as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{prelude::*, rngs::StdRng};
+
+    fn check(x: u64) {
+        let r = isqrt(x) as u64;
+        assert!(r * r <= x, "isqrt({}) = {}", x, r);
+        assert!((r as u128 + 1).pow(2) > x as u128, "isqrt({}) = {}", x, r);
+    }
+
+    #[test]
+    fn small() {
+        for x in 0..1 << 20 {
+            check(x);
+        }
+    }
+
+    #[test]
+    fn squares() {
+        let mut n = 1u64;
+        while n <= u32::MAX as u64 {
+            for m in [n - 1, n, n + 1].iter() {
+                let s = m * m;
+                assert_eq!(isqrt(s) as u64, *m);
+                check(s.saturating_sub(1));
+                check(s + 1);
+            }
+            n = n * 3 / 2 + 1;
+        }
+        assert_eq!(isqrt(u64::MAX), u32::MAX);
+        assert_eq!(isqrt(1 << 63), 3_037_000_499);
+        let m = u32::MAX as u64;
+        assert_eq!(isqrt(m * m), u32::MAX);
+        assert_eq!(isqrt(m * m - 1), u32::MAX - 1);
+    }
+
+    #[test]
+    fn random() {
+        let mut rng = StdRng::seed_from_u64(42);
+        for _ in 0..1 << 16 {
+            let x = rng.gen::<u64>() >> rng.gen_range(0..64);
+            let r = isqrt(x);
+            let want = (x as f64).sqrt();
+            assert!((r as f64 - want).abs() <= 1., "{} {} {}", x, r, want);
+            check(x);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Period and jitter statistics of edge timestamps.
+use super::Reset;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Statistics of the intervals between consecutive edges.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Jitter {
+    /// Number of intervals.
+    pub count: u32,
+    /// Mean period in timer ticks.
+    pub mean: f32,
+    /// Sample standard deviation of the period in timer ticks. Zero for
+    /// fewer than two intervals.
+    pub std: f32,
+    /// Difference between the longest and the shortest period in timer
+    /// ticks.
+    pub peak_to_peak: u32,
+}
+
+/// Period statistics accumulator.
+///
+/// The timestamps are from a free running, wrapping `u32` timer. The periods
+/// must be shorter than the timer period. To reduce the dynamic range, the
+/// sums of the periods and their squares are accumulated in `i64` relative
+/// to the first period after a reset and saturate.
+#[derive(Copy, Clone, Debug)]
+pub struct Stats {
+    last: Option<u32>,
+    offset: u32,
+    count: u32,
+    sum: i64,
+    sum2: i64,
+    min: u32,
+    max: u32,
+}
+
+impl Stats {
+    /// Create a new, empty accumulator.
+    pub const fn new() -> Self {
+        Self {
+            last: None,
+            offset: 0,
+            count: 0,
+            sum: 0,
+            sum2: 0,
+            min: u32::MAX,
+            max: 0,
+        }
+    }
+
+    /// Number of intervals accumulated since the last reset.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Ingest an edge timestamp.
+    ///
+    /// # Args
+    /// * `timestamp`: Timer value (wrapping) of the edge.
+    pub fn update(&mut self, timestamp: u32) {
+        if let Some(last) = self.last {
+            let period = timestamp.wrapping_sub(last);
+            if self.count == 0 {
+                self.offset = period;
+            }
+            let d = period as i64 - self.offset as i64;
+            self.sum = self.sum.saturating_add(d);
+            self.sum2 = self.sum2.saturating_add(d.saturating_mul(d));
+            self.min = self.min.min(period);
+            self.max = self.max.max(period);
+            self.count += 1;
+        }
+        self.last = Some(timestamp);
+    }
+
+    /// Return the statistics and restart the accumulation.
+    ///
+    /// The last timestamp is kept: the next interval starts at the last
+    /// edge.
+    pub fn read_and_reset(&mut self) -> Jitter {
+        let mut jitter = Jitter {
+            count: self.count,
+            ..Default::default()
+        };
+        if self.count > 0 {
+            let n = self.count as f64;
+            let mean = self.sum as f64 / n;
+            jitter.mean = (self.offset as f64 + mean) as f32;
+            jitter.peak_to_peak = self.max - self.min;
+            if self.count > 1 {
+                let var =
+                    (self.sum2 as f64 - mean * self.sum as f64) / (n - 1.);
+                jitter.std = libm::sqrt(var.max(0.)) as f32;
+            }
+        }
+        *self = Self {
+            last: self.last,
+            ..Self::new()
+        };
+        jitter
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reset for Stats {
+    /// Clear the statistics and forget the last timestamp: the next interval
+    /// starts at the next edge.
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    // Feed `n` edges with a mean period and Gaussian period jitter,
+    // starting at `t0`.
+    fn feed(stats: &mut Stats, t0: u32, period: u32, sigma: i32, n: usize) {
+        let mut rng = Xoshiro::new(1);
+        let mut t = t0;
+        for _ in 0..n {
+            stats.update(t);
+            t = t
+                .wrapping_add(period)
+                .wrapping_add(rng.gaussian_i32(sigma) as u32);
+        }
+    }
+
+    #[test]
+    fn gaussian() {
+        for &sigma in [10, 100, 1000].iter() {
+            let mut stats = Stats::new();
+            feed(&mut stats, 0, 100_000, sigma, 10_000);
+            let j = stats.read_and_reset();
+            assert_eq!(j.count, 9_999);
+            assert!((j.mean - 100_000.).abs() < 0.1 * sigma as f32, "{:?}", j);
+            assert!((j.std / sigma as f32 - 1.).abs() < 3e-2, "{:?}", j);
+            // The tails are truncated at 3.46 sigma
+            assert!(j.peak_to_peak as f32 <= 7. * sigma as f32, "{:?}", j);
+            assert!(j.peak_to_peak as f32 >= 5. * sigma as f32, "{:?}", j);
+        }
+    }
+
+    #[test]
+    fn wrap() {
+        let mut stats = Stats::new();
+        // Straddle the 32 bit timer boundary
+        feed(&mut stats, u32::MAX - 50_000, 1000, 0, 100);
+        let j = stats.read_and_reset();
+        assert_eq!(
+            j,
+            Jitter {
+                count: 99,
+                mean: 1000.,
+                std: 0.,
+                peak_to_peak: 0,
+            }
+        );
+        let mut stats = Stats::new();
+        feed(&mut stats, u32::MAX - 500_000, 1000, 10, 1000);
+        let j = stats.read_and_reset();
+        assert!((j.mean - 1000.).abs() < 1., "{:?}", j);
+        assert!((j.std / 10. - 1.).abs() < 0.1, "{:?}", j);
+    }
+
+    #[test]
+    fn reset() {
+        let mut stats = Stats::new();
+        assert_eq!(stats.read_and_reset(), Jitter::default());
+        stats.update(10);
+        assert_eq!(stats.read_and_reset(), Jitter::default());
+        stats.update(30);
+        assert_eq!(stats.count(), 1);
+        let j = stats.read_and_reset();
+        assert_eq!((j.count, j.mean, j.std), (1, 20., 0.));
+        // The interval chain continues across the reset
+        stats.update(60);
+        stats.update(80);
+        let j = stats.read_and_reset();
+        assert_eq!((j.count, j.mean, j.peak_to_peak), (2, 25., 10));
+        assert!((j.std - 50f32.sqrt()).abs() < 1e-5);
+        // Unlike `read_and_reset()`, `reset()` also drops the last edge
+        stats.update(100);
+        stats.reset();
+        stats.update(130);
+        assert_eq!(stats.count(), 0);
+        stats.update(140);
+        let j = stats.read_and_reset();
+        assert_eq!((j.count, j.mean, j.peak_to_peak), (1, 10., 0));
+    }
+}
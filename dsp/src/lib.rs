@@ -1,37 +1,13 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "bench")), no_std)]
 #![cfg_attr(feature = "nightly", feature(asm, core_intrinsics))]
 
-use core::ops::{Add, Mul, Neg};
-
-fn abs<T>(x: T) -> T
-where
-    T: PartialOrd + Default + Neg<Output = T>,
-{
-    if x >= T::default() {
-        x
-    } else {
-        -x
-    }
-}
+use core::ops::{Add, Mul};
 
 // These are implemented here because core::f32 doesn't have them (yet).
-// They are naive and don't handle inf/nan.
+// They are naive and don't handle nan.
 // `compiler-intrinsics`/llvm should have better (robust, universal, and
 // faster) implementations.
 
-fn copysign<T>(x: T, y: T) -> T
-where
-    T: PartialOrd + Default + Neg<Output = T>,
-{
-    if (x >= T::default() && y >= T::default())
-        || (x <= T::default() && y <= T::default())
-    {
-        x
-    } else {
-        -x
-    }
-}
-
 #[cfg(not(feature = "nightly"))]
 fn max<T>(x: T, y: T) -> T
 where
@@ -80,22 +56,333 @@ where
         .fold(y0, |y, xa| y + xa)
 }
 
+// Fixed-point multiply-accumulate vectors `x` and `a` with an `i64`
+// accumulator.
+//
+// `shift` is the number of fractional bits in the coefficients `a`.
+// Rounding is half up.
+fn macc_i32(y0: i32, x: &[i32], a: &[i32], shift: u32) -> i32 {
+    // Rounding bias, half up
+    let y0 = ((y0 as i64) << shift) + (1 << (shift - 1));
+    let y = x
+        .iter()
+        .zip(a)
+        .map(|(x, a)| *x as i64 * *a as i64)
+        .fold(y0, |y, xa| y + xa);
+    (y >> shift) as i32
+}
+
+/// Multiply-accumulate `i16` vectors `x` and `a` with an `i64`
+/// accumulator.
+///
+/// Pairs of samples are processed with a dual 16 bit multiply-accumulate.
+/// On `thumbv7em` with the `nightly` feature this is the `SMLALD`
+/// instruction. Otherwise a portable implementation is used. The result is
+/// exact and identical in both cases.
+///
+/// # Args
+/// * `y0`: Initial accumulator value.
+/// * `x`: Samples. Must have the same length as `a`.
+/// * `a`: Coefficients.
+pub fn macc_i16(y0: i64, x: &[i16], a: &[i16]) -> i64 {
+    debug_assert_eq!(x.len(), a.len());
+    let xs = x.chunks_exact(2);
+    let ac = a.chunks_exact(2);
+    let rest = xs
+        .remainder()
+        .iter()
+        .zip(ac.remainder())
+        .fold(0, |y, (x, a)| y + *x as i64 * *a as i64);
+    // Pack two samples into one word, low half first
+    let pack = |v: &[i16]| (v[0] as u16 as u32) | ((v[1] as u16 as u32) << 16);
+    xs.zip(ac).fold(y0, |y, (x, a)| smlald(y, pack(x), pack(a))) + rest
+}
+
+// Dual signed 16 bit multiply with 64 bit accumulate.
+#[cfg(all(feature = "nightly", target_arch = "arm"))]
+#[inline(always)]
+fn smlald(y: i64, x: u32, a: u32) -> i64 {
+    let (mut lo, mut hi) = (y as u32, (y >> 32) as u32);
+    // Note(unsafe): Register only arithmetic.
+    unsafe {
+        asm!(
+            "smlald {lo}, {hi}, {x}, {a}",
+            lo = inout(reg) lo,
+            hi = inout(reg) hi,
+            x = in(reg) x,
+            a = in(reg) a,
+            options(pure, nomem, nostack),
+        );
+    }
+    (((hi as u64) << 32) | lo as u64) as i64
+}
+
+// Dual signed 16 bit multiply with 64 bit accumulate.
+#[cfg(not(all(feature = "nightly", target_arch = "arm")))]
+#[inline(always)]
+fn smlald(y: i64, x: u32, a: u32) -> i64 {
+    y + (x as i16 as i64) * (a as i16 as i64)
+        + ((x >> 16) as i16 as i64) * ((a >> 16) as i16 as i64)
+}
+
+/// Rounding mode of a fixed point right shift.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Rounding {
+    /// Round toward negative infinity (arithmetic shift).
+    Truncate,
+    /// Round to nearest, ties toward positive infinity.
+    HalfUp,
+    /// Round to nearest, ties to even.
+    NearestEven,
+    /// Round to nearest, ties away from zero.
+    NearestAway,
+}
+
+/// Shift and round a wide accumulator.
+///
+/// # Args
+/// * `acc`: Accumulator.
+/// * `shift`: Number of bits to shift right, 0..=63.
+/// * `rounding`: Rounding mode.
+fn round_shift(acc: i64, shift: u32, rounding: Rounding) -> i64 {
+    debug_assert!(shift < 64);
+    if shift == 0 {
+        return acc;
+    }
+    let floor = acc >> shift;
+    let rem = acc as u64 & ((1u64 << shift) - 1);
+    let half = 1u64 << (shift - 1);
+    let up = match rounding {
+        Rounding::Truncate => false,
+        Rounding::HalfUp => rem >= half,
+        Rounding::NearestEven => rem > half || (rem == half && floor & 1 != 0),
+        Rounding::NearestAway => rem > half || (rem == half && floor >= 0),
+    };
+    // `floor < i64::MAX` since `shift > 0`
+    floor + up as i64
+}
+
+/// Shift, round, and saturate a wide accumulator to `i32`.
+///
+/// # Args
+/// * `acc`: Accumulator, e.g. a sum of `i32` products.
+/// * `shift`: Number of fractional bits of `acc` to remove, 0..=63. This is
+///   only checked in debug builds, see `checked_scale_i64()`.
+/// * `rounding`: Rounding mode.
+pub fn saturating_scale_i64(acc: i64, shift: u32, rounding: Rounding) -> i32 {
+    let y = round_shift(acc, shift, rounding);
+    y.max(i32::MIN as i64).min(i32::MAX as i64) as i32
+}
+
+/// Shift, round, and saturate a wide accumulator to `i32` with a checked
+/// shift.
+///
+/// Like `saturating_scale_i64()` but returns `Error::ShiftRange` for a shift
+/// larger than 63.
+pub fn checked_scale_i64(
+    acc: i64,
+    shift: u32,
+    rounding: Rounding,
+) -> Result<i32, Error> {
+    if shift > 63 {
+        return Err(Error::ShiftRange);
+    }
+    Ok(saturating_scale_i64(acc, shift, rounding))
+}
+
+/// Multiply a Q1.31 sample by a Q1.31 factor.
+///
+/// Rounding is half up. The result saturates at `i32::MAX`
+/// (only for `i32::MIN * i32::MIN`).
+pub fn saturating_scale(x: i32, y: i32) -> i32 {
+    saturating_scale_i64(x as i64 * y as i64, 31, Rounding::HalfUp)
+}
+
 pub mod accu;
+pub mod agc;
+pub mod allan;
+pub mod alpha_beta;
 mod atan2;
+pub mod boxcar;
+pub mod cic;
+pub mod clamp;
+pub mod coherent;
 mod complex;
+pub mod cordic;
 mod cossin;
+pub mod dcblock;
+pub mod decimate;
+mod error;
+pub mod fft;
+pub mod filter;
+pub mod fir;
+pub mod fir_int;
+pub mod freq_counter;
+pub mod goertzel;
+pub mod halfband;
+pub mod histogram;
 pub mod iir;
 pub mod iir_int;
+pub mod integrator;
+mod isqrt;
+pub mod jitter;
 pub mod lockin;
+mod log2;
 pub mod lowpass;
+pub mod math;
+pub mod median;
+pub mod minmax;
+pub mod noise;
+pub mod peak;
+pub mod phase_meter;
 pub mod pll;
+pub mod prbs;
+mod q;
+pub mod requantize;
+mod reset;
+pub mod rms;
 pub mod rpll;
+pub mod scale;
+pub mod signal_generator;
+pub mod slew;
+pub mod swap;
+pub mod sweep;
+pub mod thd;
 pub mod unwrap;
+pub mod window;
+pub mod xcorr;
 
 pub use accu::Accu;
-pub use atan2::atan2;
+pub use atan2::{atan2, atan2_precise};
 pub use complex::Complex;
-pub use cossin::cossin;
+pub use cossin::{
+    cossin, cossin_complex, cossin_f32, cossin_q31, COSSIN_AMPLITUDE_ERROR,
+    COSSIN_MAX_ERROR, COSSIN_PHASE_ERROR, COSSIN_RMS_ERROR,
+};
+pub use error::Error;
+pub use filter::{Chain, Filter, FilterF32};
+pub use isqrt::isqrt;
+pub use log2::{exp2_q, from_db, log2_q, to_db};
+pub use q::{Q15, Q31};
+pub use reset::Reset;
 
-#[cfg(test)]
+#[cfg(any(test, feature = "testing"))]
 pub mod testing;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{prelude::*, rngs::StdRng};
+
+    const ROUNDINGS: [Rounding; 4] = [
+        Rounding::Truncate,
+        Rounding::HalfUp,
+        Rounding::NearestEven,
+        Rounding::NearestAway,
+    ];
+
+    #[test]
+    fn macc_i16_exact() {
+        let mut rng = StdRng::seed_from_u64(0x16);
+        for n in 0..40 {
+            for _ in 0..10 {
+                let x: Vec<i16> = (0..n).map(|_| rng.gen()).collect();
+                let a: Vec<i16> = (0..n).map(|_| rng.gen()).collect();
+                let y0 = rng.gen::<i32>() as i64;
+                let want = macc(
+                    y0,
+                    &x.iter().map(|x| *x as i64).collect::<Vec<_>>(),
+                    &a.iter().map(|a| *a as i64).collect::<Vec<_>>(),
+                );
+                assert_eq!(macc_i16(y0, &x, &a), want, "{}", n);
+            }
+        }
+        // Full scale
+        let x = [i16::MIN; 7];
+        assert_eq!(macc_i16(-1, &x, &x), 7 * (1 << 30) - 1);
+        let a = [i16::MAX; 7];
+        assert_eq!(macc_i16(0, &x, &a), -7 * (1 << 15) * i16::MAX as i64);
+    }
+
+    #[test]
+    fn rounding_ties() {
+        // x.5 in Q.31 for x in -3..=2
+        let ties = [-5i64, -3, -1, 1, 3, 5];
+        let expect = |r, y: [i32; 6]| {
+            for (t, y) in ties.iter().zip(y.iter()) {
+                assert_eq!(saturating_scale_i64(t << 30, 31, r), *y, "{:?}", r);
+            }
+        };
+        expect(Rounding::Truncate, [-3, -2, -1, 0, 1, 2]);
+        expect(Rounding::HalfUp, [-2, -1, 0, 1, 2, 3]);
+        expect(Rounding::NearestEven, [-2, -2, 0, 0, 2, 2]);
+        expect(Rounding::NearestAway, [-3, -2, -1, 1, 2, 3]);
+    }
+
+    #[test]
+    fn rounding_edges() {
+        for &r in ROUNDINGS.iter() {
+            assert_eq!(saturating_scale_i64(i64::MAX, 0, r), i32::MAX);
+            assert_eq!(saturating_scale_i64(i64::MIN, 0, r), i32::MIN);
+            assert_eq!(saturating_scale_i64(-7, 0, r), -7);
+            assert_eq!(saturating_scale_i64(i64::MAX, 31, r), i32::MAX);
+            assert_eq!(saturating_scale_i64(i64::MIN, 31, r), i32::MIN);
+            let one = (r != Rounding::Truncate) as i32;
+            assert_eq!(saturating_scale_i64(i64::MAX, 63, r), one);
+            assert_eq!(saturating_scale_i64(i64::MIN, 63, r), -1);
+            let max = (i32::MAX as i64) << 31;
+            assert_eq!(saturating_scale_i64(max, 31, r), i32::MAX);
+            assert_eq!(saturating_scale_i64(max + (1 << 30), 31, r), i32::MAX);
+            let min = (i32::MIN as i64) << 31;
+            assert_eq!(saturating_scale_i64(min, 31, r), i32::MIN);
+            assert_eq!(saturating_scale_i64(min - 1, 31, r), i32::MIN);
+        }
+        assert_eq!(saturating_scale(i32::MIN, i32::MIN), i32::MAX);
+        assert_eq!(saturating_scale(i32::MIN, i32::MAX), -i32::MAX);
+    }
+
+    #[test]
+    fn rounding_bias() {
+        // Accumulated rounding error over an exhaustive, symmetric range of
+        // inputs.
+        let bias = |r| {
+            (-1i64 << 16..1 << 16)
+                .map(|x| ((saturating_scale_i64(x, 4, r) as i64) << 4) - x)
+                .sum::<i64>()
+        };
+        assert_eq!(bias(Rounding::Truncate), -(15 << 16));
+        assert_eq!(bias(Rounding::HalfUp), 1 << 16);
+        assert_eq!(bias(Rounding::NearestEven), 0);
+        assert_eq!(bias(Rounding::NearestAway), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "defmt")]
+    fn defmt_format() {
+        fn format<T: defmt::Format>() {}
+        format::<iir::IIR>();
+        format::<iir_int::IIR>();
+        format::<Complex<i32>>();
+        format::<Complex<f32>>();
+        format::<pll::PLL>();
+        format::<pll::Config>();
+        format::<rpll::RPLL>();
+    }
+
+    #[test]
+    fn checked_scale() {
+        for &r in ROUNDINGS.iter() {
+            for shift in 0..64 {
+                let acc = -0x1234_5678_9abc_def0;
+                let y = saturating_scale_i64(acc, shift, r);
+                assert_eq!(checked_scale_i64(acc, shift, r), Ok(y));
+            }
+            for &shift in [64, 65, u32::MAX].iter() {
+                assert_eq!(
+                    checked_scale_i64(1, shift, r),
+                    Err(Error::ShiftRange)
+                );
+            }
+        }
+    }
+}
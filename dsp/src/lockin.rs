@@ -1,12 +1,55 @@
-use super::{lowpass::Lowpass, Complex};
-use generic_array::typenum::U2;
+use super::{lowpass::Lowpass2, Accu, Complex, Reset, Q15, Q31};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Default)]
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Lockin {
-    state: [Lowpass<U2>; 2],
+    #[cfg_attr(feature = "serde", serde(skip))]
+    state: [Lowpass2; 2],
+    /// Harmonic of the reference to demodulate. Negative harmonics
+    /// demodulate the complex conjugate. The default is `-1`, i.e.
+    /// _de_modulation of the fundamental.
+    pub harmonic: i32,
+    /// Demodulation phase offset (`1 << 31` corresponds to pi).
+    pub phase_offset: i32,
+}
+
+impl Default for Lockin {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Lockin {
+    /// Create a new lockin demodulating the fundamental, with cleared state
+    /// and zero phase offset.
+    pub const fn new() -> Self {
+        Self {
+            state: [Lowpass2::new(); 2],
+            harmonic: -1,
+            phase_offset: 0,
+        }
+    }
+
+    /// Demodulation phase for a given reference phase:
+    /// `harmonic * reference_phase + phase_offset` (wrapping).
+    pub fn demodulation_phase(&self, reference_phase: i32) -> i32 {
+        self.phase_offset
+            .wrapping_add(reference_phase.wrapping_mul(self.harmonic))
+    }
+
+    /// Demodulation frequency for a given reference frequency:
+    /// `harmonic * reference_frequency` (wrapping).
+    pub fn demodulation_frequency(&self, reference_frequency: i32) -> i32 {
+        reference_frequency.wrapping_mul(self.harmonic)
+    }
+
+    /// Lowpass filter state of the in-phase and quadrature components.
+    pub fn state(&self) -> &[Lowpass2; 2] {
+        &self.state
+    }
+
     /// Update the lockin with a sample taken at a given phase.
     /// The lowpass has a gain of `1 << k`.
     pub fn update(&mut self, sample: i16, phase: i32, k: u8) -> Complex<i32> {
@@ -23,4 +66,306 @@ impl Lockin {
             self.state[1].update(mix.1, k),
         )
     }
+
+    /// Update the lockin with a `Q15` sample and return `Q31` IQ data.
+    ///
+    /// Same as `update()` with the lowpass gain of `1 << k` compensated. For
+    /// a sample `a*cos(phase + p)` the output settles to magnitude `a/2`.
+    ///
+    /// # Args
+    /// * `sample`: Input sample.
+    /// * `phase`: Demodulation phase.
+    /// * `k`: Log2 lowpass time constant, 0..=16.
+    pub fn update_q15(
+        &mut self,
+        sample: Q15,
+        phase: i32,
+        k: u8,
+    ) -> Complex<Q31> {
+        debug_assert!(k <= 16);
+        let iq = self.update(sample.0, phase, k);
+        Complex(Q31(iq.0 << (17 - k)), Q31(iq.1 << (17 - k)))
+    }
+
+    /// Demodulate and filter a batch of samples.
+    ///
+    /// This is the same as calling `update()` for each sample with the
+    /// demodulation phase advancing by `frequency` per sample.
+    ///
+    /// # Args
+    /// * `samples`: Input samples, e.g. an ADC DMA buffer.
+    /// * `phase`: Demodulation phase of the first sample.
+    /// * `frequency`: Demodulation phase increment per sample.
+    /// * `k`: Log2 lowpass time constant.
+    ///
+    /// # Return
+    /// Filtered IQ output after the last sample (zero for an empty batch),
+    /// with a gain of `1 << k`.
+    pub fn process_batch(
+        &mut self,
+        samples: &[i16],
+        phase: i32,
+        frequency: i32,
+        k: u8,
+    ) -> Complex<i32> {
+        let mut output = Complex(0, 0);
+        for (&sample, phase) in samples.iter().zip(Accu::new(phase, frequency))
+        {
+            output = self.update(sample, phase, k);
+        }
+        output
+    }
+
+    /// Update the lockin with a sample taken at a given reference phase.
+    /// The demodulation phase is derived from the reference phase
+    /// using `harmonic` and `phase_offset`.
+    pub fn update_reference(
+        &mut self,
+        sample: i16,
+        reference_phase: i32,
+        k: u8,
+    ) -> Complex<i32> {
+        let phase = self.demodulation_phase(reference_phase);
+        self.update(sample, phase, k)
+    }
+}
+
+/// Lockin demodulating several harmonics of one reference at once.
+///
+/// Each harmonic has its own `Lockin` (harmonic, phase offset, and lowpass
+/// state) while the reference phase and frequency as well as the lowpass
+/// time constant are shared.
+#[derive(Copy, Clone)]
+pub struct MultiLockin<const K: usize> {
+    pub lockins: [Lockin; K],
+}
+
+impl<const K: usize> MultiLockin<K> {
+    /// Create a new multi-harmonic lockin with cleared state and zero phase
+    /// offsets.
+    ///
+    /// # Args
+    /// * `harmonics`: Harmonic for each of the `K` outputs.
+    pub fn new(harmonics: [i32; K]) -> Self {
+        let mut lockins = [Lockin::default(); K];
+        for (lockin, &harmonic) in lockins.iter_mut().zip(harmonics.iter()) {
+            lockin.harmonic = harmonic;
+        }
+        Self { lockins }
+    }
+
+    /// Demodulate and filter a batch of samples.
+    ///
+    /// # Args
+    /// * `samples`: Input samples.
+    /// * `reference_phase`: Reference phase of the first sample.
+    /// * `reference_frequency`: Reference phase increment per sample.
+    /// * `k`: Log2 lowpass time constant, shared by all harmonics.
+    ///
+    /// # Return
+    /// Filtered IQ output for each harmonic after the last sample
+    /// (zero for an empty batch), with a gain of `1 << k`.
+    pub fn update(
+        &mut self,
+        samples: &[i16],
+        reference_phase: i32,
+        reference_frequency: i32,
+        k: u8,
+    ) -> [Complex<i32>; K] {
+        let mut output = [Complex(0, 0); K];
+        for (&sample, phase) in samples
+            .iter()
+            .zip(Accu::new(reference_phase, reference_frequency))
+        {
+            for (lockin, output) in
+                self.lockins.iter_mut().zip(output.iter_mut())
+            {
+                *output = lockin.update_reference(sample, phase, k);
+            }
+        }
+        output
+    }
+}
+
+impl Reset for Lockin {
+    fn reset(&mut self) {
+        self.state.reset();
+    }
+}
+
+impl<const K: usize> Reset for MultiLockin<K> {
+    fn reset(&mut self) {
+        self.lockins.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Demodulate a cosine at the 3rd harmonic of the reference and return
+    /// the magnitude of the filtered IQ output relative to the amplitude.
+    fn demodulate_third(harmonic: i32) -> f64 {
+        let mut lockin = Lockin {
+            harmonic,
+            ..Default::default()
+        };
+        let amplitude = 1 << 14;
+        let frequency = 1i32 << 26;
+        let k = 10;
+        let mut output = Complex(0, 0);
+        for n in 0..1 << 14 {
+            let reference_phase = frequency.wrapping_mul(n);
+            let phase = (reference_phase.wrapping_mul(3) as f64)
+                * core::f64::consts::PI
+                / (1u64 << 31) as f64;
+            let sample = (amplitude as f64 * phase.cos()).round() as i16;
+            output = lockin.update_reference(sample, reference_phase, k);
+        }
+        // The mixer gain is 1/2 and the lowpass gain is `1 << k`.
+        // Mixing a real signal yields another factor of 1/2.
+        let scale = (amplitude as f64) / 4. * (1 << k) as f64;
+        (output.0 as f64).hypot(output.1 as f64) / scale
+    }
+
+    #[test]
+    fn harmonic() {
+        let on = demodulate_third(3);
+        assert!((on - 1.).abs() < 1e-2, "{}", on);
+        let conjugate = demodulate_third(-3);
+        assert!((conjugate - 1.).abs() < 1e-2, "{}", conjugate);
+        let off = demodulate_third(1);
+        assert!(off < 1e-2, "{}", off);
+    }
+
+    /// Demodulate the 1st and 3rd harmonic of a two-tone input and return
+    /// the output magnitudes relative to `1 << 13` amplitude.
+    fn two_tone(amplitude: [i16; 2]) -> [f64; 2] {
+        let mut lockin = MultiLockin::new([-1, -3]);
+        let frequency = 1i32 << 26;
+        let k = 10;
+        let mut samples = [0; 8];
+        let mut output = [Complex(0, 0); 2];
+        for batch in 0..1 << 11 {
+            let reference_phase =
+                frequency.wrapping_mul(batch * samples.len() as i32);
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let phase = reference_phase
+                    .wrapping_add(frequency.wrapping_mul(i as i32))
+                    as f64
+                    * core::f64::consts::PI
+                    / (1u64 << 31) as f64;
+                *sample = (amplitude[0] as f64 * phase.cos()
+                    + amplitude[1] as f64 * (3. * phase + 1.).sin())
+                .round() as i16;
+            }
+            output = lockin.update(&samples, reference_phase, frequency, k);
+        }
+        let scale = (1 << 13) as f64 / 4. * (1 << k) as f64;
+        let mut have = [0.; 2];
+        for (have, output) in have.iter_mut().zip(output.iter()) {
+            *have = (output.0 as f64).hypot(output.1 as f64) / scale;
+        }
+        have
+    }
+
+    #[test]
+    fn multi_harmonic() {
+        let both = two_tone([1 << 13, 1 << 12]);
+        assert!((both[0] - 1.).abs() < 1e-2, "{:?}", both);
+        assert!((both[1] - 0.5).abs() < 1e-2, "{:?}", both);
+        // Cross-talk
+        let first = two_tone([1 << 13, 0]);
+        assert!(first[1] < 1e-2, "{:?}", first);
+        let third = two_tone([0, 1 << 13]);
+        assert!(third[0] < 1e-2, "{:?}", third);
+    }
+
+    #[test]
+    fn phase() {
+        let lockin = Lockin {
+            harmonic: 3,
+            phase_offset: 1 << 30,
+            ..Default::default()
+        };
+        assert_eq!(lockin.demodulation_phase(0), 1 << 30);
+        assert_eq!(lockin.demodulation_phase(1 << 30), 0);
+        assert_eq!(lockin.demodulation_frequency(1 << 30), -1 << 30);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let mut lockin = Lockin {
+            harmonic: 2,
+            phase_offset: 1 << 30,
+            ..Default::default()
+        };
+        lockin.update(1000, 0x1234_5678, 8);
+        let (json, de) = crate::testing::round_trip(&lockin);
+        assert_eq!(json, r#"{"harmonic":2,"phase_offset":1073741824}"#);
+        assert_eq!((de.harmonic, de.phase_offset), (2, 1 << 30));
+        // The state is neither serialized nor changed
+        assert_eq!(de.state()[0].state(), &[0; 2]);
+        assert_ne!(lockin.state()[0].state(), &[0; 2]);
+    }
+
+    #[test]
+    fn const_new() {
+        const LOCKIN: Lockin = Lockin::new();
+        let lockin = Lockin::default();
+        assert_eq!(LOCKIN.harmonic, lockin.harmonic);
+        assert_eq!(LOCKIN.phase_offset, lockin.phase_offset);
+        for (a, b) in LOCKIN.state().iter().zip(lockin.state().iter()) {
+            assert_eq!(a.state(), b.state());
+        }
+    }
+
+    #[test]
+    fn batch() {
+        // Batch processing matches sample-by-sample processing exactly
+        let mut rng = crate::noise::Xoshiro::new(1);
+        for &n in [1, 8, 16].iter() {
+            let mut batch = Lockin::new();
+            let mut scalar = Lockin::new();
+            let frequency = rng.next_u32() as i32;
+            let mut phase = rng.next_u32() as i32;
+            let mut samples = [0i16; 16];
+            for _ in 0..100 {
+                for sample in samples[..n].iter_mut() {
+                    *sample = (rng.next_u32() >> 16) as i16;
+                }
+                let have =
+                    batch.process_batch(&samples[..n], phase, frequency, 6);
+                let mut want = Complex(0, 0);
+                for &sample in samples[..n].iter() {
+                    want = scalar.update(sample, phase, 6);
+                    phase = phase.wrapping_add(frequency);
+                }
+                assert_eq!(have, want);
+                assert_eq!(batch.state()[0].state(), scalar.state()[0].state());
+                assert_eq!(batch.state()[1].state(), scalar.state()[1].state());
+            }
+        }
+        let mut lockin = Lockin::new();
+        assert_eq!(lockin.process_batch(&[], 0, 0, 6), Complex(0, 0));
+    }
+
+    #[test]
+    fn q15() {
+        let mut lockin = Lockin::default();
+        let frequency = 1i32 << 26;
+        let (k, a, p) = (10, 0.75, 0.3);
+        let mut iq = Complex(Q31::ZERO, Q31::ZERO);
+        for i in 0..1 << 14 {
+            let phase = frequency.wrapping_mul(i);
+            let t = phase as f64 * core::f64::consts::PI / (1u64 << 31) as f64;
+            let sample = Q15::from_f32((a * (t + p).cos()) as f32);
+            iq = lockin.update_q15(sample, phase, k);
+        }
+        let iq = Complex(iq.0.to_f32(), iq.1.to_f32());
+        assert!((iq.abs() - a as f32 / 2.).abs() < 1e-3, "{:?}", iq);
+        // The LO is the conjugate of the (positive) phase
+        assert!((iq.arg() + p as f32).abs() < 1e-2, "{:?}", iq);
+    }
 }
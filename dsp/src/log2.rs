@@ -0,0 +1,176 @@
+// log2(1 + f) for f in [0, 1), minimax polynomial without constant term
+// and exact at f = 1, Q2.30 coefficients, highest order first.
+// Max error 1.6e-5.
+const LOG2_POLY: [i64; 5] =
+    [48478413, -207850308, 446253595, -761386508, 1548246632];
+
+// 2**f - 1 for f in [0, 1), minimax polynomial without constant term
+// and exact at f = 1, Q2.30 coefficients, highest order first.
+// Max error 4.8e-6.
+const EXP2_POLY: [i64; 4] = [14712409, 55559977, 259362141, 744107297];
+
+// 20*log10(2) in Q3.29
+const DB_PER_OCTAVE: i64 = 3232284966;
+// 1/(20*log10(2)) in Q0.32
+const OCTAVE_PER_DB: i64 = 713378626;
+
+// Evaluate a polynomial without constant term at `f` (Q0.32) by Horner's
+// method. The coefficients and the result are Q2.30.
+fn horner(poly: &[i64], f: u32) -> i64 {
+    let f = f as i64;
+    poly.iter().fold(0, |acc, c| ((acc + c) * f) >> 32)
+}
+
+/// Binary logarithm.
+///
+/// The integer part is the position of the most significant bit, the
+/// fractional part is a fifth order polynomial in the remaining mantissa bits.
+/// The cost is a leading zero count and five 64 bit multiplications.
+///
+/// # Arguments
+/// * `x` - Input value.
+///
+/// # Returns
+/// `log2(x)` in Q16.16. The error is less than two LSB (3e-5). Powers of
+/// two are exact. `log2_q(0)` is `i32::MIN`.
+pub fn log2_q(x: u32) -> i32 {
+    if x == 0 {
+        return i32::MIN;
+    }
+    let z = x.leading_zeros();
+    // Mantissa bits below the leading one, Q0.32
+    let f = x.checked_shl(z + 1).unwrap_or(0);
+    let p = horner(&LOG2_POLY, f);
+    (((31 - z) as i32) << 16) + ((p + (1 << 13)) >> 14) as i32
+}
+
+/// Binary exponential.
+///
+/// The integer part of the input is the shift, the fractional part is
+/// evaluated with a fourth order polynomial. The cost is four 64 bit
+/// multiplications.
+///
+/// # Arguments
+/// * `x` - Exponent in Q16.16.
+///
+/// # Returns
+/// `2**x` rounded to an integer, saturating at `u32::MAX` for `x >= 32.0`.
+/// The relative error is less than 1e-5 plus the output rounding.
+/// Integer exponents are exact.
+pub fn exp2_q(x: i32) -> u32 {
+    let n = x >> 16;
+    if n >= 32 {
+        return u32::MAX;
+    }
+    // Q2.30 mantissa in [1, 2)
+    let f = ((x & 0xffff) as u32) << 16;
+    let m = ((1 << 30) + horner(&EXP2_POLY, f)) as u64;
+    let y = if n >= 30 {
+        m << (n - 30)
+    } else if n >= -33 {
+        let s = (30 - n) as u32;
+        (m + (1 << (s - 1))) >> s
+    } else {
+        0
+    };
+    y.min(u32::MAX as u64) as u32
+}
+
+/// Amplitude ratio to decibels, `20*log10(x)`.
+///
+/// # Arguments
+/// * `x` - Amplitude ratio.
+///
+/// # Returns
+/// Level in dB, Q16.16. `to_db(0)` is `i32::MIN`.
+pub fn to_db(x: u32) -> i32 {
+    let l = log2_q(x);
+    if l == i32::MIN {
+        return l;
+    }
+    ((l as i64 * DB_PER_OCTAVE + (1 << 28)) >> 29) as i32
+}
+
+/// Decibels to amplitude ratio, `10**(x/20)`.
+///
+/// # Arguments
+/// * `x` - Level in dB, Q16.16.
+///
+/// # Returns
+/// Amplitude ratio rounded to an integer, saturating at `u32::MAX`.
+pub fn from_db(x: i32) -> u32 {
+    let l = (x as i64 * OCTAVE_PER_DB + (1 << 31)) >> 32;
+    exp2_q(l as i32)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn q16(x: i32) -> f64 {
+        x as f64 / (1 << 16) as f64
+    }
+
+    #[test]
+    fn log2_exact() {
+        assert_eq!(log2_q(0), i32::MIN);
+        assert_eq!(log2_q(1), 0);
+        for k in 0..32 {
+            assert_eq!(log2_q(1 << k), k << 16);
+        }
+        // Largest input
+        let y = log2_q(u32::MAX);
+        assert_eq!(y, 32 << 16);
+    }
+
+    #[test]
+    fn log2_sweep() {
+        let mut x = 1f64;
+        while x < u32::MAX as f64 {
+            let xi = x as u32;
+            let err = q16(log2_q(xi)) - (xi as f64).log2();
+            assert!(err.abs() < 3e-5, "{}: {}", xi, err);
+            x *= 1.0123;
+        }
+    }
+
+    #[test]
+    fn exp2_exact() {
+        assert_eq!(exp2_q(0), 1);
+        for k in 0..32 {
+            assert_eq!(exp2_q(k << 16), 1 << k);
+        }
+        assert_eq!(exp2_q(32 << 16), u32::MAX);
+        assert_eq!(exp2_q(i32::MAX), u32::MAX);
+        assert_eq!(exp2_q(-1 << 16), 1);
+        assert_eq!(exp2_q(-2 << 16), 0);
+        assert_eq!(exp2_q(i32::MIN), 0);
+    }
+
+    #[test]
+    fn exp2_sweep() {
+        for x in (-4 << 16..32 << 16).step_by(997) {
+            let y = exp2_q(x) as f64;
+            let want = 2f64.powf(q16(x)).min(u32::MAX as f64);
+            assert!((y - want).abs() <= 1e-5 * want + 0.5, "{}: {}", x, y);
+        }
+    }
+
+    #[test]
+    fn db() {
+        assert_eq!(to_db(0), i32::MIN);
+        assert_eq!(to_db(1), 0);
+        for &(x, db) in
+            [(10, 20.), (1000, 60.), (2, 6.0206), (u32::MAX, 192.66)].iter()
+        {
+            let err = q16(to_db(x)) - db;
+            assert!(err.abs() < 1e-3, "{}: {}", x, err);
+        }
+        assert_eq!(from_db(0), 1);
+        assert_eq!(from_db(200 << 16), u32::MAX);
+        for &x in [3u32, 10, 12345, 1 << 20, 987_654_321].iter() {
+            let y = from_db(to_db(x)) as f64;
+            assert!((y / x as f64 - 1.).abs() < 1e-4 + 0.5 / x as f64);
+        }
+    }
+}
@@ -1,21 +1,50 @@
-use generic_array::{ArrayLength, GenericArray};
+use super::{saturating_scale_i64, Error, Reset, Rounding, Q31};
 
 /// Arbitrary order, high dynamic range, wide coefficient range,
 /// lowpass filter implementation. DC gain is 1.
 ///
-/// Type argument N is the filter order.
-#[derive(Clone, Default)]
-pub struct Lowpass<N: ArrayLength<i32>> {
+/// Type argument N is the filter order. The same first-order stage is
+/// applied N times.
+#[derive(Copy, Clone)]
+pub struct Lowpass<const N: usize> {
     // IIR state storage
-    y: GenericArray<i32, N>,
+    y: [i32; N],
 }
 
-impl<N: ArrayLength<i32>> Lowpass<N> {
+/// Second-order lowpass.
+pub type Lowpass2 = Lowpass<2>;
+
+impl<const N: usize> Default for Lowpass<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Lowpass<N> {
+    /// Create a new lowpass with cleared state.
+    pub const fn new() -> Self {
+        Self { y: [0; N] }
+    }
+
+    /// Filter state: the output of each stage, with a gain of `1 << k`.
+    pub fn state(&self) -> &[i32; N] {
+        &self.y
+    }
+
+    /// Update the filter with a `Q31` sample at unity DC gain.
+    ///
+    /// The input is truncated by `k` bits to provide the headroom, see
+    /// `update()`.
+    pub fn update_q31(&mut self, x: Q31, k: u8) -> Q31 {
+        Q31(self.update(x.0 >> k, k))
+    }
+
     /// Update the filter with a new sample.
     ///
     /// # Args
     /// * `x`: Input data, needs `k` bits headroom.
-    /// * `k`: Log2 time constant, 0..31.
+    /// * `k`: Log2 time constant, 0..=31. This is only checked in debug
+    ///   builds, see `check_time_constant()`.
     ///
     /// # Return
     /// Filtered output y, with gain of `1 << k`.
@@ -26,10 +55,119 @@ impl<N: ArrayLength<i32>> Lowpass<N> {
         // Note DF-II and the zeros at Nyquist.
         let mut x = x << k;
         for y in self.y.iter_mut() {
-            let dy = (x - *y + (1 << (k - 1))) >> k;
+            let dy = saturating_scale_i64(
+                x as i64 - *y as i64,
+                k as _,
+                Rounding::HalfUp,
+            );
             *y += dy;
             x = *y - (dy >> 1);
         }
         x
     }
 }
+
+impl<const N: usize> Reset for Lowpass<N> {
+    fn reset(&mut self) {
+        self.y = [0; N];
+    }
+}
+
+/// Check a lowpass time constant.
+///
+/// # Args
+/// * `k`: Log2 time constant.
+///
+/// # Returns
+/// `k` or `Error::ShiftRange` if it is larger than 31.
+pub fn check_time_constant(k: u8) -> Result<u8, Error> {
+    if k > 31 {
+        return Err(Error::ShiftRange);
+    }
+    Ok(k)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::f64::consts::PI;
+
+    fn dc_gain<const N: usize>() {
+        let mut lp = Lowpass::<N>::default();
+        let k = 4;
+        let mut y = 0;
+        for _ in 0..N << 12 {
+            y = lp.update(1000, k);
+        }
+        // Truncation leaves less than half an input LSB error per stage.
+        assert!((y - (1000 << k)).abs() < (N as i32) << (k - 1), "{}", y);
+    }
+
+    #[test]
+    fn dc() {
+        dc_gain::<1>();
+        dc_gain::<2>();
+        dc_gain::<3>();
+        dc_gain::<4>();
+    }
+
+    // Amplitude response at the expected -3 dB frequency of the N-th order
+    // cascade: `f_1*sqrt(2**(1/N) - 1)` with f_1 the corner of one stage.
+    fn corner<const N: usize>() {
+        let mut lp = Lowpass::<N>::default();
+        let k = 8;
+        let f1 = 1. / (2. * PI * (1 << k) as f64);
+        let f = f1 * (2f64.powf(1. / N as f64) - 1.).sqrt();
+        let a = (1 << 20) as f64;
+        let settle = (N << (k + 6)) as usize;
+        // Integer number of periods
+        let periods = 16.;
+        let m = (periods / f).round() as usize;
+        let f = periods / m as f64;
+        let mut iq = (0f64, 0f64);
+        for i in 0..settle + m {
+            let p = 2. * PI * f * i as f64;
+            let y = lp.update((a * p.sin()).round() as i32, k) as f64
+                / (1 << k) as f64;
+            if i >= settle {
+                iq.0 += y * p.cos();
+                iq.1 += y * p.sin();
+            }
+        }
+        let g = 2. * iq.0.hypot(iq.1) / m as f64 / a;
+        assert!((g * 2f64.sqrt() - 1.).abs() < 2e-2, "N={}: {}", N, g);
+    }
+
+    #[test]
+    fn corner_frequency() {
+        corner::<1>();
+        corner::<2>();
+        corner::<3>();
+        corner::<4>();
+    }
+    #[test]
+    fn time_constant() {
+        assert_eq!(check_time_constant(31), Ok(31));
+        assert_eq!(check_time_constant(32), Err(Error::ShiftRange));
+        assert_eq!(check_time_constant(u8::MAX), Err(Error::ShiftRange));
+    }
+
+    #[test]
+    fn const_new() {
+        const LOWPASS: Lowpass<3> = Lowpass::new();
+        assert_eq!(LOWPASS.state(), Lowpass::<3>::default().state());
+    }
+
+    #[test]
+    fn q31() {
+        let mut lp = Lowpass::<2>::new();
+        let k = 6;
+        for x in [Q31::MAX, Q31::MIN, Q31::from_f32(0.3)].iter().copied() {
+            let mut y = Q31::ZERO;
+            for _ in 0..2 << 12 {
+                y = lp.update_q31(x, k);
+            }
+            assert!((y - x).0.abs() <= 2 << k, "{:?} {:?}", x, y);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+//! Sign manipulation helpers.
+//!
+//! `core` doesn't provide `abs()` and `copysign()` for floats (yet). These
+//! operate on the sign bit and behave like the `libm`/`std` versions for all
+//! inputs including NaN (payload preserved), infinities, and signed zeros.
+
+const SIGN_F32: u32 = 1 << 31;
+const SIGN_F64: u64 = 1 << 63;
+
+/// Absolute value of a `f32`.
+pub fn abs(x: f32) -> f32 {
+    f32::from_bits(x.to_bits() & !SIGN_F32)
+}
+
+/// `x` with the sign of `y`.
+pub fn copysign(x: f32, y: f32) -> f32 {
+    f32::from_bits((x.to_bits() & !SIGN_F32) | (y.to_bits() & SIGN_F32))
+}
+
+/// Absolute value of a `f64`.
+pub fn abs_f64(x: f64) -> f64 {
+    f64::from_bits(x.to_bits() & !SIGN_F64)
+}
+
+/// `x` with the sign of `y`.
+pub fn copysign_f64(x: f64, y: f64) -> f64 {
+    f64::from_bits((x.to_bits() & !SIGN_F64) | (y.to_bits() & SIGN_F64))
+}
+
+/// Absolute value of an `i32`, saturating at `i32::MAX` for `i32::MIN`.
+pub fn saturating_abs(x: i32) -> i32 {
+    if x >= 0 {
+        x
+    } else if x == i32::MIN {
+        i32::MAX
+    } else {
+        -x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SPECIALS: [u32; 12] = [
+        0x0000_0000, // 0
+        0x8000_0000, // -0
+        0x0000_0001, // smallest subnormal
+        0x807f_ffff, // largest negative subnormal
+        0x0080_0000, // smallest normal
+        0x3f80_0000, // 1
+        0xbf80_0000, // -1
+        0x7f7f_ffff, // largest normal
+        0x7f80_0000, // inf
+        0xff80_0000, // -inf
+        0x7fc0_1234, // quiet NaN with payload
+        0xff80_0001, // negative signaling NaN
+    ];
+
+    #[test]
+    fn f32_specials() {
+        for &x in SPECIALS.iter() {
+            let x = f32::from_bits(x);
+            assert_eq!(abs(x).to_bits(), x.abs().to_bits(), "{:?}", x);
+            for &y in SPECIALS.iter() {
+                let y = f32::from_bits(y);
+                assert_eq!(
+                    copysign(x, y).to_bits(),
+                    x.copysign(y).to_bits(),
+                    "{:?} {:?}",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn f64_specials() {
+        for &x in SPECIALS.iter() {
+            let x = f32::from_bits(x) as f64;
+            assert_eq!(abs_f64(x).to_bits(), x.abs().to_bits());
+            for &y in SPECIALS.iter() {
+                let y = f32::from_bits(y) as f64;
+                assert_eq!(
+                    copysign_f64(x, y).to_bits(),
+                    x.copysign(y).to_bits()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn saturating() {
+        assert_eq!(saturating_abs(i32::MIN), i32::MAX);
+        assert_eq!(saturating_abs(i32::MIN + 1), i32::MAX);
+        assert_eq!(saturating_abs(-1), 1);
+        assert_eq!(saturating_abs(0), 0);
+        assert_eq!(saturating_abs(i32::MAX), i32::MAX);
+    }
+}
@@ -0,0 +1,147 @@
+use super::{Filter, FilterF32, Reset};
+
+/// Running median filter of odd length `N`.
+///
+/// The filter keeps the last `N` inputs in insertion order and in sorted
+/// order. An update removes the oldest input from the sorted buffer and
+/// inserts the new input by moving it from the freed position to its sorted
+/// position.
+///
+/// The worst case cost of an update is `N` comparisons to locate the oldest
+/// input plus `N - 1` comparisons and moves to insert the new input. Use small
+/// `N` (up to about 15) in the sample interrupt.
+///
+/// Works for `i32` and `f32` (and any other ordered `Copy` type). `NaN` inputs
+/// are not supported: the output is then unspecified (but there is no panic).
+#[derive(Copy, Clone, Debug)]
+pub struct Median<T, const N: usize> {
+    // delay line (ring buffer)
+    x: [T; N],
+    // index of the oldest sample
+    i: usize,
+    // sorted delay line
+    sorted: [T; N],
+}
+
+impl<T: Copy + Default + PartialOrd, const N: usize> Median<T, N> {
+    /// Create a new median filter with the state cleared to `T::default()`.
+    pub fn new() -> Self {
+        assert!(N & 1 == 1);
+        Self {
+            x: [T::default(); N],
+            i: 0,
+            sorted: [T::default(); N],
+        }
+    }
+
+    /// Feed a new input value into the filter and return the median of the
+    /// last `N` inputs.
+    ///
+    /// # Args
+    /// * `x0`: New input.
+    pub fn update(&mut self, x0: T) -> T {
+        let old = self.x[self.i];
+        self.x[self.i] = x0;
+        self.i += 1;
+        if self.i == N {
+            self.i = 0;
+        }
+        let mut p = self.sorted.iter().position(|&s| s == old).unwrap_or(0);
+        // Move the freed slot to the sorted position of x0.
+        while p > 0 && self.sorted[p - 1] > x0 {
+            self.sorted[p] = self.sorted[p - 1];
+            p -= 1;
+        }
+        while p < N - 1 && self.sorted[p + 1] < x0 {
+            self.sorted[p] = self.sorted[p + 1];
+            p += 1;
+        }
+        self.sorted[p] = x0;
+        self.sorted[N / 2]
+    }
+}
+
+impl<T: Copy + Default + PartialOrd, const N: usize> Default for Median<T, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy + Default + PartialOrd, const N: usize> Reset for Median<T, N> {
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl<const N: usize> Filter for Median<i32, N> {
+    type Config = ();
+    fn config(&self) {}
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x)
+    }
+}
+
+impl<const N: usize> FilterF32 for Median<f32, N> {
+    type Config = ();
+    fn config(&self) {}
+    fn update(&mut self, x: f32) -> f32 {
+        Self::update(self, x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rand::{prelude::*, rngs::StdRng};
+
+    #[test]
+    fn constant() {
+        let mut m = Median::<i32, 5>::new();
+        for _ in 0..3 {
+            m.update(-7);
+        }
+        for _ in 0..10 {
+            assert_eq!(m.update(-7), -7);
+        }
+    }
+
+    #[test]
+    fn outlier() {
+        fn check<const N: usize>() {
+            let mut m = Median::<f32, N>::new();
+            for i in 0..10 * N {
+                let x = if i == 4 * N { 1e9 } else { 3. };
+                let y = m.update(x);
+                if i >= N {
+                    assert_eq!(y, 3.);
+                }
+            }
+        }
+        check::<3>();
+        check::<5>();
+        check::<15>();
+    }
+
+    #[test]
+    fn reference() {
+        fn check<const N: usize>(rng: &mut StdRng) {
+            let mut m = Median::<i32, N>::new();
+            let mut mf = Median::<f32, N>::new();
+            let mut x = [0; N];
+            for i in 0..1 << 10 {
+                // Small range to exercise duplicates
+                let x0 = rng.gen_range(-20..20);
+                x[i % N] = x0;
+                let mut sorted = x;
+                sorted.sort_unstable();
+                assert_eq!(m.update(x0), sorted[N / 2]);
+                assert_eq!(mf.update(x0 as f32), sorted[N / 2] as f32);
+            }
+        }
+        let mut rng = StdRng::seed_from_u64(42);
+        check::<1>(&mut rng);
+        check::<3>(&mut rng);
+        check::<7>(&mut rng);
+        check::<15>(&mut rng);
+    }
+}
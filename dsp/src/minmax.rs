@@ -0,0 +1,139 @@
+use super::Reset;
+
+/// Minimum and maximum tracker.
+///
+/// Records the extrema of a signal between reads, e.g. over a telemetry
+/// period, to capture transients between status updates.
+///
+/// In the empty state (after creation and after each read) the minimum is
+/// the largest and the maximum is the smallest value of the type, i.e.
+/// `min > max`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MinMax<T> {
+    /// Minimum since the last reset.
+    pub min: T,
+    /// Maximum since the last reset.
+    pub max: T,
+}
+
+impl MinMax<i32> {
+    /// Create a new, empty tracker.
+    pub const fn new() -> Self {
+        Self {
+            min: i32::MAX,
+            max: i32::MIN,
+        }
+    }
+}
+
+impl MinMax<f32> {
+    /// Create a new, empty tracker.
+    pub const fn new() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+}
+
+impl<T: Copy + PartialOrd> MinMax<T>
+where
+    Self: Default,
+{
+    /// Update the extrema with a new sample.
+    ///
+    /// `NaN` samples are ignored.
+    pub fn update(&mut self, x: T) {
+        if x < self.min {
+            self.min = x;
+        }
+        if x > self.max {
+            self.max = x;
+        }
+    }
+
+    /// Read the extrema and reset to the empty state.
+    ///
+    /// When shared between contexts, read and reset within the same critical
+    /// section (e.g. an RTIC resource lock) such that every sample is
+    /// counted in exactly one period.
+    ///
+    /// # Returns
+    /// `(min, max)`. `min > max` if there were no samples since the last
+    /// reset.
+    pub fn read_and_reset(&mut self) -> (T, T) {
+        let MinMax { min, max } = core::mem::take(self);
+        (min, max)
+    }
+}
+
+impl Default for MinMax<i32> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Default for MinMax<f32> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Copy + PartialOrd> Reset for MinMax<T>
+where
+    Self: Default,
+{
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn extrema() {
+        let mut m = MinMax::<i32>::new();
+        assert!(m.min > m.max);
+        for &x in [3, -7, 12, 0, i32::MIN].iter() {
+            m.update(x);
+        }
+        assert_eq!(m.read_and_reset(), (i32::MIN, 12));
+        assert_eq!(m, MinMax::<i32>::new());
+        m.update(5);
+        assert_eq!(m.read_and_reset(), (5, 5));
+
+        let mut m = MinMax::<f32>::default();
+        for &x in [0.5, f32::NAN, -1.5, 2.].iter() {
+            m.update(x);
+        }
+        assert_eq!(m.read_and_reset(), (-1.5, 2.));
+        let (min, max) = m.read_and_reset();
+        assert!(min > max);
+    }
+
+    #[test]
+    fn interleaved() {
+        // Each sample is a unique value and a period holds a consecutive
+        // range: every sample falls into exactly one period
+        let mut m = MinMax::<i32>::new();
+        let mut next = 0;
+        let mut x = 0;
+        for period in 1..50 {
+            for _ in 0..period % 7 {
+                m.update(x);
+                x += 1;
+            }
+            let (min, max) = m.read_and_reset();
+            if period % 7 == 0 {
+                assert!(min > max);
+            } else {
+                assert_eq!(min, next);
+                assert_eq!(max, x - 1);
+                next = x;
+            }
+        }
+        assert_eq!(next, x);
+    }
+}
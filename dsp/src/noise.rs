@@ -0,0 +1,202 @@
+use super::{saturating_scale_i64, Rounding};
+
+// sqrt(3) in Q2.30
+const SQRT3: i64 = 1859775393;
+
+/// Xoshiro128++ pseudo random number generator.
+///
+/// Small, fast, and with good statistical quality, but not cryptographically
+/// secure. The sequence only depends on the seed, on the host as well as on
+/// target. See <https://prng.di.unimi.it/>.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Xoshiro {
+    s: [u32; 4],
+}
+
+impl Xoshiro {
+    /// Create a new generator.
+    ///
+    /// The state is expanded from the seed using SplitMix64 as recommended
+    /// by the authors. All seeds are valid.
+    ///
+    /// # Args
+    /// * `seed`: Seed.
+    pub fn new(seed: u64) -> Self {
+        let mut x = seed;
+        let mut splitmix = || {
+            x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+            z ^ (z >> 31)
+        };
+        let (a, b) = (splitmix(), splitmix());
+        Self {
+            s: [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32],
+        }
+    }
+
+    /// Generate the next uniformly distributed `u32`.
+    pub fn next_u32(&mut self) -> u32 {
+        let s = &mut self.s;
+        let y = s[0].wrapping_add(s[3]).rotate_left(7).wrapping_add(s[0]);
+        let t = s[1] << 9;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(11);
+        y
+    }
+
+    /// Uniform noise.
+    ///
+    /// # Args
+    /// * `amplitude`: Amplitude.
+    ///
+    /// # Returns
+    /// A sample uniformly distributed in `[-amplitude, amplitude)` (`amplitude`
+    /// positive). The variance is `amplitude**2/3`.
+    pub fn uniform_i32(&mut self, amplitude: i32) -> i32 {
+        ((self.next_u32() as i32 as i64 * amplitude as i64) >> 31) as i32
+    }
+
+    /// Approximately Gaussian noise.
+    ///
+    /// The sum of four uniform samples (Irwin-Hall distribution). The tails
+    /// are truncated at `2*sqrt(3) = 3.46` standard deviations. The excess
+    /// kurtosis is -0.3.
+    ///
+    /// # Args
+    /// * `sigma`: Standard deviation.
+    ///
+    /// # Returns
+    /// Noise sample, saturating.
+    pub fn gaussian_i32(&mut self, sigma: i32) -> i32 {
+        let sum = (0..4).fold(0i64, |s, _| s + self.next_u32() as i32 as i64);
+        // The standard deviation of `sum >> 3` is `(1 << 29)/sqrt(3)`.
+        // Both factors are below `1 << 32` and the product does not overflow.
+        let k = (sigma as i64 * SQRT3) >> 30;
+        saturating_scale_i64((sum >> 3) * k, 29, Rounding::HalfUp)
+    }
+
+    /// Triangular probability density (TPDF) dither.
+    ///
+    /// The difference of two uniform samples in `[0, 1 << shift)`. This is to
+    /// be added before truncating `shift` bits, e.g. before converting the
+    /// `i32` DSP domain to DAC codes with `shift = 16`. TPDF dither renders
+    /// the mean and the variance of the quantization error independent of
+    /// the signal.
+    ///
+    /// # Args
+    /// * `shift`: Number of bits to be truncated, 1..=31.
+    ///
+    /// # Returns
+    /// A zero mean sample in `(-1 << shift, 1 << shift)` with triangular
+    /// distribution.
+    pub fn tpdf_dither(&mut self, shift: u32) -> i32 {
+        debug_assert!(shift > 0 && shift < 32);
+        let a = (self.next_u32() >> (32 - shift)) as i32;
+        let b = (self.next_u32() >> (32 - shift)) as i32;
+        a - b
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn stats(mut f: impl FnMut() -> i32, n: usize) -> (f64, f64) {
+        let (mut s1, mut s2) = (0f64, 0f64);
+        for _ in 0..n {
+            let x = f() as f64;
+            s1 += x;
+            s2 += x * x;
+        }
+        let mean = s1 / n as f64;
+        (mean, s2 / n as f64 - mean * mean)
+    }
+
+    #[test]
+    fn reproducible() {
+        let mut a = Xoshiro::new(0x1234);
+        let mut b = Xoshiro::new(0x1234);
+        let mut c = Xoshiro::new(0x1235);
+        let mut same = 0;
+        for _ in 0..1000 {
+            let x = a.next_u32();
+            assert_eq!(x, b.next_u32());
+            same += (x == c.next_u32()) as u32;
+        }
+        assert_eq!(same, 0);
+        // The zero seed is valid
+        let mut z = Xoshiro::new(0);
+        assert!((0..4).any(|_| z.next_u32() != 0));
+    }
+
+    #[test]
+    fn uniform() {
+        let mut rng = Xoshiro::new(1);
+        let a = 1 << 20;
+        let (mean, var) = stats(|| rng.uniform_i32(a), 1 << 18);
+        let n = (1 << 18) as f64;
+        let (a, var_want) = (a as f64, (a as f64).powi(2) / 3.);
+        // Five standard errors
+        assert!(mean.abs() < 5. * (var_want / n).sqrt(), "{}", mean);
+        assert!(
+            (var / var_want - 1.).abs() < 5. * (0.8 / n).sqrt(),
+            "{}",
+            var
+        );
+        for _ in 0..1000 {
+            let x = rng.uniform_i32(a as i32) as f64;
+            assert!(x >= -a && x < a);
+        }
+    }
+
+    #[test]
+    fn gaussian() {
+        let mut rng = Xoshiro::new(2);
+        let sigma = 1 << 24;
+        let n = 1 << 18;
+        let (mean, var) = stats(|| rng.gaussian_i32(sigma), n);
+        let s = sigma as f64;
+        assert!(mean.abs() < 5. * s / (n as f64).sqrt(), "{}", mean);
+        assert!((var.sqrt() / s - 1.).abs() < 1e-2, "{}", var.sqrt() / s);
+        // Truncated tails
+        for _ in 0..10_000 {
+            assert!((rng.gaussian_i32(sigma) as f64).abs() <= 3.47 * s);
+        }
+        // Saturation
+        for _ in 0..100 {
+            let _ = rng.gaussian_i32(i32::MAX);
+        }
+    }
+
+    #[test]
+    fn tpdf() {
+        let mut rng = Xoshiro::new(3);
+        let shift = 4;
+        let n = 1 << 20;
+        let mut hist = [0u32; 32];
+        let (mut s1, mut s2) = (0i64, 0i64);
+        for _ in 0..n {
+            let x = rng.tpdf_dither(shift);
+            assert!(x.abs() < 1 << shift);
+            hist[(x + 16) as usize] += 1;
+            s1 += x as i64;
+            s2 += (x * x) as i64;
+        }
+        // Triangular: p(x) = (16 - |x|)/256
+        for (i, &h) in hist.iter().enumerate() {
+            let x = i as i32 - 16;
+            let want = n as f64 * (16 - x.abs()).max(0) as f64 / 256.;
+            assert!((h as f64 - want).abs() <= 5. * want.sqrt(), "{} {}", x, h);
+        }
+        assert!((s1 as f64 / n as f64).abs() < 0.02);
+        // Variance of the difference of two uniforms: 2*(16**2 - 1)/12
+        let var = s2 as f64 / n as f64;
+        assert!((var / (2. * 255. / 12.) - 1.).abs() < 1e-2, "{}", var);
+    }
+}
@@ -0,0 +1,121 @@
+use super::Reset;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Peak detector with instantaneous attack and exponential decay.
+///
+/// The detector tracks the input magnitude. It follows increases
+/// immediately. Otherwise it decays by `peak >> decay_shift` per sample, but
+/// at least by one LSB, such that it does not stall and eventually
+/// reaches zero.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct PeakDetector {
+    /// Log2 of the decay time constant in samples.
+    pub decay_shift: u32,
+    // Current peak magnitude, non-negative
+    #[cfg_attr(feature = "serde", serde(skip))]
+    peak: i32,
+}
+
+impl PeakDetector {
+    /// Create a new peak detector with zero peak.
+    ///
+    /// # Args
+    /// * `decay_shift`: Log2 of the decay time constant in samples.
+    pub const fn new(decay_shift: u32) -> Self {
+        Self {
+            decay_shift,
+            peak: 0,
+        }
+    }
+
+    /// Current peak magnitude.
+    pub fn peak(&self) -> i32 {
+        self.peak
+    }
+
+    /// Update the detector with a new sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    ///
+    /// # Returns
+    /// The new peak magnitude. `i32::MIN` saturates to `i32::MAX`.
+    pub fn update(&mut self, x: i32) -> i32 {
+        if self.peak > 0 {
+            let decay = self.peak.checked_shr(self.decay_shift).unwrap_or(0);
+            self.peak -= decay.max(1);
+        }
+        let a = x.unsigned_abs().min(i32::MAX as u32) as i32;
+        self.peak = self.peak.max(a);
+        self.peak
+    }
+
+    /// Read and reset the peak.
+    ///
+    /// # Returns
+    /// The peak magnitude since the last reset, including the decay.
+    pub fn read_and_reset(&mut self) -> i32 {
+        let peak = self.peak;
+        self.peak = 0;
+        peak
+    }
+}
+
+impl Reset for PeakDetector {
+    fn reset(&mut self) {
+        self.peak = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn spike() {
+        let shift = 10;
+        let mut p = PeakDetector::new(shift);
+        assert_eq!(p.update(i32::MIN), i32::MAX);
+        // The spike decays to half in `ln(2) << shift` samples
+        let half = (core::f64::consts::LN_2 * (1 << shift) as f64) as usize;
+        let mut n = 0;
+        while p.update(0) > i32::MAX / 2 {
+            n += 1;
+        }
+        assert!((n as i32 - half as i32).abs() <= 2, "{} {}", n, half);
+        // Eventually exactly zero, with the final linear decay
+        let mut n = 0;
+        while p.update(0) > 0 {
+            n += 1;
+            assert!(n < 32 << shift);
+        }
+        assert!(n > 10 << shift);
+        assert_eq!(p.update(0), 0);
+    }
+
+    #[test]
+    fn no_stall() {
+        let mut p = PeakDetector::new(31);
+        p.update(100);
+        for i in 1..=100 {
+            assert_eq!(p.update(0), 100 - i);
+        }
+        assert_eq!(p.update(0), 0);
+        let mut p = PeakDetector::new(40);
+        p.update(-1);
+        assert_eq!(p.update(0), 0);
+    }
+
+    #[test]
+    fn attack_reset() {
+        let mut p = PeakDetector::new(4);
+        assert_eq!(p.update(-1000), 1000);
+        assert_eq!(p.update(500), 1000 - (1000 >> 4));
+        assert_eq!(p.update(2000), 2000);
+        assert_eq!(p.read_and_reset(), 2000);
+        assert_eq!(p.peak(), 0);
+        assert_eq!(p.update(3), 3);
+    }
+}
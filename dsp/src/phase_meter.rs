@@ -0,0 +1,170 @@
+use super::{atan2_precise, unwrap::Unwrapper, Complex, Reset};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Continuous phase readout from IQ data.
+///
+/// The phase of the IQ input (`atan2_precise()`, `1 << 31` is pi) is
+/// unwrapped to an extended `i64` phase (`1 << 32` per turn) and filtered by
+/// a first-order lowpass.
+/// Below the squelch magnitude the phase is not determined by the input and
+/// the output holds its last value. When the input recovers, the phase
+/// continues from the last unwrapped phase.
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct PhaseMeter {
+    /// Log2 time constant of the output lowpass. Zero is no filtering.
+    pub k: u8,
+    /// Input magnitude below which the output is held.
+    pub squelch: i32,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    unwrapper: Unwrapper,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    y: i64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    started: bool,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    squelched: bool,
+}
+
+impl Default for PhaseMeter {
+    fn default() -> Self {
+        Self::new(0, 0)
+    }
+}
+
+impl PhaseMeter {
+    /// Create a new phase meter.
+    ///
+    /// # Args
+    /// * `k`: Log2 time constant of the output lowpass, 0..=62.
+    /// * `squelch`: Input magnitude below which the output is held.
+    pub const fn new(k: u8, squelch: i32) -> Self {
+        Self {
+            k,
+            squelch,
+            unwrapper: Unwrapper::new(),
+            y: 0,
+            started: false,
+            squelched: true,
+        }
+    }
+
+    /// Update the phase meter with new IQ data.
+    ///
+    /// # Args
+    /// * `iq`: IQ input, e.g. the lockin output.
+    ///
+    /// # Returns
+    /// The filtered extended phase (`1 << 32` per turn).
+    pub fn update(&mut self, iq: Complex<i32>) -> i64 {
+        let squelch = self.squelch.max(0) as u64;
+        self.squelched = iq.abs_sqr() < squelch * squelch;
+        if self.squelched {
+            return self.y;
+        }
+        let x = atan2_precise(iq.1, iq.0);
+        let (_, wraps) = self.unwrapper.update(x);
+        let x = ((wraps as i64) << 32).wrapping_add(x as i64);
+        if self.started {
+            let dy = x.wrapping_sub(self.y);
+            let dy = dy.wrapping_add((1 << self.k) >> 1) >> self.k;
+            self.y = self.y.wrapping_add(dy);
+        } else {
+            // Start at the first valid phase.
+            self.started = true;
+            self.y = x;
+        }
+        self.y
+    }
+
+    /// The filtered extended phase (`1 << 32` per turn).
+    pub fn phase(&self) -> i64 {
+        self.y
+    }
+
+    /// Whether the last input was below the squelch magnitude.
+    pub fn squelched(&self) -> bool {
+        self.squelched
+    }
+}
+
+impl Reset for PhaseMeter {
+    fn reset(&mut self) {
+        *self = Self::new(self.k, self.squelch);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn phasor(phase: i64, amplitude: i32) -> Complex<i32> {
+        let lo = Complex::from_angle(phase as i32);
+        Complex(
+            ((lo.0 as i64 * amplitude as i64) >> 31) as i32,
+            ((lo.1 as i64 * amplitude as i64) >> 31) as i32,
+        )
+    }
+
+    #[test]
+    fn ramp() {
+        // About 0.07 turns per sample: the phase wraps many times
+        let rate = 0x1234_5678i64;
+        for &k in [0, 4].iter() {
+            let mut meter = PhaseMeter::new(k, 1 << 10);
+            let p0 = -0x7000_0000i64;
+            let mut last = meter.update(phasor(p0, 1 << 30));
+            assert!((last - p0).abs() < 1 << 15, "{}", last);
+            for i in 1..1000 {
+                let y = meter.update(phasor(p0 + rate * i, 1 << 30));
+                // Clean ramp after settling
+                if i > 20 << k {
+                    assert!((y - last - rate).abs() < 1 << 15, "{} {}", i, y);
+                }
+                last = y;
+            }
+            // The lag of the first-order lowpass on a ramp
+            let want = p0 + rate * 999 - rate * ((1 << k) - 1);
+            assert!((last - want).abs() < 1 << 16, "{} {}", last, want);
+            assert!(last > 64 << 32);
+        }
+    }
+
+    #[test]
+    fn negative() {
+        let rate = -0x7000_0000i64;
+        let mut meter = PhaseMeter::new(0, 0);
+        let mut y = 0;
+        for i in 0..100 {
+            y = meter.update(phasor(rate * i, i32::MAX));
+        }
+        assert!((y - rate * 99).abs() < 1 << 15, "{}", y);
+    }
+
+    #[test]
+    fn squelch() {
+        let mut meter = PhaseMeter::new(2, 1 << 20);
+        let rate = 0x0100_0000i64;
+        let mut y = 0;
+        for i in 0..100 {
+            y = meter.update(phasor(rate * i, 1 << 24));
+        }
+        assert!(!meter.squelched());
+        // Zero magnitude: hold
+        for i in 100..200 {
+            assert_eq!(meter.update(phasor(rate * i, 0)), y);
+            assert!(meter.squelched());
+        }
+        // Below threshold: hold
+        assert_eq!(meter.update(phasor(12345, 1 << 19)), y);
+        assert_eq!(meter.update(Complex(0, 0)), meter.phase());
+        // Recovery continues from the last valid phase
+        let y1 = meter.update(phasor(rate * 100, 1 << 24));
+        assert!((y1 - y - rate).abs() < 1 << 15, "{} {}", y, y1);
+        meter.reset();
+        assert_eq!(meter.phase(), 0);
+        let y = meter.update(phasor(1 << 30, 1 << 24));
+        assert!((y - (1 << 30)).abs() < 1 << 15, "{}", y);
+    }
+}
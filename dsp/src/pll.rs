@@ -1,3 +1,5 @@
+use super::{Error, Reset};
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 /// Type-II, sampled phase, discrete time PLL
@@ -28,19 +30,69 @@ use serde::{Deserialize, Serialize};
 /// implemented elsewhere by unwrapping and scaling the input phase and un-scaling
 /// and wrapping output phase and frequency. This affects dynamic range, gain, and noise accordingly.
 ///
-/// The extension to I^3,I^2,I behavior to track chirps phase-accurately or to i64 data to
-/// increase resolution for extremely narrowband applications is obvious.
-#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+/// `update3()` adds a third integrator (frequency rate) for I^3,I^2,I (type-III) behavior. A
+/// type-II loop has a constant phase error under a linear frequency ramp (a chirp), a type-III
+/// loop tracks it without phase error. The extension to i64 data to increase resolution for
+/// extremely narrowband applications is obvious.
+///
+/// Lock detection is based on the envelope of the absolute phase error: it follows increases
+/// immediately and decays with the phase settling time constant. The PLL is considered locked
+/// once the envelope has stayed below a threshold for at least `lock_updates` updates
+/// (see `set_lock_updates()`). Lock is lost as soon as the envelope exceeds the threshold.
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct PLL {
     // last input phase
+    #[cfg_attr(feature = "serde", serde(skip))]
     x: i32,
     // filtered frequency
+    #[cfg_attr(feature = "serde", serde(skip))]
     f: i32,
+    // filtered frequency rate (type-III only), `shift_frequency` fractional bits
+    #[cfg_attr(feature = "serde", serde(skip))]
+    a: i32,
     // filtered output phase
+    #[cfg_attr(feature = "serde", serde(skip))]
     y: i32,
+    // absolute phase error envelope
+    #[cfg_attr(feature = "serde", serde(skip))]
+    e: i32,
+    // error envelope peak in the current and the previous lock detection block
+    #[cfg_attr(feature = "serde", serde(skip))]
+    peak: [i32; 2],
+    // updates in the current lock detection block
+    #[cfg_attr(feature = "serde", serde(skip))]
+    n: u32,
+    // lock detection block length
+    lock_updates: u32,
+    // a lock detection block has been completed
+    #[cfg_attr(feature = "serde", serde(skip))]
+    valid: bool,
+}
+
+impl Default for PLL {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PLL {
+    /// Create a new PLL with cleared state and lock detection disabled.
+    pub const fn new() -> Self {
+        Self {
+            x: 0,
+            f: 0,
+            a: 0,
+            y: 0,
+            e: 0,
+            peak: [0; 2],
+            n: 0,
+            lock_updates: 0,
+            valid: false,
+        }
+    }
+
     /// Update the PLL with a new phase sample. This needs to be called (sampled) periodically.
     /// The signal's phase/frequency is reconstructed relative to the sampling period.
     ///
@@ -51,6 +103,9 @@ impl PLL {
     /// * `shift_phase`: Phase error scaling. The phase gain is `1/(1 << shift_phase)`
     ///   per update. A good value is typically `shift_frequency - 1`.
     ///
+    /// Both shifts must be in `1..=30`. This is only checked in debug builds, see
+    /// `Config::validate()`.
+    ///
     /// Returns:
     /// A tuple of instantaneous phase and frequency (the current phase increment).
     pub fn update(
@@ -58,31 +113,294 @@ impl PLL {
         x: i32,
         shift_frequency: u8,
         shift_phase: u8,
+    ) -> (i32, i32) {
+        self.step(x, shift_frequency, shift_phase, None)
+    }
+
+    /// Update the type-III PLL with a new phase sample.
+    ///
+    /// This is `update()` with an additional frequency rate integrator in the frequency
+    /// path. It tracks a linear frequency ramp without phase error.
+    ///
+    /// Args:
+    /// * `x`: New input phase sample.
+    /// * `shift_frequency`: Frequency error scaling, see `update()`.
+    /// * `shift_phase`: Phase error scaling, see `update()`.
+    /// * `shift_rate`: Frequency rate error scaling. The frequency rate gain per update is
+    ///   `1/(1 << shift_rate)`.
+    ///
+    /// The frequency path is an alpha-beta tracker with `alpha = 1/(1 << shift_frequency)`
+    /// and `beta = 1/(1 << shift_rate)`. It is stable for `shift_rate > shift_frequency` and
+    /// about critically damped for `shift_rate = 2*shift_frequency + 1`. Smaller `shift_rate`
+    /// are faster and ring. All shifts must be in `1..=30`. This is only checked in debug
+    /// builds, see `Config::validate()`.
+    ///
+    /// Returns:
+    /// A tuple of instantaneous phase and frequency (the current phase increment).
+    pub fn update3(
+        &mut self,
+        x: i32,
+        shift_frequency: u8,
+        shift_phase: u8,
+        shift_rate: u8,
+    ) -> (i32, i32) {
+        debug_assert!((shift_frequency + 1..=30).contains(&shift_rate));
+        self.step(x, shift_frequency, shift_phase, Some(shift_rate))
+    }
+
+    /// Current frequency rate estimate (frequency change per update) with `shift_frequency`
+    /// fractional bits. Zero for type-II.
+    pub fn frequency_rate(&self) -> i32 {
+        self.a
+    }
+
+    #[inline]
+    fn step(
+        &mut self,
+        x: i32,
+        shift_frequency: u8,
+        shift_phase: u8,
+        shift_rate: Option<u8>,
     ) -> (i32, i32) {
         debug_assert!((1..=30).contains(&shift_frequency));
         debug_assert!((1..=30).contains(&shift_phase));
-        let e = x.wrapping_sub(self.f);
-        self.f = self.f.wrapping_add(
-            (1i32 << (shift_frequency - 1))
-                .wrapping_add(e)
-                .wrapping_sub(self.x)
-                >> shift_frequency,
+        // Frequency rate contribution to the frequency prediction
+        let a = match shift_rate {
+            Some(_) => {
+                self.a.wrapping_add(1 << (shift_frequency - 1))
+                    >> shift_frequency
+            }
+            None => 0,
+        };
+        // Phase error with respect to the predicted frequency
+        let e = x.wrapping_sub(self.f.wrapping_add(a));
+        // Frequency error: input frequency minus predicted frequency
+        let ef = e.wrapping_sub(self.x);
+        self.f = self.f.wrapping_add(a).wrapping_add(
+            (1i32 << (shift_frequency - 1)).wrapping_add(ef) >> shift_frequency,
         );
+        if let Some(shift) = shift_rate {
+            // The rate has `shift_frequency` fractional bits.
+            let shift = shift - shift_frequency;
+            self.a = self
+                .a
+                .wrapping_add((1i32 << (shift - 1)).wrapping_add(ef) >> shift);
+        }
         self.x = x;
+        let p = e.wrapping_sub(self.y);
         let f = self.f.wrapping_add(
-            (1i32 << (shift_phase - 1))
-                .wrapping_add(e)
-                .wrapping_sub(self.y)
-                >> shift_phase,
+            (1i32 << (shift_phase - 1)).wrapping_add(p) >> shift_phase,
         );
         self.y = self.y.wrapping_add(f);
+        self.detect_lock(p, shift_phase);
         (self.y, f)
     }
+
+    /// Update the phase error envelope and the lock detection blocks.
+    fn detect_lock(&mut self, p: i32, shift: u8) {
+        let p = p.saturating_abs();
+        self.e = if p >= self.e {
+            p
+        } else {
+            self.e - ((self.e - p) >> shift)
+        };
+        self.peak[0] = self.peak[0].max(self.e);
+        self.n += 1;
+        if self.n >= self.lock_updates.max(1) {
+            self.peak = [0, self.peak[0]];
+            self.n = 0;
+            self.valid = true;
+        }
+    }
+
+    /// Set the number of updates the phase error envelope needs to stay below the threshold
+    /// before lock is asserted. The default is 1.
+    ///
+    /// Args:
+    /// * `lock_updates`: Minimum number of updates below threshold.
+    pub fn set_lock_updates(&mut self, lock_updates: u32) {
+        self.lock_updates = lock_updates;
+        self.n = 0;
+        self.valid = false;
+    }
+
+    /// Current state.
+    ///
+    /// Returns:
+    /// A tuple of the last input phase, the frequency estimate, and the output
+    /// phase estimate.
+    pub fn state(&self) -> (i32, i32, i32) {
+        (self.x, self.f, self.y)
+    }
+
+    /// The absolute phase error envelope.
+    pub fn lock_error(&self) -> i32 {
+        self.e
+    }
+
+    /// Whether the PLL is locked.
+    ///
+    /// Args:
+    /// * `threshold`: Phase error threshold. The phase error envelope must have been lower
+    ///   than this for at least the configured number of updates.
+    ///
+    /// Returns:
+    /// `true` if the PLL is locked.
+    pub fn locked(&self, threshold: i32) -> bool {
+        self.valid && self.peak[0].max(self.peak[1]) < threshold
+    }
+}
+
+/// PLL loop gain configuration.
+///
+/// The shifts are those of `PLL::update()` and `PLL::update3()`: `shift_i` is
+/// `shift_frequency`, `shift_p` is `shift_phase`, and `shift_a` is `shift_rate`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Config {
+    /// Phase (proportional) gain shift.
+    pub shift_p: u32,
+    /// Frequency (integral) gain shift.
+    pub shift_i: u32,
+    /// Loop order (type): 2 (the default) or 3. See `PLL::update3()`.
+    #[cfg_attr(feature = "serde", serde(default = "Config::default_order"))]
+    pub order: u32,
+    /// Frequency rate (double integral) gain shift. Only used for order 3.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub shift_a: u32,
+}
+
+impl Config {
+    /// A type-II configuration.
+    pub const fn new(shift_p: u32, shift_i: u32) -> Self {
+        Self {
+            shift_p,
+            shift_i,
+            order: 2,
+            shift_a: 0,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    fn default_order() -> u32 {
+        2
+    }
+
+    /// Update a PLL with this configuration.
+    ///
+    /// Args:
+    /// * `pll`: The PLL.
+    /// * `x`: New input phase sample.
+    ///
+    /// Returns:
+    /// The result of `PLL::update()` or `PLL::update3()` according to the order.
+    pub fn update(&self, pll: &mut PLL, x: i32) -> (i32, i32) {
+        let (shift_i, shift_p) = (self.shift_i as u8, self.shift_p as u8);
+        if self.order == 3 {
+            pll.update3(x, shift_i, shift_p, self.shift_a as u8)
+        } else {
+            pll.update(x, shift_i, shift_p)
+        }
+    }
+
+    /// Construct a configuration for a given loop bandwidth.
+    ///
+    /// The frequency shift is chosen such that the loop bandwidth `1/(2*pi*(1 << shift_i))`
+    /// is closest to the desired bandwidth (in the log domain). The phase shift is one less.
+    ///
+    /// Args:
+    /// * `bandwidth`: Desired loop bandwidth in units of the update rate.
+    ///
+    /// Returns:
+    /// The configuration or an error if the bandwidth is out of range.
+    pub fn from_bandwidth(bandwidth: f32) -> Result<Self, Error> {
+        if bandwidth.is_nan() || bandwidth <= 0. {
+            return Err(Error::BandwidthRange);
+        }
+        let shift =
+            libm::roundf(-libm::log2f(2. * core::f32::consts::PI * bandwidth));
+        if !(2. ..=30.).contains(&shift) {
+            return Err(Error::BandwidthRange);
+        }
+        let config = Self::new(shift as u32 - 1, shift as u32);
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Check the configuration.
+    ///
+    /// The order must be 2 or 3. The shifts must be in `1..=30` and the frequency shift must
+    /// be larger than the phase shift. For order 3 the frequency rate shift must be larger
+    /// than the frequency shift.
+    pub fn validate(&self) -> Result<(), Error> {
+        if !(2..=3).contains(&self.order) {
+            return Err(Error::Order);
+        }
+        if !(1..=30).contains(&self.shift_p)
+            || !(1..=30).contains(&self.shift_i)
+            || (self.order == 3 && !(1..=30).contains(&self.shift_a))
+        {
+            return Err(Error::ShiftRange);
+        }
+        if self.shift_i < self.shift_p + 1
+            || (self.order == 3 && self.shift_a < self.shift_i + 1)
+        {
+            return Err(Error::ShiftOrder);
+        }
+        Ok(())
+    }
+}
+
+/// Convert a PLL frequency word to Hz.
+///
+/// Args:
+/// * `word`: Frequency word (phase increment per update, `1 << 32` is one turn).
+/// * `update_rate_hz`: PLL update rate in Hz.
+///
+/// Returns:
+/// The frequency in Hz. Negative words correspond to negative frequencies. The full scale
+/// word `i32::MIN` corresponds to minus half the update rate.
+pub fn frequency_to_hz(word: i32, update_rate_hz: f32) -> f32 {
+    (word as f64 * update_rate_hz as f64 / (1u64 << 32) as f64) as f32
+}
+
+/// Convert a frequency in Hz to a PLL frequency word.
+///
+/// This is the inverse of `frequency_to_hz()`. The word is rounded "half up" and the
+/// frequency is wrapped into the first Nyquist zone. A round trip through `frequency_to_hz()`
+/// is exact to 1 LSB for `|word| <= 1 << 24`. Beyond that it is limited by `f32` resolution.
+///
+/// Args:
+/// * `hz`: Frequency in Hz.
+/// * `update_rate_hz`: PLL update rate in Hz.
+///
+/// Returns:
+/// The frequency word.
+pub fn hz_to_frequency(hz: f32, update_rate_hz: f32) -> i32 {
+    to_word(hz, update_rate_hz) as i32
+}
+
+/// Scale a frequency to units of `1 << 32` of the update rate and round half up.
+pub(crate) fn to_word(hz: f32, update_rate_hz: f32) -> i64 {
+    libm::floor(hz as f64 / update_rate_hz as f64 * (1u64 << 32) as f64 + 0.5)
+        as i64
+}
+
+impl Reset for PLL {
+    fn reset(&mut self) {
+        *self = Self {
+            lock_updates: self.lock_updates,
+            ..Default::default()
+        };
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{prelude::*, rngs::StdRng};
+
     #[test]
     fn mini() {
         let mut p = PLL::default();
@@ -110,4 +428,223 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn config() {
+        assert!(Config::new(9, 10).validate().is_ok());
+        assert!(Config {
+            order: 3,
+            shift_a: 21,
+            ..Config::new(9, 10)
+        }
+        .validate()
+        .is_ok());
+        for &(shift_p, shift_i, e) in [
+            (0, 1, Error::ShiftRange),
+            (10, 10, Error::ShiftOrder),
+            (11, 10, Error::ShiftOrder),
+            (30, 31, Error::ShiftRange),
+            (29, 32, Error::ShiftRange),
+        ]
+        .iter()
+        {
+            assert_eq!(Config::new(shift_p, shift_i).validate(), Err(e));
+        }
+        for &(order, shift_a, e) in [
+            (1, 0, Error::Order),
+            (4, 21, Error::Order),
+            (3, 0, Error::ShiftRange),
+            (3, 31, Error::ShiftRange),
+            (3, 10, Error::ShiftOrder),
+        ]
+        .iter()
+        {
+            let c = Config {
+                order,
+                shift_a,
+                ..Config::new(9, 10)
+            };
+            assert_eq!(c.validate(), Err(e));
+        }
+        let config =
+            Config::from_bandwidth(1. / (2. * core::f32::consts::PI * 1024.))
+                .unwrap();
+        assert_eq!(config, Config::new(9, 10));
+        for &bandwidth in [0., -1., f32::NAN, 0.5, 1e-12].iter() {
+            assert_eq!(
+                Config::from_bandwidth(bandwidth),
+                Err(Error::BandwidthRange)
+            );
+        }
+    }
+
+    #[test]
+    fn hz() {
+        let rate = 100e6 / 1024.;
+        assert_eq!(frequency_to_hz(0, rate), 0.);
+        assert_eq!(frequency_to_hz(1 << 30, rate), rate / 4.);
+        assert_eq!(frequency_to_hz(-1 << 30, rate), -rate / 4.);
+        assert_eq!(frequency_to_hz(i32::MIN, rate), -rate / 2.);
+        assert_eq!(hz_to_frequency(-rate / 2., rate), i32::MIN);
+        // Nyquist aliases
+        assert_eq!(hz_to_frequency(rate / 2., rate), i32::MIN);
+        assert_eq!(hz_to_frequency(rate * 0.75, rate), -1 << 30);
+        assert_eq!(hz_to_frequency(rate, rate), 0);
+        // Rounding half up
+        let lsb = rate / (1u64 << 32) as f32;
+        assert_eq!(hz_to_frequency(0.5 * lsb, rate), 1);
+        assert_eq!(hz_to_frequency(-0.5 * lsb, rate), 0);
+        assert_eq!(hz_to_frequency(-0.51 * lsb, rate), -1);
+        // Round trip
+        for &word in [1, -1, 12345, -(1 << 20) - 7, 1 << 24, -(1 << 24)].iter()
+        {
+            let have = hz_to_frequency(frequency_to_hz(word, rate), rate);
+            assert!((have - word).abs() <= 1, "{} {}", word, have);
+        }
+        for &word in [i32::MAX, i32::MIN, 0x71f63049, -0x1234_5678].iter() {
+            let have = hz_to_frequency(frequency_to_hz(word, rate), rate);
+            let err = (have.wrapping_sub(word) as f32 / word as f32).abs();
+            assert!(err <= f32::EPSILON, "{} {}", word, have);
+        }
+    }
+
+    const LOCK_THRESHOLD: i32 = 1 << 20;
+
+    /// Feed a phase ramp with optional uniform phase noise and return the
+    /// update index at which lock was last acquired (if locked at the end).
+    fn ramp(
+        p: &mut PLL,
+        f0: i32,
+        x: &mut i32,
+        n: usize,
+        noise: i32,
+    ) -> Option<usize> {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut acquired = None;
+        for i in 0..n {
+            *x = x.wrapping_add(f0);
+            let dx = if noise > 0 {
+                rng.gen_range(-noise..=noise)
+            } else {
+                0
+            };
+            p.update(x.wrapping_add(dx), 10, 9);
+            if !p.locked(LOCK_THRESHOLD) {
+                acquired = None;
+            } else if acquired.is_none() {
+                acquired = Some(i);
+            }
+        }
+        acquired
+    }
+
+    #[test]
+    fn lock_ramp() {
+        let mut p = PLL::default();
+        p.set_lock_updates(1 << 10);
+        let mut x = 0;
+        assert!(!p.locked(i32::MAX));
+        let acquired = ramp(&mut p, 0x71f63049, &mut x, 1 << 16, 0).unwrap();
+        assert!(acquired > 1 << 10);
+        assert!(p.lock_error() < LOCK_THRESHOLD);
+    }
+
+    #[test]
+    fn lock_frequency_step() {
+        let mut p = PLL::default();
+        p.set_lock_updates(1 << 10);
+        let mut x = 0;
+        let f0 = 0x1234_5678;
+        ramp(&mut p, f0, &mut x, 1 << 16, 0).unwrap();
+        // Lock is lost immediately on a frequency step
+        x = x.wrapping_add(f0 + (1 << 24));
+        p.update(x, 10, 9);
+        assert!(!p.locked(LOCK_THRESHOLD));
+        // And reacquired later
+        let acquired = ramp(&mut p, f0 + (1 << 24), &mut x, 1 << 16, 0);
+        assert!(acquired.unwrap() > 1 << 10);
+    }
+
+    #[test]
+    fn lock_noise() {
+        let mut p = PLL::default();
+        p.set_lock_updates(1 << 10);
+        let mut x = 0;
+        // Small noise: locks
+        assert!(ramp(&mut p, 0x1234_5678, &mut x, 1 << 16, 1 << 18).is_some());
+        // Random phase: never locks
+        let mut p = PLL::default();
+        p.set_lock_updates(1 << 10);
+        ramp(&mut p, 0x1234_5678, &mut x, 1 << 16, i32::MAX);
+        assert!(!p.locked(LOCK_THRESHOLD));
+        assert!(p.lock_error() > LOCK_THRESHOLD);
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let mut p = PLL::default();
+        p.set_lock_updates(5);
+        p.update(0x1000, 10, 9);
+        let (json, de) = crate::testing::round_trip(&p);
+        assert_eq!(json, r#"{"lock_updates":5}"#);
+        assert_eq!(de.lock_updates, 5);
+        assert_eq!(de.state(), (0, 0, 0));
+        assert_ne!(p.state(), (0, 0, 0));
+        let c = Config::from_bandwidth(1e-3).unwrap();
+        assert_eq!(crate::testing::round_trip(&c).1, c);
+    }
+
+    /// Feed a linear frequency ramp and return the last phase error.
+    fn chirp(p: &mut PLL, config: &Config, rate: i32, n: usize) -> i32 {
+        let (mut x, mut f) = (0i32, 0x1234_5678i32);
+        let mut e = 0;
+        for _ in 0..n {
+            f = f.wrapping_add(rate);
+            x = x.wrapping_add(f);
+            let (y, _) = config.update(p, x);
+            e = y.wrapping_sub(x);
+        }
+        e
+    }
+
+    #[test]
+    fn order() {
+        let (sf, sp) = (10, 9);
+        let config = Config::new(sp, sf);
+        for &rate in [1 << 8, -77, 1000].iter() {
+            // Type-II: constant phase error from the frequency lag
+            // `rate*((1 << sf) - 1)` and the phase gain.
+            let mut p = PLL::default();
+            let e2 = chirp(&mut p, &config, rate, 1 << 16);
+            let want = (rate << sf) - (rate << sp) * ((1 << sf) - 1);
+            assert!((e2 - want).abs() < want.abs() / 100, "{} {}", e2, want);
+            assert_eq!(p.frequency_rate(), 0);
+            // Type-III: no phase error beyond the truncation error (see
+            // `converge()`)
+            let config = Config {
+                order: 3,
+                shift_a: 2 * sf as u32 + 1,
+                ..config
+            };
+            config.validate().unwrap();
+            let mut p = PLL::default();
+            let e3 = chirp(&mut p, &config, rate, 1 << 16);
+            assert!(e3.abs() < 1 << 19, "{}", e3);
+            assert!(e3.abs() < want.abs() / 100, "{} {}", e3, want);
+            let a = p.frequency_rate() as f32 / (1 << sf) as f32;
+            assert!((a - rate as f32).abs() < 0.5, "{}", a);
+        }
+    }
+
+    #[test]
+    fn const_new() {
+        const PLL0: PLL = PLL::new();
+        let (mut p, mut q) = (PLL0, PLL::default());
+        assert_eq!(p.state(), q.state());
+        for i in 0..1000 {
+            assert_eq!(p.update(i << 20, 8, 4), q.update(i << 20, 8, 4));
+        }
+        assert_eq!(p.lock_error(), q.lock_error());
+    }
 }
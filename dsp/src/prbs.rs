@@ -0,0 +1,171 @@
+use super::Reset;
+
+/// Feedback taps of the ITU-T O.150 PRBS7 polynomial `x^7 + x^6 + 1`.
+pub const PRBS7: u32 = 1 << 6 | 1 << 5;
+/// Feedback taps of the ITU-T O.150 PRBS15 polynomial `x^15 + x^14 + 1`.
+pub const PRBS15: u32 = 1 << 14 | 1 << 13;
+/// Feedback taps of the ITU-T O.150 PRBS23 polynomial `x^23 + x^18 + 1`.
+pub const PRBS23: u32 = 1 << 22 | 1 << 17;
+/// Feedback taps of the ITU-T O.150 PRBS31 polynomial `x^31 + x^28 + 1`.
+pub const PRBS31: u32 = 1 << 30 | 1 << 27;
+
+/// Maximal length pseudo random binary sequence generator.
+///
+/// A Fibonacci linear feedback shift register. The new bit is the parity of
+/// the tapped register bits. It is shifted in at the LSB and is the output.
+/// For a polynomial of order `n` the sequence period is `2**n - 1`.
+///
+/// The sequence only depends on the taps and the seed so that a host
+/// can regenerate it for correlation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Prbs {
+    lfsr: u32,
+    taps: u32,
+    seed: u32,
+}
+
+impl Prbs {
+    /// Create a new PRBS generator.
+    ///
+    /// # Args
+    /// * `taps`: Feedback taps, bit `k` for the `x^(k + 1)` term, e.g.
+    ///   `PRBS15`. The highest tap determines the register length.
+    /// * `seed`: Initial register content. Bits above the register length are
+    ///   ignored. Must not be zero.
+    pub fn new(taps: u32, seed: u32) -> Result<Self, &'static str> {
+        if taps == 0 {
+            return Err("Taps must not be zero");
+        }
+        let mut prbs = Self {
+            lfsr: 0,
+            taps,
+            seed: 0,
+        };
+        prbs.set_seed(seed)?;
+        Ok(prbs)
+    }
+
+    // Mask of the register bits.
+    fn mask(&self) -> u32 {
+        u32::MAX >> self.taps.leading_zeros()
+    }
+
+    /// Change the seed and restart the sequence.
+    pub fn set_seed(&mut self, seed: u32) -> Result<(), &'static str> {
+        let seed = seed & self.mask();
+        if seed == 0 {
+            return Err("Seed must not be zero");
+        }
+        self.seed = seed;
+        self.reset();
+        Ok(())
+    }
+
+    /// Restart the sequence at the seed.
+    pub fn reset(&mut self) {
+        self.lfsr = self.seed;
+    }
+
+    /// Current register content.
+    pub fn state(&self) -> u32 {
+        self.lfsr
+    }
+
+    /// Generate the next bit.
+    pub fn next_bit(&mut self) -> bool {
+        let bit = (self.lfsr & self.taps).count_ones() & 1;
+        self.lfsr = ((self.lfsr << 1) | bit) & self.mask();
+        bit != 0
+    }
+
+    /// Generate the next sample.
+    ///
+    /// # Args
+    /// * `amplitude`: Output amplitude.
+    ///
+    /// # Returns
+    /// `amplitude` for a one bit and `-amplitude` (saturating) for a zero bit.
+    pub fn next_i32(&mut self, amplitude: i32) -> i32 {
+        if self.next_bit() {
+            amplitude
+        } else {
+            amplitude.saturating_neg()
+        }
+    }
+}
+
+impl Reset for Prbs {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn check_period(taps: u32, order: u32) {
+        let mut prbs = Prbs::new(taps, 1).unwrap();
+        let mut ones = 0u32;
+        let mut n = 0u32;
+        loop {
+            ones += prbs.next_bit() as u32;
+            n += 1;
+            if prbs.state() == 1 {
+                break;
+            }
+            assert!(n < 1 << order);
+        }
+        // Maximal length and balanced
+        assert_eq!(n, (1 << order) - 1);
+        assert_eq!(ones, 1 << (order - 1));
+    }
+
+    #[test]
+    fn period() {
+        check_period(PRBS7, 7);
+        check_period(PRBS15, 15);
+        check_period(PRBS23, 23);
+    }
+
+    #[test]
+    fn reference() {
+        // PRBS7, all ones seed, MSB first, including the seed
+        const REF: [u8; 16] = [
+            0xfe, 0x04, 0x18, 0x51, 0xe4, 0x59, 0xd4, 0xfa, 0x1c, 0x49, 0xb5,
+            0xbd, 0x8d, 0x2e, 0xe6, 0x55,
+        ];
+        let mut prbs = Prbs::new(PRBS7, 0x7f).unwrap();
+        for _ in 0..2 {
+            let bits = [true; 7]
+                .iter()
+                .copied()
+                .chain((0..REF.len() * 8 - 7).map(|_| prbs.next_bit()));
+            let mut bytes = [0u8; 16];
+            for (i, bit) in bits.enumerate() {
+                bytes[i / 8] |= (bit as u8) << (7 - i % 8);
+            }
+            assert_eq!(bytes, REF);
+            prbs.reset();
+        }
+    }
+
+    #[test]
+    fn seed() {
+        let mut a = Prbs::new(PRBS15, 0x1234).unwrap();
+        let mut b = Prbs::new(PRBS15, 0x1234 | 1 << 20).unwrap();
+        for _ in 0..1000 {
+            assert_eq!(a.next_i32(1 << 20), b.next_i32(1 << 20));
+        }
+        a.set_seed(0x4321).unwrap();
+        assert_eq!(a.state(), 0x4321);
+        assert!(a.set_seed(1 << 15).is_err());
+        assert!(Prbs::new(PRBS23, 0).is_err());
+        assert!(Prbs::new(0, 1).is_err());
+        for _ in 0..100 {
+            let y = a.next_i32(i32::MIN);
+            assert!(y == i32::MIN || y == i32::MAX);
+            assert_eq!(a.next_i32(7).abs(), 7);
+        }
+    }
+}
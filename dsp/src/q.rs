@@ -0,0 +1,179 @@
+use core::ops::{Add, Mul, Neg, Sub};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Signed Q1.31 fixed point fraction.
+///
+/// The value is `.0 / 2**31`, in `[-1, 1)`. This is the `i32` DSP domain
+/// (full scale `1 << 31`), e.g. the `cossin()` outputs and the DAC outputs.
+///
+/// `Mul` is the 64 bit product shifted by 31, rounded half up. `Add`, `Sub`,
+/// `Mul`, and `Neg` saturate: the only overflows are at `-1` (`Q31::MIN`):
+/// `-Q31::MIN` and `Q31::MIN * Q31::MIN` are `Q31::MAX`.
+///
+/// ```
+/// use dsp::Q31;
+/// let half = Q31::from_f32(0.5);
+/// assert_eq!(half.0, 1 << 30);
+/// assert_eq!(half * half, Q31(1 << 29));
+/// assert_eq!(half + half, Q31::MAX);
+/// assert_eq!((half * Q31::MIN).to_f32(), -0.5);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Q31(pub i32);
+
+/// Signed Q1.15 fixed point fraction.
+///
+/// The value is `.0 / 2**15`, in `[-1, 1)`, e.g. the ADC samples. See `Q31`
+/// for the arithmetic.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Q15(pub i16);
+
+macro_rules! impl_q {
+    ($Q:ident, $T:ty, $W:ty, $bits:literal) => {
+        impl $Q {
+            /// Number of fractional bits.
+            pub const SHIFT: u32 = $bits;
+            /// `-1`
+            pub const MIN: Self = Self(<$T>::MIN);
+            /// `1 - 2**-SHIFT`
+            pub const MAX: Self = Self(<$T>::MAX);
+            /// `0`
+            pub const ZERO: Self = Self(0);
+
+            /// Convert from floating point.
+            ///
+            /// Rounded to nearest and saturated to `[MIN, MAX]`. NaN is zero.
+            pub fn from_f32(x: f32) -> Self {
+                Self(libm::roundf(x * (1u32 << $bits) as f32) as $T)
+            }
+
+            /// Convert to floating point.
+            pub fn to_f32(self) -> f32 {
+                self.0 as f32 / (1u32 << $bits) as f32
+            }
+        }
+
+        impl Add for $Q {
+            type Output = Self;
+            fn add(self, other: Self) -> Self {
+                Self(self.0.saturating_add(other.0))
+            }
+        }
+
+        impl Sub for $Q {
+            type Output = Self;
+            fn sub(self, other: Self) -> Self {
+                Self(self.0.saturating_sub(other.0))
+            }
+        }
+
+        impl Neg for $Q {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self(self.0.saturating_neg())
+            }
+        }
+
+        impl Mul for $Q {
+            type Output = Self;
+            fn mul(self, other: Self) -> Self {
+                let p = self.0 as $W * other.0 as $W + (1 << ($bits - 1));
+                Self((p >> $bits).min(<$T>::MAX as $W) as $T)
+            }
+        }
+    };
+}
+
+impl_q!(Q31, i32, i64, 31);
+impl_q!(Q15, i16, i32, 15);
+
+impl From<Q15> for Q31 {
+    /// Exact conversion.
+    fn from(x: Q15) -> Self {
+        Self((x.0 as i32) << 16)
+    }
+}
+
+impl Q31 {
+    /// Convert to `Q15`, rounded half up and saturating.
+    pub fn to_q15(self) -> Q15 {
+        let y = (self.0 as i64 + (1 << 15)) >> 16;
+        Q15(y.min(i16::MAX as i64) as i16)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    #[test]
+    fn identities() {
+        let mut rng = Xoshiro::new(1);
+        for _ in 0..10_000 {
+            let (x, y) =
+                (Q31(rng.next_u32() as i32), Q31(rng.next_u32() as i32));
+            assert_eq!(x * y, y * x);
+            assert_eq!(x + Q31::ZERO, x);
+            assert_eq!(x * Q31::ZERO, Q31::ZERO);
+            assert!((x * Q31::MAX).0.wrapping_sub(x.0).abs() <= 1);
+            if x != Q31::MIN {
+                assert_eq!(-(-x), x);
+                assert_eq!(x * Q31::MIN, -x);
+                assert_eq!(x - x, Q31::ZERO);
+            }
+            let (a, b) = (x.to_q15(), y.to_q15());
+            assert_eq!(a * b, b * a);
+            assert_eq!(Q31::from(a).to_q15(), a);
+            // Q15 multiplication is Q31 multiplication rounded
+            let p = Q31::from(a) * Q31::from(b);
+            assert!((Q31::from(a * b).0 - p.0).abs() <= 1 << 15);
+        }
+    }
+
+    #[test]
+    fn saturation() {
+        assert_eq!(Q31::MAX + Q31::MAX, Q31::MAX);
+        assert_eq!(Q31::MIN + Q31::MIN, Q31::MIN);
+        assert_eq!(Q31::MIN - Q31::MAX, Q31::MIN);
+        assert_eq!(Q31::MAX - Q31::MIN, Q31::MAX);
+        assert_eq!(-Q31::MIN, Q31::MAX);
+        assert_eq!(Q31::MIN * Q31::MIN, Q31::MAX);
+        assert_eq!(Q31::MAX * Q31::MAX, Q31(i32::MAX - 1));
+        assert_eq!(Q31::MIN * Q31::MAX, Q31(i32::MIN + 1));
+        assert_eq!(Q15::MIN * Q15::MIN, Q15::MAX);
+        assert_eq!(-Q15::MIN, Q15::MAX);
+        assert_eq!(Q15::MAX + Q15(1), Q15::MAX);
+        assert_eq!(Q31::MAX.to_q15(), Q15::MAX);
+        assert_eq!(Q31::MIN.to_q15(), Q15::MIN);
+    }
+
+    #[test]
+    fn conversion() {
+        // Extremes
+        assert_eq!(Q31::MIN.to_f32(), -1.);
+        assert_eq!(Q31::from_f32(-1.), Q31::MIN);
+        assert_eq!(Q31::from_f32(Q31::MAX.to_f32()), Q31::MAX);
+        assert_eq!(Q31::from_f32(2.), Q31::MAX);
+        assert_eq!(Q31::from_f32(-2.), Q31::MIN);
+        assert_eq!(Q31::from_f32(f32::NAN), Q31::ZERO);
+        assert_eq!(Q31::from(Q15::MIN), Q31::MIN);
+        assert_eq!(Q31::from(Q15::MAX).0, i32::MAX - 0xffff);
+        // Q15 round trips exactly
+        for x in i16::MIN..=i16::MAX {
+            assert_eq!(Q15::from_f32(Q15(x).to_f32()), Q15(x));
+        }
+        // Q31 round trips within the f32 mantissa
+        let mut rng = Xoshiro::new(2);
+        for _ in 0..10_000 {
+            let x = Q31(rng.next_u32() as i32);
+            let y = Q31::from_f32(x.to_f32());
+            assert!((x.0 as i64 - y.0 as i64).abs() <= 1 << 7, "{:?}", x);
+        }
+    }
+}
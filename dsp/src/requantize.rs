@@ -0,0 +1,207 @@
+use super::{Filter, Reset};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Noise transfer function order of a `Requantizer`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum NoiseShaping {
+    /// Plain truncation toward zero, white quantization noise.
+    None,
+    /// First order, noise transfer function `1 - z^-1`.
+    First,
+    /// Second order, noise transfer function `(1 - z^-1)^2`.
+    Second,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for NoiseShaping {
+    fn default() -> Self {
+        NoiseShaping::None
+    }
+}
+
+// Limit of the error state. The error is within `[0, 1 << 16)` unless the
+// output saturates. Limiting it keeps the loop stable.
+const ERROR_LIMIT: i64 = 1 << 17;
+
+/// Error feedback noise shaping requantizer from the `i32` DSP domain to
+/// 16 bit DAC codes.
+///
+/// The quantizer truncates 16 bits and the quantization error is fed back
+/// such that its spectrum is shaped by the noise transfer function. This
+/// moves the quantization noise from low frequencies to high frequencies
+/// (Nyquist). The total noise power increases (by 4.8 dB for first and
+/// 10 dB for second order). The time average of the output matches that of
+/// the input to within the DAC resolution without the truncation bias.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Requantizer {
+    /// Noise transfer function order.
+    pub order: NoiseShaping,
+    // Past quantization errors, most recent first
+    #[cfg_attr(feature = "serde", serde(skip))]
+    error: [i32; 2],
+}
+
+impl Requantizer {
+    /// Create a new requantizer.
+    pub const fn new(order: NoiseShaping) -> Self {
+        Self {
+            order,
+            error: [0; 2],
+        }
+    }
+
+    /// Requantize a sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample in the `i32` DSP domain (full scale `1 << 31`).
+    ///
+    /// # Returns
+    /// Output sample (DAC LSB), saturating.
+    pub fn update(&mut self, x: i32) -> i16 {
+        self.update_dithered(x, 0)
+    }
+
+    /// Requantize a sample with dither.
+    ///
+    /// The dither is added before quantization and is subject to the noise
+    /// shaping. See `noise::Xoshiro::tpdf_dither()`.
+    ///
+    /// # Args
+    /// * `x`: Input sample in the `i32` DSP domain (full scale `1 << 31`).
+    /// * `dither`: Dither sample, `i32` domain.
+    ///
+    /// # Returns
+    /// Output sample (DAC LSB), saturating.
+    pub fn update_dithered(&mut self, x: i32, dither: i32) -> i16 {
+        let e = [self.error[0] as i64, self.error[1] as i64];
+        let v = x as i64
+            + match self.order {
+                NoiseShaping::None => 0,
+                NoiseShaping::First => e[0],
+                NoiseShaping::Second => 2 * e[0] - e[1],
+            };
+        let q = v + dither as i64;
+        // Without noise shaping, truncate toward zero like a float to integer
+        // conversion.
+        let bias = match self.order {
+            NoiseShaping::None if q < 0 => (1 << 16) - 1,
+            _ => 0,
+        };
+        let y = ((q + bias) >> 16).max(i16::MIN as i64).min(i16::MAX as i64);
+        let e0 = (v - (y << 16)).clamp(-ERROR_LIMIT, ERROR_LIMIT);
+        self.error = [e0 as i32, self.error[0]];
+        y as i16
+    }
+}
+
+impl Reset for Requantizer {
+    fn reset(&mut self) {
+        self.error = [0; 2];
+    }
+}
+
+impl Filter for Requantizer {
+    type Config = NoiseShaping;
+    fn config(&self) -> NoiseShaping {
+        self.order
+    }
+    /// The output is in DAC LSB, see `Requantizer::update()`.
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x) as i32
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+
+    // In-band power of the requantization error of a slow ramp.
+    fn in_band_error(order: NoiseShaping, dither: bool) -> f64 {
+        let mut q = Requantizer::new(order);
+        let mut rng = Xoshiro::new(1);
+        // Two cascaded single pole lowpasses at 1e-3 of the sample rate
+        let k = 2. * core::f64::consts::PI * 1e-3;
+        let mut lp = [0f64; 2];
+        let n = 1 << 18;
+        let mut power = 0.;
+        for i in 0..n {
+            // Ramp over 12 LSB
+            let x = -(1 << 20) - (i as i32) * 3 - (i as i32 >> 7);
+            let d = if dither { rng.tpdf_dither(16) } else { 0 };
+            let y = q.update_dithered(x, d);
+            let err = ((y as i64) << 16) as f64 - x as f64;
+            lp[0] += k * (err - lp[0]);
+            lp[1] += k * (lp[0] - lp[1]);
+            // Skip the settling
+            if i > n / 8 {
+                power += lp[1] * lp[1];
+            }
+        }
+        power / (1u64 << 32) as f64
+    }
+
+    #[test]
+    fn shaping() {
+        let none = in_band_error(NoiseShaping::None, false);
+        let first = in_band_error(NoiseShaping::First, false);
+        let second = in_band_error(NoiseShaping::Second, false);
+        let db = |p: f64| 10. * libm::log10(p / none);
+        println!("first: {} dB, second: {} dB", db(first), db(second));
+        assert!(db(first) < -10.);
+        assert!(db(second) < -20.);
+        // Shaped dither also stays out of band
+        let second_dither = in_band_error(NoiseShaping::Second, true);
+        println!("dithered second: {} dB", db(second_dither));
+        assert!(db(second_dither) < -20.);
+    }
+
+    #[test]
+    fn dc() {
+        // The mean output matches the input
+        for &order in [NoiseShaping::First, NoiseShaping::Second].iter() {
+            let mut q = Requantizer::new(order);
+            let x = (1234 << 16) + 12345;
+            let n = 1 << 16;
+            let sum: i64 = (0..n).map(|_| q.update(x) as i64).sum();
+            let mean = (sum << 16) as f64 / n as f64;
+            assert!((mean - x as f64).abs() < 16., "{:?} {}", order, mean);
+        }
+        // Plain truncation
+        let mut q = Requantizer::new(NoiseShaping::None);
+        assert_eq!(q.update((5 << 16) + 0xffff), 5);
+        assert_eq!(q.update(-1), 0);
+    }
+
+    #[test]
+    fn truncation() {
+        // Without noise shaping the output matches the float to integer
+        // conversion of the DAC output it replaces.
+        let mut q = Requantizer::new(NoiseShaping::None);
+        for &y in [-3.75f32, -1.5, -1., -0.25, -1e-5, 0.5, 2.99, -32768.].iter()
+        {
+            assert_eq!(
+                q.update((y * (1 << 16) as f32) as i32),
+                y as i16,
+                "{}",
+                y
+            );
+        }
+    }
+
+    #[test]
+    fn saturation() {
+        let mut q = Requantizer::new(NoiseShaping::Second);
+        for &(x, y) in [(i32::MAX, i16::MAX), (i32::MIN, i16::MIN)].iter() {
+            // Transient from the error state
+            let ys: Vec<i16> = (0..100).map(|_| q.update(x)).collect();
+            assert!(ys[4..].iter().all(|&yi| yi == y), "{:?}", ys);
+        }
+        // Recovers quickly
+        let ys: Vec<i16> = (0..10).map(|_| q.update(0)).collect();
+        assert!(ys[4..].iter().all(|y| y.abs() <= 2), "{:?}", ys);
+    }
+}
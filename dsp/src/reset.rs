@@ -0,0 +1,174 @@
+/// Clear the state of a stateful processing block.
+///
+/// After `reset()` a block produces the same output as a freshly constructed
+/// block with the same configuration (coefficients, gains, limits, time
+/// constants). Only the state (delay lines, accumulators, estimates, flags)
+/// is cleared.
+///
+/// When the block is shared with an interrupt, reset it within the critical
+/// section (e.g. an RTIC resource lock) it is updated in.
+pub trait Reset {
+    /// Clear the state.
+    fn reset(&mut self);
+}
+
+impl<T: Reset, const N: usize> Reset for [T; N] {
+    fn reset(&mut self) {
+        for x in self.iter_mut() {
+            x.reset();
+        }
+    }
+}
+
+impl<T: Reset> Reset for Option<T> {
+    fn reset(&mut self) {
+        if let Some(x) = self {
+            x.reset();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::noise::Xoshiro;
+    use crate::{
+        accu::Accu, agc::Agc, allan, alpha_beta::AlphaBeta, boxcar, cic::Cic,
+        clamp::Clamp, dcblock::DcBlock, fir, fir_int, goertzel::Goertzel,
+        halfband::HalfBand, histogram::Histogram, iir, iir_int,
+        integrator::Integrator, jitter, lockin::Lockin, lowpass::Lowpass2,
+        median::Median, minmax::MinMax, peak::PeakDetector, pll::PLL, prbs,
+        requantize, rms::Rms, rpll::RPLL, signal_generator, slew, sweep,
+        unwrap, xcorr::XCorr,
+    };
+
+    // Run a block on a noisy ramp, reset it, and compare the output on the
+    // continued ramp with that of a fresh block.
+    fn check<T: Reset + Clone, O: PartialEq + core::fmt::Debug>(
+        fresh: T,
+        mut update: impl FnMut(&mut T, i32) -> O,
+    ) {
+        let mut rng = Xoshiro::new(1);
+        let mut u = -1 << 24;
+        let mut next = || {
+            u += (1 << 14) + rng.uniform_i32(1 << 14);
+            u
+        };
+        let mut x = fresh.clone();
+        for _ in 0..1000 {
+            update(&mut x, next());
+        }
+        x.reset();
+        let mut y = fresh;
+        for i in 0..1000 {
+            let u = next();
+            assert_eq!(
+                update(&mut x, u),
+                update(&mut y, u),
+                "{} {}",
+                core::any::type_name::<T>(),
+                i
+            );
+        }
+    }
+
+    #[test]
+    fn fresh() {
+        check(Accu::new(0, 0x1234_5678), |a, _| a.next());
+        check(Agc::new(1 << 24, 8, 1 << 10), |a, x| a.update(x));
+        check(allan::Accumulator::<4>::new(), |a, x| {
+            a.update(x as i64);
+            a.read()
+        });
+        check(AlphaBeta::from_bandwidth(1e-2), |a, x| a.update(x));
+        let rounding = boxcar::Rounding::HalfUp;
+        check(boxcar::MovingAverage::<5>::new(rounding), |a, x| {
+            a.update(x)
+        });
+        check(boxcar::MovingAverageF32::<5>::default(), |a, x| {
+            a.update(x as f32)
+        });
+        check(Cic::<3>::new(4).unwrap(), |a, x| a.update(x >> 8));
+        check(Clamp::new(0.5), |a, x| {
+            (a.update(x as f32, -1e6, 1e6), a.take_railed())
+        });
+        check(DcBlock::new(8), |a, x| a.update(x));
+        check(fir::Fir::new([0.1, 0.2, 0.3]), |a, x| a.update(x as f32));
+        check(fir_int::Fir::new([1 << 29; 3]), |a, x| a.update(x));
+        check(Goertzel::new(0.1), |a, x| {
+            a.update(x as f32);
+            a.len()
+        });
+        check(HalfBand::new([1 << 29, -1 << 26]), |a, x| a.update2(x, -x));
+        check(Histogram::<4>::new(0, 23), |a, x| {
+            a.update(x);
+            *a.counts()
+        });
+        let mut biquad = iir::IIR::new(1., -1e9, 1e9);
+        biquad.ba = iir::Vec5([0.05, 0.1, 0.05, 1.2, -0.4]);
+        check(iir::Vec5::default(), |a, x| biquad.update(a, x as f32));
+        let mut biquad = iir_int::IIR::default();
+        biquad.ba = iir_int::Vec5::lowpass(1e-2, 0.7, 1.);
+        check(iir_int::Vec5::default(), |a, x| biquad.update(a, x >> 2));
+        check(Integrator::new(4, Some(12)), |a, x| a.update(x, false));
+        check(jitter::Stats::default(), |a, x| {
+            a.update(x as u32);
+            a.count()
+        });
+        check(Lockin::default(), |a, x| a.update((x >> 8) as i16, x, 4));
+        check(Lowpass2::default(), |a, x| a.update(x >> 8, 6));
+        check(Median::<i32, 5>::new(), |a, x| a.update(x));
+        check(MinMax::<i32>::new(), |a, x| {
+            a.update(x);
+            *a
+        });
+        check(PeakDetector::new(4), |a, x| a.update(x));
+        check(PLL::default(), |a, x| a.update(x, 12, 11));
+        check(prbs::Prbs::new(prbs::PRBS7, 3).unwrap(), |a, _| {
+            a.next_bit()
+        });
+        let noise_shaping = requantize::NoiseShaping::Second;
+        check(requantize::Requantizer::new(noise_shaping), |a, x| {
+            a.update(x << 7)
+        });
+        check(Rms::<4>::new(), |a, x| (a.update(x), a.rms()));
+        check(RPLL::new(4), |a, x| a.update(Some(x << 4), 16, 15));
+        let waveform = signal_generator::Waveform::Triangle;
+        check(
+            signal_generator::SignalGenerator::new(
+                waveform,
+                1 << 20,
+                1 << 30,
+                7,
+            ),
+            |a, _| a.next(),
+        );
+        check(slew::SlewLimiter::new(1 << 16), |a, x| a.update(x));
+        let law = sweep::SweepLaw::Linear;
+        let mode = sweep::SweepMode::Loop;
+        check(
+            sweep::Sweep::new(1 << 20, 1 << 28, 1 << 24, law, mode).unwrap(),
+            |a, _| a.next(),
+        );
+        check(unwrap::Unwrapper::default(), |a, x| a.update(x << 7));
+        check(unwrap::SaturatingUnwrapper::default(), |a, x| {
+            a.update(x << 7)
+        });
+        check(unwrap::UnwrapperF32::new(1.), |a, x| {
+            a.update(x as f32 / (1 << 25) as f32)
+        });
+        check(XCorr::<4>::new(7).unwrap(), |a, x| {
+            a.update(x & 1 != 0, x);
+            a.read()
+        });
+    }
+
+    #[test]
+    fn containers() {
+        let mut x = [Some(PeakDetector::new(4)), None];
+        x[0].as_mut().unwrap().update(1 << 20);
+        x.reset();
+        assert_eq!(x[0].unwrap().peak(), 0);
+        assert!(x[1].is_none());
+    }
+}
@@ -0,0 +1,135 @@
+use super::{isqrt, Reset};
+
+/// Maximum window length exponent of `Rms`.
+///
+/// The accumulator sums squares of `x >> 8`, at most `1 << 46` each.
+/// For full scale input `1 << 17` of them fit into a `u64`.
+pub const RMS_MAX_WINDOW_LOG2: u32 = 17;
+
+/// True RMS measurement over consecutive windows of `1 << N` samples.
+///
+/// The squares are accumulated in `u64` with the input truncated to 24 bits.
+/// Two accumulators are used alternately (ping-pong): one accumulates the
+/// current window while the other holds the last complete window.
+/// `rms()` thus never returns a partially accumulated value.
+///
+/// `N` must not exceed `RMS_MAX_WINDOW_LOG2`. This is checked at compile
+/// time.
+#[derive(Copy, Clone, Debug)]
+pub struct Rms<const N: u32> {
+    acc: [u64; 2],
+    // Index of the accumulator of the current window
+    active: usize,
+    count: u32,
+}
+
+impl<const N: u32> Default for Rms<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: u32> Rms<N> {
+    // Fails to evaluate for a window exponent that may overflow.
+    const WINDOW_OK: () = [()][(N > RMS_MAX_WINDOW_LOG2) as usize];
+
+    /// Create a new RMS accumulator.
+    ///
+    /// `rms()` returns zero until the first window is complete.
+    #[allow(clippy::let_unit_value)]
+    pub const fn new() -> Self {
+        let _ = Self::WINDOW_OK;
+        Self {
+            acc: [0; 2],
+            active: 0,
+            count: 0,
+        }
+    }
+
+    /// Accumulate a sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    ///
+    /// # Returns
+    /// `true` if a window has been completed with this sample.
+    pub fn update(&mut self, x: i32) -> bool {
+        let x = (x >> 8) as i64;
+        self.acc[self.active] += (x * x) as u64;
+        self.count += 1;
+        if self.count < 1 << N {
+            return false;
+        }
+        self.count = 0;
+        self.active ^= 1;
+        self.acc[self.active] = 0;
+        true
+    }
+
+    /// RMS of the last complete window.
+    ///
+    /// # Returns
+    /// The RMS value, rounded down to a multiple of `1 << 8` and saturated
+    /// to `i32::MAX`.
+    pub fn rms(&self) -> i32 {
+        let mean = self.acc[self.active ^ 1] >> N;
+        ((isqrt(mean) as u64) << 8).min(i32::MAX as u64) as i32
+    }
+}
+
+impl<const N: u32> Reset for Rms<N> {
+    fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn square() {
+        let mut rms = Rms::<RMS_MAX_WINDOW_LOG2>::new();
+        assert_eq!(rms.rms(), 0);
+        // Full scale, no overflow
+        for i in 0..1 << RMS_MAX_WINDOW_LOG2 {
+            let x = if i & 1 == 0 { i32::MIN } else { i32::MAX };
+            rms.update(x);
+        }
+        assert!(i32::MAX - rms.rms() <= 1 << 8, "{}", rms.rms());
+        let mut rms = Rms::<4>::new();
+        for i in 0..16 {
+            rms.update(if i & 4 == 0 { 1 << 30 } else { -1 << 30 });
+        }
+        assert_eq!(rms.rms(), 1 << 30);
+    }
+
+    #[test]
+    fn sine() {
+        let mut rms = Rms::<12>::new();
+        let a = 1.2e9;
+        for i in 0..1 << 12 {
+            // Integer number of periods
+            let p = 2. * core::f64::consts::PI * 7. * i as f64 / 4096.;
+            rms.update((a * p.sin()) as i32);
+        }
+        let want = a / core::f64::consts::SQRT_2;
+        let lsb = (1 << 16) as f64;
+        assert!((rms.rms() as f64 - want).abs() < lsb, "{}", rms.rms());
+    }
+
+    #[test]
+    fn ping_pong() {
+        let mut rms = Rms::<3>::new();
+        for k in 1..10 {
+            let x = k << 20;
+            for i in 0..8 {
+                // The previous window, never a partial one
+                let want = if k > 1 { (k - 1) << 20 } else { 0 };
+                assert_eq!(rms.rms(), want);
+                assert_eq!(rms.update(-x), i == 7);
+            }
+            assert_eq!(rms.rms(), x);
+        }
+    }
+}
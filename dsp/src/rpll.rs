@@ -1,3 +1,5 @@
+use super::{Error, Reset};
+
 /// Reciprocal PLL.
 ///
 /// Consumes noisy, quantized timestamps of a reference signal and reconstructs
@@ -5,13 +7,22 @@
 /// 1 << 32 of) that reference.
 /// In other words, `update()` rate ralative to reference frequency,
 /// `u32::MAX` corresponding to both being equal.
+///
+/// If enabled (see `set_holdover()`), the RPLL enters holdover when no timestamp has been
+/// received for more than the configured number of `update()` cycles. In holdover the
+/// frequency is frozen at the frequency loop estimate and the phase keeps advancing
+/// accordingly. The next timestamp reinitializes the phase without updating the frequency.
 #[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct RPLL {
     dt2: u8, // 1 << dt2 is the counter rate to update() rate ratio
     x: i32,  // previous timestamp
     ff: u32, // current frequency estimate from frequency loop
     f: u32,  // current frequency estimate from both frequency and phase loop
     y: i32,  // current phase estimate
+    ho: u32, // holdover after this many update() cycles without timestamp
+    n: u32,  // update() cycles since the last timestamp
+    h: bool, // holdover active
 }
 
 impl RPLL {
@@ -22,13 +33,84 @@ impl RPLL {
     ///
     /// Returns:
     /// Initialized RPLL instance.
-    pub fn new(dt2: u8) -> Self {
+    pub const fn new(dt2: u8) -> Self {
         Self {
             dt2,
-            ..Default::default()
+            x: 0,
+            ff: 0,
+            f: 0,
+            y: 0,
+            ho: 0,
+            n: 0,
+            h: false,
         }
     }
 
+    /// Check the `update()` settling times for an inverse update rate.
+    ///
+    /// Args:
+    /// * dt2: inverse update() rate, at most 30.
+    /// * shift_frequency: Frequency lock settling time, `dt2 + 1..=32`.
+    /// * shift_phase: Phase lock settling time, `dt2..=dt2 + 31`.
+    ///
+    /// Returns:
+    /// `Error::ShiftRange` if any of them is out of range.
+    pub fn check_shifts(
+        dt2: u8,
+        shift_frequency: u8,
+        shift_phase: u8,
+    ) -> Result<(), Error> {
+        if dt2 > 30
+            || !(dt2 + 1..=32).contains(&shift_frequency)
+            || !(dt2..=dt2 + 31).contains(&shift_phase)
+        {
+            return Err(Error::ShiftRange);
+        }
+        Ok(())
+    }
+
+    /// Configure holdover.
+    ///
+    /// Args:
+    /// * holdover: Number of `update()` cycles without a timestamp after which the RPLL
+    ///   enters holdover. 0 disables holdover (the default).
+    pub fn set_holdover(&mut self, holdover: u32) {
+        self.ho = holdover;
+    }
+
+    /// Current state.
+    ///
+    /// Returns:
+    /// A tuple of the last timestamp, the phase estimate, the frequency estimate,
+    /// and the frequency loop estimate.
+    pub fn state(&self) -> (i32, i32, u32, u32) {
+        (self.x, self.y, self.f, self.ff)
+    }
+
+    /// Reconstructed reference phase at the last `update()`.
+    ///
+    /// This is the phase returned by `update()`: the phase of the reference at
+    /// the `update()` invocation, i.e. at the counter time that is the next
+    /// multiple of `1 << dt2` after the timestamps supplied (the batch
+    /// boundary), wrapping at the i32 boundary (pi).
+    pub fn phase(&self) -> i32 {
+        self.y
+    }
+
+    /// Reference frequency estimate at the last `update()`.
+    ///
+    /// This is the frequency returned by `update()`: the reference phase
+    /// increment per `update()` cycle, `1 << 32` is one turn.
+    pub fn frequency(&self) -> u32 {
+        self.f
+    }
+
+    /// Whether the RPLL was in holdover at the last `update()`, see
+    /// `set_holdover()`.
+    pub fn holdover(&self) -> bool {
+        self.h
+    }
+
     /// Advance the RPLL and optionally supply a new timestamp.
     ///
     /// Args:
@@ -40,20 +122,43 @@ impl RPLL {
     /// * shift_phase: Phase lock settling time. Usually one less than
     ///   `shift_frequency` (see there).
     ///
+    /// The shifts are only checked in debug builds, see `check_shifts()`.
+    ///
     /// Returns:
-    /// A tuple containing the current phase (wrapping at the i32 boundary, pi) and
-    /// frequency.
+    /// A tuple containing the current phase (wrapping at the i32 boundary, pi)
+    /// and frequency. See `phase()`, `frequency()`, and `holdover()`.
     pub fn update(
         &mut self,
         input: Option<i32>,
         shift_frequency: u8,
         shift_phase: u8,
     ) -> (i32, u32) {
-        debug_assert!(shift_frequency >= self.dt2);
-        debug_assert!(shift_phase >= self.dt2);
+        debug_assert!(Self::check_shifts(
+            self.dt2,
+            shift_frequency,
+            shift_phase
+        )
+        .is_ok());
         // Advance phase
         self.y = self.y.wrapping_add(self.f as i32);
-        if let Some(x) = input {
+        if input.is_some() {
+            self.n = 0;
+        } else {
+            self.n = self.n.saturating_add(1);
+            if self.ho > 0 && self.n > self.ho && !self.h {
+                // Freeze the frequency at the frequency loop estimate.
+                self.h = true;
+                self.f = self.ff;
+            }
+        }
+        if let (Some(x), true) = (input, self.h) {
+            // Resume from holdover. The previous timestamp is stale and can not
+            // be used to update the frequency. Reinitialize the phase.
+            self.x = x;
+            let dt = (x.wrapping_neg() & ((1 << self.dt2) - 1)) as u32;
+            self.y = (self.f >> self.dt2).wrapping_mul(dt) as i32;
+            self.h = false;
+        } else if let Some(x) = input {
             // Reference period in counter cycles
             let dx = x.wrapping_sub(self.x);
             // Store timestamp for next time.
@@ -80,9 +185,47 @@ impl RPLL {
     }
 }
 
+/// Convert an RPLL frequency word to Hz.
+///
+/// Args:
+/// * word: Frequency word as returned by `RPLL::update()` (reference phase increment
+///   per `update()` cycle, `1 << 32` is one turn).
+/// * update_rate_hz: `update()` rate in Hz, i.e. the counter rate divided by `1 << dt2`.
+///
+/// Returns:
+/// The reference frequency in Hz, from 0 up to the update rate.
+pub fn frequency_to_hz(word: u32, update_rate_hz: f32) -> f32 {
+    (word as f64 * update_rate_hz as f64 / (1u64 << 32) as f64) as f32
+}
+
+/// Convert a reference frequency in Hz to an RPLL frequency word.
+///
+/// This is the inverse of `frequency_to_hz()`. The word is rounded "half up" and wrapped
+/// modulo the update rate (negative frequencies alias to positive words).
+///
+/// Args:
+/// * hz: Reference frequency in Hz.
+/// * update_rate_hz: `update()` rate in Hz.
+///
+/// Returns:
+/// The frequency word.
+pub fn hz_to_frequency(hz: f32, update_rate_hz: f32) -> u32 {
+    super::pll::to_word(hz, update_rate_hz) as u32
+}
+
+impl Reset for RPLL {
+    fn reset(&mut self) {
+        *self = Self {
+            dt2: self.dt2,
+            ho: self.ho,
+            ..Default::default()
+        };
+    }
+}
+
 #[cfg(test)]
 mod test {
-    use super::RPLL;
+    use super::{frequency_to_hz, hz_to_frequency, Error, RPLL};
     use ndarray::prelude::*;
     use rand::{prelude::*, rngs::StdRng};
     use std::vec::Vec;
@@ -92,6 +235,48 @@ mod test {
         let _ = RPLL::new(8);
     }
 
+    #[test]
+    fn const_new() {
+        const RPLL8: RPLL = RPLL::new(8);
+        let mut p = RPLL8;
+        let mut q = RPLL {
+            dt2: 8,
+            ..Default::default()
+        };
+        assert_eq!(p.state(), q.state());
+        for i in 0..1000 {
+            let x = Some(i * 0x1234);
+            assert_eq!(p.update(x, 12, 11), q.update(x, 12, 11));
+        }
+    }
+
+    #[test]
+    fn shifts() {
+        assert_eq!(RPLL::check_shifts(8, 32, 39), Ok(()));
+        assert_eq!(RPLL::check_shifts(8, 9, 8), Ok(()));
+        for &(dt2, sf, sp) in
+            [(31, 32, 32), (8, 8, 8), (8, 33, 8), (8, 9, 7), (8, 9, 40)].iter()
+        {
+            assert_eq!(RPLL::check_shifts(dt2, sf, sp), Err(Error::ShiftRange));
+        }
+    }
+
+    #[test]
+    fn hz() {
+        let rate = 100e6 / 1024.;
+        assert_eq!(frequency_to_hz(0, rate), 0.);
+        assert_eq!(frequency_to_hz(1 << 31, rate), rate / 2.);
+        assert_eq!(frequency_to_hz(u32::MAX, rate), rate);
+        assert_eq!(hz_to_frequency(rate / 2., rate), 1 << 31);
+        assert_eq!(hz_to_frequency(-rate / 4., rate), 3 << 30);
+        assert_eq!(hz_to_frequency(rate, rate), 0);
+        for &word in [1, 12345, 1 << 24, 3 << 30, u32::MAX - 7].iter() {
+            let have = hz_to_frequency(frequency_to_hz(word, rate), rate);
+            let err = (have.wrapping_sub(word) as i32).abs();
+            assert!(err <= 1.max((word as f32 * f32::EPSILON) as i32));
+        }
+    }
+
     struct Harness {
         rpll: RPLL,
         shift_frequency: u8,
@@ -102,6 +287,8 @@ mod test {
         next_noisy: i32,
         time: i32,
         rng: StdRng,
+        gap: bool,
+        holdover: bool,
     }
 
     impl Harness {
@@ -116,6 +303,8 @@ mod test {
                 next_noisy: 111,
                 time: 0,
                 rng: StdRng::seed_from_u64(42),
+                gap: false,
+                holdover: false,
             }
         }
 
@@ -127,13 +316,21 @@ mod test {
             let mut y = Vec::<f32>::new();
             let mut f = Vec::<f32>::new();
             for _ in 0..n {
-                let timestamp = if self.time - self.next_noisy >= 0 {
-                    assert!(self.time - self.next_noisy < 1 << self.rpll.dt2);
+                let timestamp = if self.time.wrapping_sub(self.next_noisy) >= 0
+                {
+                    assert!(
+                        self.time.wrapping_sub(self.next_noisy)
+                            < 1 << self.rpll.dt2
+                    );
                     self.next = self.next.wrapping_add(self.period);
                     let timestamp = self.next_noisy;
                     let p_noise = self.rng.gen_range(-self.noise..=self.noise);
                     self.next_noisy = self.next.wrapping_add(p_noise);
-                    Some(timestamp)
+                    if self.gap {
+                        None
+                    } else {
+                        Some(timestamp)
+                    }
                 } else {
                     None
                 };
@@ -142,6 +339,7 @@ mod test {
                     self.shift_frequency,
                     self.shift_phase,
                 );
+                self.holdover = self.rpll.holdover();
 
                 let y_ref = (self.time.wrapping_sub(self.next) as i64
                     * (1i64 << 32)
@@ -267,6 +465,46 @@ mod test {
         h.measure(1 << 16, [2e-4, 6e-3, 2e-4, 2e-3]);
     }
 
+    #[test]
+    fn holdover() {
+        let mut h = Harness::default();
+        h.rpll.set_holdover(4);
+        h.measure(1 << 16, [1e-11, 4e-8, 2e-8, 2e-8]);
+        assert!(!h.holdover);
+
+        // Reference edges stop arriving
+        h.gap = true;
+        let (_, f) = h.run(1 << 10);
+        assert!(h.holdover);
+        // The frequency is frozen
+        assert!(f[8..].iter().all(|&fi| fi == f[8]));
+        assert!(f[8].abs() < 1e-8);
+
+        // Reference edges resume: no large frequency or phase transients
+        h.gap = false;
+        let (y, f) = h.run(16);
+        assert!(!h.holdover);
+        assert!(y.iter().all(|yi| yi.abs() < 1e-6));
+        assert!(f.iter().all(|fi| fi.abs() < 1e-6));
+        h.measure(1 << 16, [1e-11, 4e-8, 2e-8, 2e-8]);
+    }
+
+    #[test]
+    fn wrap() {
+        // The timestamps and the update times wrap at the i32 boundary
+        // during the measurement.
+        let mut h = Harness::default();
+        h.time = i32::MAX - (1 << 20) + 1;
+        h.next = h.time.wrapping_add(111);
+        h.next_noisy = h.next;
+        h.measure(1 << 16, [1e-11, 4e-8, 2e-8, 2e-8]);
+        assert!(h.time < 0);
+        // The accessors return the last update
+        let (y, f) = h.rpll.update(None, h.shift_frequency, h.shift_phase);
+        assert_eq!((h.rpll.phase(), h.rpll.frequency()), (y, f));
+        assert_eq!(h.rpll.state().1, y);
+    }
+
     #[test]
     fn batch_fast_narrow() {
         let mut h = Harness::default();
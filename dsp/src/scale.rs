@@ -0,0 +1,157 @@
+//! Conversions between converter codes, the DSP domain, and volts.
+//!
+//! The normative scaling is:
+//!
+//! * ADC codes are 16 bit two's complement, DAC codes are 16 bit offset
+//!   binary. The code range is `-32768..=32767` (machine units) in both
+//!   cases.
+//! * In the `i32` DSP domain a code `c` is `c << 16`. Full scale is `1 << 31`.
+//! * In `f32` machine units a code `c` is `c as f32`. Full scale is `32768.`.
+//! * In volts full scale (`1 << 31` resp. `32768.`) is `full_scale`. The
+//!   negative full scale code is exactly `-full_scale`.
+//! * dBFS is the amplitude level relative to full scale, `1 << 31`.
+
+use super::{saturating_scale_i64, Rounding};
+
+/// Full scale of the `f32` machine units.
+pub const UNITS_FULL_SCALE: f32 = (1 << 15) as f32;
+
+/// Convert an ADC code to the `i32` DSP domain. This is exact.
+pub fn adc_code_to_i32(code: u16) -> i32 {
+    (code as i16 as i32) << 16
+}
+
+/// Convert from the `i32` DSP domain to a DAC code.
+///
+/// Rounds half up to the nearest code and saturates at the DAC rails
+/// (`i16::MIN`, `i16::MAX`).
+pub fn i32_to_dac_code(x: i32) -> u16 {
+    let y = saturating_scale_i64(x as i64, 16, Rounding::HalfUp);
+    let y = y.min(i16::MAX as i32) as i16;
+    y as u16 ^ 0x8000
+}
+
+/// Convert from the `i32` DSP domain to volts.
+///
+/// # Args
+/// * `x`: Value.
+/// * `full_scale`: Full scale in volts.
+pub fn i32_to_volts(x: i32, full_scale: f32) -> f32 {
+    (x as f64 * (full_scale as f64 / (1u64 << 31) as f64)) as f32
+}
+
+/// Convert volts to the `i32` DSP domain, rounding to nearest and
+/// saturating.
+///
+/// # Args
+/// * `v`: Voltage.
+/// * `full_scale`: Full scale in volts.
+pub fn volts_to_i32(v: f32, full_scale: f32) -> i32 {
+    let x = libm::round(v as f64 / full_scale as f64 * (1u64 << 31) as f64);
+    if x.is_nan() {
+        0
+    } else if x >= i32::MAX as f64 {
+        i32::MAX
+    } else if x <= i32::MIN as f64 {
+        i32::MIN
+    } else {
+        x as i32
+    }
+}
+
+/// Convert `f32` machine units to volts.
+///
+/// # Args
+/// * `x`: Value in machine units.
+/// * `full_scale`: Full scale in volts.
+pub fn units_to_volts(x: f32, full_scale: f32) -> f32 {
+    (x as f64 * (full_scale as f64 / UNITS_FULL_SCALE as f64)) as f32
+}
+
+/// Convert volts to `f32` machine units.
+///
+/// # Args
+/// * `v`: Voltage.
+/// * `full_scale`: Full scale in volts.
+pub fn volts_to_units(v: f32, full_scale: f32) -> f32 {
+    (v as f64 * (UNITS_FULL_SCALE as f64 / full_scale as f64)) as f32
+}
+
+/// Amplitude level of a value in the `i32` DSP domain in dBFS.
+///
+/// Full scale is 0 dBFS, zero is negative infinity.
+pub fn i32_to_dbfs(x: i32) -> f32 {
+    let a = x.unsigned_abs() as f64 / (1u64 << 31) as f64;
+    (20. * libm::log10(a)) as f32
+}
+
+/// Amplitude in the `i32` DSP domain of a level in dBFS, saturating at
+/// `i32::MAX`.
+pub fn dbfs_to_i32(db: f32) -> i32 {
+    let a = libm::pow(10., db as f64 / 20.) * (1u64 << 31) as f64;
+    libm::round(a).min(i32::MAX as f64) as i32
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        for &c in [0i16, 1, -1, i16::MAX, i16::MIN, 12345].iter() {
+            let x = adc_code_to_i32(c as u16);
+            assert_eq!(x, (c as i32) << 16);
+            // DAC codes are offset binary
+            assert_eq!(i32_to_dac_code(x), c as u16 ^ 0x8000);
+        }
+        // Rounding to nearest code
+        assert_eq!(i32_to_dac_code((5 << 16) + (1 << 15) - 1), 5 ^ 0x8000);
+        assert_eq!(i32_to_dac_code((5 << 16) + (1 << 15)), 6 ^ 0x8000);
+        assert_eq!(
+            i32_to_dac_code((-5 << 16) - (1 << 15)),
+            -5i16 as u16 ^ 0x8000
+        );
+    }
+
+    #[test]
+    fn dac_rails() {
+        assert_eq!(i32_to_dac_code(i32::MAX), 0xffff);
+        assert_eq!(i32_to_dac_code(i32::MAX - (1 << 15)), 0xffff);
+        assert_eq!(i32_to_dac_code(i32::MIN), 0x0000);
+    }
+
+    #[test]
+    fn volts() {
+        let fs = 10.24;
+        assert_eq!(i32_to_volts(i32::MIN, fs), -fs);
+        assert_eq!(i32_to_volts(0, fs), 0.);
+        assert_eq!(i32_to_volts(1 << 30, fs), fs / 2.);
+        // Exact for values with at most 24 significant bits below full scale
+        for &x in [0, 1, -1, 1 << 16, -7 << 16, 0x7f_ffff, i32::MIN].iter() {
+            assert_eq!(volts_to_i32(i32_to_volts(x, fs), fs), x);
+        }
+        assert_eq!(volts_to_i32(2. * fs, fs), i32::MAX);
+        assert_eq!(volts_to_i32(-2. * fs, fs), i32::MIN);
+        assert_eq!(volts_to_i32(f32::NAN, fs), 0);
+        assert_eq!(units_to_volts(-32768., fs), -fs);
+        assert_eq!(volts_to_units(fs / 4., fs), 8192.);
+        for &c in [-32768i16, -1, 0, 1, 32767].iter() {
+            let v = units_to_volts(c as f32, fs);
+            assert_eq!(v, i32_to_volts(adc_code_to_i32(c as u16), fs));
+            // Codes survive the round trip through volts
+            assert_eq!(libm::roundf(volts_to_units(v, fs)), c as f32);
+            assert_eq!(i32_to_dac_code(volts_to_i32(v, fs)), c as u16 ^ 0x8000);
+        }
+    }
+
+    #[test]
+    fn dbfs() {
+        assert_eq!(i32_to_dbfs(i32::MIN), 0.);
+        assert!(i32_to_dbfs(i32::MAX) < 0. && i32_to_dbfs(i32::MAX) > -1e-6);
+        assert_eq!(i32_to_dbfs(0), f32::NEG_INFINITY);
+        assert!((i32_to_dbfs(1 << 30) + 6.0206).abs() < 1e-4);
+        assert_eq!(dbfs_to_i32(0.), i32::MAX);
+        assert_eq!(dbfs_to_i32(f32::NEG_INFINITY), 0);
+        assert_eq!(dbfs_to_i32(-20.), 214748365);
+    }
+}
@@ -0,0 +1,280 @@
+use super::{cossin, saturating_scale, Accu, Reset};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Signal generator waveform.
+///
+/// All waveforms are in phase with the sine: they cross zero upwards at zero
+/// phase (the square wave switches to positive there).
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Waveform {
+    Sine,
+    Square,
+    Triangle,
+    Sawtooth,
+}
+
+#[allow(clippy::derivable_impls)]
+impl Default for Waveform {
+    fn default() -> Self {
+        Waveform::Sine
+    }
+}
+
+impl Waveform {
+    /// Full scale waveform value at a phase.
+    ///
+    /// # Args
+    /// * `phase`: Phase, full scale (`1 << 32`) is one turn.
+    ///
+    /// # Returns
+    /// The waveform value with a peak of (almost) full scale, `1 << 31`.
+    pub fn value(&self, phase: i32) -> i32 {
+        match self {
+            Waveform::Sine => cossin(phase).1,
+            Waveform::Square => {
+                if phase >= 0 {
+                    i32::MAX
+                } else {
+                    -i32::MAX
+                }
+            }
+            Waveform::Triangle => {
+                // Fold the outer half turn back
+                let p = phase as i64;
+                let t = if p >= 1 << 30 {
+                    (1 << 31) - p
+                } else if p < -1 << 30 {
+                    (-1 << 31) - p
+                } else {
+                    p
+                };
+                (t << 1).min(i32::MAX as i64) as i32
+            }
+            Waveform::Sawtooth => phase,
+        }
+    }
+}
+
+/// Direct digital synthesis signal generator.
+///
+/// A phase accumulator drives one of the `Waveform`s. The output is scaled
+/// by the amplitude and offset, saturating. The cost of `next()` is a table
+/// lookup (sine) or a few integer operations and one multiplication.
+#[derive(Copy, Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct SignalGenerator {
+    accu: Accu,
+    /// Waveform
+    pub waveform: Waveform,
+    /// Peak amplitude, full scale `1 << 31`
+    pub amplitude: i32,
+    /// DC offset, full scale `1 << 31`
+    pub offset: i32,
+}
+
+impl SignalGenerator {
+    /// Create a new signal generator.
+    ///
+    /// # Args
+    /// * `waveform`: Waveform.
+    /// * `frequency`: Phase increment per sample, `1 << 32` is the sample
+    ///   rate. See `frequency_to_step()`.
+    /// * `amplitude`: Peak amplitude.
+    /// * `offset`: DC offset.
+    pub const fn new(
+        waveform: Waveform,
+        frequency: i32,
+        amplitude: i32,
+        offset: i32,
+    ) -> Self {
+        Self {
+            accu: Accu::new(0, frequency),
+            waveform,
+            amplitude,
+            offset,
+        }
+    }
+
+    /// Change the frequency (phase increment per sample) without a phase
+    /// discontinuity.
+    pub fn set_frequency(&mut self, frequency: i32) {
+        self.accu.set_step(frequency);
+    }
+
+    /// Phase increment per sample.
+    pub fn frequency(&self) -> i32 {
+        self.accu.step()
+    }
+
+    /// Phase of the next sample, `1 << 32` is one turn.
+    pub fn phase(&self) -> i32 {
+        self.accu.phase()
+    }
+
+    /// Generate the next sample.
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> i32 {
+        // Note(unwrap): `Accu` is an infinite iterator.
+        let phase = self.accu.next().unwrap();
+        let y = saturating_scale(self.waveform.value(phase), self.amplitude);
+        y.saturating_add(self.offset)
+    }
+}
+
+/// Convert a frequency in units of the sample rate to a phase increment.
+///
+/// Frequencies above Nyquist alias. The phase increment is rounded to nearest.
+pub fn frequency_to_step(f: f32) -> i32 {
+    let turns = f as f64;
+    let frac = turns - libm::floor(turns);
+    libm::round(frac * (1u64 << 32) as f64) as u64 as u32 as i32
+}
+
+/// Convert a phase increment to a frequency in units of the sample rate.
+pub fn step_to_frequency(step: i32) -> f32 {
+    (step as f64 / (1u64 << 32) as f64) as f32
+}
+
+impl Reset for SignalGenerator {
+    /// Reset the phase to zero.
+    fn reset(&mut self) {
+        self.accu.reset();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::goertzel::Goertzel;
+
+    #[test]
+    fn tone_frequency() {
+        let n = 1000;
+        // On a DFT bin
+        let f0 = 12. / n as f32;
+        let amp = 0.5;
+        let mut gen = SignalGenerator::new(
+            Waveform::Sine,
+            frequency_to_step(f0),
+            (amp * (1u64 << 31) as f64) as i32,
+            0,
+        );
+        let x: Vec<f32> = (0..n)
+            .map(|_| gen.next() as f32 / (1u64 << 31) as f32)
+            .collect();
+        let power = |f: f32| {
+            let mut g = Goertzel::new(f);
+            x.iter().for_each(|&x| g.update(x));
+            let y = g.finish();
+            y.0 * y.0 + y.1 * y.1
+        };
+        // |X| = amp*n/2
+        let p0 = power(f0);
+        let a = p0.sqrt() * 2. / n as f32;
+        assert!((a - amp as f32).abs() < 1e-4, "{}", a);
+        // A frequency error would leak into the neighboring bins
+        for &k in [11., 13., 24.].iter() {
+            let p = power(k / n as f32);
+            assert!(p < 1e-8 * p0, "{}: {}", k, p / p0);
+        }
+        // Frequency round trip
+        assert!((step_to_frequency(gen.frequency()) - f0).abs() < 1e-9);
+        assert_eq!(frequency_to_step(0.5), i32::MIN);
+        assert_eq!(frequency_to_step(-0.25), -1 << 30);
+    }
+
+    #[test]
+    fn phase_continuous_frequency() {
+        let mut gen =
+            SignalGenerator::new(Waveform::Sawtooth, 1 << 20, i32::MAX, 0);
+        let y0 = gen.next();
+        gen.set_frequency(3 << 20);
+        let y1 = gen.next();
+        let y2 = gen.next();
+        assert_eq!(gen.frequency(), 3 << 20);
+        // The phase continues where it stopped
+        assert!((y1 - y0 - (1 << 20)).abs() <= 1);
+        assert!((y2 - y1 - (3 << 20)).abs() <= 1);
+    }
+
+    #[test]
+    fn amplitude_scaling() {
+        let amp = 1 << 28;
+        let offset = -1 << 26;
+        for &w in [
+            Waveform::Sine,
+            Waveform::Square,
+            Waveform::Triangle,
+            Waveform::Sawtooth,
+        ]
+        .iter()
+        {
+            let mut gen = SignalGenerator::new(w, 1 << 22, amp, offset);
+            let (mut min, mut max, mut sum) = (i32::MAX, i32::MIN, 0i64);
+            // One full period
+            for _ in 0..1 << 10 {
+                let y = gen.next();
+                min = min.min(y);
+                max = max.max(y);
+                sum += y as i64;
+            }
+            let mean = sum >> 10;
+            // Peak and mean match amplitude and offset, up to the
+            // sampling granularity
+            assert!((max - offset - amp).abs() < amp >> 8, "{:?} {}", w, max);
+            assert!((min - offset + amp).abs() < amp >> 8, "{:?} {}", w, min);
+            assert!(
+                (mean - offset as i64).abs() < (amp >> 8) as i64,
+                "{:?}",
+                w
+            );
+        }
+    }
+
+    #[test]
+    fn waveform_shapes() {
+        // Zero crossing at zero phase, peak at a quarter turn
+        for &w in
+            [Waveform::Sine, Waveform::Triangle, Waveform::Sawtooth].iter()
+        {
+            assert!(w.value(0).abs() < 1 << 16, "{:?}", w);
+        }
+        assert_eq!(Waveform::Square.value(0), i32::MAX);
+        assert_eq!(Waveform::Square.value(-1), -i32::MAX);
+        assert_eq!(Waveform::Triangle.value(1 << 30), i32::MAX);
+        assert_eq!(Waveform::Triangle.value(-1 << 30), i32::MIN);
+        assert_eq!(Waveform::Triangle.value(1 << 29), 1 << 30);
+        assert_eq!(Waveform::Triangle.value(3 << 29), 1 << 30);
+        assert_eq!(Waveform::Triangle.value(i32::MIN), 0);
+        assert_eq!(Waveform::Sawtooth.value(i32::MIN), i32::MIN);
+        assert!(Waveform::Sine.value(1 << 30) > i32::MAX - (1 << 16));
+    }
+
+    #[test]
+    fn saturation() {
+        let mut gen =
+            SignalGenerator::new(Waveform::Square, i32::MIN, i32::MAX, 1 << 30);
+        assert_eq!(gen.next(), i32::MAX);
+        let low = saturating_scale(-i32::MAX, i32::MAX);
+        assert_eq!(gen.next(), low + (1 << 30));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn serde() {
+        let mut g = SignalGenerator::new(Waveform::Square, 1000, 1 << 30, -5);
+        g.next();
+        let (json, mut de) = crate::testing::round_trip(&g);
+        assert_eq!(
+            json,
+            r#"{"accu":{"step":1000},"waveform":"Square","amplitude":1073741824,"offset":-5}"#
+        );
+        assert_eq!(de.frequency(), 1000);
+        // The phase is not serialized
+        let mut fresh =
+            SignalGenerator::new(Waveform::Square, 1000, 1 << 30, -5);
+        assert_eq!(de.next(), fresh.next());
+    }
+}
@@ -0,0 +1,120 @@
+use super::{Filter, Reset};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Slew rate limiter.
+///
+/// The output follows the input with the change per sample limited to
+/// `max_step`. A `max_step` of `u32::MAX` (full scale) passes all signals
+/// untouched.
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct SlewLimiter {
+    /// Maximum absolute output change per sample.
+    pub max_step: u32,
+    // Current output
+    #[cfg_attr(feature = "serde", serde(skip))]
+    y: i32,
+}
+
+impl Default for SlewLimiter {
+    fn default() -> Self {
+        Self::new(u32::MAX)
+    }
+}
+
+impl SlewLimiter {
+    /// Create a new slew rate limiter with zero output.
+    ///
+    /// # Args
+    /// * `max_step`: Maximum absolute output change per sample.
+    pub const fn new(max_step: u32) -> Self {
+        Self { max_step, y: 0 }
+    }
+
+    /// Current output.
+    pub fn get(&self) -> i32 {
+        self.y
+    }
+
+    /// Preset the output.
+    pub fn set(&mut self, y: i32) {
+        self.y = y;
+    }
+
+    /// Update the limiter with a new sample.
+    ///
+    /// # Args
+    /// * `x`: Input sample.
+    ///
+    /// # Returns
+    /// Slew rate limited output.
+    pub fn update(&mut self, x: i32) -> i32 {
+        // The difference is computed in i64 and does not wrap.
+        let max = self.max_step as i64;
+        let dy = (x as i64 - self.y as i64).max(-max).min(max);
+        self.y = (self.y as i64 + dy) as i32;
+        self.y
+    }
+}
+
+impl Reset for SlewLimiter {
+    fn reset(&mut self) {
+        self.y = 0;
+    }
+}
+
+impl Filter for SlewLimiter {
+    type Config = u32;
+    fn config(&self) -> u32 {
+        self.max_step
+    }
+    fn update(&mut self, x: i32) -> i32 {
+        Self::update(self, x)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn step() {
+        let mut s = SlewLimiter::new(10);
+        for i in 1..10 {
+            assert_eq!(s.update(95), 10 * i);
+        }
+        assert_eq!(s.update(95), 95);
+        assert_eq!(s.update(95), 95);
+        assert_eq!(s.update(-3), 85);
+    }
+
+    #[test]
+    fn sign_change() {
+        let mut s = SlewLimiter::new(1 << 30);
+        s.set(i32::MIN);
+        assert_eq!(s.update(i32::MAX), -1 << 30);
+        assert_eq!(s.update(i32::MAX), 0);
+        assert_eq!(s.update(i32::MAX), 1 << 30);
+        assert_eq!(s.update(i32::MAX), i32::MAX);
+        assert_eq!(s.update(i32::MIN), i32::MAX - (1 << 30));
+        assert_eq!(s.update(-5), -1);
+        assert_eq!(s.update(-5), -5);
+    }
+
+    #[test]
+    fn pass_through() {
+        let mut s = SlewLimiter::default();
+        for &x in [0, i32::MIN, i32::MAX, i32::MIN, 1, -1, 0].iter() {
+            assert_eq!(s.update(x), x);
+        }
+    }
+
+    #[test]
+    fn zero() {
+        let mut s = SlewLimiter::new(0);
+        s.set(7);
+        assert_eq!(s.update(i32::MAX), 7);
+        assert_eq!(s.update(i32::MIN), 7);
+    }
+}
@@ -0,0 +1,162 @@
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicU8, Ordering};
+
+// Flag in `middle` indicating that it holds a value not yet seen by the reader
+const FRESH: u8 = 1 << 2;
+
+/// Lock-free single writer, single reader cell (triple buffer).
+///
+/// The writer (e.g. the network/idle context) publishes complete values, the
+/// reader (e.g. the DSP interrupt) always sees the latest complete value and
+/// never a partially written one. Neither side blocks the other: there is no
+/// critical section, the hand-over is a single atomic swap.
+///
+/// There are three slots: one owned by the writer, one owned by the reader,
+/// and one in the middle for the hand-over.
+pub struct SwapCell<T> {
+    slots: [UnsafeCell<T>; 3],
+    // Hand-over slot index and `FRESH` flag
+    middle: AtomicU8,
+    // Writer owned slot index
+    back: AtomicU8,
+    // Slot index of the last published value
+    last: AtomicU8,
+    // Reader owned slot index
+    front: AtomicU8,
+}
+
+// The slots are only accessed according to the ownership protocol described
+// above.
+unsafe impl<T: Send> Sync for SwapCell<T> {}
+
+impl<T: Copy> SwapCell<T> {
+    /// Create a new cell with an initial value.
+    pub fn new(value: T) -> Self {
+        Self {
+            slots: [
+                UnsafeCell::new(value),
+                UnsafeCell::new(value),
+                UnsafeCell::new(value),
+            ],
+            middle: AtomicU8::new(1),
+            back: AtomicU8::new(2),
+            last: AtomicU8::new(0),
+            front: AtomicU8::new(0),
+        }
+    }
+}
+
+impl<T> SwapCell<T> {
+    /// Publish a new value.
+    ///
+    /// # Safety
+    /// Must only be called from a single (writer) context. `publish()` and
+    /// `published()` must not be called concurrently.
+    pub unsafe fn publish(&self, value: T) {
+        let back = self.back.load(Ordering::Relaxed);
+        *self.slots[back as usize].get() = value;
+        let old = self.middle.swap(back | FRESH, Ordering::AcqRel);
+        self.back.store(old & !FRESH, Ordering::Relaxed);
+        self.last.store(back, Ordering::Relaxed);
+    }
+
+    /// The value last published by the writer.
+    ///
+    /// # Safety
+    /// Must only be called from the writer context. The reference must be
+    /// dropped before the next call to `publish()`.
+    pub unsafe fn published(&self) -> &T {
+        &*self.slots[self.last.load(Ordering::Relaxed) as usize].get()
+    }
+
+    /// The latest published value.
+    ///
+    /// # Safety
+    /// Must only be called from a single (reader) context. The reference must
+    /// be dropped before the next call to `latest()`.
+    pub unsafe fn latest(&self) -> &T {
+        if self.middle.load(Ordering::Relaxed) & FRESH != 0 {
+            let front = self.front.load(Ordering::Relaxed);
+            let old = self.middle.swap(front, Ordering::AcqRel);
+            self.front.store(old & !FRESH, Ordering::Relaxed);
+        }
+        &*self.slots[self.front.load(Ordering::Relaxed) as usize].get()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{atomic::AtomicBool, Arc};
+    use std::thread;
+
+    #[test]
+    fn sequential() {
+        let c = SwapCell::new(1);
+        unsafe {
+            assert_eq!(*c.latest(), 1);
+            assert_eq!(*c.published(), 1);
+            c.publish(2);
+            assert_eq!(*c.published(), 2);
+            assert_eq!(*c.latest(), 2);
+            assert_eq!(*c.latest(), 2);
+            c.publish(3);
+            c.publish(4);
+            assert_eq!(*c.latest(), 4);
+            c.publish(5);
+            assert_eq!(*c.latest(), 5);
+            assert_eq!(*c.published(), 5);
+        }
+    }
+
+    #[derive(Copy, Clone)]
+    struct Checked {
+        data: [u32; 16],
+        sum: u32,
+    }
+
+    impl Checked {
+        fn new(i: u32) -> Self {
+            let mut data = [0; 16];
+            for (j, d) in data.iter_mut().enumerate() {
+                *d = i.wrapping_mul(0x9e37_79b9).rotate_left(j as _);
+            }
+            let sum = data.iter().fold(0u32, |s, d| s.wrapping_add(*d));
+            Self { data, sum }
+        }
+
+        fn valid(&self) -> bool {
+            self.data.iter().fold(0u32, |s, d| s.wrapping_add(*d)) == self.sum
+        }
+    }
+
+    #[test]
+    fn threads() {
+        let c = Arc::new(SwapCell::new(Checked::new(0)));
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (c, done) = (c.clone(), done.clone());
+            thread::spawn(move || {
+                for i in 1..200_000 {
+                    unsafe { c.publish(Checked::new(i)) };
+                }
+                done.store(true, Ordering::Release);
+            })
+        };
+        let mut seen = 0;
+        let mut last = 0;
+        while !done.load(Ordering::Acquire) || seen == 0 {
+            let v = unsafe { c.latest() };
+            assert!(v.valid());
+            let i = v.data[0];
+            if i != last {
+                seen += 1;
+                last = i;
+            }
+        }
+        writer.join().unwrap();
+        let v = unsafe { c.latest() };
+        assert!(v.valid());
+        assert_eq!(v.data[0], Checked::new(199_999).data[0]);
+    }
+}
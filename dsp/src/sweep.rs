@@ -0,0 +1,268 @@
+use super::{exp2_q, log2_q, Reset};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Frequency trajectory of a `Sweep`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum SweepLaw {
+    /// The frequency changes by a constant amount per sample.
+    Linear,
+    /// The frequency changes by a constant factor per sample.
+    Log,
+}
+
+/// What a `Sweep` does once it reaches the stop frequency.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum SweepMode {
+    /// Finish.
+    Single,
+    /// Restart at the start frequency, phase continuous.
+    Loop,
+}
+
+/// Swept sine (chirp) phase generator.
+///
+/// The phase increment per sample (frequency tuning word, FTW, `1 << 32` is
+/// the sample rate) advances from `start_ftw` to `stop_ftw` following the
+/// `SweepLaw`. The yielded phase words are continuous and suitable for
+/// `cossin()`.
+///
+/// For a `Linear` sweep the `rate` is the FTW change per sample in Q16.16,
+/// i.e. in units of `2**-16` FTW LSB. For a `Log` sweep the `rate`
+/// is the change of `log2(ftw)` per sample in Q0.32, i.e. octaves per sample
+/// times `1 << 32`. The log sweep FTW is computed with `exp2_q()` and has a
+/// relative error of about 2e-5.
+#[derive(Copy, Clone, Debug)]
+pub struct Sweep {
+    start_ftw: i32,
+    stop_ftw: i32,
+    rate: i32,
+    law: SweepLaw,
+    mode: SweepMode,
+    // Linear: ftw in Q32.16, Log: log2(ftw) in Q32.32
+    state: i64,
+    phase: i32,
+    finished: bool,
+}
+
+impl Sweep {
+    /// Create a new sweep.
+    ///
+    /// # Args
+    /// * `start_ftw`: Start frequency tuning word.
+    /// * `stop_ftw`: Stop frequency tuning word.
+    /// * `rate`: Sweep rate, see `Sweep`. Its sign must match the sweep
+    ///   direction.
+    /// * `law`: Frequency trajectory.
+    /// * `mode`: Single shot or loop.
+    pub fn new(
+        start_ftw: i32,
+        stop_ftw: i32,
+        rate: i32,
+        law: SweepLaw,
+        mode: SweepMode,
+    ) -> Result<Self, &'static str> {
+        if law == SweepLaw::Log && (start_ftw <= 0 || stop_ftw <= 0) {
+            return Err("Log sweep frequencies must be positive");
+        }
+        if rate == 0 || (rate > 0) != (stop_ftw > start_ftw) {
+            return Err("Sweep rate does not match direction");
+        }
+        let mut sweep = Self {
+            start_ftw,
+            stop_ftw,
+            rate,
+            law,
+            mode,
+            state: 0,
+            phase: 0,
+            finished: false,
+        };
+        sweep.restart();
+        Ok(sweep)
+    }
+
+    // Internal state for a frequency tuning word.
+    fn state_of(&self, ftw: i32) -> i64 {
+        match self.law {
+            SweepLaw::Linear => (ftw as i64) << 16,
+            SweepLaw::Log => (log2_q(ftw as u32) as i64) << 16,
+        }
+    }
+
+    /// Restart the sweep at the start frequency, keeping the phase.
+    pub fn restart(&mut self) {
+        self.state = self.state_of(self.start_ftw);
+        self.finished = false;
+    }
+
+    /// Whether a single shot sweep has reached the stop frequency.
+    pub fn finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Current frequency tuning word.
+    pub fn ftw(&self) -> i32 {
+        match self.law {
+            SweepLaw::Linear => (self.state >> 16) as i32,
+            SweepLaw::Log => exp2_q((self.state >> 16) as i32) as i32,
+        }
+    }
+
+    /// Progress of the sweep from start (`0.`) to stop (`1.`).
+    ///
+    /// The progress is linear in frequency for `Linear` sweeps and linear in
+    /// log frequency for `Log` sweeps, i.e. it is linear in time.
+    pub fn progress(&self) -> f32 {
+        if self.finished {
+            return 1.;
+        }
+        let start = self.state_of(self.start_ftw);
+        let stop = self.state_of(self.stop_ftw);
+        ((self.state - start) as f64 / (stop - start) as f64) as f32
+    }
+}
+
+impl Iterator for Sweep {
+    type Item = i32;
+
+    /// Return the next phase word. `None` once a single shot sweep has
+    /// finished.
+    fn next(&mut self) -> Option<i32> {
+        if self.finished {
+            return None;
+        }
+        let stop = self.state_of(self.stop_ftw);
+        if (self.rate > 0 && self.state > stop)
+            || (self.rate < 0 && self.state < stop)
+        {
+            match self.mode {
+                SweepMode::Single => {
+                    self.finished = true;
+                    return None;
+                }
+                SweepMode::Loop => self.restart(),
+            }
+        }
+        let phase = self.phase;
+        self.phase = self.phase.wrapping_add(self.ftw());
+        self.state += self.rate as i64;
+        Some(phase)
+    }
+}
+
+impl Reset for Sweep {
+    /// Restart the sweep at the start frequency and zero phase.
+    fn reset(&mut self) {
+        self.restart();
+        self.phase = 0;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Instantaneous frequency tuning words: the phase differences
+    fn ftws(sweep: Sweep) -> Vec<i32> {
+        let phase: Vec<i32> = sweep.collect();
+        phase.windows(2).map(|p| p[1].wrapping_sub(p[0])).collect()
+    }
+
+    #[test]
+    fn linear() {
+        let (start, stop) = (1 << 20, 3 << 24);
+        // 1000.5 LSB per sample
+        let rate = 1000 << 16 | 1 << 15;
+        let sweep =
+            Sweep::new(start, stop, rate, SweepLaw::Linear, SweepMode::Single)
+                .unwrap();
+        let f = ftws(sweep);
+        let n = f.len();
+        // Number of samples to reach the stop frequency
+        let want_n = ((stop - start) as f64 / 1000.5) as usize + 1;
+        assert_eq!(n, want_n - 1);
+        for &k in [0, 1, 10, n / 3, n / 2, n - 1].iter() {
+            let want = start as f64 + 1000.5 * k as f64;
+            assert!((f[k] as f64 - want).abs() < 1., "{}: {}", k, f[k]);
+        }
+    }
+
+    #[test]
+    fn linear_down() {
+        let sweep = Sweep::new(
+            1 << 24,
+            1 << 20,
+            -(7 << 16),
+            SweepLaw::Linear,
+            SweepMode::Single,
+        )
+        .unwrap();
+        let f = ftws(sweep);
+        for (k, &f) in f.iter().enumerate().step_by(1000) {
+            assert_eq!(f, (1 << 24) - 7 * k as i32);
+        }
+        assert!(*f.last().unwrap() >= 1 << 20);
+    }
+
+    #[test]
+    fn log() {
+        let (start, stop) = (1 << 16, 1 << 26);
+        // 10 octaves in 100_000 samples
+        let rate = ((1u64 << 32) as f64 * 10. / 1e5) as i32;
+        let mut sweep =
+            Sweep::new(start, stop, rate, SweepLaw::Log, SweepMode::Single)
+                .unwrap();
+        assert_eq!(sweep.progress(), 0.);
+        let f = ftws(sweep);
+        assert!((f.len() as i32 - 100_000).abs() <= 2, "{}", f.len());
+        for &k in [0, 1, 1000, 25_000, 50_000, 99_990].iter() {
+            let want = start as f64
+                * 2f64.powf(rate as f64 / 2f64.powi(32) * k as f64);
+            assert!((f[k] as f64 / want - 1.).abs() < 5e-5, "{}: {}", k, f[k]);
+        }
+        // Half way in time and log frequency
+        for _ in 0..50_000 {
+            sweep.next();
+        }
+        assert!((sweep.progress() - 0.5).abs() < 1e-4);
+        assert!((sweep.ftw() as f64 / (1 << 21) as f64 - 1.).abs() < 5e-5);
+    }
+
+    #[test]
+    fn modes() {
+        let mut sweep =
+            Sweep::new(100, 200, 10 << 16, SweepLaw::Linear, SweepMode::Single)
+                .unwrap();
+        assert_eq!(sweep.by_ref().count(), 11);
+        assert!(sweep.finished());
+        assert_eq!(sweep.progress(), 1.);
+        assert_eq!(sweep.next(), None);
+        sweep.restart();
+        assert!(!sweep.finished());
+        assert_eq!(sweep.ftw(), 100);
+        assert_eq!(sweep.count(), 11);
+
+        let mut sweep =
+            Sweep::new(100, 200, 10 << 16, SweepLaw::Linear, SweepMode::Loop)
+                .unwrap();
+        let p: Vec<i32> = sweep.by_ref().take(30).collect();
+        assert!(!sweep.finished());
+        // Frequency wraps back to the start, the phase is continuous
+        assert_eq!(p[12] - p[11], 100);
+        assert_eq!(p[11] - p[10], 200);
+    }
+
+    #[test]
+    fn invalid() {
+        let lin = SweepLaw::Linear;
+        let single = SweepMode::Single;
+        assert!(Sweep::new(100, 200, -1, lin, single).is_err());
+        assert!(Sweep::new(200, 100, 1, lin, single).is_err());
+        assert!(Sweep::new(100, 200, 0, lin, single).is_err());
+        assert!(Sweep::new(0, 200, 1, SweepLaw::Log, single).is_err());
+        assert!(Sweep::new(-100, 200, 1, lin, single).is_ok());
+    }
+}
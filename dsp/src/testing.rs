@@ -1,5 +1,15 @@
-#![allow(dead_code)]
-use super::Complex;
+//! Comparison helpers and signal sources for tests.
+//!
+//! Available with the `testing` feature for host side models and tests of
+//! downstream crates (and always in the tests of this crate). Not intended
+//! for firmware. Everything here is `no_std`, deterministic, and panics only
+//! in the `assert_*` functions.
+use super::{
+    math::{abs, abs_f64},
+    noise::Xoshiro,
+    Complex,
+};
+use core::f32::consts::PI;
 
 /// Maximum acceptable error between a computed and actual value given fixed and relative
 /// tolerances.
@@ -14,17 +24,20 @@ use super::Complex;
 /// # Returns
 /// Maximum acceptable error.
 pub fn max_error(a: f64, b: f64, rtol: f64, atol: f64) -> f64 {
-    rtol * a.abs().max(b.abs()) + atol
+    rtol * abs_f64(a).max(abs_f64(b)) + atol
 }
 
+/// Whether `a` and `b` are equal within the tolerances, see `max_error()`.
 pub fn isclose(a: f64, b: f64, rtol: f64, atol: f64) -> bool {
-    (a - b).abs() <= a.abs().max(b.abs()) * rtol + atol
+    abs_f64(a - b) <= max_error(a, b, rtol, atol)
 }
 
+/// Single precision `isclose()`.
 pub fn isclosef(a: f32, b: f32, rtol: f32, atol: f32) -> bool {
-    (a - b).abs() <= a.abs().max(b.abs()) * rtol + atol
+    abs(a - b) <= abs(a).max(abs(b)) * rtol + atol
 }
 
+/// Whether the real and imaginary parts are close, see `isclosef()`.
 pub fn complex_isclose(
     a: Complex<f32>,
     b: Complex<f32>,
@@ -34,6 +47,7 @@ pub fn complex_isclose(
     isclosef(a.0, b.0, rtol, atol) && isclosef(a.1, b.1, rtol, atol)
 }
 
+/// Whether all pairs of complex values are close, see `complex_isclose()`.
 pub fn complex_allclose(
     a: &[Complex<f32>],
     b: &[Complex<f32>],
@@ -44,3 +58,98 @@ pub fn complex_allclose(
         .zip(b)
         .all(|(&i, &j)| complex_isclose(i, j, rtol, atol))
 }
+
+/// Whether magnitude and phase of two complex values are close.
+///
+/// # Args
+/// * `a` - First input.
+/// * `b` - Second input.
+/// * `rtol` - Relative tolerance of the magnitude.
+/// * `phase_tol` - Absolute tolerance of the phase in radians. The phase
+///   difference is wrapped into `[-pi, pi)`. It is not compared if both
+///   magnitudes are within `atol` of zero.
+/// * `atol` - Fixed tolerance of the magnitude.
+pub fn complex_isclose_polar(
+    a: Complex<f32>,
+    b: Complex<f32>,
+    rtol: f32,
+    phase_tol: f32,
+    atol: f32,
+) -> bool {
+    let (ma, mb) = (a.abs(), b.abs());
+    if !isclosef(ma, mb, rtol, atol) {
+        return false;
+    }
+    if ma <= atol && mb <= atol {
+        return true;
+    }
+    let d = a.arg() - b.arg();
+    let d = d - 2. * PI * libm::floorf((d + PI) / (2. * PI));
+    abs(d) <= phase_tol
+}
+
+/// Assert that two magnitudes agree within a tolerance in dB.
+///
+/// # Args
+/// * `a` - First magnitude (e.g. a measured amplitude).
+/// * `b` - Second magnitude (e.g. the expected amplitude).
+/// * `tol_db` - Tolerance of `20*log10(a/b)`.
+pub fn assert_close_db(a: f32, b: f32, tol_db: f32) {
+    let db = 20. * libm::log10f(abs(a) / abs(b));
+    assert!(
+        abs(db) <= tol_db,
+        "{} and {} differ by {} dB (tolerance {} dB)",
+        a,
+        b,
+        db,
+        tol_db
+    );
+}
+
+/// Deterministic white noise source.
+///
+/// Uniformly distributed in `[-amplitude, amplitude)` with variance
+/// `amplitude**2/3`. The sequence only depends on the seed.
+#[derive(Copy, Clone, Debug)]
+pub struct WhiteNoise {
+    rng: Xoshiro,
+    /// Peak amplitude.
+    pub amplitude: f32,
+}
+
+impl WhiteNoise {
+    /// Create a new source.
+    ///
+    /// # Args
+    /// * `seed` - Seed, see `noise::Xoshiro`.
+    /// * `amplitude` - Peak amplitude.
+    pub fn new(seed: u64, amplitude: f32) -> Self {
+        Self {
+            rng: Xoshiro::new(seed),
+            amplitude,
+        }
+    }
+}
+
+impl Iterator for WhiteNoise {
+    type Item = f32;
+    fn next(&mut self) -> Option<f32> {
+        let u = self.rng.next_u32() as i32 as f64 / (1u64 << 31) as f64;
+        Some((u * self.amplitude as f64) as f32)
+    }
+}
+
+/// Serialize to JSON and deserialize again.
+///
+/// # Returns
+/// The JSON string (at most 256 bytes as in the server responses) and the
+/// deserialized value.
+#[cfg(all(test, feature = "serde"))]
+pub fn round_trip<T>(value: &T) -> (heapless::String<heapless::consts::U256>, T)
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let json = serde_json_core::to_string(value).unwrap();
+    let (value, _) = serde_json_core::from_str(&json).unwrap();
+    (json, value)
+}
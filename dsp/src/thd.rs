@@ -0,0 +1,206 @@
+//! Total harmonic distortion (THD) measurement.
+use super::{lockin::MultiLockin, Complex, Reset};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Number of demodulated harmonics, including the fundamental.
+pub const HARMONICS: usize = 5;
+
+/// THD measurement result.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct Thd {
+    /// Fundamental amplitude relative to full scale (`1 << 15`), dB.
+    pub fundamental_dbfs: f32,
+    /// Total power of the harmonics 2 to 5 relative to the fundamental, dB.
+    pub thd_db: f32,
+    /// Amplitude of the harmonics 2 to 5 relative to the fundamental, dB.
+    pub per_harmonic_db: [f32; HARMONICS - 1],
+}
+
+/// One-shot THD estimator.
+///
+/// The input is demodulated at the harmonics 1 to `HARMONICS` of the
+/// reference (the phase of a generated test tone) with a `MultiLockin`.
+/// The measurement is armed with `start()`, settles for a given number of
+/// batches and then yields one `Thd`. The highest harmonic must be below
+/// Nyquist, otherwise it aliases.
+///
+/// The lockin lowpass time constant `1 << k` samples determines the
+/// resolution bandwidth: the settle time should be several time constants.
+#[derive(Copy, Clone)]
+pub struct Estimator {
+    lockin: MultiLockin<HARMONICS>,
+    k: u8,
+    settle: u32,
+    remaining: Option<u32>,
+}
+
+impl Estimator {
+    /// Create a new, idle estimator.
+    ///
+    /// # Args
+    /// * `k`: Log2 lowpass time constant, 0..=16.
+    /// * `settle`: Number of batches (`update()` calls) to settle before
+    ///   the result is computed.
+    pub fn new(k: u8, settle: u32) -> Self {
+        Self {
+            lockin: MultiLockin::new([-1, -2, -3, -4, -5]),
+            k,
+            settle,
+            remaining: None,
+        }
+    }
+
+    /// Settle time in batches.
+    pub fn settle(&self) -> u32 {
+        self.settle
+    }
+
+    /// Change the settle time. The change applies to the next `start()`.
+    pub fn set_settle(&mut self, settle: u32) {
+        self.settle = settle;
+    }
+
+    /// Arm a measurement. A running measurement restarts.
+    pub fn start(&mut self) {
+        self.lockin.reset();
+        self.remaining = Some(self.settle);
+    }
+
+    /// A measurement is armed and not yet complete.
+    pub fn busy(&self) -> bool {
+        self.remaining.is_some()
+    }
+
+    /// Process a batch of samples.
+    ///
+    /// Idle estimators ignore the samples.
+    ///
+    /// # Args
+    /// * `samples`: Input samples.
+    /// * `reference_phase`: Test tone phase of the first sample.
+    /// * `reference_frequency`: Test tone phase increment per sample.
+    ///
+    /// # Returns
+    /// The result once the settle time has elapsed, `None` otherwise.
+    pub fn update(
+        &mut self,
+        samples: &[i16],
+        reference_phase: i32,
+        reference_frequency: i32,
+    ) -> Option<Thd> {
+        let remaining = self.remaining?;
+        let output = self.lockin.update(
+            samples,
+            reference_phase,
+            reference_frequency,
+            self.k,
+        );
+        if remaining > 1 {
+            self.remaining = Some(remaining - 1);
+            None
+        } else {
+            self.remaining = None;
+            Some(self.evaluate(&output))
+        }
+    }
+
+    fn evaluate(&self, output: &[Complex<i32>; HARMONICS]) -> Thd {
+        let mut power = [0f64; HARMONICS];
+        for (power, iq) in power.iter_mut().zip(output.iter()) {
+            // Mixer and real signal gain 1/4, lowpass gain `1 << k`
+            let scale = 4. / (1u32 << self.k) as f64;
+            let (i, q) = (iq.0 as f64 * scale, iq.1 as f64 * scale);
+            *power = i * i + q * q;
+        }
+        let db = |ratio: f64| (10. * libm::log10(ratio)) as f32;
+        let full_scale = (1u64 << 30) as f64;
+        let mut per_harmonic_db = [0.; HARMONICS - 1];
+        for (db_h, &p) in per_harmonic_db.iter_mut().zip(power[1..].iter()) {
+            *db_h = db(p / power[0]);
+        }
+        Thd {
+            fundamental_dbfs: db(power[0] / full_scale),
+            thd_db: db(power[1..].iter().sum::<f64>() / power[0]),
+            per_harmonic_db,
+        }
+    }
+}
+
+impl Reset for Estimator {
+    /// Stop a running measurement and clear the lockin state.
+    fn reset(&mut self) {
+        self.lockin.reset();
+        self.remaining = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Measure a tone with the given relative amplitudes of the harmonics
+    /// 2 and 3.
+    fn measure(distortion: [f64; 2]) -> Thd {
+        let mut thd = Estimator::new(12, 1 << 13);
+        let amplitude = (1 << 14) as f64;
+        // Not commensurate with the sample rate
+        let frequency = 0x0345_6789i32;
+        let mut samples = [0; 8];
+        thd.start();
+        for batch in 0.. {
+            let reference_phase =
+                frequency.wrapping_mul(batch * samples.len() as i32);
+            for (i, sample) in samples.iter_mut().enumerate() {
+                let phase = reference_phase
+                    .wrapping_add(frequency.wrapping_mul(i as i32))
+                    as f64
+                    * core::f64::consts::PI
+                    / (1u64 << 31) as f64;
+                let x = phase.cos()
+                    + distortion[0] * (2. * phase).cos()
+                    + distortion[1] * (3. * phase).sin();
+                *sample = (amplitude * x).round() as i16;
+            }
+            assert!(thd.busy());
+            if let Some(result) =
+                thd.update(&samples, reference_phase, frequency)
+            {
+                assert!(!thd.busy());
+                assert_eq!(batch, (1 << 13) - 1);
+                return result;
+            }
+        }
+        unreachable!()
+    }
+
+    #[test]
+    fn distortion() {
+        for &(d, expect) in [(1e-2, -40.), (1e-3, -60.)].iter() {
+            let h = d / 2f64.sqrt();
+            let result = measure([h, h]);
+            assert!((result.thd_db - expect).abs() < 0.5, "{} {:?}", d, result);
+            assert!((result.per_harmonic_db[0] - expect + 3.).abs() < 0.5);
+            assert!((result.per_harmonic_db[1] - expect + 3.).abs() < 0.5);
+            assert!(result.per_harmonic_db[2] < expect - 20.);
+            assert!(result.per_harmonic_db[3] < expect - 20.);
+            assert!((result.fundamental_dbfs + 6.02).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn idle() {
+        let mut thd = Estimator::new(8, 2);
+        assert!(!thd.busy());
+        assert_eq!(thd.update(&[1 << 14; 8], 0, 1 << 28), None);
+        thd.start();
+        assert_eq!(thd.update(&[1 << 14; 8], 0, 1 << 28), None);
+        assert!(thd.update(&[1 << 14; 8], 0, 1 << 28).is_some());
+        assert_eq!(thd.update(&[1 << 14; 8], 0, 1 << 28), None);
+        thd.start();
+        thd.reset();
+        assert!(!thd.busy());
+    }
+}
@@ -1,3 +1,5 @@
+use super::Reset;
+#[cfg(feature = "serde")]
 use serde::{Deserialize, Serialize};
 
 /// Subtract `y - x` with signed overflow.
@@ -20,7 +22,8 @@ pub fn overflowing_sub(y: i32, x: i32) -> (i32, i8) {
 ///
 /// This is unwrapping as in the phase and overflow unwrapping context, not
 /// unwrapping as in the `Result`/`Option` context.
-#[derive(Copy, Clone, Default, Deserialize, Serialize)]
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 pub struct Unwrapper {
     // last input
     x: i32,
@@ -29,6 +32,11 @@ pub struct Unwrapper {
 }
 
 impl Unwrapper {
+    /// Create a new unwrapper with zero last input and wraps.
+    pub const fn new() -> Self {
+        Self { x: 0, w: 0 }
+    }
+
     /// Unwrap a new sample from a sequence and update the unwrapper state.
     ///
     /// Args:
@@ -43,11 +51,242 @@ impl Unwrapper {
         self.w = self.w.wrapping_add(dw as i32);
         (dx, self.w)
     }
+
+    /// Current state.
+    ///
+    /// Returns:
+    /// A tuple of the last input and the signed number of wraps.
+    pub fn state(&self) -> (i32, i32) {
+        (self.x, self.w)
+    }
+}
+
+/// Saturating overflow unwrapper.
+///
+/// Like `Unwrapper` but additionally reconstructs the extended `i64` value
+/// `(wraps << 32) + x`. Instead of silently wrapping the wrap counter, the
+/// extended value saturates at `i64::MIN` or `i64::MAX` and a sticky
+/// overflow flag is set. The extended value remains saturated until `reset()`.
+#[derive(Copy, Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct SaturatingUnwrapper {
+    // last input
+    x: i32,
+    // last wraps
+    w: i32,
+    // sticky overflow flag
+    overflowed: bool,
+}
+
+impl SaturatingUnwrapper {
+    /// Unwrap a new sample from a sequence and update the unwrapper state.
+    ///
+    /// Args:
+    /// * `x`: New sample
+    ///
+    /// Returns:
+    /// A tuple containing the (wrapped) difference `x - x_old` and the
+    /// extended (unwrapped) value.
+    pub fn update(&mut self, x: i32) -> (i32, i64) {
+        let (dx, dw) = overflowing_sub(x, self.x);
+        self.x = x;
+        if !self.overflowed {
+            match self.w.checked_add(dw as i32).and_then(|w| {
+                ((w as i64) << 32).checked_add(x as i64).map(|_| w)
+            }) {
+                Some(w) => self.w = w,
+                None => self.overflowed = true,
+            }
+        }
+        (dx, self.value())
+    }
+
+    /// The extended (unwrapped) value, saturated on overflow.
+    pub fn value(&self) -> i64 {
+        if !self.overflowed {
+            ((self.w as i64) << 32) + self.x as i64
+        } else if self.w < 0 {
+            i64::MIN
+        } else {
+            i64::MAX
+        }
+    }
+
+    /// The signed number of wraps accumulated. Saturated on overflow.
+    pub fn wraps(&self) -> i32 {
+        self.w
+    }
+
+    /// Whether the extended value has overflowed (sticky).
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    /// Clear the wrap count and the overflow flag.
+    pub fn reset(&mut self) {
+        self.w = 0;
+        self.overflowed = false;
+    }
+}
+
+/// Floating point phase unwrapper.
+///
+/// Unwraps a sequence of angles in `(-period/2, period/2]` into a continuous
+/// phase. The period is `2*pi` for radians or `1` for turns. Differences of
+/// exactly half a period are unwrapped to `+period/2`. The state is
+/// accumulated in `f64` to avoid precision loss on long sequences.
+#[derive(Copy, Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct UnwrapperF32 {
+    // wrapping period
+    period: f64,
+    // last input
+    #[cfg_attr(feature = "serde", serde(skip))]
+    x: f64,
+    // unwrapped output
+    #[cfg_attr(feature = "serde", serde(skip))]
+    y: f64,
+}
+
+impl Default for UnwrapperF32 {
+    fn default() -> Self {
+        Self::new(2. * core::f32::consts::PI)
+    }
+}
+
+impl UnwrapperF32 {
+    /// Create a new unwrapper.
+    ///
+    /// Args:
+    /// * `period`: Wrapping period, e.g. `2*pi` for radians or `1` for turns.
+    pub fn new(period: f32) -> Self {
+        Self {
+            period: period as f64,
+            x: 0.,
+            y: 0.,
+        }
+    }
+
+    /// Unwrap a new sample from a sequence and update the unwrapper state.
+    ///
+    /// Args:
+    /// * `x`: New sample
+    ///
+    /// Returns:
+    /// The unwrapped phase.
+    pub fn update(&mut self, x: f32) -> f64 {
+        let x = x as f64;
+        let mut dx = x - self.x;
+        self.x = x;
+        if dx > 0.5 * self.period {
+            dx -= self.period;
+        } else if dx <= -0.5 * self.period {
+            dx += self.period;
+        }
+        self.y += dx;
+        self.y
+    }
+}
+
+impl Reset for Unwrapper {
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Reset for SaturatingUnwrapper {
+    /// Unlike `SaturatingUnwrapper::reset()` this also clears the last input.
+    fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Reset for UnwrapperF32 {
+    fn reset(&mut self) {
+        self.x = 0.;
+        self.y = 0.;
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rand::{prelude::*, rngs::StdRng};
+
+    #[test]
+    fn float_ramp() {
+        for &period in [2. * core::f32::consts::PI, 1.].iter() {
+            let mut u = UnwrapperF32::new(period);
+            let period = period as f64;
+            for i in 0..1 << 17 {
+                let phi = 0.3 * period * i as f64;
+                // Wrap into (-period/2, period/2]
+                let x =
+                    -((-phi + 0.5 * period).rem_euclid(period) - 0.5 * period);
+                let y = u.update(x as f32);
+                assert!((y - phi).abs() < 1e-6 * period, "{} {}", y, phi);
+            }
+        }
+    }
+
+    #[test]
+    fn float_half_period() {
+        let pi = core::f32::consts::PI;
+        let mut u = UnwrapperF32::default();
+        assert_eq!(u.update(pi), pi as f64);
+        for _ in 0..1 << 10 {
+            assert_eq!(u.update(-pi), pi as f64);
+            assert_eq!(u.update(pi), pi as f64);
+        }
+    }
+
+    #[test]
+    fn saturating_identical() {
+        let mut rng = StdRng::seed_from_u64(42);
+        let mut u = Unwrapper::default();
+        let mut s = SaturatingUnwrapper::default();
+        for _ in 0..1 << 12 {
+            let x = rng.gen();
+            let (dx, w) = u.update(x);
+            let (dxs, y) = s.update(x);
+            assert_eq!(dx, dxs);
+            assert_eq!(w, s.wraps());
+            assert_eq!(y, ((w as i64) << 32) + x as i64);
+        }
+        assert!(!s.overflowed());
+    }
+
+    #[test]
+    fn saturating_overflow() {
+        let mut s = SaturatingUnwrapper {
+            w: i32::MAX,
+            ..Default::default()
+        };
+        let mut x = 0i32;
+        for i in 0..8 {
+            x = x.wrapping_add(i32::MAX);
+            let (_, y) = s.update(x);
+            // The first wrap happens on the second update
+            assert_eq!(s.overflowed(), i > 0);
+            if i > 0 {
+                assert_eq!(y, i64::MAX);
+                assert_eq!(s.wraps(), i32::MAX);
+            }
+        }
+        s.reset();
+        assert!(!s.overflowed());
+        assert_eq!(s.value(), x as i64);
+
+        let mut s = SaturatingUnwrapper {
+            w: i32::MIN,
+            ..Default::default()
+        };
+        // `(i32::MIN << 32) - 1` is already out of range.
+        let (_, y) = s.update(-1);
+        assert!(s.overflowed());
+        assert_eq!(y, i64::MIN);
+        assert_eq!(s.update(0).1, i64::MIN);
+    }
     #[test]
     fn mini() {
         for (x0, x1, v) in [
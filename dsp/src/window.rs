@@ -0,0 +1,181 @@
+use core::f64::consts::PI;
+
+/// Symmetric cosine-sum window functions.
+///
+/// The `n` point window is `w[i] = sum_k (-1)^k a_k cos(2*pi*k*i/(n - 1))`.
+/// It is symmetric (`w[i] == w[n - 1 - i]`) and has a peak value of 1 at
+/// the center for odd `n`.
+///
+/// The coherent gain (the mean of the window for large `n`, i.e. the
+/// amplitude scaling of a windowed tone in a DFT) is `a_0`:
+///
+/// | Window | Coherent gain |
+/// |--------|---------------|
+/// | `Hann` | 0.5 |
+/// | `BlackmanHarris` | 0.35875 |
+/// | `FlatTop` | 0.21557895 |
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Window {
+    /// Hann (raised cosine) window.
+    Hann,
+    /// Four term minimum side lobe Blackman-Harris window.
+    BlackmanHarris,
+    /// Five term flat-top window. This has a small negative overshoot.
+    FlatTop,
+}
+
+impl Window {
+    fn coefficients(&self) -> &'static [f64] {
+        match self {
+            Window::Hann => &[0.5, 0.5],
+            Window::BlackmanHarris => &[0.35875, 0.48829, 0.14128, 0.01168],
+            Window::FlatTop => &[
+                0.215_578_95,
+                0.416_631_58,
+                0.277_263_158,
+                0.083_578_947,
+                0.006_947_368,
+            ],
+        }
+    }
+
+    /// The coherent gain of the window.
+    pub fn coherent_gain(&self) -> f32 {
+        self.coefficients()[0] as f32
+    }
+
+    fn value_f64(&self, n: usize, i: usize) -> f64 {
+        if n < 2 {
+            return 1.;
+        }
+        let x = 2. * PI * i as f64 / (n - 1) as f64;
+        self.coefficients()
+            .iter()
+            .enumerate()
+            .map(|(k, a)| {
+                let c = a * libm::cos(k as f64 * x);
+                if k & 1 != 0 {
+                    -c
+                } else {
+                    c
+                }
+            })
+            .sum()
+    }
+
+    /// Window value.
+    ///
+    /// # Args
+    /// * `n`: Window length.
+    /// * `i`: Index, `0..n`.
+    pub fn value(&self, n: usize, i: usize) -> f32 {
+        self.value_f64(n, i) as f32
+    }
+
+    /// Window value in Q1.31.
+    ///
+    /// A value of 1 is represented as `i32::MAX` such that scaling a full-scale
+    /// sample with `saturating_scale()` never exceeds the sample magnitude.
+    ///
+    /// # Args
+    /// * `n`: Window length.
+    /// * `i`: Index, `0..n`.
+    pub fn value_i32(&self, n: usize, i: usize) -> i32 {
+        // Rounding half up, `as` saturates
+        libm::floor(self.value_f64(n, i) * (1u64 << 31) as f64 + 0.5) as i32
+    }
+
+    /// Fill a buffer with the window of the buffer length.
+    pub fn fill(&self, buf: &mut [f32]) {
+        let n = buf.len();
+        for (i, w) in buf.iter_mut().enumerate() {
+            *w = self.value(n, i);
+        }
+    }
+
+    /// Fill a buffer with the window of the buffer length in Q1.31.
+    pub fn fill_i32(&self, buf: &mut [i32]) {
+        let n = buf.len();
+        for (i, w) in buf.iter_mut().enumerate() {
+            *w = self.value_i32(n, i);
+        }
+    }
+}
+
+/// Hann window value. See `Window`.
+pub fn hann(n: usize, i: usize) -> f32 {
+    Window::Hann.value(n, i)
+}
+
+/// Blackman-Harris window value. See `Window`.
+pub fn blackman_harris(n: usize, i: usize) -> f32 {
+    Window::BlackmanHarris.value(n, i)
+}
+
+/// Flat-top window value. See `Window`.
+pub fn flat_top(n: usize, i: usize) -> f32 {
+    Window::FlatTop.value(n, i)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::saturating_scale;
+
+    const WINDOWS: [Window; 3] =
+        [Window::Hann, Window::BlackmanHarris, Window::FlatTop];
+
+    #[test]
+    fn symmetry() {
+        for w in WINDOWS.iter() {
+            for &n in [2, 7, 64, 65].iter() {
+                let mut buf = [0; 65];
+                w.fill_i32(&mut buf[..n]);
+                for i in 0..n {
+                    assert_eq!(buf[i], buf[n - 1 - i]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn endpoints() {
+        assert_eq!(hann(9, 0), 0.);
+        assert_eq!(hann(9, 8), 0.);
+        assert_eq!(Window::Hann.value_i32(9, 0), 0);
+        assert!((blackman_harris(9, 0) - 6e-5).abs() < 1e-6);
+        assert!((flat_top(9, 0) + 4.2e-4).abs() < 1e-5);
+        for w in WINDOWS.iter() {
+            assert!((w.value(9, 4) - 1.).abs() < 1e-6);
+            assert_eq!(w.value_i32(9, 4), i32::MAX);
+            assert_eq!(w.value(1, 0), 1.);
+        }
+    }
+
+    #[test]
+    fn coherent_gain() {
+        let mut buf = [0.; 4096];
+        for w in WINDOWS.iter() {
+            w.fill(&mut buf);
+            let mean = buf.iter().sum::<f32>() / buf.len() as f32;
+            assert!((mean - w.coherent_gain()).abs() < 1e-3, "{:?}", w);
+        }
+        assert_eq!(Window::Hann.coherent_gain(), 0.5);
+        assert_eq!(Window::BlackmanHarris.coherent_gain(), 0.35875);
+        assert_eq!(Window::FlatTop.coherent_gain(), 0.215_578_95);
+    }
+
+    #[test]
+    fn full_scale() {
+        let mut buf = [0; 33];
+        for w in WINDOWS.iter() {
+            w.fill_i32(&mut buf);
+            for &w in buf.iter() {
+                for &x in [i32::MIN, i32::MAX].iter() {
+                    let y = saturating_scale(x, w);
+                    assert!(y.unsigned_abs() <= x.unsigned_abs());
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use super::{prbs::Prbs, Reset};
+
+/// Cross-correlator for PRBS plant identification.
+///
+/// Correlates the response `y` of a plant with the maximum length PRBS
+/// `u = ±1` exciting it at `LAGS` delays. Over a full period `N` of the
+/// sequence the autocorrelation of `u` is `N` at zero lag and `-1`
+/// otherwise. With `s` the sum of `y` over the period, the impulse response
+/// at lag `k` is then exactly `(sum(u[n - k] y[n]) + s) / (N + 1)`,
+/// aliased with period `N`.
+///
+/// A constant offset of `y` appears in the estimate at all lags.
+///
+/// The first period after a reset is not accumulated. It fills the PRBS
+/// history and lets the plant settle. Only complete periods are used in the
+/// estimate.
+#[derive(Copy, Clone, Debug)]
+pub struct XCorr<const LAGS: usize> {
+    period: u32,
+    // Past PRBS bits, ring buffer
+    history: [bool; LAGS],
+    // Index of the most recent bit in `history`
+    head: usize,
+    // Sample index within the period
+    index: u32,
+    // Number of completed periods, including the settling period
+    periods: u32,
+    // Running correlation and response sum in the current period
+    acc: [i64; LAGS],
+    sum: i64,
+    // Correlation and response sum of all completed periods
+    total: [i64; LAGS],
+    total_sum: i64,
+}
+
+impl<const LAGS: usize> XCorr<LAGS> {
+    /// Create a new cross-correlator.
+    ///
+    /// # Args
+    /// * `period`: PRBS period, `2**n - 1` for a sequence of order `n`. Must
+    ///   not be smaller than `LAGS`.
+    pub fn new(period: u32) -> Result<Self, &'static str> {
+        if (period as usize) < LAGS || LAGS == 0 {
+            return Err("Period shorter than the number of lags");
+        }
+        Ok(Self {
+            period,
+            history: [false; LAGS],
+            head: 0,
+            index: 0,
+            periods: 0,
+            acc: [0; LAGS],
+            sum: 0,
+            total: [0; LAGS],
+            total_sum: 0,
+        })
+    }
+
+    /// Clear the accumulators.
+    ///
+    /// This must be synchronous with the start of a PRBS period, see
+    /// `reset_with()`.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.period).unwrap();
+    }
+
+    /// Clear the accumulators and restart the PRBS sequence.
+    pub fn reset_with(&mut self, prbs: &mut Prbs) {
+        prbs.reset();
+        self.reset();
+    }
+
+    /// Number of complete periods accumulated.
+    pub fn periods(&self) -> u32 {
+        self.periods.saturating_sub(1)
+    }
+
+    /// Accumulate a sample.
+    ///
+    /// # Args
+    /// * `u`: The PRBS bit that has been applied to the plant with this
+    ///   sample.
+    /// * `y`: The plant response.
+    pub fn update(&mut self, u: bool, y: i32) {
+        self.head = (self.head + 1) % LAGS;
+        self.history[self.head] = u;
+        if self.periods > 0 {
+            let y = y as i64;
+            for (k, acc) in self.acc.iter_mut().enumerate() {
+                let bit = self.history[(self.head + LAGS - k) % LAGS];
+                *acc += if bit { y } else { -y };
+            }
+            self.sum += y;
+        }
+        self.index += 1;
+        if self.index == self.period {
+            self.index = 0;
+            self.periods = self.periods.saturating_add(1);
+            for (t, a) in self.total.iter_mut().zip(self.acc.iter_mut()) {
+                *t += *a;
+                *a = 0;
+            }
+            self.total_sum += self.sum;
+            self.sum = 0;
+        }
+    }
+
+    /// Impulse response estimate.
+    ///
+    /// # Returns
+    /// The impulse response at lags `0..LAGS` in units of `y` per unit PRBS
+    /// amplitude, averaged over the complete periods. Zero if there are
+    /// none. Divide by the excitation amplitude to obtain the plant
+    /// impulse response.
+    pub fn read(&self) -> [f32; LAGS] {
+        let mut h = [0.; LAGS];
+        let periods = self.periods();
+        if periods > 0 {
+            let norm = (self.period as f64 + 1.) * periods as f64;
+            for (h, t) in h.iter_mut().zip(self.total.iter()) {
+                *h = ((*t + self.total_sum) as f64 / norm) as f32;
+            }
+        }
+        h
+    }
+}
+
+impl<const LAGS: usize> Reset for XCorr<LAGS> {
+    fn reset(&mut self) {
+        Self::reset(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::prbs::PRBS7;
+
+    #[test]
+    fn first_order() {
+        // y[n] = a y[n - 1] + b u[n]
+        let (a, b) = (0.8, 0.5);
+        let amplitude = 1 << 20;
+        let mut prbs = Prbs::new(PRBS7, 1).unwrap();
+        let mut xcorr = XCorr::<32>::new(127).unwrap();
+        let mut y = 0f64;
+        for _ in 0..127 * 5 {
+            let u = prbs.next_bit();
+            let x = if u { amplitude } else { -amplitude };
+            y = a * y + b * x as f64;
+            xcorr.update(u, y as i32);
+        }
+        assert_eq!(xcorr.periods(), 4);
+        let h = xcorr.read();
+        for (k, &h) in h.iter().enumerate() {
+            let want = b * a.powi(k as i32);
+            let h = h as f64 / amplitude as f64;
+            // Rounding of the response
+            assert!((h - want).abs() < 1e-5, "{}: {} {}", k, h, want);
+        }
+    }
+
+    #[test]
+    fn reset() {
+        let mut prbs = Prbs::new(PRBS7, 0x55).unwrap();
+        let mut xcorr = XCorr::<4>::new(127).unwrap();
+        assert_eq!(xcorr.read(), [0.; 4]);
+        // Pure delay by 2 and an offset
+        let mut u = [false; 3];
+        for i in 0..127 * 3 + 50 {
+            if i == 127 {
+                xcorr.reset_with(&mut prbs);
+            }
+            u = [prbs.next_bit(), u[0], u[1]];
+            let y = if u[2] { 1000 } else { -1000 } + 7;
+            xcorr.update(u[0], y);
+        }
+        assert_eq!(xcorr.periods(), 1);
+        // The offset is not separable from the impulse response
+        assert_eq!(xcorr.read(), [7., 7., 1007., 7.]);
+        assert!(XCorr::<4>::new(3).is_err());
+    }
+}
@@ -0,0 +1,52 @@
+//! Use of the `testing` feature from a downstream crate.
+use dsp::{goertzel::Goertzel, testing::*, Complex};
+
+#[test]
+fn white_noise() {
+    let n = 1 << 16;
+    let (mut sum, mut sum2) = (0f64, 0f64);
+    for x in WhiteNoise::new(1, 2.).take(n) {
+        assert!((-2. ..2.).contains(&x));
+        sum += x as f64;
+        sum2 += (x as f64).powi(2);
+    }
+    assert!(isclose(sum / n as f64, 0., 0., 2e-2));
+    assert!(isclose(sum2 / n as f64, 4. / 3., 1e-2, 0.));
+    // Deterministic
+    assert!(WhiteNoise::new(3, 1.)
+        .take(10)
+        .eq(WhiteNoise::new(3, 1.).take(10)));
+}
+
+#[test]
+fn polar() {
+    let a = Complex::<f32>::from_radians(3.1);
+    let b = Complex::<f32>::from_radians(-3.1) * 1.01;
+    // The phase difference wraps
+    assert!(complex_isclose_polar(a, b, 2e-2, 0.1, 0.));
+    assert!(!complex_isclose_polar(a, b, 2e-3, 0.1, 0.));
+    assert!(!complex_isclose_polar(a, b, 2e-2, 0.05, 0.));
+    // Phase is ignored near zero
+    let z = Complex(1e-7, 0.);
+    assert!(complex_isclose_polar(z, z.conj() * -1., 0., 0., 1e-6));
+}
+
+#[test]
+fn tone_db() {
+    // A tone in noise measured by a Goertzel DFT
+    let n = 1000;
+    let mut g = Goertzel::new(0.1);
+    let noise = WhiteNoise::new(2, 1e-2);
+    for (i, x) in noise.take(n).enumerate() {
+        let phase = 2. * core::f64::consts::PI * 0.1 * i as f64;
+        g.update(0.5 * phase.cos() as f32 + x);
+    }
+    let amplitude = 2. * g.finish().abs() / n as f32;
+    assert_close_db(amplitude, 0.5, 0.01);
+}
+
+#[test]
+#[should_panic]
+fn not_close_db() {
+    assert_close_db(1., 1.01, 0.01);
+}
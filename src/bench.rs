@@ -0,0 +1,267 @@
+///! On-target DSP micro-benchmarks
+///!
+///! With the `bench` feature, `run()` measures the cost of the DSP kernels in CPU cycles per
+///! sample using the DWT cycle counter (enabled during setup). The applications expose the
+///! results as the read-only `stabilizer/bench` attribute.
+///!
+///! The measurement runs in the idle context and can be preempted by the processing and
+///! network interrupts. Each kernel is therefore run several times on a short block of samples
+///! and the fastest run is reported.
+///!
+///! `Load` measures the cycles the processing interrupt takes per batch. The applications expose
+///! them as the `stabilizer/bench/load` attribute, to be compared with `BATCH_CYCLES`.
+use crate::hardware::design_parameters::{
+    ADC_SAMPLE_TICKS, SAMPLE_BUFFER_SIZE,
+};
+use serde::Serialize;
+
+// CPU clock cycles per sampling timer tick, 400 MHz over 100 MHz.
+const CYCLES_PER_TICK: u32 = 4;
+
+/// The cycle budget of the processing interrupt: the batch period in CPU cycles.
+pub const BATCH_CYCLES: u32 =
+    SAMPLE_BUFFER_SIZE as u32 * ADC_SAMPLE_TICKS as u32 * CYCLES_PER_TICK;
+
+/// Kernel costs in CPU cycles per sample.
+#[derive(Serialize, Copy, Clone, Debug, Default)]
+pub struct BenchResults {
+    /// `iir::IIR::update()`
+    pub iir: f32,
+    /// `iir::IIR::update_block()`
+    pub iir_block: f32,
+    /// `iir_int::IIR::update()`
+    pub iir_int: f32,
+    /// `lowpass::Lowpass::<2>::update()`
+    pub lowpass: f32,
+    /// `cossin()`
+    pub cossin: f32,
+    /// `atan2()`
+    pub atan2: f32,
+    /// `macc_i16()` with 32 taps
+    pub macc: f32,
+    /// `lockin::Lockin::update()` on a batch with decimation
+    pub lockin: f32,
+}
+
+/// Run the micro-benchmarks.
+///
+/// Returns:
+/// The kernel costs or an error if the firmware was built without the `bench` feature.
+pub fn run() -> Result<BenchResults, ()> {
+    #[cfg(feature = "bench")]
+    {
+        Ok(kernels::run())
+    }
+    #[cfg(not(feature = "bench"))]
+    {
+        Err(())
+    }
+}
+
+/// Processing interrupt cycles per batch since the last read.
+#[derive(Serialize, Copy, Clone, Debug, Default)]
+pub struct LoadResults {
+    /// Number of batches measured.
+    pub batches: u32,
+    /// Mean cycles per batch.
+    pub mean: f32,
+    /// Maximum cycles per batch.
+    pub max: u32,
+    /// Cycle budget per batch, see `BATCH_CYCLES`.
+    pub budget: u32,
+}
+
+/// Cycle count accumulator of the processing interrupt.
+///
+/// Without the `bench` feature nothing is measured.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Load {
+    batches: u32,
+    sum: u64,
+    max: u32,
+}
+
+impl Load {
+    /// Create a new, empty accumulator.
+    pub const fn new() -> Self {
+        Self {
+            batches: 0,
+            sum: 0,
+            max: 0,
+        }
+    }
+
+    /// The cycle counter at the start of the batch, see `update()`.
+    pub fn start() -> u32 {
+        #[cfg(feature = "bench")]
+        {
+            cortex_m::peripheral::DWT::get_cycle_count()
+        }
+        #[cfg(not(feature = "bench"))]
+        {
+            0
+        }
+    }
+
+    /// Record the end of a batch.
+    ///
+    /// Args:
+    /// * `start` - The cycle count at the start of the batch, see `start()`.
+    pub fn update(&mut self, start: u32) {
+        #[cfg(feature = "bench")]
+        {
+            let cycles = Self::start().wrapping_sub(start);
+            self.batches = self.batches.saturating_add(1);
+            self.sum = self.sum.saturating_add(cycles as u64);
+            self.max = self.max.max(cycles);
+        }
+        #[cfg(not(feature = "bench"))]
+        let _ = start;
+    }
+
+    /// Return the load and restart the accumulation.
+    ///
+    /// Returns:
+    /// The load or an error if the firmware was built without the `bench` feature.
+    pub fn read_and_reset(&mut self) -> Result<LoadResults, ()> {
+        if cfg!(not(feature = "bench")) {
+            return Err(());
+        }
+        let load = LoadResults {
+            batches: self.batches,
+            mean: if self.batches > 0 {
+                (self.sum as f64 / self.batches as f64) as f32
+            } else {
+                0.
+            },
+            max: self.max,
+            budget: BATCH_CYCLES,
+        };
+        *self = Self::new();
+        Ok(load)
+    }
+}
+
+#[cfg(feature = "bench")]
+mod kernels {
+    use super::BenchResults;
+    use crate::hardware::design_parameters::SAMPLE_BUFFER_SIZE;
+    use cortex_m::peripheral::DWT;
+    use dsp::{
+        atan2, cossin, iir, iir_int, lockin::Lockin, lowpass::Lowpass,
+        macc_i16, noise::Xoshiro, Accu,
+    };
+
+    // Samples per run.
+    const SAMPLES: usize = 64;
+    // Runs per kernel.
+    const REPEATS: usize = 8;
+    // Taps of the `macc_i16()` benchmark.
+    const TAPS: usize = 32;
+
+    // Force the evaluation of `x` and hide its value from the optimizer.
+    fn opaque<T: Copy>(x: T) -> T {
+        // Note(unsafe): `x` is a valid, aligned, initialized local.
+        unsafe { core::ptr::read_volatile(&x) }
+    }
+
+    // Minimum cycles per sample of `REPEATS` runs of `f`.
+    fn cycles(mut f: impl FnMut()) -> f32 {
+        let mut best = u32::MAX;
+        for _ in 0..REPEATS {
+            let start = DWT::get_cycle_count();
+            f();
+            best = best.min(DWT::get_cycle_count().wrapping_sub(start));
+        }
+        best as f32 / SAMPLES as f32
+    }
+
+    pub fn run() -> BenchResults {
+        let mut rng = Xoshiro::new(1);
+        let mut x = [0i32; SAMPLES];
+        let mut y = [0i32; SAMPLES];
+        let mut x16 = [0i16; SAMPLES + TAPS];
+        x.iter_mut().for_each(|x| *x = rng.next_u32() as i32);
+        y.iter_mut().for_each(|y| *y = rng.next_u32() as i32);
+        x16.iter_mut()
+            .for_each(|x| *x = (rng.next_u32() >> 16) as i16);
+        let mut xf = [0f32; SAMPLES];
+        let mut yf = [0f32; SAMPLES];
+        xf.iter_mut()
+            .zip(x.iter())
+            .for_each(|(xf, &x)| *xf = x as f32 / i32::MAX as f32);
+        let (x, y, x16) = (opaque(&x), opaque(&y), opaque(&x16));
+
+        let mut biquad = iir::IIR::new(1., -1., 1.);
+        biquad.ba = iir::Vec5([0.05, 0.1, 0.05, 1.2, -0.4]);
+        let mut xy = iir::Vec5::default();
+        let iir = cycles(|| {
+            for &x in xf.iter() {
+                opaque(biquad.update(&mut xy, x));
+            }
+        });
+        let iir_block = cycles(|| {
+            biquad.update_block(&mut xy, opaque(&xf), &mut yf);
+            opaque(&yf);
+        });
+
+        let mut biquad = iir_int::IIR::default();
+        biquad.ba = iir_int::Vec5::lowpass(1e-2, 0.7, 1.);
+        let mut xy = iir_int::Vec5::default();
+        let iir_int = cycles(|| {
+            for &x in x.iter() {
+                opaque(biquad.update(&mut xy, x >> 2));
+            }
+        });
+
+        let mut lowpass = Lowpass::<2>::default();
+        let lowpass = cycles(|| {
+            for &x in x.iter() {
+                opaque(lowpass.update(x >> 2, 8));
+            }
+        });
+
+        let cossin = cycles(|| {
+            for &x in x.iter() {
+                opaque(cossin(x));
+            }
+        });
+        let atan2 = cycles(|| {
+            for (&y, &x) in y.iter().zip(x.iter()) {
+                opaque(atan2(y, x));
+            }
+        });
+
+        let taps = [-0x2345i16; TAPS];
+        let macc = cycles(|| {
+            for x in x16.windows(TAPS).take(SAMPLES) {
+                opaque(macc_i16(0, x, &taps));
+            }
+        });
+
+        let mut lockin = Lockin::default();
+        let lockin = cycles(|| {
+            for batch in x16[..SAMPLES].chunks_exact(SAMPLE_BUFFER_SIZE) {
+                let frequency = lockin.demodulation_frequency(1 << 29);
+                let phase = lockin.demodulation_phase(opaque(0));
+                let output = batch
+                    .iter()
+                    .zip(Accu::new(phase, frequency))
+                    .map(|(&x, phase)| lockin.update(x, phase, 8))
+                    .last();
+                opaque(output);
+            }
+        });
+
+        BenchResults {
+            iir,
+            iir_block,
+            iir_int,
+            lowpass,
+            cossin,
+            atan2,
+            macc,
+            lockin,
+        }
+    }
+}
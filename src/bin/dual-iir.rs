@@ -8,22 +8,391 @@ use stm32h7xx_hal as hal;
 extern crate log;
 
 use rtic::cyccnt::{Instant, U32Ext};
+use rtic::Mutex;
+use typenum::Unsigned;
 
-use heapless::{consts::*, String};
+use heapless::{consts::*, Vec};
 
-use stabilizer::{hardware, server};
+use stabilizer::{
+    bench, broadcast, capture::Capture, hardware, hardware::design_parameters,
+    mqtt, server, streaming, streaming::Stream,
+};
 
-use dsp::iir;
-use hardware::{Adc0Input, Adc1Input, Dac0Output, Dac1Output, AFE0, AFE1};
+use dsp::{
+    clamp::Clamp,
+    cossin,
+    histogram::Histogram,
+    iir,
+    minmax::MinMax,
+    peak::PeakDetector,
+    prbs::{self, Prbs},
+    requantize::{NoiseShaping, Requantizer},
+    rms::Rms,
+    saturating_scale, scale,
+    signal_generator::{self, SignalGenerator, Waveform},
+    slew::SlewLimiter,
+    swap::SwapCell,
+    sweep::{Sweep, SweepLaw},
+    thd, Chain, Filter, Reset,
+};
+use hardware::{
+    Adc0Input, Adc1Input, Dac0Output, Dac1Output, InputStamper, AFE0, AFE1,
+};
 
 const SCALE: f32 = i16::MAX as _;
 
-const TCP_RX_BUFFER_SIZE: usize = 8192;
-const TCP_TX_BUFFER_SIZE: usize = 8192;
+// Log2 of the ADC input peak detector decay time constant in samples.
+const PEAK_DECAY_SHIFT: u32 = 16;
+
+// Log2 of the ADC input RMS window length in samples.
+const RMS_WINDOW_LOG2: u32 = 16;
+
+// Number of ADC input histogram bins, limited by the response size.
+const HISTOGRAM_BINS: usize = 16;
+
+// Log2 of the THD measurement lockin time constant in samples.
+const THD_K: u8 = 12;
+
+// THD measurement settle time in batches, 16 lockin time constants.
+const THD_SETTLE: u32 =
+    (16 << THD_K) / design_parameters::SAMPLE_BUFFER_SIZE as u32;
+
+// The ADC/DAC sample rate in Hz.
+const SAMPLE_RATE: f32 = design_parameters::TIMER_FREQUENCY.0 as f32 * 1e6
+    / design_parameters::ADC_SAMPLE_TICKS as f32;
+
+// Convert ADC machine units to volts at the front-end input.
+fn adc_volts(x: f32, gain: f32) -> f32 {
+    scale::units_to_volts(x, design_parameters::ADC_FULL_SCALE) / gain
+}
+
+// Convert DAC machine units to volts.
+fn dac_volts(y: f32) -> f32 {
+    scale::units_to_volts(y, design_parameters::DAC_FULL_SCALE)
+}
+
+// Read and reset extrema, converted to volts. Zero if empty.
+fn take_minmax(
+    minmax: &mut MinMax<f32>,
+    volts: impl Fn(f32) -> f32,
+) -> (f32, f32) {
+    match minmax.read_and_reset() {
+        (min, max) if min <= max => (volts(min), volts(max)),
+        _ => (0., 0.),
+    }
+}
+
+// Report the status of the first and of the last IIR stage of both channels.
+//
+// The extrema, the railed flags and the peaks are taken: they restart. Called
+// once per telemetry period, every reader of the status gets the latched copy.
+fn take_status(r: &mut idle::Resources, t: u32) -> [server::Status; 2] {
+    let gains = [
+        r.afes.0.get_gain().map_or(1., |g| g.as_multiplier()),
+        r.afes.1.get_gain().map_or(1., |g| g.as_multiplier()),
+    ];
+    let mut state = r.iir_state.lock(|iir_state| {
+        let stage = |stage: usize| server::Status {
+            t,
+            x0: adc_volts(iir_state[0][stage].0[0], gains[0]),
+            y0: dac_volts(iir_state[0][stage].0[2]),
+            x1: adc_volts(iir_state[1][stage].0[0], gains[1]),
+            y1: dac_volts(iir_state[1][stage].0[2]),
+            x0_min: 0.,
+            x0_max: 0.,
+            y0_min: 0.,
+            y0_max: 0.,
+            x1_min: 0.,
+            x1_max: 0.,
+            y1_min: 0.,
+            y1_max: 0.,
+            railed_low: [false; 2],
+            railed_high: [false; 2],
+            peak: [0.; 2],
+            rms: [0.; 2],
+            stream_dropped: 0,
+        };
+        [stage(0), stage(IIR_CASCADE_LENGTH - 1)]
+    });
+    let railed = r
+        .clamp
+        .lock(|clamp| [clamp[0].take_railed(), clamp[1].take_railed()]);
+    let extrema = r.minmax.lock(|minmax| {
+        let [m0, m1] = minmax;
+        [
+            take_minmax(&mut m0[0], |x| adc_volts(x, gains[0])),
+            take_minmax(&mut m0[1], dac_volts),
+            take_minmax(&mut m1[0], |x| adc_volts(x, gains[1])),
+            take_minmax(&mut m1[1], dac_volts),
+        ]
+    });
+    let peak = r
+        .peak
+        .lock(|peak| [peak[0].read_and_reset(), peak[1].read_and_reset()]);
+    let rms = r.rms.lock(|rms| [rms[0].rms(), rms[1].rms()]);
+    let stream_dropped = r.stream.lock(|stream| stream.dropped());
+
+    for state in state.iter_mut() {
+        let [x0, y0, x1, y1] = extrema;
+        state.x0_min = x0.0;
+        state.x0_max = x0.1;
+        state.y0_min = y0.0;
+        state.y0_max = y0.1;
+        state.x1_min = x1.0;
+        state.x1_max = x1.1;
+        state.y1_min = y1.0;
+        state.y1_max = y1.1;
+        for i in 0..2 {
+            state.railed_low[i] = railed[i].0;
+            state.railed_high[i] = railed[i].1;
+            let full_scale = design_parameters::ADC_FULL_SCALE;
+            state.peak[i] = scale::i32_to_volts(peak[i], full_scale) / gains[i];
+            state.rms[i] = scale::i32_to_volts(rms[i], full_scale) / gains[i];
+        }
+        state.stream_dropped = stream_dropped;
+    }
+    state
+}
+
+// Write a stage of the IIR cascade of a channel, staged if in a batch.
+//
+// Without ramp the state is transferred bumpless. A write cancels a running ramp
+// on the channel.
+fn set_stage(
+    r: &mut idle::Resources,
+    staged: &mut Staged,
+    channel: usize,
+    index: usize,
+    iir: iir::IIR,
+    ramp_samples: u32,
+) -> Result<(), &'static str> {
+    iir.validate().map_err(|e| e.as_str())?;
+    if channel > 1 {
+        return Err("invalid channel");
+    }
+    if index >= IIR_CASCADE_LENGTH {
+        return Err("invalid stage");
+    }
+    if staged.active {
+        if ramp_samples > 0 {
+            return Err("ramps are not supported in a batch");
+        }
+        staged.iir[channel][index] = Some(iir);
+        return Ok(());
+    }
+
+    let iir_ch = &r.iir_ch[channel];
+    // Note(unsafe): This is the only writer context.
+    let mut cascade = *unsafe { iir_ch.published() };
+    let ramp = if ramp_samples > 0 {
+        Some((
+            index,
+            iir::Interpolator::new(cascade.stages[index], iir, ramp_samples),
+        ))
+    } else {
+        None
+    };
+    r.ramp.lock(|r| r[channel] = ramp);
+    r.iir_state.lock(|iir_state| {
+        let stage = &mut cascade.stages[index];
+        if ramp.is_none() {
+            // Bumpless transfer to the new configuration
+            iir.transfer_state(stage, &mut iir_state[channel][index]);
+        }
+        *stage = iir;
+        unsafe { iir_ch.publish(cascade) };
+    });
+    Ok(())
+}
+
+// Reset the DSP chain state and the input statistics of a channel and cancel
+// its ramp.
+//
+// Nested such that process never sees a partially reset channel.
+fn reset_dsp(r: &mut idle::Resources, channel: usize) {
+    let (ramp, trim_state, clamp, slew, requantizer) = (
+        &mut r.ramp,
+        &mut r.trim_state,
+        &mut r.clamp,
+        &mut r.slew,
+        &mut r.requantizer,
+    );
+    let (minmax, peak, histogram, rms) =
+        (&mut r.minmax, &mut r.peak, &mut r.histogram, &mut r.rms);
+    r.iir_state.lock(|iir_state| {
+        ramp.lock(|ramp| {
+            trim_state.lock(|trim_state| {
+                clamp.lock(|clamp| {
+                    slew.lock(|slew| {
+                        requantizer.lock(|requantizer| {
+                            minmax.lock(|minmax| {
+                                peak.lock(|peak| {
+                                    histogram.lock(|histogram| {
+                                        rms.lock(|rms| {
+                                            iir_state[channel].reset();
+                                            ramp[channel] = None;
+                                            trim_state[channel] = [0.; 2];
+                                            clamp[channel].reset();
+                                            slew[channel].reset();
+                                            requantizer[channel].reset();
+                                            minmax[channel].reset();
+                                            peak[channel].reset();
+                                            histogram[channel].reset();
+                                            rms[channel].reset();
+                                        })
+                                    })
+                                })
+                            })
+                        })
+                    })
+                })
+            })
+        })
+    });
+}
+
+// Read and reset the histogram counts of a channel.
+fn histogram_counts(
+    histogram: &mut Histogram<HISTOGRAM_BINS>,
+) -> server::HistogramCounts {
+    let counts = histogram.read_and_reset();
+    server::HistogramCounts {
+        bins: counts.bins,
+        underflow: counts.underflow,
+        overflow: counts.overflow,
+    }
+}
+
+// Report the signal generator configuration of a channel.
+fn signal_generator_config(
+    channel: u8,
+    generator: &SignalGenerator,
+) -> server::SignalGeneratorRequest {
+    let full_scale = design_parameters::DAC_FULL_SCALE;
+    server::SignalGeneratorRequest {
+        channel,
+        waveform: generator.waveform,
+        frequency: signal_generator::step_to_frequency(generator.frequency())
+            * SAMPLE_RATE,
+        amplitude: scale::i32_to_volts(generator.amplitude, full_scale),
+        offset: scale::i32_to_volts(generator.offset, full_scale),
+    }
+}
+
+// Report the sweep progress of a channel.
+fn sweep_status(sweep: &Option<(Sweep, i32)>) -> server::SweepStatus {
+    match sweep {
+        Some((sweep, _)) => server::SweepStatus {
+            active: true,
+            finished: sweep.finished(),
+            frequency: signal_generator::step_to_frequency(sweep.ftw())
+                * SAMPLE_RATE,
+            progress: sweep.progress(),
+        },
+        None => server::SweepStatus {
+            active: false,
+            finished: false,
+            frequency: 0.,
+            progress: 0.,
+        },
+    }
+}
+
+// Report the THD measurement state and the last result.
+fn thd_status(
+    channel: usize,
+    busy: bool,
+    result: Option<thd::Thd>,
+) -> server::ThdStatus {
+    let valid = result.is_some();
+    let result = result.unwrap_or_default();
+    server::ThdStatus {
+        channel: channel as u8,
+        busy,
+        valid,
+        fundamental_dbfs: result.fundamental_dbfs,
+        thd_db: result.thd_db,
+        per_harmonic_db: result.per_harmonic_db,
+    }
+}
+
+// Per control connection, see `server::MAX_CLIENTS`.
+const TCP_RX_BUFFER_SIZE: usize = 4096;
+const TCP_TX_BUFFER_SIZE: usize = 4096;
+
+// Sample stream data port, frame buffer, and socket transmit buffer sizes.
+const STREAM_PORT: u16 = 1236;
+const STREAM_FRAMES: usize = 16;
+const STREAM_TX_BUFFER_SIZE: usize = 8 * streaming::FRAME_SIZE;
+
+// MQTT client socket buffer sizes, see `mqtt::Client`.
+const MQTT_RX_BUFFER_SIZE: usize = 1024;
+const MQTT_TX_BUFFER_SIZE: usize = 2048;
+
+// UDP status telemetry datagrams buffered, see `broadcast::Broadcast`.
+const BROADCAST_PACKETS: usize = 4;
+
+// The attributes pushed to their subscribers, see `server::Server::notify()`.
+const NOTIFIED: [&str; 6] = [
+    "stabilizer/mqtt",
+    "stabilizer/broadcast",
+    "stabilizer/afe0/gain",
+    "stabilizer/afe1/gain",
+    "stabilizer/iir/0",
+    "stabilizer/iir/1",
+];
+
+// Triggered capture buffer size in samples.
+const CAPTURE_SIZE: usize = 8192;
+
+// Samples per triggered capture readout chunk, limited by the response size.
+const CAPTURE_CHUNK: usize = server::CaptureChunk::USIZE;
 
 // The number of cascaded IIR biquads per channel. Select 1 or 2!
 const IIR_CASCADE_LENGTH: usize = 1;
 
+// Power-on defaults of the per channel processing.
+struct Settings {
+    iir: iir::Cascade<IIR_CASCADE_LENGTH>,
+    slew: SlewLimiter,
+    signal_generator: SignalGenerator,
+    requantizer: Requantizer,
+}
+
+static SETTINGS: Settings = Settings {
+    // Unity gain, limited to the DAC range
+    iir: iir::Cascade::new(
+        [iir::IIR::new(1., -SCALE, SCALE); IIR_CASCADE_LENGTH],
+    ),
+    // No slew rate limit
+    slew: SlewLimiter::new(u32::MAX),
+    // Off
+    signal_generator: SignalGenerator::new(Waveform::Sine, 0, 0, 0),
+    requantizer: Requantizer::new(NoiseShaping::None),
+};
+
+// The attributes that can be written in a batch request, see `Staged`.
+// Indexed attributes are given without the index.
+const BATCH_ATTRIBUTES: [&str; 8] = [
+    "stabilizer/iir",
+    "stabilizer/iir0/state",
+    "stabilizer/iir1/state",
+    "stabilizer/iir_b0/state",
+    "stabilizer/iir_b1/state",
+    "stabilizer/iir/design",
+    "stabilizer/afe0/gain",
+    "stabilizer/afe1/gain",
+];
+
+// Writes of a batch request, staged to be committed at once, see `server::Batch`.
+#[derive(Default)]
+struct Staged {
+    active: bool,
+    // New IIR stages per channel
+    iir: [[Option<iir::IIR>; IIR_CASCADE_LENGTH]; 2],
+    afe: [Option<hardware::AfeGain>; 2],
+}
+
 #[rtic::app(device = stm32h7xx_hal::stm32, peripherals = true, monotonic = rtic::cyccnt::CYCCNT)]
 const APP: () = {
     struct Resources {
@@ -35,8 +404,62 @@ const APP: () = {
         // Format: iir_state[ch][cascade-no][coeff]
         #[init([[iir::Vec5([0.; 5]); IIR_CASCADE_LENGTH]; 2])]
         iir_state: [[iir::Vec5; IIR_CASCADE_LENGTH]; 2],
-        #[init([[iir::IIR::new(1., -SCALE, SCALE); IIR_CASCADE_LENGTH]; 2])]
-        iir_ch: [[iir::IIR; IIR_CASCADE_LENGTH]; 2],
+        // Written by idle, read by process without locking
+        iir_ch: [SwapCell<iir::Cascade<IIR_CASCADE_LENGTH>>; 2],
+        // Gain scheduling ramps of one IIR stage per channel
+        #[init([None; 2])]
+        ramp: [Option<(usize, iir::Interpolator)>; 2],
+        // First-order trim sections and their positions
+        #[init([None; 2])]
+        trim: [Option<(iir::FirstOrder, server::TrimPosition)>; 2],
+        #[init([[0.; 2]; 2])]
+        trim_state: [[f32; 2]; 2],
+        // Railed flags of the IIR output limits
+        #[init([Clamp::new(0.); 2])]
+        clamp: [Clamp; 2],
+        // Extrema of the ADC inputs and the IIR outputs, machine units
+        #[init([[MinMax::<f32>::new(); 2]; 2])]
+        minmax: [[MinMax<f32>; 2]; 2],
+        // ADC input peak detectors
+        #[init([PeakDetector::new(PEAK_DECAY_SHIFT); 2])]
+        peak: [PeakDetector; 2],
+        // ADC input histograms, one ADC LSB bins centered on zero
+        #[init([Histogram::new(0, 16); 2])]
+        histogram: [Histogram<HISTOGRAM_BINS>; 2],
+        // ADC input RMS
+        #[init([Rms::new(); 2])]
+        rms: [Rms<RMS_WINDOW_LOG2>; 2],
+        // DAC output slew rate limiters, DAC LSB per sample
+        slew: [SlewLimiter; 2],
+        // Signal generators summed into the DAC outputs
+        signal_generator: [SignalGenerator; 2],
+        // Swept sines and their amplitudes summed into the DAC outputs
+        #[init([None; 2])]
+        sweep: [Option<(Sweep, i32)>; 2],
+        // PRBS excitations and their amplitudes summed into the DAC outputs
+        #[init([None; 2])]
+        prbs: [Option<(Prbs, i32)>; 2],
+        // DAC output requantizers
+        requantizer: [Requantizer; 2],
+        // THD of an ADC input with respect to its signal generator tone
+        thd: thd::Estimator,
+        #[init(0)]
+        thd_channel: usize,
+        #[init(None)]
+        thd_result: Option<thd::Thd>,
+        // Binary ADC/DAC sample stream and the sample counter timestamping it
+        stream: Stream<STREAM_FRAMES>,
+        #[init(0)]
+        timestamp: u32,
+        // Triggered capture of an ADC input, DI0 edges
+        #[init(Capture::new())]
+        capture: Capture<CAPTURE_SIZE>,
+        #[init(0)]
+        capture_channel: usize,
+        timestamper: InputStamper,
+        // Processing cycles per batch, see `bench::Load`
+        #[init(bench::Load::new())]
+        load: bench::Load,
     }
 
     #[init]
@@ -44,20 +467,35 @@ const APP: () = {
         // Configure the microcontroller
         let (mut stabilizer, _pounder) = hardware::setup(c.core, c.device);
 
+        let cascade = SETTINGS.iir;
+
         // Enable ADC/DAC events
         stabilizer.adcs.0.start();
         stabilizer.adcs.1.start();
         stabilizer.dacs.0.start();
         stabilizer.dacs.1.start();
 
+        // Start recording digital input timestamps.
+        stabilizer.timestamp_timer.start();
+
         // Start sampling ADCs.
         stabilizer.adc_dac_timer.start();
 
+        // Enable the timestamper, the capture trigger.
+        stabilizer.timestamper.start();
+
         init::LateResources {
             afes: stabilizer.afes,
             adcs: stabilizer.adcs,
             dacs: stabilizer.dacs,
             net_interface: stabilizer.net.interface,
+            iir_ch: [SwapCell::new(cascade), SwapCell::new(cascade)],
+            slew: [SETTINGS.slew; 2],
+            signal_generator: [SETTINGS.signal_generator; 2],
+            requantizer: [SETTINGS.requantizer; 2],
+            thd: thd::Estimator::new(THD_K, THD_SETTLE),
+            stream: Stream::new(),
+            timestamper: stabilizer.timestamper,
         }
     }
 
@@ -77,8 +515,13 @@ const APP: () = {
     ///
     /// Because the ADC and DAC operate at the same rate, these two constraints actually implement
     /// the same time bounds, meeting one also means the other is also met.
-    #[task(binds=DMA1_STR4, resources=[adcs, dacs, iir_state, iir_ch], priority=2)]
+    ///
+    /// With the `bench` feature the cycles per batch, including the THD, stream, capture and
+    /// ramp work, are measured and reported as `stabilizer/bench/load`.
+    #[task(binds=DMA1_STR4, resources=[adcs, dacs, iir_state, &iir_ch, ramp, trim, trim_state, clamp, minmax, peak, histogram, rms, slew, signal_generator, sweep, prbs, requantizer, thd, thd_channel, thd_result, stream, timestamp, capture, capture_channel, timestamper, load], priority=2)]
     fn process(c: process::Context) {
+        let start = bench::Load::start();
+
         let adc_samples = [
             c.resources.adcs.0.acquire_buffer(),
             c.resources.adcs.1.acquire_buffer(),
@@ -89,46 +532,218 @@ const APP: () = {
             c.resources.dacs.1.acquire_buffer(),
         ];
 
+        // A DI0 edge during the batch, attributed to its first sample.
+        let edge = c
+            .resources
+            .timestamper
+            .latest_timestamp()
+            .unwrap_or_else(|t| t)
+            .is_some();
+
         for channel in 0..adc_samples.len() {
+            // Requantize to DAC LSB, then limit the slew rate. Without noise
+            // shaping the truncation introduces 1/2 LSB distortion. The
+            // limiter output stays within the range of its inputs.
+            let mut output = Chain((
+                &mut c.resources.requantizer[channel],
+                &mut c.resources.slew[channel],
+            ));
+            // Test tone phase of the first sample
+            let tone = &c.resources.signal_generator[channel];
+            let (tone_phase, tone_frequency) = (tone.phase(), tone.frequency());
             for sample in 0..adc_samples[0].len() {
-                let x = f32::from(adc_samples[channel][sample] as i16);
-                let mut y = x;
-                for i in 0..c.resources.iir_state[channel].len() {
-                    y = c.resources.iir_ch[channel][i]
-                        .update(&mut c.resources.iir_state[channel][i], y);
+                let code = adc_samples[channel][sample];
+                let x = scale::adc_code_to_i32(code);
+                c.resources.peak[channel].update(x);
+                c.resources.rms[channel].update(x);
+                c.resources.histogram[channel].update(x);
+                let x = f32::from(code as i16);
+                c.resources.minmax[channel][0].update(x);
+                let trim = c.resources.trim[channel];
+                let trim_state = &mut c.resources.trim_state[channel];
+                let x = match trim {
+                    Some((trim, server::TrimPosition::Before)) => {
+                        trim.update(trim_state, x)
+                    }
+                    _ => x,
+                };
+                // Note(unsafe): This is the only reader context.
+                let iir_ch = unsafe { c.resources.iir_ch[channel].latest() };
+                // Override the ramping stage with the interpolated one.
+                let ramped;
+                let iir_ch = match &mut c.resources.ramp[channel] {
+                    Some((stage, interp)) => {
+                        let mut cascade = *iir_ch;
+                        cascade.stages[*stage] = interp.next();
+                        ramped = cascade;
+                        &ramped
+                    }
+                    None => iir_ch,
+                };
+                let y = iir_ch.update(&mut c.resources.iir_state[channel], x);
+                if matches!(&c.resources.ramp[channel], Some((_, interp)) if interp.done())
+                {
+                    c.resources.ramp[channel] = None;
+                }
+                let y = match trim {
+                    Some((trim, server::TrimPosition::After)) => {
+                        trim.update(trim_state, y)
+                    }
+                    _ => y,
+                };
+                // Record whether the output limits of the last stage were hit.
+                let last = &iir_ch.stages[IIR_CASCADE_LENGTH - 1];
+                let y = c.resources.clamp[channel]
+                    .update(y, last.y_min, last.y_max);
+                c.resources.minmax[channel][1].update(y);
+                // Convert to the i32 DSP domain, rounding toward zero and
+                // saturating.
+                let y = (y * (1 << 16) as f32) as i32;
+                // Sum in the signal sources, saturating.
+                let mut y = y.saturating_add(
+                    c.resources.signal_generator[channel].next(),
+                );
+                if let Some((sweep, amplitude)) =
+                    &mut c.resources.sweep[channel]
+                {
+                    if let Some(phase) = sweep.next() {
+                        y = y.saturating_add(saturating_scale(
+                            cossin(phase).1,
+                            *amplitude,
+                        ));
+                    }
                 }
-                // Note(unsafe): The filter limits ensure that the value is in range.
-                // The truncation introduces 1/2 LSB distortion.
-                let y = unsafe { y.to_int_unchecked::<i16>() };
+                if let Some((prbs, amplitude)) = &mut c.resources.prbs[channel]
+                {
+                    y = y.saturating_add(prbs.next_i32(*amplitude));
+                }
+                let y = output.update(y) as i16;
                 // Convert to DAC code
                 dac_samples[channel][sample] = y as u16 ^ 0x8000;
             }
+            let mut x = [0i16; design_parameters::SAMPLE_BUFFER_SIZE];
+            x.iter_mut()
+                .zip(adc_samples[channel].iter())
+                .for_each(|(x, &code)| *x = code as i16);
+            let mut y = [0i16; design_parameters::SAMPLE_BUFFER_SIZE];
+            y.iter_mut()
+                .zip(dac_samples[channel].iter())
+                .for_each(|(y, &code)| *y = (code ^ 0x8000) as i16);
+            let stream = &mut c.resources.stream;
+            stream.push(channel as u8, *c.resources.timestamp, &x);
+            stream.push(2 + channel as u8, *c.resources.timestamp, &y);
+            if channel == *c.resources.capture_channel {
+                for (i, &x) in x.iter().enumerate() {
+                    c.resources.capture.update(x, edge && i == 0);
+                }
+            }
+            if channel == *c.resources.thd_channel {
+                if let Some(result) =
+                    c.resources.thd.update(&x, tone_phase, tone_frequency)
+                {
+                    *c.resources.thd_result = Some(result);
+                }
+            }
         }
+        *c.resources.timestamp = c
+            .resources
+            .timestamp
+            .wrapping_add(design_parameters::SAMPLE_BUFFER_SIZE as u32);
+        c.resources.load.update(start);
     }
 
-    #[idle(resources=[net_interface, iir_state, iir_ch, afes])]
+    #[idle(resources=[net_interface, iir_state, &iir_ch, ramp, trim, trim_state, clamp, minmax, peak, histogram, rms, slew, signal_generator, sweep, prbs, requantizer, thd, thd_channel, thd_result, stream, capture, capture_channel, afes, load])]
     fn idle(mut c: idle::Context) -> ! {
         let mut socket_set_entries: [_; 8] = Default::default();
         let mut sockets =
             smoltcp::socket::SocketSet::new(&mut socket_set_entries[..]);
 
-        let mut rx_storage = [0; TCP_RX_BUFFER_SIZE];
-        let mut tx_storage = [0; TCP_TX_BUFFER_SIZE];
-        let tcp_handle = {
-            let tcp_rx_buffer =
-                smoltcp::socket::TcpSocketBuffer::new(&mut rx_storage[..]);
-            let tcp_tx_buffer =
-                smoltcp::socket::TcpSocketBuffer::new(&mut tx_storage[..]);
-            let tcp_socket =
-                smoltcp::socket::TcpSocket::new(tcp_rx_buffer, tcp_tx_buffer);
-            sockets.add(tcp_socket)
+        // One socket per control connection
+        let mut rx_storage = [[0; TCP_RX_BUFFER_SIZE]; server::MAX_CLIENTS];
+        let mut tx_storage = [[0; TCP_TX_BUFFER_SIZE]; server::MAX_CLIENTS];
+        let tcp_handles: Vec<_, U4> = rx_storage
+            .iter_mut()
+            .zip(tx_storage.iter_mut())
+            .map(|(rx_storage, tx_storage)| {
+                let tcp_rx_buffer =
+                    smoltcp::socket::TcpSocketBuffer::new(&mut rx_storage[..]);
+                let tcp_tx_buffer =
+                    smoltcp::socket::TcpSocketBuffer::new(&mut tx_storage[..]);
+                let tcp_socket = smoltcp::socket::TcpSocket::new(
+                    tcp_rx_buffer,
+                    tcp_tx_buffer,
+                );
+                sockets.add(tcp_socket)
+            })
+            .collect();
+
+        // Streaming only: the receive buffer is unused.
+        let mut stream_rx_storage = [0; 64];
+        let mut stream_tx_storage = [0; STREAM_TX_BUFFER_SIZE];
+        let stream_handle = {
+            let rx_buffer = smoltcp::socket::TcpSocketBuffer::new(
+                &mut stream_rx_storage[..],
+            );
+            let tx_buffer = smoltcp::socket::TcpSocketBuffer::new(
+                &mut stream_tx_storage[..],
+            );
+            sockets.add(smoltcp::socket::TcpSocket::new(rx_buffer, tx_buffer))
+        };
+
+        let mut mqtt_rx_storage = [0; MQTT_RX_BUFFER_SIZE];
+        let mut mqtt_tx_storage = [0; MQTT_TX_BUFFER_SIZE];
+        let mqtt_handle = {
+            let rx_buffer =
+                smoltcp::socket::TcpSocketBuffer::new(&mut mqtt_rx_storage[..]);
+            let tx_buffer =
+                smoltcp::socket::TcpSocketBuffer::new(&mut mqtt_tx_storage[..]);
+            sockets.add(smoltcp::socket::TcpSocket::new(rx_buffer, tx_buffer))
         };
 
-        let mut server = server::Server::new();
+        // The telemetry socket only sends.
+        let mut broadcast_rx_metadata =
+            [smoltcp::socket::UdpPacketMetadata::EMPTY; 1];
+        let mut broadcast_rx_storage = [0; 0];
+        let mut broadcast_tx_metadata =
+            [smoltcp::socket::UdpPacketMetadata::EMPTY; BROADCAST_PACKETS];
+        let mut broadcast_tx_storage =
+            [0; BROADCAST_PACKETS * broadcast::PACKET_SIZE];
+        let broadcast_handle = {
+            let rx_buffer = smoltcp::socket::UdpSocketBuffer::new(
+                &mut broadcast_rx_metadata[..],
+                &mut broadcast_rx_storage[..],
+            );
+            let tx_buffer = smoltcp::socket::UdpSocketBuffer::new(
+                &mut broadcast_tx_metadata[..],
+                &mut broadcast_tx_storage[..],
+            );
+            sockets.add(smoltcp::socket::UdpSocket::new(rx_buffer, tx_buffer))
+        };
+
+        let mut server = server::Server::new(1235, &NOTIFIED);
+
+        // Status telemetry, disabled until a broker is configured
+        let mut mqtt = mqtt::Client::new(
+            c.resources.net_interface.ethernet_addr(),
+            "stabilizer/iir/state",
+        );
+        let mut mqtt_settings = mqtt.settings().clone();
+
+        // UDP status telemetry, disabled until a target is configured
+        let mut broadcast = broadcast::Broadcast::new("stabilizer/iir/state");
+        let mut broadcast_settings = *broadcast.settings();
+
+        // Triggered capture readout position
+        let mut capture_offset = 0usize;
+
+        let mut staged = Staged::default();
 
         let mut time = 0u32;
         let mut next_ms = Instant::now();
 
+        // The status of the last complete telemetry period
+        let mut status = take_status(&mut c.resources, time);
+
         // TODO: Replace with reference to CPU clock from CCDR.
         next_ms += 400_000.cycles();
 
@@ -140,104 +755,449 @@ const APP: () = {
                 time += 1;
             }
 
-            {
-                let socket =
-                    &mut *sockets.get::<smoltcp::socket::TcpSocket>(tcp_handle);
-                if socket.state() == smoltcp::socket::TcpState::CloseWait {
-                    socket.close();
-                } else if !(socket.is_open() || socket.is_listening()) {
-                    socket
-                        .listen(1235)
-                        .unwrap_or_else(|e| warn!("TCP listen error: {:?}", e));
-                } else {
-                    server.poll(socket, |req| {
-                        info!("Got request: {:?}", req);
-                        stabilizer::route_request!(req,
-                            readable_attributes: [
-                                "stabilizer/iir/state": (|| {
-                                    let state = c.resources.iir_state.lock(|iir_state|
-                                        server::Status {
-                                            t: time,
-                                            x0: iir_state[0][0].0[0],
-                                            y0: iir_state[0][0].0[2],
-                                            x1: iir_state[1][0].0[0],
-                                            y1: iir_state[1][0].0[2],
-                                    });
-
-                                    Ok::<server::Status, ()>(state)
-                                }),
-                                // "_b" means cascades 2nd IIR
-                                "stabilizer/iir_b/state": (|| { let state = c.resources.iir_state.lock(|iir_state|
-                                        server::Status {
-                                            t: time,
-                                            x0: iir_state[0][IIR_CASCADE_LENGTH-1].0[0],
-                                            y0: iir_state[0][IIR_CASCADE_LENGTH-1].0[2],
-                                            x1: iir_state[1][IIR_CASCADE_LENGTH-1].0[0],
-                                            y1: iir_state[1][IIR_CASCADE_LENGTH-1].0[2],
-                                    });
-
-                                    Ok::<server::Status, ()>(state)
-                                }),
-                                "stabilizer/afe0/gain": (|| c.resources.afes.0.get_gain()),
-                                "stabilizer/afe1/gain": (|| c.resources.afes.1.get_gain())
-                            ],
-
-                            modifiable_attributes: [
-                                "stabilizer/iir0/state": server::IirRequest, (|req: server::IirRequest| {
-                                    c.resources.iir_ch.lock(|iir_ch| {
-                                        if req.channel > 1 {
-                                            return Err(());
-                                        }
+            let period = tick && time % broadcast::PERIOD_MS == 0;
+            if period {
+                status = take_status(&mut c.resources, time);
+            }
 
-                                        iir_ch[req.channel as usize][0] = req.iir;
+            let mut route = |req: &server::Request| {
+                info!("Got request: {:?}", req);
+                if staged.active
+                    && matches!(req.req, server::AccessRequest::Write)
+                    && !BATCH_ATTRIBUTES.contains(&req.attribute)
+                    && !BATCH_ATTRIBUTES
+                        .contains(&server::split_index(req.attribute).0)
+                {
+                    return server::Response::validation_failed(
+                        req.attribute,
+                        "not supported in a batch",
+                    );
+                }
+                stabilizer::route_request!(req,
+                    readable_attributes: [
+                        "stabilizer/iir/state": (|| {
+                            Ok::<server::Status, ()>(status[0])
+                        }),
+                        // "_b" means cascades 2nd IIR
+                        "stabilizer/iir_b/state": (|| {
+                            Ok::<server::Status, ()>(status[1])
+                        }),
+                        "stabilizer/slew0/max_step": (|| {
+                            let max_step = c.resources.slew.lock(|slew| slew[0].max_step);
+                            Ok::<u32, ()>(max_step)
+                        }),
+                        "stabilizer/slew1/max_step": (|| {
+                            let max_step = c.resources.slew.lock(|slew| slew[1].max_step);
+                            Ok::<u32, ()>(max_step)
+                        }),
+                        "stabilizer/signal_generator0": (|| {
+                            let config = c.resources.signal_generator.lock(|gen| signal_generator_config(0, &gen[0]));
+                            Ok::<server::SignalGeneratorRequest, ()>(config)
+                        }),
+                        "stabilizer/signal_generator1": (|| {
+                            let config = c.resources.signal_generator.lock(|gen| signal_generator_config(1, &gen[1]));
+                            Ok::<server::SignalGeneratorRequest, ()>(config)
+                        }),
+                        "stabilizer/sweep0": (|| {
+                            let status = c.resources.sweep.lock(|sweep| sweep_status(&sweep[0]));
+                            Ok::<server::SweepStatus, ()>(status)
+                        }),
+                        "stabilizer/sweep1": (|| {
+                            let status = c.resources.sweep.lock(|sweep| sweep_status(&sweep[1]));
+                            Ok::<server::SweepStatus, ()>(status)
+                        }),
+                        "stabilizer/noise_shaping0": (|| {
+                            let order = c.resources.requantizer.lock(|q| q[0].order);
+                            Ok::<NoiseShaping, ()>(order)
+                        }),
+                        "stabilizer/noise_shaping1": (|| {
+                            let order = c.resources.requantizer.lock(|q| q[1].order);
+                            Ok::<NoiseShaping, ()>(order)
+                        }),
+                        "stabilizer/histogram0": (|| {
+                            let counts = c.resources.histogram.lock(|h| histogram_counts(&mut h[0]));
+                            Ok::<server::HistogramCounts, ()>(counts)
+                        }),
+                        "stabilizer/histogram1": (|| {
+                            let counts = c.resources.histogram.lock(|h| histogram_counts(&mut h[1]));
+                            Ok::<server::HistogramCounts, ()>(counts)
+                        }),
+                        "stabilizer/afe0/gain": (|| c.resources.afes.0.get_gain()),
+                        "stabilizer/afe1/gain": (|| c.resources.afes.1.get_gain()),
+                        "stabilizer/stream": (|| {
+                            let req = c.resources.stream.lock(|stream| server::StreamRequest {
+                                channels: stream.channels(),
+                                decimation: stream.decimation(),
+                            });
+                            Ok::<server::StreamRequest, ()>(req)
+                        }),
+                        "stabilizer/mqtt": (|| Ok::<_, ()>(mqtt_settings.clone())),
+                        "stabilizer/broadcast": (|| Ok::<_, ()>(broadcast_settings)),
+                        "stabilizer/capture": (|| {
+                            let channel = c.resources.capture_channel.lock(|ch| *ch);
+                            let status = c.resources.capture.lock(|capture| server::CaptureStatus {
+                                state: capture.state(),
+                                count: capture.count(),
+                                channel: channel as u8,
+                                depth: capture.depth() as u32,
+                                pre_trigger: capture.pre_trigger() as u32,
+                                offset: capture_offset as u32,
+                            });
+                            Ok::<server::CaptureStatus, ()>(status)
+                        }),
+                        // Reads advance the offset by the chunk length.
+                        "stabilizer/capture/data": (|| {
+                            let mut samples = [0; CAPTURE_CHUNK];
+                            let (count, len) = c.resources.capture.lock(|capture| {
+                                capture.read(capture_offset, &mut samples).map(|len| (capture.count(), len))
+                            })?;
+                            let data = server::CaptureData {
+                                count,
+                                offset: capture_offset as u32,
+                                samples: Vec::from_slice(&samples[..len]).unwrap(),
+                            };
+                            capture_offset += len;
+                            Ok::<server::CaptureData, &str>(data)
+                        }),
+                        "stabilizer/thd": (|| {
+                            let (busy, channel) = (c.resources.thd.lock(|thd| thd.busy()), c.resources.thd_channel.lock(|ch| *ch));
+                            let result = c.resources.thd_result.lock(|r| *r);
+                            Ok::<server::ThdStatus, ()>(thd_status(channel, busy, result))
+                        }),
+                        "stabilizer/bench": (bench::run),
+                        "stabilizer/bench/load": (|| c.resources.load.lock(|load| load.read_and_reset()))
+                    ],
 
-                                        Ok::<server::IirRequest, ()>(req)
-                                    })
-                                }),
-                                "stabilizer/iir1/state": server::IirRequest, (|req: server::IirRequest| {
-                                    c.resources.iir_ch.lock(|iir_ch| {
-                                        if req.channel > 1 {
-                                            return Err(());
-                                        }
+                    modifiable_attributes: [
+                        "stabilizer/iir0/state": server::IirRequest, (|req: server::IirRequest| {
+                            let index = req.stage as usize;
+                            set_stage(&mut c.resources, &mut staged, req.channel as usize, index, req.iir, req.ramp_samples)?;
+                            Ok::<server::IirRequest, &str>(req)
+                        }),
+                        "stabilizer/iir1/state": server::IirRequest, (|req: server::IirRequest| {
+                            let index = req.stage as usize;
+                            set_stage(&mut c.resources, &mut staged, req.channel as usize, index, req.iir, req.ramp_samples)?;
+                            Ok::<server::IirRequest, &str>(req)
+                        }),
+                        "stabilizer/iir_b0/state": server::IirRequest, (|req: server::IirRequest| {
+                            let index = IIR_CASCADE_LENGTH - 1;
+                            set_stage(&mut c.resources, &mut staged, req.channel as usize, index, req.iir, req.ramp_samples)?;
+                            Ok::<server::IirRequest, &str>(req)
+                        }),
+                        "stabilizer/iir_b1/state": server::IirRequest, (|req: server::IirRequest| {
+                            let index = IIR_CASCADE_LENGTH - 1;
+                            set_stage(&mut c.resources, &mut staged, req.channel as usize, index, req.iir, req.ramp_samples)?;
+                            Ok::<server::IirRequest, &str>(req)
+                        }),
+                        "stabilizer/iir/design": server::IirDesignRequest, (|req: server::IirDesignRequest| {
+                            let (channel, index) = (req.channel as usize, req.stage as usize);
+                            if channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            if index >= IIR_CASCADE_LENGTH {
+                                return Err("invalid stage");
+                            }
+                            let ba = iir::design::design(req.filter, req.f0 / SAMPLE_RATE, req.q, req.gain)?;
 
-                                        iir_ch[req.channel as usize][0] = req.iir;
+                            // Keep offset and limits, staged in a batch
+                            // Note(unsafe): This is the only writer context.
+                            let current = unsafe { c.resources.iir_ch[channel].published() }.stages[index];
+                            let mut iir = staged.iir[channel][index].unwrap_or(current);
+                            iir.ba = iir::Vec5(ba);
+                            set_stage(&mut c.resources, &mut staged, channel, index, iir, 0)?;
 
-                                        Ok::<server::IirRequest, ()>(req)
-                                    })
-                                }),
-                                "stabilizer/iir_b0/state": server::IirRequest, (|req: server::IirRequest| {
-                                    c.resources.iir_ch.lock(|iir_ch| {
-                                        if req.channel > 1 {
-                                            return Err(());
-                                        }
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/slew0/max_step": u32, (|max_step| {
+                            c.resources.slew.lock(|slew| slew[0].max_step = max_step);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/slew1/max_step": u32, (|max_step| {
+                            c.resources.slew.lock(|slew| slew[1].max_step = max_step);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/signal_generator": server::SignalGeneratorRequest, (|req: server::SignalGeneratorRequest| {
+                            if req.channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            let f = req.frequency / SAMPLE_RATE;
+                            if f.is_nan() || dsp::math::abs(f) > 0.5 {
+                                return Err("invalid frequency");
+                            }
+                            let full_scale = design_parameters::DAC_FULL_SCALE;
+                            let amplitude = scale::volts_to_i32(req.amplitude, full_scale);
+                            let offset = scale::volts_to_i32(req.offset, full_scale);
+                            c.resources.signal_generator.lock(|gen| {
+                                let gen = &mut gen[req.channel as usize];
+                                // Phase continuous frequency change
+                                gen.set_frequency(signal_generator::frequency_to_step(f));
+                                gen.waveform = req.waveform;
+                                gen.amplitude = amplitude;
+                                gen.offset = offset;
+                            });
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/sweep": server::SweepRequest, (|req: server::SweepRequest| {
+                            if req.channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            let fs = SAMPLE_RATE as f64;
+                            let ftw = |f: f32| signal_generator::frequency_to_step(f / SAMPLE_RATE);
+                            // FTW LSB per sample in Q16.16, octaves per sample in Q0.32
+                            let rate = match req.law {
+                                SweepLaw::Linear => req.rate as f64 * (1u64 << 48) as f64 / (fs * fs),
+                                SweepLaw::Log => req.rate as f64 * (1u64 << 32) as f64 / fs,
+                            };
+                            let sweep = Sweep::new(ftw(req.start), ftw(req.stop), rate as i32, req.law, req.mode)?;
+                            let amplitude = scale::volts_to_i32(req.amplitude, design_parameters::DAC_FULL_SCALE);
+                            c.resources.sweep.lock(|s| s[req.channel as usize] = Some((sweep, amplitude)));
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/prbs": server::PrbsRequest, (|req: server::PrbsRequest| {
+                            if req.channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            let taps = match req.order {
+                                7 => prbs::PRBS7,
+                                15 => prbs::PRBS15,
+                                23 => prbs::PRBS23,
+                                31 => prbs::PRBS31,
+                                _ => return Err("invalid order"),
+                            };
+                            let amplitude = scale::volts_to_i32(req.amplitude, design_parameters::DAC_FULL_SCALE);
+                            let prbs = if amplitude == 0 {
+                                None
+                            } else {
+                                Some((Prbs::new(taps, req.seed)?, amplitude))
+                            };
+                            c.resources.prbs.lock(|p| p[req.channel as usize] = prbs);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/noise_shaping0": NoiseShaping, (|order| {
+                            c.resources.requantizer.lock(|q| q[0].order = order);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/noise_shaping1": NoiseShaping, (|order| {
+                            c.resources.requantizer.lock(|q| q[1].order = order);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/trim": server::TrimRequest, (|req: server::TrimRequest| {
+                            if req.channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            let trim = if req.enable {
+                                let (fz, fp) = (req.fz / SAMPLE_RATE, req.fp / SAMPLE_RATE);
+                                let section = match req.kind {
+                                    server::TrimKind::Lead => iir::FirstOrder::lead(fz, fp)?,
+                                    server::TrimKind::Lag => iir::FirstOrder::lag(fz, fp)?,
+                                };
+                                section.validate().map_err(|e| e.as_str())?;
+                                Some((section, req.position))
+                            } else {
+                                None
+                            };
+                            let channel = req.channel as usize;
+                            c.resources.trim.lock(|t| t[channel] = trim);
+                            c.resources.trim_state.lock(|xy| xy[channel] = [0.; 2]);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/histogram": server::HistogramRequest, (|req: server::HistogramRequest| {
+                            if req.channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            if req.shift > 32 {
+                                return Err("invalid shift");
+                            }
+                            let center = scale::volts_to_i32(req.center, design_parameters::ADC_FULL_SCALE);
+                            c.resources.histogram.lock(|h| {
+                                h[req.channel as usize] = Histogram::new(center, req.shift)
+                            });
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/reset_dsp": u8, (|channel: u8| {
+                            if channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            reset_dsp(&mut c.resources, channel as usize);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/stream": server::StreamRequest, (|req: server::StreamRequest| {
+                            c.resources.stream.lock(|stream| stream.configure(req.channels, req.decimation))?;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/mqtt": server::MqttRequest, (|req: server::MqttRequest| {
+                            mqtt::validate(&req)?;
+                            mqtt_settings = req;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/broadcast": server::BroadcastRequest, (|req: server::BroadcastRequest| {
+                            broadcast::validate(&req)?;
+                            broadcast_settings = req;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/capture": server::CaptureRequest, (|req: server::CaptureRequest| {
+                            if req.channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            let r = &mut c.resources;
+                            let capture_channel = &mut r.capture_channel;
+                            // Nested such that process never captures from the old channel.
+                            r.capture.lock(|capture| {
+                                capture
+                                    .configure(req.depth as usize, req.pre_trigger as usize, req.trigger)
+                                    .map(|_| capture_channel.lock(|ch| *ch = req.channel as usize))
+                            })?;
+                            capture_offset = 0;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/capture/arm": bool, (|arm: bool| {
+                            c.resources.capture.lock(|capture| if arm { capture.arm() } else { capture.disarm() });
+                            capture_offset = 0;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/capture/force": bool, (|force: bool| {
+                            if force {
+                                c.resources.capture.lock(|capture| capture.force());
+                            }
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/capture/offset": u32, (|offset: u32| {
+                            capture_offset = offset as usize;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/measure_thd": u8, (|channel: u8| {
+                            if channel > 1 {
+                                return Err("invalid channel");
+                            }
+                            // Nested such that process starts on the new channel.
+                            let r = &mut c.resources;
+                            let (thd_channel, thd_result) = (&mut r.thd_channel, &mut r.thd_result);
+                            r.thd.lock(|thd| {
+                                thd_channel.lock(|ch| *ch = channel as usize);
+                                thd_result.lock(|r| *r = None);
+                                thd.start();
+                            });
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/afe0/gain": hardware::AfeGain, (|gain| {
+                            if staged.active {
+                                staged.afe[0] = Some(gain);
+                            } else {
+                                c.resources.afes.0.set_gain(gain);
+                            }
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/afe1/gain": hardware::AfeGain, (|gain| {
+                            if staged.active {
+                                staged.afe[1] = Some(gain);
+                            } else {
+                                c.resources.afes.1.set_gain(gain);
+                            }
+                            Ok::<(), &str>(())
+                        })
+                    ],
 
-                                        iir_ch[req.channel as usize][IIR_CASCADE_LENGTH-1] = req.iir;
+                    indexed_readable_attributes: [
+                        "stabilizer/iir" [2]: (|channel: usize| {
+                            // Note(unsafe): This is the only writer context.
+                            Ok::<_, ()>(unsafe { c.resources.iir_ch[channel].published() }.stages)
+                        })
+                    ],
 
-                                        Ok::<server::IirRequest, ()>(req)
-                                    })
-                                }),
-                                "stabilizer/iir_b1/state": server::IirRequest,(|req: server::IirRequest| {
-                                    c.resources.iir_ch.lock(|iir_ch| {
-                                        if req.channel > 1 {
-                                            return Err(());
+                    indexed_modifiable_attributes: [
+                        "stabilizer/iir" [2]: server::IirStageRequest, (|channel: usize, req: server::IirStageRequest| {
+                            set_stage(&mut c.resources, &mut staged, channel, req.stage as usize, req.iir, req.ramp_samples)?;
+                            Ok::<(), &str>(())
+                        })
+                    ],
+
+                    batch: (|batch| {
+                        match batch {
+                            server::Batch::Begin => staged.active = true,
+                            server::Batch::Discard => staged = Staged::default(),
+                            server::Batch::Commit => {
+                                let resources = &mut c.resources;
+                                let (afes, ramp, iir_ch) = (&mut resources.afes, &mut resources.ramp, &resources.iir_ch);
+                                // Apply all staged writes between two DSP process runs.
+                                resources.iir_state.lock(|iir_state| {
+                                    for (channel, stages) in staged.iir.iter().enumerate() {
+                                        if stages.iter().all(Option::is_none) {
+                                            continue;
                                         }
+                                        // Note(unsafe): This is the only writer context.
+                                        let mut cascade = *unsafe { iir_ch[channel].published() };
+                                        for (index, iir) in stages.iter().enumerate() {
+                                            if let Some(iir) = iir {
+                                                // Bumpless transfer to the new configuration
+                                                iir.transfer_state(&cascade.stages[index], &mut iir_state[channel][index]);
+                                                cascade.stages[index] = *iir;
+                                            }
+                                        }
+                                        // A new write cancels a running ramp on the channel.
+                                        ramp.lock(|r| r[channel] = None);
+                                        unsafe { iir_ch[channel].publish(cascade) };
+                                    }
+                                    if let Some(gain) = staged.afe[0] {
+                                        afes.0.set_gain(gain);
+                                    }
+                                    if let Some(gain) = staged.afe[1] {
+                                        afes.1.set_gain(gain);
+                                    }
+                                });
+                                staged = Staged::default();
+                            }
+                        }
+                        Ok::<(), &str>(())
+                    })
+                )
+            };
+            server.poll(&mut sockets, &tcp_handles, &mut route);
+            mqtt.poll(&mut sockets, mqtt_handle, time, &mut route);
+            broadcast.poll(&mut sockets, broadcast_handle, time, &mut route);
+            // Apply written settings. They are validated when written: on
+            // error keep and report the active ones.
+            if mqtt_settings != *mqtt.settings() {
+                if let Err(e) = mqtt.configure(&mqtt_settings) {
+                    warn!("MQTT settings error: {}", e);
+                    mqtt_settings = mqtt.settings().clone();
+                }
+            }
+            if broadcast_settings != *broadcast.settings() {
+                if let Err(e) = broadcast.configure(&broadcast_settings) {
+                    warn!("Broadcast settings error: {}", e);
+                    broadcast_settings = *broadcast.settings();
+                }
+            }
 
-                                        iir_ch[req.channel as usize][IIR_CASCADE_LENGTH-1] = req.iir;
+            // Push the changes to the subscribed clients once per telemetry
+            // period.
+            if period {
+                server.notify("stabilizer/mqtt", &mqtt_settings);
+                server.notify("stabilizer/broadcast", &broadcast_settings);
+                if let Ok(gain) = c.resources.afes.0.get_gain() {
+                    server.notify("stabilizer/afe0/gain", &gain);
+                }
+                if let Ok(gain) = c.resources.afes.1.get_gain() {
+                    server.notify("stabilizer/afe1/gain", &gain);
+                }
+                for (channel, iir_ch) in c.resources.iir_ch.iter().enumerate() {
+                    // Note(unsafe): This is the only writer context.
+                    let stages = unsafe { iir_ch.published() }.stages;
+                    server.notify(NOTIFIED[4 + channel], &stages);
+                }
+            }
 
-                                        Ok::<server::IirRequest, ()>(req)
-                                    })
-                                }),
-                                "stabilizer/afe0/gain": hardware::AfeGain, (|gain| {
-                                    c.resources.afes.0.set_gain(gain);
-                                    Ok::<(), ()>(())
-                                }),
-                                "stabilizer/afe1/gain": hardware::AfeGain, (|gain| {
-                                    c.resources.afes.1.set_gain(gain);
-                                    Ok::<(), ()>(())
-                                })
-                            ]
-                        )
-                    });
+            {
+                let socket = &mut *sockets
+                    .get::<smoltcp::socket::TcpSocket>(stream_handle);
+                if socket.state() == smoltcp::socket::TcpState::CloseWait {
+                    socket.close();
+                } else if !(socket.is_open() || socket.is_listening()) {
+                    socket
+                        .listen(STREAM_PORT)
+                        .unwrap_or_else(|e| warn!("TCP listen error: {:?}", e));
+                } else {
+                    streaming::poll_tcp(&mut c.resources.stream, socket);
                 }
             }
 
@@ -4,13 +4,89 @@
 
 use stm32h7xx_hal as hal;
 
-use stabilizer::{hardware, hardware::design_parameters};
+#[macro_use]
+extern crate log;
 
-use dsp::{lockin::Lockin, rpll::RPLL, Accu};
+use rtic::cyccnt::{Instant, U32Ext};
+
+use heapless::{consts::*, Vec};
+
+use stabilizer::{
+    broadcast, hardware, hardware::design_parameters, mqtt, server, telemetry,
+};
+
+use dsp::{
+    agc::{Agc, AGC_UNITY},
+    coherent::Averager,
+    decimate::Decimator,
+    freq_counter, jitter,
+    lockin::Lockin,
+    phase_meter::PhaseMeter,
+    pll, rpll,
+    rpll::RPLL,
+    scale,
+    swap::SwapCell,
+    Accu, Complex, Reset,
+};
 use hardware::{
     Adc0Input, Adc1Input, Dac0Output, Dac1Output, InputStamper, AFE0, AFE1,
 };
 
+// Per control connection, see `server::MAX_CLIENTS`.
+const TCP_RX_BUFFER_SIZE: usize = 4096;
+const TCP_TX_BUFFER_SIZE: usize = 4096;
+
+// MQTT client socket buffer sizes, see `mqtt::Client`.
+const MQTT_RX_BUFFER_SIZE: usize = 1024;
+const MQTT_TX_BUFFER_SIZE: usize = 2048;
+
+// UDP status telemetry datagrams buffered, see `broadcast::Broadcast`.
+const BROADCAST_PACKETS: usize = 4;
+
+// The attributes pushed to their subscribers, see `server::Server::notify()`.
+const NOTIFIED: [&str; 6] = [
+    "stabilizer/mqtt",
+    "stabilizer/broadcast",
+    "stabilizer/afe0/gain",
+    "stabilizer/afe1/gain",
+    "stabilizer/lockin/harmonic",
+    "stabilizer/lockin/pll",
+];
+
+// 1 << RPLL_DT2 is the timestamp counter rate to update() rate ratio.
+const RPLL_DT2: u8 = design_parameters::ADC_SAMPLE_TICKS_LOG2
+    + design_parameters::SAMPLE_BUFFER_SIZE_LOG2;
+
+// The RPLL update() rate in Hz.
+const RPLL_UPDATE_RATE: f32 =
+    design_parameters::TIMER_FREQUENCY.0 as f32 * 1e6 / (1 << RPLL_DT2) as f32;
+
+// The timestamp counter rate in Hz.
+const TIMESTAMP_RATE: u32 = design_parameters::TIMER_FREQUENCY.0 * 1_000_000;
+
+// The longest frequency counter gate in updates: one timestamp counter
+// period.
+const COUNTER_GATE_MAX: u32 = 1 << (32 - RPLL_DT2);
+
+// Lockin output magnitude below which the phase meter holds its output. This
+// is about 1e-3 of a full scale input with the lockin lowpass gain of
+// `1 << 6`.
+const PHASE_SQUELCH: i32 = 1 << 9;
+
+// Default log2 decimation ratio of the lockin telemetry.
+const TELEMETRY_LOG2_RATIO: u8 = 6;
+
+// Number of decimated lockin IQ samples buffered for telemetry, drained by
+// reading `stabilizer/lockin/iq`.
+const TELEMETRY_BUFFER_SIZE: usize = 64;
+
+// Number of coherent averaging bins per reference period. The averages of all bins fit into a
+// single response.
+const AVERAGE_BINS: usize = 16;
+
+// Number of reference periods per jitter statistics window.
+const JITTER_WINDOW: u32 = 1 << 10;
+
 #[rtic::app(device = stm32h7xx_hal::stm32, peripherals = true, monotonic = rtic::cyccnt::CYCCNT)]
 const APP: () = {
     struct Resources {
@@ -20,8 +96,37 @@ const APP: () = {
         net_interface: hardware::Ethernet,
 
         timestamper: InputStamper,
+        #[init(RPLL::new(RPLL_DT2))]
         pll: RPLL,
+        // Written by idle, read by process without locking
+        pll_config: SwapCell<pll::Config>,
+        harmonic: SwapCell<i32>,
+        // Last RPLL frequency and holdover state
+        #[init((0, false))]
+        pll_status: (u32, bool),
         lockin: Lockin,
+        // Reference frequency counter, 1 s gate by default
+        #[init(freq_counter::Counter::new(TIMESTAMP_RATE, TIMESTAMP_RATE >> RPLL_DT2))]
+        counter: freq_counter::Counter,
+        // Reference period statistics: current and last complete window
+        #[init(jitter::Stats::new())]
+        jitter: jitter::Stats,
+        #[init(jitter::Jitter { count: 0, mean: 0., std: 0., peak_to_peak: 0 })]
+        jitter_status: jitter::Jitter,
+        // Extended phase of the lockin output
+        #[init(PhaseMeter::new(4, PHASE_SQUELCH))]
+        phase_meter: PhaseMeter,
+        // Decimated lockin IQ telemetry
+        decimator: Decimator<4>,
+        telemetry: telemetry::Ring<Complex<i32>, TELEMETRY_BUFFER_SIZE>,
+        // Coherent averaging of the ADC0 input over reference periods
+        #[init(false)]
+        averaging: bool,
+        #[init(Averager::new())]
+        averager: Averager<AVERAGE_BINS>,
+        // Optional input AGC
+        #[init(None)]
+        agc: Option<Agc>,
     }
 
     #[init]
@@ -29,10 +134,7 @@ const APP: () = {
         // Configure the microcontroller
         let (mut stabilizer, _pounder) = hardware::setup(c.core, c.device);
 
-        let pll = RPLL::new(
-            design_parameters::ADC_SAMPLE_TICKS_LOG2
-                + design_parameters::SAMPLE_BUFFER_SIZE_LOG2,
-        );
+        let lockin = Lockin::new();
 
         // Enable ADC/DAC events
         stabilizer.adcs.0.start();
@@ -56,8 +158,11 @@ const APP: () = {
             net_interface: stabilizer.net.interface,
             timestamper: stabilizer.timestamper,
 
-            pll,
-            lockin: Lockin::default(),
+            pll_config: SwapCell::new(pll::Config::new(21, 21)),
+            harmonic: SwapCell::new(lockin.harmonic),
+            lockin,
+            decimator: Decimator::new(TELEMETRY_LOG2_RATIO).unwrap(),
+            telemetry: telemetry::Ring::new(Complex(0, 0)),
         }
     }
 
@@ -68,7 +173,7 @@ const APP: () = {
     /// This is an implementation of a externally (DI0) referenced PLL lockin on the ADC0 signal.
     /// It outputs either I/Q or power/phase on DAC0/DAC1. Data is normalized to full scale.
     /// PLL bandwidth, filter bandwidth, slope, and x/y or power/phase post-filters are available.
-    #[task(binds=DMA1_STR4, resources=[adcs, dacs, lockin, timestamper, pll], priority=2)]
+    #[task(binds=DMA1_STR4, resources=[adcs, dacs, lockin, agc, timestamper, pll, &pll_config, &harmonic, pll_status, counter, jitter, jitter_status, phase_meter, decimator, telemetry, averaging, averager], priority=2)]
     fn process(c: process::Context) {
         let adc_samples = [
             c.resources.adcs.0.acquire_buffer(),
@@ -81,6 +186,9 @@ const APP: () = {
         ];
 
         let lockin = c.resources.lockin;
+        let agc = c.resources.agc;
+        // Note(unsafe): This is the only reader context.
+        lockin.harmonic = *unsafe { c.resources.harmonic.latest() };
 
         let timestamp = c
             .resources
@@ -88,40 +196,77 @@ const APP: () = {
             .latest_timestamp()
             .unwrap_or(None) // Ignore data from timer capture overflows.
             .map(|t| t as i32);
+        // Frequency and phase settling times (log2 counter cycles)
+        // Note(unsafe): This is the only reader context.
+        let pll_config = unsafe { c.resources.pll_config.latest() };
         let (pll_phase, pll_frequency) = c.resources.pll.update(
             timestamp,
-            21, // frequency settling time (log2 counter cycles), TODO: expose
-            21, // phase settling time, TODO: expose
+            pll_config.shift_i as u8,
+            pll_config.shift_p as u8,
         );
-
-        // Harmonic index of the LO: -1 to _de_modulate the fundamental (complex conjugate)
-        let harmonic: i32 = -1; // TODO: expose
-
-        // Demodulation LO phase offset
-        let phase_offset: i32 = 0; // TODO: expose
+        *c.resources.pll_status = (pll_frequency, c.resources.pll.holdover());
+        c.resources
+            .counter
+            .update(timestamp.unwrap_or(0) as u32, timestamp.is_some() as u32);
+        // The periods are only meaningful for at most one reference edge per
+        // batch.
+        if let Some(t) = timestamp {
+            c.resources.jitter.update(t as u32);
+            if c.resources.jitter.count() >= JITTER_WINDOW {
+                *c.resources.jitter_status =
+                    c.resources.jitter.read_and_reset();
+            }
+        }
 
         // Log2 lowpass time constant
         let time_constant: u8 = 6; // TODO: expose
 
-        let sample_frequency = ((pll_frequency
-            // half-up rounding bias
-            // .wrapping_add(1 << design_parameters::SAMPLE_BUFFER_SIZE_LOG2 - 1)
-            >> design_parameters::SAMPLE_BUFFER_SIZE_LOG2)
-            as i32)
-            .wrapping_mul(harmonic);
-        let sample_phase =
-            phase_offset.wrapping_add(pll_phase.wrapping_mul(harmonic));
+        // The demodulation harmonic is configured through the network
+        // interface.
+        let sample_frequency = lockin.demodulation_frequency(
+            (pll_frequency
+                // half-up rounding bias
+                // .wrapping_add(1 << design_parameters::SAMPLE_BUFFER_SIZE_LOG2 - 1)
+                >> design_parameters::SAMPLE_BUFFER_SIZE_LOG2)
+                as i32,
+        );
+        let sample_phase = lockin.demodulation_phase(pll_phase);
+
+        if *c.resources.averaging {
+            let reference_frequency = (pll_frequency
+                >> design_parameters::SAMPLE_BUFFER_SIZE_LOG2)
+                as i32;
+            for (&sample, phase) in adc_samples[0]
+                .iter()
+                .zip(Accu::new(pll_phase, reference_frequency))
+            {
+                c.resources
+                    .averager
+                    .update(phase, (sample as i16 as i32) << 16);
+            }
+        }
 
         let output = adc_samples[0]
             .iter()
             .zip(Accu::new(sample_phase, sample_frequency))
             // Convert to signed, MSB align the ADC sample.
             .map(|(&sample, phase)| {
-                lockin.update(sample as i16, phase, time_constant)
+                let sample = match agc {
+                    Some(agc) => {
+                        (agc.update((sample as i16 as i32) << 16) >> 16) as i16
+                    }
+                    None => sample as i16,
+                };
+                lockin.update(sample, phase, time_constant)
             })
             .last()
             .unwrap();
 
+        c.resources.phase_meter.update(output);
+        if let Some(iq) = c.resources.decimator.update(output) {
+            c.resources.telemetry.push(iq);
+        }
+
         let conf = "frequency_discriminator";
         let output = match conf {
             // Convert from IQ to power and phase.
@@ -137,11 +282,305 @@ const APP: () = {
         }
     }
 
-    #[idle(resources=[afes])]
-    fn idle(_: idle::Context) -> ! {
+    #[idle(resources=[net_interface, &harmonic, &pll_config, pll_status, agc, afes, counter, jitter_status, phase_meter, decimator, telemetry, averaging, averager])]
+    fn idle(mut c: idle::Context) -> ! {
+        let mut socket_set_entries: [_; 8] = Default::default();
+        let mut sockets =
+            smoltcp::socket::SocketSet::new(&mut socket_set_entries[..]);
+
+        // One socket per control connection
+        let mut rx_storage = [[0; TCP_RX_BUFFER_SIZE]; server::MAX_CLIENTS];
+        let mut tx_storage = [[0; TCP_TX_BUFFER_SIZE]; server::MAX_CLIENTS];
+        let tcp_handles: Vec<_, U4> = rx_storage
+            .iter_mut()
+            .zip(tx_storage.iter_mut())
+            .map(|(rx_storage, tx_storage)| {
+                let tcp_rx_buffer =
+                    smoltcp::socket::TcpSocketBuffer::new(&mut rx_storage[..]);
+                let tcp_tx_buffer =
+                    smoltcp::socket::TcpSocketBuffer::new(&mut tx_storage[..]);
+                let tcp_socket = smoltcp::socket::TcpSocket::new(
+                    tcp_rx_buffer,
+                    tcp_tx_buffer,
+                );
+                sockets.add(tcp_socket)
+            })
+            .collect();
+
+        let mut mqtt_rx_storage = [0; MQTT_RX_BUFFER_SIZE];
+        let mut mqtt_tx_storage = [0; MQTT_TX_BUFFER_SIZE];
+        let mqtt_handle = {
+            let rx_buffer =
+                smoltcp::socket::TcpSocketBuffer::new(&mut mqtt_rx_storage[..]);
+            let tx_buffer =
+                smoltcp::socket::TcpSocketBuffer::new(&mut mqtt_tx_storage[..]);
+            sockets.add(smoltcp::socket::TcpSocket::new(rx_buffer, tx_buffer))
+        };
+
+        // The telemetry socket only sends.
+        let mut broadcast_rx_metadata =
+            [smoltcp::socket::UdpPacketMetadata::EMPTY; 1];
+        let mut broadcast_rx_storage = [0; 0];
+        let mut broadcast_tx_metadata =
+            [smoltcp::socket::UdpPacketMetadata::EMPTY; BROADCAST_PACKETS];
+        let mut broadcast_tx_storage =
+            [0; BROADCAST_PACKETS * broadcast::PACKET_SIZE];
+        let broadcast_handle = {
+            let rx_buffer = smoltcp::socket::UdpSocketBuffer::new(
+                &mut broadcast_rx_metadata[..],
+                &mut broadcast_rx_storage[..],
+            );
+            let tx_buffer = smoltcp::socket::UdpSocketBuffer::new(
+                &mut broadcast_tx_metadata[..],
+                &mut broadcast_tx_storage[..],
+            );
+            sockets.add(smoltcp::socket::UdpSocket::new(rx_buffer, tx_buffer))
+        };
+
+        let mut server = server::Server::new(1235, &NOTIFIED);
+
+        // Status telemetry, disabled until a broker is configured
+        let mut mqtt = mqtt::Client::new(
+            c.resources.net_interface.ethernet_addr(),
+            "stabilizer/lockin/status",
+        );
+        let mut mqtt_settings = mqtt.settings().clone();
+
+        // UDP status telemetry, disabled until a target is configured
+        let mut broadcast =
+            broadcast::Broadcast::new("stabilizer/lockin/status");
+        let mut broadcast_settings = *broadcast.settings();
+
+        let mut time = 0u32;
+        let mut next_ms = Instant::now();
+
+        // TODO: Replace with reference to CPU clock from CCDR.
+        next_ms += 400_000.cycles();
+
         loop {
-            // TODO: Implement network interface.
-            cortex_m::asm::wfi();
+            let tick = Instant::now() > next_ms;
+
+            if tick {
+                next_ms += 400_000.cycles();
+                time += 1;
+            }
+
+            let mut route = |req: &server::Request| {
+                info!("Got request: {:?}", req);
+                stabilizer::route_request!(req,
+                    readable_attributes: [
+                        "stabilizer/lockin/harmonic": (|| {
+                            // Note(unsafe): This is the only writer context.
+                            let harmonic = *unsafe { c.resources.harmonic.published() };
+                            Ok::<i32, ()>(harmonic)
+                        }),
+                        "stabilizer/lockin/status": (|| {
+                            let (frequency, holdover) = c.resources.pll_status.lock(|status| *status);
+                            let agc_gain = c.resources.agc.lock(|agc| agc.map_or(AGC_UNITY, |agc| agc.gain()));
+                            let (phase, phase_squelched) = c.resources.phase_meter.lock(|meter| (meter.phase(), meter.squelched()));
+                            Ok::<server::LockinStatus, ()>(server::LockinStatus {
+                                t: time,
+                                frequency: rpll::frequency_to_hz(frequency, RPLL_UPDATE_RATE),
+                                holdover,
+                                agc_gain: agc_gain as f32 / AGC_UNITY as f32,
+                                phase,
+                                phase_squelched,
+                            })
+                        }),
+                        "stabilizer/lockin/pll": (|| {
+                            let config = *unsafe { c.resources.pll_config.published() };
+                            Ok::<pll::Config, ()>(config)
+                        }),
+                        "stabilizer/lockin/counter": (|| {
+                            let (output, gate) = c.resources.counter.lock(|counter| (counter.output(), counter.gate()));
+                            Ok::<server::CounterStatus, ()>(server::CounterStatus {
+                                frequency: output.to_hz(),
+                                edges: output.edges,
+                                valid: output.valid,
+                                gate: gate as f32 / RPLL_UPDATE_RATE,
+                            })
+                        }),
+                        "stabilizer/lockin/jitter": (|| {
+                            let jitter = c.resources.jitter_status.lock(|jitter| *jitter);
+                            let tick = 1. / TIMESTAMP_RATE as f32;
+                            Ok::<server::JitterStatus, ()>(server::JitterStatus {
+                                count: jitter.count,
+                                period: jitter.mean * tick,
+                                std: jitter.std * tick,
+                                peak_to_peak: jitter.peak_to_peak as f32 * tick,
+                            })
+                        }),
+                        "stabilizer/lockin/iq": (|| {
+                            let mut telemetry = server::IqTelemetry {
+                                iq: Vec::new(),
+                                pending: 0,
+                                overruns: 0,
+                            };
+                            c.resources.telemetry.lock(|ring| {
+                                while telemetry.iq.len() < telemetry.iq.capacity() {
+                                    match ring.pop() {
+                                        // Note(unwrap): The capacity is checked.
+                                        Some(iq) => telemetry.iq.push([iq.0, iq.1]).unwrap(),
+                                        None => break,
+                                    }
+                                }
+                                telemetry.pending = ring.len() as u32;
+                                telemetry.overruns = ring.overruns();
+                            });
+                            Ok::<server::IqTelemetry, ()>(telemetry)
+                        }),
+                        "stabilizer/lockin/decimation": (|| {
+                            let log2_ratio = c.resources.decimator.lock(|decimator| decimator.log2_ratio());
+                            Ok::<u8, ()>(log2_ratio)
+                        }),
+                        "stabilizer/lockin/average": (|| {
+                            let enable = c.resources.averaging.lock(|averaging| *averaging);
+                            let (counts, bins) = c.resources.averager.lock(|averager| (*averager.counts(), averager.read()));
+                            Ok::<server::AverageStatus, ()>(server::AverageStatus {
+                                enable,
+                                samples: counts.iter().fold(0, |sum, &count| sum.saturating_add(count)),
+                                bins,
+                            })
+                        }),
+                        "stabilizer/afe0/gain": (|| c.resources.afes.0.get_gain()),
+                        "stabilizer/afe1/gain": (|| c.resources.afes.1.get_gain()),
+                        "stabilizer/mqtt": (|| Ok::<_, ()>(mqtt_settings.clone())),
+                        "stabilizer/broadcast": (|| Ok::<_, ()>(broadcast_settings)),
+                        "stabilizer/bench": (stabilizer::bench::run)
+                    ],
+
+                    modifiable_attributes: [
+                        "stabilizer/lockin/harmonic": i32, (|harmonic| {
+                            unsafe { c.resources.harmonic.publish(harmonic) };
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/lockin/pll": pll::Config, (|config: pll::Config| {
+                            // The reference RPLL is type-II. Its shifts are only limited by its update rate.
+                            if config.order != 2 {
+                                return Err("Unsupported RPLL loop order");
+                            }
+                            if config.shift_i > u8::MAX as u32 || config.shift_p > u8::MAX as u32 {
+                                return Err("RPLL shift out of range");
+                            }
+                            RPLL::check_shifts(RPLL_DT2, config.shift_i as u8, config.shift_p as u8)?;
+                            unsafe { c.resources.pll_config.publish(config) };
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/lockin/agc": server::AgcRequest, (|req: server::AgcRequest| {
+                            let full_scale = design_parameters::ADC_FULL_SCALE;
+                            let squelch = scale::volts_to_i32(req.squelch, full_scale);
+                            if req.rate_shift > 31 || squelch <= 0 || req.target <= 0. {
+                                return Err("Invalid AGC configuration");
+                            }
+                            if req.gain_max.is_nan() || req.gain_max < 1. / 16. {
+                                return Err("Invalid AGC gain limit");
+                            }
+                            let agc = if req.enable {
+                                let mut agc = Agc::new(
+                                    scale::volts_to_i32(req.target, full_scale),
+                                    req.rate_shift,
+                                    squelch,
+                                );
+                                agc.gain_max = (req.gain_max * AGC_UNITY as f32) as i32;
+                                Some(agc)
+                            } else {
+                                None
+                            };
+                            c.resources.agc.lock(|current| *current = agc);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/lockin/counter": server::CounterRequest, (|req: server::CounterRequest| {
+                            let gate = req.gate * RPLL_UPDATE_RATE;
+                            if !(gate >= 1. && gate < COUNTER_GATE_MAX as f32) {
+                                return Err("Invalid counter gate");
+                            }
+                            c.resources.counter.lock(|counter| counter.set_gate(gate as u32));
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/lockin/decimation": u8, (|log2_ratio| {
+                            c.resources.decimator.lock(|decimator| decimator.set_log2_ratio(log2_ratio))
+                        }),
+                        "stabilizer/lockin/average": bool, (|enable| {
+                            // Starting clears the previous averages, stopping keeps them.
+                            let running = c.resources.averaging.lock(|averaging| *averaging);
+                            if enable && !running {
+                                c.resources.averager.lock(|averager| averager.reset());
+                            }
+                            c.resources.averaging.lock(|averaging| *averaging = enable);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/afe0/gain": hardware::AfeGain, (|gain| {
+                            c.resources.afes.0.set_gain(gain);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/afe1/gain": hardware::AfeGain, (|gain| {
+                            c.resources.afes.1.set_gain(gain);
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/mqtt": server::MqttRequest, (|req: server::MqttRequest| {
+                            mqtt::validate(&req)?;
+                            mqtt_settings = req;
+                            Ok::<(), &str>(())
+                        }),
+                        "stabilizer/broadcast": server::BroadcastRequest, (|req: server::BroadcastRequest| {
+                            broadcast::validate(&req)?;
+                            broadcast_settings = req;
+                            Ok::<(), &str>(())
+                        })
+                    ]
+                )
+            };
+            server.poll(&mut sockets, &tcp_handles, &mut route);
+            mqtt.poll(&mut sockets, mqtt_handle, time, &mut route);
+            broadcast.poll(&mut sockets, broadcast_handle, time, &mut route);
+            // Apply written settings. They are validated when written: on
+            // error keep and report the active ones.
+            if mqtt_settings != *mqtt.settings() {
+                if let Err(e) = mqtt.configure(&mqtt_settings) {
+                    warn!("MQTT settings error: {}", e);
+                    mqtt_settings = mqtt.settings().clone();
+                }
+            }
+            if broadcast_settings != *broadcast.settings() {
+                if let Err(e) = broadcast.configure(&broadcast_settings) {
+                    warn!("Broadcast settings error: {}", e);
+                    broadcast_settings = *broadcast.settings();
+                }
+            }
+
+            // Push the changes to the subscribed clients once per telemetry
+            // period.
+            if tick && time % broadcast::PERIOD_MS == 0 {
+                server.notify("stabilizer/mqtt", &mqtt_settings);
+                server.notify("stabilizer/broadcast", &broadcast_settings);
+                if let Ok(gain) = c.resources.afes.0.get_gain() {
+                    server.notify("stabilizer/afe0/gain", &gain);
+                }
+                if let Ok(gain) = c.resources.afes.1.get_gain() {
+                    server.notify("stabilizer/afe1/gain", &gain);
+                }
+                // Note(unsafe): This is the only writer context.
+                let harmonic = *unsafe { c.resources.harmonic.published() };
+                server.notify("stabilizer/lockin/harmonic", &harmonic);
+                let pll = *unsafe { c.resources.pll_config.published() };
+                server.notify("stabilizer/lockin/pll", &pll);
+            }
+
+            let sleep = match c.resources.net_interface.poll(
+                &mut sockets,
+                smoltcp::time::Instant::from_millis(time as i64),
+            ) {
+                Ok(changed) => !changed,
+                Err(smoltcp::Error::Unrecognized) => true,
+                Err(e) => {
+                    info!("iface poll error: {:?}", e);
+                    true
+                }
+            };
+
+            if sleep {
+                cortex_m::asm::wfi();
+            }
         }
     }
 
@@ -2,7 +2,7 @@
 #![no_std]
 #![no_main]
 
-use dsp::{lockin::Lockin, Accu};
+use dsp::lockin::Lockin;
 use hardware::{Adc1Input, Dac0Output, Dac1Output, AFE0, AFE1};
 use stabilizer::{hardware, hardware::design_parameters};
 
@@ -20,6 +20,11 @@ const APP: () = {
         adc: Adc1Input,
         dacs: (Dac0Output, Dac1Output),
 
+        #[init(Lockin {
+            // Demodulation LO phase offset
+            phase_offset: (0.25 * i32::MAX as f32) as i32, // TODO: expose
+            ..Lockin::new()
+        })]
         lockin: Lockin,
     }
 
@@ -37,7 +42,6 @@ const APP: () = {
         stabilizer.adc_dac_timer.start();
 
         init::LateResources {
-            lockin: Lockin::default(),
             afes: stabilizer.afes,
             adc: stabilizer.adcs.1,
             dacs: stabilizer.dacs,
@@ -82,30 +86,26 @@ const APP: () = {
         let pll_frequency =
             1i32 << (32 - design_parameters::SAMPLE_BUFFER_SIZE_LOG2);
 
-        // Harmonic index of the LO: -1 to _de_modulate the fundamental
-        let harmonic: i32 = -1; // TODO: expose
-
-        // Demodulation LO phase offset
-        let phase_offset: i32 = (0.25 * i32::MAX as f32) as i32; // TODO: expose
-
         // Log2 lowpass time constant.
         let time_constant: u8 = 8;
 
-        let sample_frequency = (pll_frequency as i32).wrapping_mul(harmonic);
-        let sample_phase = phase_offset
-            .wrapping_add((pll_phase as i32).wrapping_mul(harmonic));
-
-        let output = adc_samples
-            .iter()
-            // Zip in the LO phase.
-            .zip(Accu::new(sample_phase, sample_frequency))
-            // Convert to signed, MSB align the ADC sample, update the Lockin (demodulate, filter)
-            .map(|(&sample, phase)| {
-                lockin.update(sample as i16, phase, time_constant)
-            })
-            // Decimate
-            .last()
-            .unwrap();
+        let sample_frequency = lockin.demodulation_frequency(pll_frequency);
+        let sample_phase = lockin.demodulation_phase(pll_phase);
+
+        // Convert to signed, MSB align the ADC sample.
+        let mut samples = [0i16; design_parameters::SAMPLE_BUFFER_SIZE];
+        samples
+            .iter_mut()
+            .zip(adc_samples.iter())
+            .for_each(|(s, &a)| *s = a as i16);
+
+        // Update the Lockin (demodulate, filter) and decimate.
+        let output = lockin.process_batch(
+            &samples,
+            sample_phase,
+            sample_frequency,
+            time_constant,
+        );
 
         for value in dac_samples[1].iter_mut() {
             *value = (output.arg() >> 16) as u16 ^ 0x8000;
@@ -0,0 +1,352 @@
+///! UDP status telemetry
+///!
+///! Dashboards receive the status pushed at a fixed rate instead of polling the control
+///! server. `Broadcast` reads the status attribute through the closure handling the control
+///! requests once every `divider` telemetry periods of `PERIOD_MS` and sends it in a datagram
+///! to the configured target, e.g. the subnet broadcast address.
+///!
+///! Datagram layout, little endian:
+///!
+///! | Offset | Type | Content                                                  |
+///! |--------|------|----------------------------------------------------------|
+///! | 0      | u16  | `MAGIC`                                                  |
+///! | 2      | u8   | `VERSION`                                                |
+///! | 3      | u8   | Reserved, zero                                           |
+///! | 4      | u32  | Sequence number, wrapping. A gap is a lost status.       |
+///! | 8      | [u8] | The status, JSON                                         |
+///!
+///! In Python: `struct.unpack_from("<HBBI", datagram)` and `json.loads(datagram[8:])`.
+///!
+///! Datagrams are never waited for: if the socket buffer is full, e.g. while the target address
+///! can not be resolved, the status is dropped and its sequence number is skipped.
+use smoltcp as net;
+use typenum::Unsigned;
+
+use super::server::{
+    AccessRequest, BroadcastRequest, Request, Response, ResponseCode, ValueSize,
+};
+
+/// Datagram start marker.
+pub const MAGIC: u16 = 0x57ac;
+/// Datagram layout version.
+pub const VERSION: u8 = 1;
+/// Header size in bytes.
+pub const HEADER_SIZE: usize = 8;
+/// Maximum datagram size in bytes: the header and a reply value, see `server::ValueSize`.
+pub const PACKET_SIZE: usize = HEADER_SIZE + ValueSize::USIZE;
+/// Telemetry period in milliseconds, the unit of `BroadcastRequest::divider`.
+pub const PERIOD_MS: u32 = 10;
+/// Local UDP port the datagrams are sent from.
+pub const LOCAL_PORT: u16 = 1237;
+
+/// Serialize a datagram.
+///
+/// Args:
+/// * `buf`: Output buffer.
+/// * `sequence`: Sequence number.
+/// * `payload`: The status.
+///
+/// Returns:
+/// The length of the datagram or an error if it does not fit.
+pub fn encode(
+    buf: &mut [u8],
+    sequence: u32,
+    payload: &[u8],
+) -> Result<usize, &'static str> {
+    let len = HEADER_SIZE + payload.len();
+    if buf.len() < len {
+        return Err("Status too long");
+    }
+    buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+    buf[2] = VERSION;
+    buf[3] = 0;
+    buf[4..8].copy_from_slice(&sequence.to_le_bytes());
+    buf[HEADER_SIZE..len].copy_from_slice(payload);
+    Ok(len)
+}
+
+/// Check a telemetry configuration.
+pub fn validate(settings: &BroadcastRequest) -> Result<(), &'static str> {
+    if settings.port == 0 {
+        return Err("Invalid broadcast port");
+    }
+    if settings.divider == 0 {
+        return Err("Invalid broadcast divider");
+    }
+    Ok(())
+}
+
+/// The UDP status telemetry.
+pub struct Broadcast {
+    settings: BroadcastRequest,
+    attribute: &'static str,
+    sequence: u32,
+    // Start of the current period, `None` until the first datagram
+    last: Option<u32>,
+}
+
+impl Broadcast {
+    /// Construct a new telemetry sender, disabled.
+    ///
+    /// Args:
+    /// * `attribute`: The attribute sent, e.g. `stabilizer/iir/state`.
+    pub fn new(attribute: &'static str) -> Self {
+        Self {
+            settings: BroadcastRequest {
+                target: [0; 4],
+                port: 1238,
+                divider: 10,
+            },
+            attribute,
+            sequence: 0,
+            last: None,
+        }
+    }
+
+    /// The telemetry configuration.
+    pub fn settings(&self) -> &BroadcastRequest {
+        &self.settings
+    }
+
+    /// Change the configuration.
+    ///
+    /// A new configuration restarts the periods: the next status is sent on the next poll.
+    pub fn configure(
+        &mut self,
+        settings: &BroadcastRequest,
+    ) -> Result<(), &'static str> {
+        validate(settings)?;
+        if *settings != self.settings {
+            self.settings = *settings;
+            self.last = None;
+        }
+        Ok(())
+    }
+
+    /// Whether the telemetry is enabled.
+    pub fn enabled(&self) -> bool {
+        self.settings.target != [0; 4]
+    }
+
+    // Whether a status is due, starting the next period if so.
+    //
+    // The periods stay on their schedule if a poll is late by less than a period. After a
+    // longer delay the missed periods are skipped.
+    fn due(&mut self, now: u32) -> bool {
+        let period = self.settings.divider.saturating_mul(PERIOD_MS);
+        let last = match self.last {
+            None => {
+                self.last = Some(now);
+                return true;
+            }
+            Some(last) => last,
+        };
+        let elapsed = now.wrapping_sub(last);
+        if elapsed < period {
+            return false;
+        }
+        self.last = Some(if elapsed < period.saturating_mul(2) {
+            last.wrapping_add(period)
+        } else {
+            now
+        });
+        true
+    }
+
+    /// Read the status if it is due and serialize its datagram.
+    ///
+    /// Args:
+    /// * `now`: The current time in milliseconds, wrapping.
+    /// * `f`: The closure handling the status read, see `server::Server::poll()`.
+    /// * `buf`: Output buffer of `PACKET_SIZE` bytes.
+    ///
+    /// Returns:
+    /// The length of the datagram, or `None` if disabled, not due or the read failed.
+    pub fn update<F>(&mut self, now: u32, f: F, buf: &mut [u8]) -> Option<usize>
+    where
+        F: FnOnce(&Request) -> Response,
+    {
+        if !self.enabled() || !self.due(now) {
+            return None;
+        }
+        let response = f(&Request {
+            req: AccessRequest::Read,
+            attribute: self.attribute,
+            value: heapless::String::new(),
+            id: 0,
+            batch: None,
+        });
+        if response.code() != ResponseCode::Ok {
+            warn!("Broadcast read failed: {}", response.value());
+            return None;
+        }
+        match encode(buf, self.sequence, response.value().as_bytes()) {
+            Ok(len) => {
+                self.sequence = self.sequence.wrapping_add(1);
+                Some(len)
+            }
+            Err(msg) => {
+                warn!("Broadcast error: {}", msg);
+                None
+            }
+        }
+    }
+
+    /// Poll the telemetry and send the status if it is due.
+    ///
+    /// Args:
+    /// * `sockets`: The socket set of the telemetry socket.
+    /// * `handle`: The UDP telemetry socket.
+    /// * `now`: The current time in milliseconds, wrapping.
+    /// * `f`: The closure handling the status read, see `server::Server::poll()`.
+    pub fn poll<F>(
+        &mut self,
+        sockets: &mut net::socket::SocketSet,
+        handle: net::socket::SocketHandle,
+        now: u32,
+        f: F,
+    ) where
+        F: FnOnce(&Request) -> Response,
+    {
+        let socket = &mut *sockets.get::<net::socket::UdpSocket>(handle);
+        if !socket.is_open() {
+            // Note(unwrap): The port is non-zero and the socket unbound.
+            socket.bind(LOCAL_PORT).unwrap();
+        }
+        let mut buf = [0; PACKET_SIZE];
+        let len = match self.update(now, f, &mut buf) {
+            Some(len) => len,
+            None => return,
+        };
+        let [a, b, c, d] = self.settings.target;
+        let endpoint = net::wire::IpEndpoint::new(
+            net::wire::IpAddress::v4(a, b, c, d),
+            self.settings.port,
+        );
+        // A datagram that does not fit the socket buffer is dropped.
+        if socket.can_send() {
+            socket.send_slice(&buf[..len], endpoint).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server;
+    use heapless::{consts::*, Vec};
+
+    fn route(req: &Request) -> Response {
+        crate::route_request!(req,
+            readable_attributes: [
+                "test/status": (|| Ok::<u32, ()>(7)),
+                "test/error": (|| Err::<u32, _>(()))
+            ],
+            modifiable_attributes: []
+        )
+    }
+
+    fn enabled(divider: u32) -> Broadcast {
+        let mut broadcast = Broadcast::new("test/status");
+        broadcast
+            .configure(&BroadcastRequest {
+                target: [10, 0, 0, 255],
+                port: 1238,
+                divider,
+            })
+            .unwrap();
+        broadcast
+    }
+
+    #[test]
+    fn packet() {
+        let mut buf = [0; PACKET_SIZE];
+        let len = encode(&mut buf, 0x0403_0201, b"{\"a\":1}").unwrap();
+        assert_eq!(len, HEADER_SIZE + 7);
+        assert_eq!(&buf[..HEADER_SIZE], &[0xac, 0x57, 1, 0, 1, 2, 3, 4]);
+        assert_eq!(&buf[HEADER_SIZE..len], b"{\"a\":1}");
+        assert!(encode(&mut buf[..HEADER_SIZE + 6], 0, b"{\"a\":1}").is_err());
+
+        let mut broadcast = enabled(1);
+        let mut buf = [0; PACKET_SIZE];
+        for sequence in 0..3u32 {
+            let now = sequence * PERIOD_MS;
+            let len = broadcast.update(now, route, &mut buf).unwrap();
+            assert_eq!(&buf[4..8], &sequence.to_le_bytes());
+            assert_eq!(&buf[HEADER_SIZE..len], b"7");
+        }
+    }
+
+    #[test]
+    fn divider() {
+        let mut broadcast = enabled(3);
+        let mut buf = [0; PACKET_SIZE];
+        let mut sent: Vec<u32, U16> = Vec::new();
+        // Polled every 7 ms from 1 ms, the periods are 30 ms from the first datagram.
+        for now in (1..200).step_by(7) {
+            if broadcast.update(now, route, &mut buf).is_some() {
+                sent.push(now).unwrap();
+            }
+        }
+        assert_eq!(&sent[..], &[1, 36, 64, 92, 127, 155, 183]);
+
+        // A longer delay skips the missed periods.
+        let mut broadcast = enabled(3);
+        assert!(broadcast.update(0, route, &mut buf).is_some());
+        assert!(broadcast.update(100, route, &mut buf).is_some());
+        assert!(broadcast.update(129, route, &mut buf).is_none());
+        assert!(broadcast.update(130, route, &mut buf).is_some());
+
+        // The divider saturates and does not wrap.
+        let mut broadcast = enabled(u32::MAX);
+        assert!(broadcast.update(0, route, &mut buf).is_some());
+        assert!(broadcast.update(u32::MAX - 1, route, &mut buf).is_none());
+    }
+
+    #[test]
+    fn disable() {
+        let mut broadcast = enabled(1);
+        let mut buf = [0; PACKET_SIZE];
+        assert!(broadcast.update(0, route, &mut buf).is_some());
+        assert!(broadcast.update(10, route, &mut buf).is_some());
+        let mut settings = *broadcast.settings();
+        settings.target = [0; 4];
+        broadcast.configure(&settings).unwrap();
+        for now in (20..1000).step_by(10) {
+            assert!(broadcast.update(now, route, &mut buf).is_none());
+        }
+
+        // Enabling again continues the sequence.
+        settings.target = [10, 0, 0, 255];
+        broadcast.configure(&settings).unwrap();
+        assert!(broadcast.update(1000, route, &mut buf).is_some());
+        assert_eq!(&buf[4..8], &2u32.to_le_bytes());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut broadcast = Broadcast::new("test/error");
+        assert!(!broadcast.enabled());
+        let settings = BroadcastRequest {
+            target: [10, 0, 0, 255],
+            port: 1238,
+            divider: 0,
+        };
+        assert!(broadcast.configure(&settings).is_err());
+        assert!(broadcast
+            .configure(&BroadcastRequest {
+                port: 0,
+                ..settings
+            })
+            .is_err());
+        assert!(!broadcast.enabled());
+        broadcast
+            .configure(&BroadcastRequest {
+                divider: 1,
+                ..settings
+            })
+            .unwrap();
+        let mut buf = [0; PACKET_SIZE];
+        assert!(broadcast.update(0, route, &mut buf).is_none());
+    }
+}
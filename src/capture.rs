@@ -0,0 +1,427 @@
+///! Triggered capture ("scope mode")
+///!
+///! `Capture` records a one-shot trace of a signal around a trigger event: once armed, samples are
+///! written into a circular buffer of the configured depth. The trigger is accepted after the
+///! configured number of pre-trigger samples has been recorded. After the trigger the capture
+///! continues until the buffer holds `depth` samples: `pre_trigger` samples before the trigger
+///! sample, the trigger sample, and the post-trigger samples. The record is then frozen and can be
+///! read out in chunks until the capture is re-armed.
+///!
+///! The trigger sources are a level crossing of the captured signal (e.g. an error signal), an
+///! edge on a digital input, or a manual (forced) trigger.
+use serde::{Deserialize, Serialize};
+
+/// Trigger source.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Source {
+    /// Only `Capture::force()`.
+    Manual,
+    /// The signal crosses the trigger level with the trigger slope.
+    Level,
+    /// A digital input edge. Its polarity is determined by the input, not the slope.
+    Digital,
+}
+
+/// Direction of a level crossing.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Slope {
+    /// From below the level to at or above it.
+    Rising,
+    /// From above the level to at or below it.
+    Falling,
+    /// Either direction.
+    Both,
+}
+
+/// Trigger configuration.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Trigger {
+    pub source: Source,
+    /// Level in signal units.
+    pub level: i16,
+    pub slope: Slope,
+}
+
+impl Trigger {
+    /// Whether the signal crossed the level between two samples.
+    fn crossed(&self, last: i16, x: i16) -> bool {
+        let rising = last < self.level && x >= self.level;
+        let falling = last > self.level && x <= self.level;
+        match self.slope {
+            Slope::Rising => rising,
+            Slope::Falling => falling,
+            Slope::Both => rising || falling,
+        }
+    }
+}
+
+/// Capture state.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum State {
+    /// Not armed, no record available.
+    Idle,
+    /// Recording and waiting for the trigger.
+    Armed,
+    /// Recording the post-trigger samples.
+    Triggered,
+    /// The record is complete and can be read.
+    Done,
+}
+
+/// One-shot triggered capture into a buffer of up to `N` samples.
+pub struct Capture<const N: usize> {
+    buffer: [i16; N],
+    depth: usize,
+    pre_trigger: usize,
+    trigger: Trigger,
+    state: State,
+    // Index of the next sample to write
+    write: usize,
+    // Samples recorded since arming, up to `depth`
+    recorded: usize,
+    // Post-trigger samples still to record
+    remaining: usize,
+    // Index of the first sample of the record
+    start: usize,
+    last: Option<i16>,
+    forced: bool,
+    count: u32,
+}
+
+impl<const N: usize> Capture<N> {
+    /// Create a new, idle capture of depth `N` without pre-trigger samples and a manual trigger.
+    pub const fn new() -> Self {
+        Self {
+            buffer: [0; N],
+            depth: N,
+            pre_trigger: 0,
+            trigger: Trigger {
+                source: Source::Manual,
+                level: 0,
+                slope: Slope::Rising,
+            },
+            state: State::Idle,
+            write: 0,
+            recorded: 0,
+            remaining: 0,
+            start: 0,
+            last: None,
+            forced: false,
+            count: 0,
+        }
+    }
+
+    /// Change the configuration. A running capture is aborted and the record discarded.
+    ///
+    /// Args:
+    /// * `depth`: Number of samples per record, `1..=N`.
+    /// * `pre_trigger`: Number of samples before the trigger sample, less than `depth`.
+    /// * `trigger`: Trigger settings.
+    pub fn configure(
+        &mut self,
+        depth: usize,
+        pre_trigger: usize,
+        trigger: Trigger,
+    ) -> Result<(), &'static str> {
+        if depth == 0 || depth > N {
+            return Err("Invalid capture depth");
+        }
+        if pre_trigger >= depth {
+            return Err("Invalid pre-trigger length");
+        }
+        self.depth = depth;
+        self.pre_trigger = pre_trigger;
+        self.trigger = trigger;
+        self.state = State::Idle;
+        Ok(())
+    }
+
+    /// Start a new capture. A running capture restarts and the previous record is discarded.
+    pub fn arm(&mut self) {
+        self.state = State::Armed;
+        self.write = 0;
+        self.recorded = 0;
+        self.last = None;
+        self.forced = false;
+    }
+
+    /// Abort a running capture or discard the record.
+    pub fn disarm(&mut self) {
+        self.state = State::Idle;
+    }
+
+    /// Trigger manually (with any source).
+    ///
+    /// The trigger takes effect with the next sample once the pre-trigger samples are
+    /// recorded. Ignored unless armed.
+    pub fn force(&mut self) {
+        if self.state == State::Armed {
+            self.forced = true;
+        }
+    }
+
+    /// Process a sample.
+    ///
+    /// Args:
+    /// * `x`: Signal sample.
+    /// * `edge`: A digital input edge occurred at this sample.
+    pub fn update(&mut self, x: i16, edge: bool) {
+        match self.state {
+            State::Armed => {
+                let index = self.write;
+                self.buffer[index] = x;
+                self.write = (index + 1) % self.depth;
+                let hit = self.forced
+                    || match self.trigger.source {
+                        Source::Manual => false,
+                        Source::Level => matches!(self.last,
+                            Some(last) if self.trigger.crossed(last, x)),
+                        Source::Digital => edge,
+                    };
+                self.last = Some(x);
+                if self.recorded < self.depth {
+                    self.recorded += 1;
+                }
+                if hit && self.recorded > self.pre_trigger {
+                    self.start =
+                        (index + self.depth - self.pre_trigger) % self.depth;
+                    self.remaining = self.depth - self.pre_trigger - 1;
+                    self.state = State::Triggered;
+                    self.finish_if_complete();
+                }
+            }
+            State::Triggered => {
+                self.buffer[self.write] = x;
+                self.write = (self.write + 1) % self.depth;
+                self.remaining -= 1;
+                self.finish_if_complete();
+            }
+            State::Idle | State::Done => {}
+        }
+    }
+
+    fn finish_if_complete(&mut self) {
+        if self.remaining == 0 {
+            self.state = State::Done;
+            self.count = self.count.wrapping_add(1);
+        }
+    }
+
+    /// Read a part of the record.
+    ///
+    /// Args:
+    /// * `offset`: Index of the first sample to read. The trigger sample is at `pre_trigger()`.
+    /// * `out`: Destination of the samples.
+    ///
+    /// Returns:
+    /// The number of samples read: up to the length of `out` or the end of the record. An error
+    /// if there is no complete record or the offset is beyond its end.
+    pub fn read(
+        &self,
+        offset: usize,
+        out: &mut [i16],
+    ) -> Result<usize, &'static str> {
+        if self.state != State::Done {
+            return Err("No complete capture");
+        }
+        if offset > self.depth {
+            return Err("Invalid capture offset");
+        }
+        let len = out.len().min(self.depth - offset);
+        for (i, y) in out[..len].iter_mut().enumerate() {
+            *y = self.buffer[(self.start + offset + i) % self.depth];
+        }
+        Ok(len)
+    }
+
+    /// Current state.
+    pub fn state(&self) -> State {
+        self.state
+    }
+
+    /// Number of completed captures (wrapping). Identifies the record being read.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Samples per record.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Samples before the trigger sample.
+    pub fn pre_trigger(&self) -> usize {
+        self.pre_trigger
+    }
+
+    /// Trigger settings.
+    pub fn trigger(&self) -> Trigger {
+        self.trigger
+    }
+}
+
+impl<const N: usize> Default for Capture<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LEVEL: Trigger = Trigger {
+        source: Source::Level,
+        level: 100,
+        slope: Slope::Rising,
+    };
+
+    fn record<const N: usize>(capture: &Capture<N>) -> [i16; N] {
+        let mut out = [0; N];
+        assert_eq!(capture.read(0, &mut out), Ok(capture.depth()));
+        out
+    }
+
+    #[test]
+    fn pre_trigger() {
+        let mut capture = Capture::<8>::new();
+        capture.configure(8, 3, LEVEL).unwrap();
+        capture.arm();
+        // Ramp crossing the level at 100
+        for x in (0..200).step_by(10) {
+            capture.update(x, false);
+        }
+        assert_eq!(capture.state(), State::Done);
+        assert_eq!(capture.count(), 1);
+        assert_eq!(record(&capture), [70, 80, 90, 100, 110, 120, 130, 140]);
+        // Frozen until re-armed
+        capture.update(-1, false);
+        assert_eq!(record(&capture)[7], 140);
+    }
+
+    #[test]
+    fn slopes() {
+        let signal = [0i16, 200, 0, 200, 0];
+        for &(slope, first) in
+            [(Slope::Rising, 1), (Slope::Falling, 2), (Slope::Both, 1)].iter()
+        {
+            let mut capture = Capture::<4>::new();
+            capture.configure(2, 1, Trigger { slope, ..LEVEL }).unwrap();
+            capture.arm();
+            for &x in signal.iter() {
+                capture.update(x, false);
+            }
+            let mut out = [0; 2];
+            assert_eq!(capture.read(0, &mut out), Ok(2));
+            assert_eq!(out, [signal[first - 1], signal[first]]);
+        }
+    }
+
+    #[test]
+    fn trigger_at_wrap() {
+        // The trigger sample is the last of the buffer, the record wraps
+        let depth = 5;
+        for late in 0..2 * depth {
+            let mut capture = Capture::<8>::new();
+            let trigger = Trigger {
+                source: Source::Digital,
+                ..LEVEL
+            };
+            capture.configure(depth, 2, trigger).unwrap();
+            capture.arm();
+            let at = 2 + late;
+            for x in 0..20 {
+                capture.update(x, x as usize == at);
+            }
+            let mut out = [0; 5];
+            assert_eq!(capture.read(0, &mut out), Ok(depth));
+            let at = at as i16;
+            assert_eq!(out, [at - 2, at - 1, at, at + 1, at + 2], "{}", late);
+        }
+    }
+
+    #[test]
+    fn pre_trigger_not_filled() {
+        // Triggers before the pre-trigger samples are recorded are ignored
+        let mut capture = Capture::<4>::new();
+        let trigger = Trigger {
+            source: Source::Digital,
+            ..LEVEL
+        };
+        capture.configure(4, 3, trigger).unwrap();
+        capture.arm();
+        for x in 0..8 {
+            capture.update(x, x == 1 || x == 4);
+        }
+        assert_eq!(record(&capture), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn manual_and_chunks() {
+        let mut capture = Capture::<16>::new();
+        capture.force();
+        capture.arm();
+        for x in 0..10 {
+            capture.update(x, true);
+        }
+        assert_eq!(capture.state(), State::Armed);
+        capture.force();
+        for x in 10..40 {
+            capture.update(x, false);
+        }
+        let mut chunk = [0; 6];
+        let mut offset = 0;
+        while offset < capture.depth() {
+            let n = capture.read(offset, &mut chunk).unwrap();
+            for (i, &y) in chunk[..n].iter().enumerate() {
+                assert_eq!(y as usize, 10 + offset + i);
+            }
+            offset += n;
+        }
+        assert_eq!(capture.read(16, &mut chunk), Ok(0));
+        assert!(capture.read(17, &mut chunk).is_err());
+    }
+
+    #[test]
+    fn rearm_while_reading() {
+        let mut capture = Capture::<8>::new();
+        capture.configure(8, 0, LEVEL).unwrap();
+        capture.arm();
+        for x in (0..200).step_by(10) {
+            capture.update(x, false);
+        }
+        let mut chunk = [0; 4];
+        assert_eq!(capture.read(0, &mut chunk), Ok(4));
+        assert_eq!(chunk, [100, 110, 120, 130]);
+        let count = capture.count();
+        // Re-arming invalidates the record being read
+        capture.arm();
+        assert!(capture.read(4, &mut chunk).is_err());
+        for x in (0..300).step_by(10).map(|x| x - 50) {
+            capture.update(x, false);
+        }
+        assert_eq!(capture.count(), count + 1);
+        assert_eq!(capture.read(4, &mut chunk), Ok(4));
+        assert_eq!(chunk, [140, 150, 160, 170]);
+        // So does reconfiguration
+        capture.configure(4, 0, LEVEL).unwrap();
+        assert_eq!(capture.state(), State::Idle);
+        assert!(capture.read(0, &mut chunk).is_err());
+    }
+
+    #[test]
+    fn config() {
+        let mut capture = Capture::<8>::new();
+        assert!(capture.configure(0, 0, LEVEL).is_err());
+        assert!(capture.configure(9, 0, LEVEL).is_err());
+        assert!(capture.configure(8, 8, LEVEL).is_err());
+        assert!(capture.configure(1, 0, LEVEL).is_ok());
+        capture.arm();
+        capture.update(0, false);
+        capture.update(200, false);
+        assert_eq!(record(&capture)[0], 200);
+    }
+}
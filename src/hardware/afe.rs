@@ -11,6 +11,18 @@ pub enum Gain {
     G10 = 0b11,
 }
 
+impl Gain {
+    /// Get the front-end gain as a multiplier.
+    pub fn as_multiplier(self) -> f32 {
+        match self {
+            Gain::G1 => 1.0,
+            Gain::G2 => 2.0,
+            Gain::G5 => 5.0,
+            Gain::G10 => 10.0,
+        }
+    }
+}
+
 /// A programmable gain amplifier that allows for setting the gain via GPIO.
 pub struct ProgrammableGainAmplifier<A0, A1> {
     a0: A0,
@@ -4,6 +4,14 @@ use stm32h7xx_hal::time::MegaHertz;
 /// may begin. This is used for performing the internal ADC conversion.
 pub const ADC_SETUP_TIME: f32 = 220e-9;
 
+/// The ADC full scale input voltage at unity front-end gain. The ADC has a
+/// differential input range of +/- 4.096 V and the input gain is 1/2.5.
+pub const ADC_FULL_SCALE: f32 = 4.096 * 2.5;
+
+/// The DAC full scale output voltage. The DAC reference is 4.096 V and the
+/// output gain is 2.5.
+pub const DAC_FULL_SCALE: f32 = 4.096 * 2.5;
+
 /// The maximum DAC/ADC serial clock line frequency. This is a hardware limit.
 pub const ADC_DAC_SCK_MAX: MegaHertz = MegaHertz(50);
 
@@ -3,6 +3,14 @@
 
 #[macro_use]
 extern crate log;
+#[cfg(feature = "defmt")]
+extern crate defmt_dep as defmt;
 
+pub mod bench;
+pub mod broadcast;
+pub mod capture;
 pub mod hardware;
+pub mod mqtt;
 pub mod server;
+pub mod streaming;
+pub mod telemetry;
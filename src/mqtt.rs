@@ -0,0 +1,948 @@
+///! MQTT settings and telemetry client
+///!
+///! A minimal MQTT 3.1.1 client on a smoltcp TCP socket. It connects to the configured broker,
+///! subscribes to the settings topic of the device and publishes telemetry at a fixed interval.
+///! All messages are QoS 0.
+///!
+///! The topics of a device are below the prefix `stabilizer/<MAC>`, e.g.
+///! `stabilizer/04-91-62-d9-7e-5f`:
+///!
+///! | Topic                              | Content                                          |
+///! |------------------------------------|--------------------------------------------------|
+///! | `<prefix>/settings/<attribute>`    | Subscribed. A write of the attribute, JSON value |
+///! | `<prefix>/response`                | The reply to a write, a JSON reply document      |
+///! | `<prefix>/telemetry`               | The value of the telemetry attribute, JSON       |
+///!
+///! The writes and the telemetry reads are handled by the same closure as the requests of the
+///! control server, see `server::route_request!()`.
+use core::fmt::Write;
+use heapless::{consts::*, ArrayLength, String, Vec};
+use smoltcp as net;
+
+use super::server::{
+    AccessRequest, ByteStream, MqttRequest, Request, Response, ResponseCode,
+};
+
+/// Keep-alive interval in seconds, announced to the broker.
+pub const KEEP_ALIVE: u16 = 10;
+
+// A ping is sent after half the keep-alive interval without receiving. The connection is
+// aborted if the broker does not reply to the connection, the subscription, or a ping within
+// the interval.
+const PING_MS: u32 = KEEP_ALIVE as u32 * 500;
+const REPLY_TIMEOUT_MS: u32 = KEEP_ALIVE as u32 * 1000;
+
+// Delay between connection attempts.
+const RECONNECT_MS: u32 = 5_000;
+
+// The packet identifier of the settings subscription.
+const SUBSCRIBE_ID: u16 = 1;
+
+// Packet types, the high nibble of the first byte.
+const CONNECT: u8 = 1;
+const CONNACK: u8 = 2;
+const PUBLISH: u8 = 3;
+const SUBSCRIBE: u8 = 8;
+const SUBACK: u8 = 9;
+const PINGREQ: u8 = 12;
+const PINGRESP: u8 = 13;
+
+/// A received packet.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Packet<'a> {
+    /// Connection acknowledgement and its return code, zero if accepted.
+    ConnAck {
+        code: u8,
+    },
+    /// Subscription acknowledgement and its return code, the granted QoS or `0x80` on failure.
+    SubAck {
+        packet_id: u16,
+        code: u8,
+    },
+    /// An application message.
+    Publish {
+        topic: &'a str,
+        payload: &'a [u8],
+    },
+    PingResp,
+    /// Another packet, by type.
+    Other(u8),
+}
+
+impl<'a> Packet<'a> {
+    /// Parse a packet.
+    ///
+    /// Args:
+    /// * `buf`: Received data beginning with a packet.
+    ///
+    /// Returns:
+    /// The packet and its length, `None` if the packet is incomplete, or an error if the
+    /// packet is invalid.
+    pub fn parse(buf: &'a [u8]) -> Result<Option<(Self, usize)>, &'static str> {
+        let (len, header) = match remaining_length(buf.get(1..).unwrap_or(&[]))?
+        {
+            Some(length) => length,
+            None => return Ok(None),
+        };
+        let end = 1 + header + len;
+        if buf.len() < end {
+            return Ok(None);
+        }
+        let body = &buf[1 + header..end];
+        let packet = match buf[0] >> 4 {
+            CONNACK if len == 2 => Packet::ConnAck { code: body[1] },
+            SUBACK if len == 3 => Packet::SubAck {
+                packet_id: u16::from_be_bytes([body[0], body[1]]),
+                code: body[2],
+            },
+            PUBLISH => {
+                let (topic, mut payload) = string(body)?;
+                // The packet identifier of QoS 1 and 2 messages
+                if buf[0] & 0x06 != 0 {
+                    payload = payload.get(2..).ok_or("Short packet")?;
+                }
+                let topic =
+                    core::str::from_utf8(topic).map_err(|_| "Invalid topic")?;
+                Packet::Publish { topic, payload }
+            }
+            PINGRESP if len == 0 => Packet::PingResp,
+            CONNACK | SUBACK | PINGRESP => return Err("Invalid packet length"),
+            kind => Packet::Other(kind),
+        };
+        Ok(Some((packet, end)))
+    }
+}
+
+// Decode a remaining length: the number of bytes after the fixed header.
+//
+// Returns the remaining length and its encoded size, `None` if incomplete.
+fn remaining_length(
+    buf: &[u8],
+) -> Result<Option<(usize, usize)>, &'static str> {
+    let mut len = 0;
+    for (i, &byte) in buf.iter().enumerate().take(4) {
+        len |= ((byte & 0x7f) as usize) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(Some((len, i + 1)));
+        }
+    }
+    if buf.len() < 4 {
+        Ok(None)
+    } else {
+        Err("Invalid remaining length")
+    }
+}
+
+// Split a length prefixed string off.
+fn string(buf: &[u8]) -> Result<(&[u8], &[u8]), &'static str> {
+    if buf.len() < 2 {
+        return Err("Short packet");
+    }
+    let len = u16::from_be_bytes([buf[0], buf[1]]) as usize;
+    if buf.len() < 2 + len {
+        return Err("Short packet");
+    }
+    Ok((&buf[2..2 + len], &buf[2 + len..]))
+}
+
+// Append a packet, or nothing if it does not fit.
+//
+// Args:
+// * `first`: The first byte, the packet type and flags.
+// * `fields`: The variable header and the payload. Strings are prefixed by their length.
+fn encode<N: ArrayLength<u8>>(
+    out: &mut Vec<u8, N>,
+    first: u8,
+    fields: &[Field],
+) -> Result<(), &'static str> {
+    let start = out.len();
+    let result = encode_fields(out, first, fields);
+    if result.is_err() {
+        out.truncate(start);
+    }
+    result.map_err(|_| "Packet too long")
+}
+
+// A part of a packet.
+enum Field<'a> {
+    Bytes(&'a [u8]),
+    Str(&'a [u8]),
+}
+
+fn encode_fields<N: ArrayLength<u8>>(
+    out: &mut Vec<u8, N>,
+    first: u8,
+    fields: &[Field],
+) -> Result<(), ()> {
+    let mut len: usize = fields
+        .iter()
+        .map(|field| match field {
+            Field::Bytes(bytes) => bytes.len(),
+            Field::Str(string) => 2 + string.len(),
+        })
+        .sum();
+    out.push(first).map_err(|_| ())?;
+    loop {
+        let byte = (len & 0x7f) as u8;
+        len >>= 7;
+        if len == 0 {
+            out.push(byte).map_err(|_| ())?;
+            break;
+        }
+        out.push(byte | 0x80).map_err(|_| ())?;
+    }
+    for field in fields.iter() {
+        match field {
+            Field::Bytes(bytes) => out.extend_from_slice(bytes)?,
+            Field::Str(string) => {
+                if string.len() > u16::MAX as usize {
+                    return Err(());
+                }
+                out.extend_from_slice(&(string.len() as u16).to_be_bytes())?;
+                out.extend_from_slice(string)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Append a CONNECT packet with a clean session.
+///
+/// Args:
+/// * `out`: The buffer to append the packet to.
+/// * `client_id`: The client identifier.
+/// * `keep_alive`: Keep-alive interval in seconds.
+pub fn connect<N: ArrayLength<u8>>(
+    out: &mut Vec<u8, N>,
+    client_id: &str,
+    keep_alive: u16,
+) -> Result<(), &'static str> {
+    let keep_alive = keep_alive.to_be_bytes();
+    encode(
+        out,
+        CONNECT << 4,
+        &[
+            Field::Str(b"MQTT"),
+            // Protocol level 4 (3.1.1), clean session
+            Field::Bytes(&[4, 0x02]),
+            Field::Bytes(&keep_alive),
+            Field::Str(client_id.as_bytes()),
+        ],
+    )
+}
+
+/// Append a SUBSCRIBE packet for one topic filter with QoS 0.
+pub fn subscribe<N: ArrayLength<u8>>(
+    out: &mut Vec<u8, N>,
+    packet_id: u16,
+    topic: &str,
+) -> Result<(), &'static str> {
+    encode(
+        out,
+        (SUBSCRIBE << 4) | 0x02,
+        &[
+            Field::Bytes(&packet_id.to_be_bytes()),
+            Field::Str(topic.as_bytes()),
+            Field::Bytes(&[0]),
+        ],
+    )
+}
+
+/// Append a PUBLISH packet with QoS 0.
+pub fn publish<N: ArrayLength<u8>>(
+    out: &mut Vec<u8, N>,
+    topic: &str,
+    payload: &[u8],
+) -> Result<(), &'static str> {
+    encode(
+        out,
+        PUBLISH << 4,
+        &[Field::Str(topic.as_bytes()), Field::Bytes(payload)],
+    )
+}
+
+/// Append a PINGREQ packet.
+pub fn ping<N: ArrayLength<u8>>(
+    out: &mut Vec<u8, N>,
+) -> Result<(), &'static str> {
+    encode(out, PINGREQ << 4, &[])
+}
+
+/// Check a client configuration.
+pub fn validate(settings: &MqttRequest) -> Result<(), &'static str> {
+    if settings.client_id.is_empty() || settings.client_id.len() > 23 {
+        return Err("Invalid MQTT client id");
+    }
+    if settings.port == 0 {
+        return Err("Invalid MQTT port");
+    }
+    Ok(())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum State {
+    // CONNECT sent
+    Connecting,
+    // SUBSCRIBE sent
+    Subscribing,
+    Connected,
+}
+
+// The MQTT session on an established connection to the broker.
+struct Session {
+    state: State,
+    // Data of the packets being received, large enough for a settings message.
+    rx: Vec<u8, U1024>,
+    // Packets not yet sent, large enough for a reply.
+    tx: Vec<u8, U2048>,
+    // Time a packet was last received
+    received: u32,
+    // Time the outstanding CONNECT, SUBSCRIBE, or PINGREQ was queued
+    request: Option<u32>,
+    // Time the telemetry was last published
+    telemetry: u32,
+}
+
+impl Session {
+    fn new(client_id: &str, now: u32) -> Self {
+        let mut session = Self {
+            state: State::Connecting,
+            rx: Vec::new(),
+            tx: Vec::new(),
+            received: now,
+            request: Some(now),
+            telemetry: now,
+        };
+        // Note(unwrap): The client identifier is short, the buffer is empty.
+        connect(&mut session.tx, client_id, KEEP_ALIVE).unwrap();
+        session
+    }
+
+    // Send as much of the queued packets as the stream takes.
+    fn flush(&mut self, stream: &mut impl ByteStream) {
+        let sent = stream.send(&self.tx);
+        let len = self.tx.len();
+        self.tx.copy_within(sent..len, 0);
+        self.tx.truncate(len - sent);
+    }
+
+    // Handle received packets, keep the connection alive and publish the telemetry.
+    //
+    // Returns an error if the connection is to be aborted.
+    fn poll<F>(
+        &mut self,
+        stream: &mut impl ByteStream,
+        now: u32,
+        topics: &Topics,
+        f: &mut F,
+    ) -> Result<(), &'static str>
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        self.flush(stream);
+        while stream.can_recv() && self.rx.len() < self.rx.capacity() {
+            let rx = &mut self.rx;
+            // Note(unwrap): Data is available.
+            stream
+                .recv(|buf| {
+                    let len = buf.len().min(rx.capacity() - rx.len());
+                    // Note(unwrap): The length is limited to the free space.
+                    rx.extend_from_slice(&buf[..len]).unwrap();
+                    (len, ())
+                })
+                .unwrap();
+        }
+
+        let mut taken = 0;
+        while let Some((packet, len)) = Packet::parse(&self.rx[taken..])? {
+            taken += len;
+            self.received = now;
+            match (self.state, packet) {
+                (State::Connecting, Packet::ConnAck { code: 0 }) => {
+                    subscribe(&mut self.tx, SUBSCRIBE_ID, &topics.settings)?;
+                    self.request = Some(now);
+                    self.state = State::Subscribing;
+                }
+                (State::Connecting, Packet::ConnAck { .. }) => {
+                    return Err("Connection refused");
+                }
+                (State::Subscribing, Packet::SubAck { code, .. }) => {
+                    if code & 0x80 != 0 {
+                        return Err("Subscription refused");
+                    }
+                    self.request = None;
+                    self.state = State::Connected;
+                }
+                (_, Packet::PingResp) => self.request = None,
+                (State::Connected, Packet::Publish { topic, payload }) => {
+                    let response = topics.write(topic, payload, f);
+                    let mut reply: String<U2048> = String::new();
+                    let queued = response
+                        .write_json(&mut reply)
+                        .map_err(|_| "Reply too long")
+                        .and_then(|()| {
+                            publish(
+                                &mut self.tx,
+                                &topics.response,
+                                reply.as_bytes(),
+                            )
+                        });
+                    if let Err(msg) = queued {
+                        warn!("MQTT reply dropped: {}", msg);
+                    }
+                }
+                (_, packet) => warn!("Unexpected MQTT packet {:?}", packet),
+            }
+        }
+        if taken == 0 && self.rx.len() == self.rx.capacity() {
+            return Err("Packet too long");
+        }
+        let len = self.rx.len();
+        self.rx.copy_within(taken..len, 0);
+        self.rx.truncate(len - taken);
+
+        if let Some(request) = self.request {
+            if now.wrapping_sub(request) > REPLY_TIMEOUT_MS {
+                return Err("Broker timeout");
+            }
+        } else if self.state == State::Connected
+            && now.wrapping_sub(self.received) >= PING_MS
+            && ping(&mut self.tx).is_ok()
+        {
+            self.request = Some(now);
+        }
+
+        let interval = topics.interval;
+        if self.state == State::Connected
+            && interval > 0
+            && now.wrapping_sub(self.telemetry) >= interval
+        {
+            self.telemetry = now;
+            let response = f(&Request {
+                req: AccessRequest::Read,
+                attribute: topics.attribute,
+                value: String::new(),
+                id: 0,
+                batch: None,
+            });
+            // Telemetry not fitting the queue is dropped.
+            if response.code() == ResponseCode::Ok {
+                publish(
+                    &mut self.tx,
+                    &topics.telemetry,
+                    response.value().as_bytes(),
+                )
+                .ok();
+            }
+        }
+        self.flush(stream);
+        Ok(())
+    }
+}
+
+// The topics of a device, see the module documentation.
+struct Topics {
+    settings: String<U64>,
+    response: String<U64>,
+    telemetry: String<U64>,
+    // The telemetry attribute and interval
+    attribute: &'static str,
+    interval: u32,
+}
+
+impl Topics {
+    fn new(prefix: &str, attribute: &'static str, interval: u32) -> Self {
+        let topic = |suffix| {
+            let mut topic: String<U64> = String::new();
+            // Note(unwrap): The prefix is short.
+            write!(topic, "{}/{}", prefix, suffix).unwrap();
+            topic
+        };
+        Self {
+            settings: topic("settings/#"),
+            response: topic("response"),
+            telemetry: topic("telemetry"),
+            attribute,
+            interval,
+        }
+    }
+
+    // Handle a message on a settings topic, a write of the attribute.
+    fn write<F>(&self, topic: &str, payload: &[u8], f: &mut F) -> Response
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        let prefix = &self.settings[..self.settings.len() - 1];
+        let attribute = match topic.strip_prefix(prefix) {
+            Some(attribute) => attribute,
+            None => return Response::unknown_attribute(topic),
+        };
+        let value = match core::str::from_utf8(payload) {
+            Ok(value) if value.len() <= 256 => value,
+            _ => return Response::decode_error(attribute, "Invalid value"),
+        };
+        f(&Request {
+            req: AccessRequest::Write,
+            attribute,
+            value: String::from(value),
+            id: 0,
+            batch: None,
+        })
+    }
+}
+
+/// The MQTT client.
+///
+/// The client connects to the broker once it is configured and reconnects after errors or a
+/// new configuration.
+pub struct Client {
+    settings: MqttRequest,
+    prefix: String<U32>,
+    attribute: &'static str,
+    // The topics, built from the prefix and the settings
+    topics: Topics,
+    session: Option<Session>,
+    // Time of the last connection attempt
+    attempt: Option<u32>,
+    local_port: u16,
+    reconnect: bool,
+}
+
+impl Client {
+    /// Construct a new client, disabled.
+    ///
+    /// Args:
+    /// * `mac` - The MAC address of the device, naming its topics.
+    /// * `attribute` - The attribute published as telemetry.
+    pub fn new(
+        mac: net::wire::EthernetAddress,
+        attribute: &'static str,
+    ) -> Self {
+        let [a, b, c, d, e, f] = mac.0;
+        let mut prefix = String::new();
+        let mut client_id = String::new();
+        // Note(unwrap): The prefix and the identifier fit.
+        write!(
+            prefix,
+            "stabilizer/{:02x}-{:02x}-{:02x}-{:02x}-{:02x}-{:02x}",
+            a, b, c, d, e, f
+        )
+        .unwrap();
+        write!(
+            client_id,
+            "stabilizer-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            a, b, c, d, e, f
+        )
+        .unwrap();
+        let interval = 1000;
+        Self {
+            settings: MqttRequest {
+                broker: [0; 4],
+                port: 1883,
+                client_id,
+                interval,
+            },
+            topics: Topics::new(&prefix, attribute, interval),
+            prefix,
+            attribute,
+            session: None,
+            attempt: None,
+            local_port: 49152,
+            reconnect: false,
+        }
+    }
+
+    /// The client configuration.
+    pub fn settings(&self) -> &MqttRequest {
+        &self.settings
+    }
+
+    /// Change the configuration.
+    ///
+    /// A connection is closed and the client connects to the new broker. A change of the
+    /// telemetry interval only applies to the current connection.
+    pub fn configure(
+        &mut self,
+        settings: &MqttRequest,
+    ) -> Result<(), &'static str> {
+        validate(settings)?;
+        if *settings == self.settings {
+            return Ok(());
+        }
+        self.reconnect = settings.broker != self.settings.broker
+            || settings.port != self.settings.port
+            || settings.client_id != self.settings.client_id;
+        self.settings = settings.clone();
+        self.topics =
+            Topics::new(&self.prefix, self.attribute, settings.interval);
+        self.attempt = None;
+        Ok(())
+    }
+
+    /// Poll the client.
+    ///
+    /// Args:
+    /// * `sockets` - The socket set of the client socket.
+    /// * `handle` - The client socket.
+    /// * `now` - The current time in milliseconds, wrapping.
+    /// * `f` - The closure handling the settings writes and the telemetry reads, see
+    ///   `server::Server::poll()`.
+    pub fn poll<F>(
+        &mut self,
+        sockets: &mut net::socket::SocketSet,
+        handle: net::socket::SocketHandle,
+        now: u32,
+        mut f: F,
+    ) where
+        F: FnMut(&Request) -> Response,
+    {
+        let socket = &mut *sockets.get::<net::socket::TcpSocket>(handle);
+        if self.reconnect {
+            self.reconnect = false;
+            self.session = None;
+            // The reset is sent before the next connection attempt.
+            socket.abort();
+            return;
+        }
+        if socket.state() == net::socket::TcpState::CloseWait {
+            socket.close();
+        }
+        if !socket.is_open() {
+            self.session = None;
+            let due = self.attempt.map_or(true, |attempt| {
+                now.wrapping_sub(attempt) >= RECONNECT_MS
+            });
+            if self.settings.broker != [0; 4] && due {
+                self.attempt = Some(now);
+                self.local_port = self.local_port.wrapping_add(1) | 0xc000;
+                let [a, b, c, d] = self.settings.broker;
+                let broker = net::wire::IpAddress::v4(a, b, c, d);
+                socket
+                    .connect((broker, self.settings.port), self.local_port)
+                    .unwrap_or_else(|e| warn!("MQTT connect error: {:?}", e));
+            }
+            return;
+        }
+        if !socket.may_send() {
+            // Not yet established, or closing.
+            return;
+        }
+        let settings = &self.settings;
+        let session = self
+            .session
+            .get_or_insert_with(|| Session::new(&settings.client_id, now));
+        if let Err(msg) = session.poll(socket, now, &self.topics, &mut f) {
+            warn!("MQTT error: {}", msg);
+            self.session = None;
+            socket.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server;
+
+    fn route(req: &Request) -> Response {
+        crate::route_request!(req,
+            readable_attributes: [
+                "test/status": (|| Ok::<u32, ()>(7))
+            ],
+            modifiable_attributes: [
+                "test/gain": u32, (|gain| {
+                    if gain > 10 {
+                        return Err("gain too large");
+                    }
+                    Ok::<(), &str>(())
+                })
+            ]
+        )
+    }
+
+    // An in-memory `ByteStream`.
+    #[derive(Default)]
+    struct Stream {
+        rx: Vec<u8, U1024>,
+        tx: Vec<u8, U4096>,
+    }
+
+    impl Stream {
+        // Remove a packet sent.
+        fn take(&mut self) -> Option<Vec<u8, U1024>> {
+            let len = match Packet::parse(&self.tx).unwrap() {
+                Some((_, len)) => len,
+                None => return None,
+            };
+            let packet = Vec::from_slice(&self.tx[..len]).unwrap();
+            let rest: Vec<u8, U4096> =
+                Vec::from_slice(&self.tx[len..]).unwrap();
+            self.tx = rest;
+            Some(packet)
+        }
+    }
+
+    impl ByteStream for Stream {
+        fn can_recv(&self) -> bool {
+            !self.rx.is_empty()
+        }
+
+        fn recv<R, F>(&mut self, f: F) -> Result<R, ()>
+        where
+            F: FnOnce(&[u8]) -> (usize, R),
+        {
+            if self.rx.is_empty() {
+                return Err(());
+            }
+            let (len, result) = f(&self.rx);
+            let rest: Vec<u8, U1024> =
+                Vec::from_slice(&self.rx[len..]).unwrap();
+            self.rx = rest;
+            Ok(result)
+        }
+
+        fn send(&mut self, data: &[u8]) -> usize {
+            self.tx.extend_from_slice(data).unwrap();
+            data.len()
+        }
+    }
+
+    #[test]
+    fn packets() {
+        let mut out: Vec<u8, U256> = Vec::new();
+        connect(&mut out, "abc", 10).unwrap();
+        assert_eq!(
+            out[..],
+            [
+                0x10, 15, 0, 4, b'M', b'Q', b'T', b'T', 4, 2, 0, 10, 0, 3,
+                b'a', b'b', b'c'
+            ]
+        );
+        out.clear();
+        subscribe(&mut out, 1, "a/#").unwrap();
+        assert_eq!(out[..], [0x82, 8, 0, 1, 0, 3, b'a', b'/', b'#', 0]);
+        out.clear();
+        ping(&mut out).unwrap();
+        assert_eq!(out[..], [0xc0, 0]);
+
+        // Publish round trip, with a two byte remaining length
+        out.clear();
+        let payload = [b'x'; 200];
+        publish(&mut out, "a/b", &payload).unwrap();
+        assert_eq!(out[..5], [0x30, 0xcd, 0x01, 0, 3]);
+        let packet = Packet::Publish {
+            topic: "a/b",
+            payload: &payload,
+        };
+        assert_eq!(Packet::parse(&out), Ok(Some((packet, out.len()))));
+        assert_eq!(Packet::parse(&out[..out.len() - 1]), Ok(None));
+        assert_eq!(Packet::parse(&out[..2]), Ok(None));
+        // A packet that does not fit is not appended.
+        let mut short: Vec<u8, U256> = Vec::from_slice(&[1, 2]).unwrap();
+        assert!(publish(&mut short, "a/b", &[0; 300]).is_err());
+        assert_eq!(short[..], [1, 2]);
+
+        assert_eq!(
+            Packet::parse(&[0x20, 2, 0, 5]),
+            Ok(Some((Packet::ConnAck { code: 5 }, 4)))
+        );
+        assert_eq!(
+            Packet::parse(&[0x90, 3, 0, 1, 0x80]),
+            Ok(Some((
+                Packet::SubAck {
+                    packet_id: 1,
+                    code: 0x80
+                },
+                5
+            )))
+        );
+        assert_eq!(
+            Packet::parse(&[0xd0, 0, 0xd0]),
+            Ok(Some((Packet::PingResp, 2)))
+        );
+        assert!(Packet::parse(&[0x20, 1, 0]).is_err());
+        assert!(Packet::parse(&[0x30, 0xff, 0xff, 0xff, 0xff]).is_err());
+        // QoS 1 publish with a packet identifier
+        assert_eq!(
+            Packet::parse(&[0x32, 7, 0, 1, b't', 0, 9, b'4', b'2']),
+            Ok(Some((
+                Packet::Publish {
+                    topic: "t",
+                    payload: b"42"
+                },
+                9
+            )))
+        );
+    }
+
+    fn topics() -> Topics {
+        Topics::new("stabilizer/00-01-02-03-04-05", "test/status", 100)
+    }
+
+    // A session connected to the broker after `CONNECT` and `SUBSCRIBE`.
+    fn connected(stream: &mut Stream) -> Session {
+        let topics = topics();
+        let mut session = Session::new("id", 0);
+        session.poll(stream, 0, &topics, &mut route).unwrap();
+        assert_eq!(stream.take().unwrap()[0], CONNECT << 4);
+        stream.rx.extend_from_slice(&[0x20, 2, 0, 0]).unwrap();
+        session.poll(stream, 1, &topics, &mut route).unwrap();
+        let packet = stream.take().unwrap();
+        assert_eq!(packet[0], (SUBSCRIBE << 4) | 0x02);
+        assert_eq!(
+            &packet[6..packet.len() - 1],
+            b"stabilizer/00-01-02-03-04-05/settings/#"
+        );
+        assert_eq!(session.state, State::Subscribing);
+        stream.rx.extend_from_slice(&[0x90, 3, 0, 1, 0]).unwrap();
+        session.poll(stream, 2, &topics, &mut route).unwrap();
+        assert_eq!(session.state, State::Connected);
+        assert!(stream.tx.is_empty());
+        session
+    }
+
+    #[test]
+    fn session() {
+        let topics = topics();
+        let mut stream = Stream::default();
+        let mut session = connected(&mut stream);
+
+        // Settings messages are writes, the reply is published.
+        let mut message: Vec<u8, U256> = Vec::new();
+        publish(
+            &mut message,
+            "stabilizer/00-01-02-03-04-05/settings/test/gain",
+            b"20",
+        )
+        .unwrap();
+        // Split across polls
+        stream.rx.extend_from_slice(&message[..10]).unwrap();
+        session.poll(&mut stream, 3, &topics, &mut route).unwrap();
+        assert!(stream.tx.is_empty());
+        stream.rx.extend_from_slice(&message[10..]).unwrap();
+        session.poll(&mut stream, 4, &topics, &mut route).unwrap();
+        let packet = stream.take().unwrap();
+        match Packet::parse(&packet).unwrap().unwrap().0 {
+            Packet::Publish { topic, payload } => {
+                assert_eq!(topic, "stabilizer/00-01-02-03-04-05/response");
+                assert!(payload.starts_with(
+                    b"{\"code\":400,\"id\":0,\"attribute\":\"test/gain\","
+                ));
+            }
+            _ => panic!(),
+        }
+
+        // Telemetry at the interval
+        session.poll(&mut stream, 99, &topics, &mut route).unwrap();
+        assert!(stream.tx.is_empty());
+        session.poll(&mut stream, 100, &topics, &mut route).unwrap();
+        let packet = stream.take().unwrap();
+        assert_eq!(
+            Packet::parse(&packet).unwrap().unwrap().0,
+            Packet::Publish {
+                topic: "stabilizer/00-01-02-03-04-05/telemetry",
+                payload: b"7"
+            }
+        );
+        assert!(stream.tx.is_empty());
+    }
+
+    #[test]
+    fn keep_alive() {
+        let topics = Topics::new("p", "test/status", 0);
+        let mut stream = Stream::default();
+        let mut session = connected(&mut stream);
+
+        // A ping after half the keep-alive interval without receiving, the
+        // SUBACK at 2
+        session
+            .poll(&mut stream, PING_MS + 1, &topics, &mut route)
+            .unwrap();
+        assert!(stream.tx.is_empty());
+        session
+            .poll(&mut stream, PING_MS + 2, &topics, &mut route)
+            .unwrap();
+        assert_eq!(stream.take().unwrap()[..], [0xc0, 0]);
+        // Answered: the next ping follows half an interval later.
+        stream.rx.extend_from_slice(&[0xd0, 0]).unwrap();
+        let now = PING_MS + 10;
+        session.poll(&mut stream, now, &topics, &mut route).unwrap();
+        session
+            .poll(&mut stream, 2 * PING_MS + 9, &topics, &mut route)
+            .unwrap();
+        assert!(stream.tx.is_empty());
+        let now = 2 * PING_MS + 10;
+        session.poll(&mut stream, now, &topics, &mut route).unwrap();
+        assert_eq!(stream.take().unwrap()[..], [0xc0, 0]);
+        // Unanswered: no second ping, the connection is aborted after the timeout.
+        let timeout = now + REPLY_TIMEOUT_MS;
+        session
+            .poll(&mut stream, timeout, &topics, &mut route)
+            .unwrap();
+        assert!(stream.tx.is_empty());
+        assert!(session
+            .poll(&mut stream, timeout + 1, &topics, &mut route)
+            .is_err());
+
+        // Telemetry does not defer the pings.
+        let topics = Topics::new("p", "test/status", 100);
+        let mut stream = Stream::default();
+        let mut session = connected(&mut stream);
+        for now in (100..PING_MS).step_by(100) {
+            session.poll(&mut stream, now, &topics, &mut route).unwrap();
+            assert_eq!(stream.take().unwrap()[0], PUBLISH << 4);
+        }
+        session
+            .poll(&mut stream, PING_MS + 2, &topics, &mut route)
+            .unwrap();
+        assert_eq!(stream.take().unwrap()[..], [0xc0, 0]);
+
+        // Unanswered subscription
+        let mut stream = Stream::default();
+        let mut session = Session::new("id", 0);
+        stream.rx.extend_from_slice(&[0x20, 2, 0, 0]).unwrap();
+        session.poll(&mut stream, 5, &topics, &mut route).unwrap();
+        assert_eq!(session.state, State::Subscribing);
+        session
+            .poll(&mut stream, 5 + REPLY_TIMEOUT_MS, &topics, &mut route)
+            .unwrap();
+        assert!(session
+            .poll(&mut stream, 6 + REPLY_TIMEOUT_MS, &topics, &mut route)
+            .is_err());
+
+        // Refused and unanswered connections
+        let mut stream = Stream::default();
+        let mut session = Session::new("id", 0);
+        stream.rx.extend_from_slice(&[0x20, 2, 0, 5]).unwrap();
+        assert!(session.poll(&mut stream, 0, &topics, &mut route).is_err());
+        let mut session = Session::new("id", 0);
+        session
+            .poll(&mut stream, REPLY_TIMEOUT_MS, &topics, &mut route)
+            .unwrap();
+        assert!(session
+            .poll(&mut stream, REPLY_TIMEOUT_MS + 1, &topics, &mut route)
+            .is_err());
+    }
+
+    #[test]
+    fn settings() {
+        let mac =
+            net::wire::EthernetAddress([0x04, 0x91, 0x62, 0xd9, 0x7e, 0x5f]);
+        let mut client = Client::new(mac, "test/status");
+        assert_eq!(client.prefix, "stabilizer/04-91-62-d9-7e-5f");
+        assert_eq!(client.settings().client_id, "stabilizer-049162d97e5f");
+        let mut settings = client.settings().clone();
+        settings.broker = [10, 0, 0, 1];
+        settings.interval = 10;
+        client.configure(&settings).unwrap();
+        assert!(client.reconnect);
+        assert_eq!(client.topics.interval, 10);
+        assert_eq!(
+            client.topics.response,
+            "stabilizer/04-91-62-d9-7e-5f/response"
+        );
+        settings.client_id.clear();
+        assert!(client.configure(&settings).is_err());
+        assert_eq!(client.settings().broker, [10, 0, 0, 1]);
+    }
+}
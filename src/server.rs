@@ -1,69 +1,210 @@
 use core::fmt::Write;
-use heapless::{consts::*, String, Vec};
+use heapless::{consts::*, ArrayLength, String, Vec};
 use serde::{Deserialize, Serialize};
-use serde_json_core::{de::from_slice, ser::to_string};
+use serde_cbor::ser::SliceWrite;
+use serde_json_core::de::from_slice;
 use smoltcp as net;
+use typenum::{Diff, Quot, Unsigned};
 
-use dsp::iir;
+use super::capture;
+use dsp::{iir, signal_generator, sweep};
 
+/// Route a request to the attribute getter and setter closures.
+///
+/// Indexed attributes `prefix/<index>` take the index as the first closure argument. Indices at or
+/// above the count of the attribute are rejected with code 404, see `parse_index()`.
+///
+/// Getter, setter and batch errors are `AttributeError`s. Each other failure has its `ResponseCode`.
+/// Large values are read in chunks, see `Response::chunked()`.
 #[macro_export]
 macro_rules! route_request {
     ($request:ident,
             readable_attributes: [$($read_attribute:tt: $getter:tt),*],
-            modifiable_attributes: [$($write_attribute:tt: $TYPE:ty, $setter:tt),*]) => {
-        match $request.req {
-            server::AccessRequest::Read => {
-                match $request.attribute {
-                $(
-                    $read_attribute => {
-                        #[allow(clippy::redundant_closure_call)]
-                        let value = match $getter() {
-                            Ok(data) => data,
-                            Err(_) => return server::Response::error($request.attribute,
-                                                                     "Failed to read attribute"),
-                        };
-
-                        let encoded_data: String<U256> = match serde_json_core::to_string(&value) {
-                            Ok(data) => data,
-                            Err(_) => return server::Response::error($request.attribute,
-                                    "Failed to encode attribute value"),
-                        };
-
-                        server::Response::success($request.attribute, &encoded_data)
-                    },
-                 )*
-                    _ => server::Response::error($request.attribute, "Unknown attribute")
+            modifiable_attributes: [$($write_attribute:tt: $TYPE:ty, $setter:tt),*]
+            $(, indexed_readable_attributes: [$($iread_attribute:tt [$iread_count:expr]: $igetter:tt),*])?
+            $(, indexed_modifiable_attributes: [$($iwrite_attribute:tt [$iwrite_count:expr]: $ITYPE:ty, $isetter:tt),*])?) => {
+        $crate::route_request!($request,
+            readable_attributes: [$($read_attribute: $getter),*],
+            modifiable_attributes: [$($write_attribute: $TYPE, $setter),*]
+            $(, indexed_readable_attributes: [$($iread_attribute [$iread_count]: $igetter),*])?
+            $(, indexed_modifiable_attributes: [$($iwrite_attribute [$iwrite_count]: $ITYPE, $isetter),*])?,
+            batch: (|_| Err::<(), &str>("Batches are not supported")))
+    };
+    ($request:ident,
+            readable_attributes: [$($read_attribute:tt: $getter:tt),*],
+            modifiable_attributes: [$($write_attribute:tt: $TYPE:ty, $setter:tt),*]
+            $(, indexed_readable_attributes: [$($iread_attribute:tt [$iread_count:expr]: $igetter:tt),*])?
+            $(, indexed_modifiable_attributes: [$($iwrite_attribute:tt [$iwrite_count:expr]: $ITYPE:ty, $isetter:tt),*])?,
+            batch: $batch:tt) => {
+        match $request.batch {
+            Some(batch) => {
+                #[allow(clippy::redundant_closure_call)]
+                match $batch(batch) {
+                    Ok(_) => server::Response::success($request.attribute, "null"),
+                    Err(error) => server::Response::rejected($request.attribute, error),
                 }
-            },
-            server::AccessRequest::Write => {
-                match $request.attribute {
-                $(
-                    $write_attribute => {
-                        let new_value = match serde_json_core::from_str::<$TYPE>(&$request.value) {
-                            Ok((data, _)) => data,
-                            Err(_) => return server::Response::error($request.attribute,
-                                    "Failed to decode value"),
-                        };
-
-                        #[allow(clippy::redundant_closure_call)]
-                        match $setter(new_value) {
-                            Ok(_) => server::Response::success($request.attribute, &$request.value),
-                            Err(_) => server::Response::error($request.attribute,
-                                    "Failed to set attribute"),
+            }
+            None => match $request.req {
+                server::AccessRequest::Read => {
+                    match $request.attribute {
+                    $(
+                        $read_attribute => {
+                            #[allow(clippy::redundant_closure_call)]
+                            match $getter() {
+                                Ok(data) => server::Response::read($request.attribute, &$request.value, &data),
+                                Err(error) => server::Response::rejected($request.attribute, error),
+                            }
+                        },
+                     )*
+                        server::LIST_ATTRIBUTE => server::Response::list(
+                            $request.attribute,
+                            &$request.value,
+                            &[$($read_attribute,)* $($(concat!($iread_attribute, "/<index>"),)*)?],
+                            &[$($write_attribute,)* $($(concat!($iwrite_attribute, "/<index>"),)*)?],
+                        ),
+                        #[allow(clippy::match_single_binding)]
+                        _ => {
+                            #[allow(unused_variables)]
+                            let (prefix, segment) = server::split_index($request.attribute);
+                            match prefix {
+                            $($(
+                                $iread_attribute => {
+                                    let index = match server::parse_index($request.attribute, segment,
+                                                                          $iread_count) {
+                                        Ok(index) => index,
+                                        Err(response) => return response,
+                                    };
+
+                                    #[allow(clippy::redundant_closure_call)]
+                                    match $igetter(index) {
+                                        Ok(data) => server::Response::read($request.attribute, &$request.value, &data),
+                                        Err(error) => server::Response::rejected($request.attribute, error),
+                                    }
+                                },
+                             )*)?
+                                _ => server::Response::unknown_attribute($request.attribute)
+                            }
+                        }
+                    }
+                },
+                server::AccessRequest::Write => {
+                    match $request.attribute {
+                    $(
+                        $write_attribute => {
+                            let new_value = match serde_json_core::from_str::<$TYPE>(&$request.value) {
+                                Ok((data, _)) => data,
+                                Err(_) => return server::Response::decode_error($request.attribute,
+                                        "Failed to decode value"),
+                            };
+
+                            #[allow(clippy::redundant_closure_call)]
+                            match $setter(new_value) {
+                                Ok(_) => server::Response::success($request.attribute, &$request.value),
+                                Err(error) => server::Response::rejected($request.attribute, error),
+                            }
+                        }
+                     )*
+                        #[allow(clippy::match_single_binding)]
+                        _ => {
+                            #[allow(unused_variables)]
+                            let (prefix, segment) = server::split_index($request.attribute);
+                            match prefix {
+                            $($(
+                                $iwrite_attribute => {
+                                    let index = match server::parse_index($request.attribute, segment,
+                                                                          $iwrite_count) {
+                                        Ok(index) => index,
+                                        Err(response) => return response,
+                                    };
+
+                                    let new_value = match serde_json_core::from_str::<$ITYPE>(&$request.value) {
+                                        Ok((data, _)) => data,
+                                        Err(_) => return server::Response::decode_error($request.attribute,
+                                                "Failed to decode value"),
+                                    };
+
+                                    #[allow(clippy::redundant_closure_call)]
+                                    match $isetter(index, new_value) {
+                                        Ok(_) => server::Response::success($request.attribute, &$request.value),
+                                        Err(error) => server::Response::rejected($request.attribute, error),
+                                    }
+                                }
+                             )*)?
+                                _ => server::Response::unknown_attribute($request.attribute)
+                            }
                         }
                     }
-                 )*
-                    _ => server::Response::error($request.attribute, "Unknown attribute")
+                }
+                server::AccessRequest::Subscribe | server::AccessRequest::Unsubscribe => {
+                    server::Response::validation_failed($request.attribute,
+                                                        "Subscriptions are handled by the server")
                 }
             }
         }
     }
 }
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Reserved attribute enumerating the attributes of a `route_request!()`.
+///
+/// Reading it returns a page of the attribute names, each with its access: `r` (read-only), `rw`
+/// (read-write), or `w` (write-only), e.g. `{"page":0,"pages":2,"attributes":[["stabilizer/a",
+/// "rw"],...]}`. The request value selects the page, empty is page 0.
+pub const LIST_ATTRIBUTE: &str = "_list";
+
+// Bytes of a `LIST_ATTRIBUTE` value page available to the attributes: the value size less the
+// framing with up to three digit page numbers.
+const LIST_PAGE_BUDGET: usize = ValueSize::USIZE - 42;
+
+/// Split an attribute at the last `/` into the prefix and the index segment.
+pub fn split_index(attribute: &str) -> (&str, &str) {
+    match attribute.rfind('/') {
+        Some(i) => (&attribute[..i], &attribute[i + 1..]),
+        None => (attribute, ""),
+    }
+}
+
+/// Parse the index segment of an indexed attribute.
+///
+/// Args:
+/// * `attribute` - The attribute of the request.
+/// * `segment` - The index segment of the attribute, see `split_index()`.
+/// * `count` - The number of indices of the attribute.
+///
+/// Returns:
+/// The index, or the `ResponseCode::UnknownAttribute` reply for an invalid index or an index out
+/// of range.
+pub fn parse_index(
+    attribute: &str,
+    segment: &str,
+    count: usize,
+) -> Result<usize, Response> {
+    if segment.is_empty() || !segment.bytes().all(|c| c.is_ascii_digit()) {
+        return Err(Response::new_message(
+            ResponseCode::UnknownAttribute,
+            attribute,
+            "Invalid index",
+        ));
+    }
+    match segment.parse::<usize>() {
+        Ok(index) if index < count => Ok(index),
+        _ => Err(Response::new_message(
+            ResponseCode::UnknownAttribute,
+            attribute,
+            "Index out of range",
+        )),
+    }
+}
+
+#[derive(Deserialize, Serialize, Copy, Clone, Debug)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub enum AccessRequest {
     Read,
     Write,
+    /// Subscribe to the changes of the attribute, see `Server::notify()`. The reply is the
+    /// current value.
+    Subscribe,
+    /// End a subscription.
+    Unsubscribe,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -71,208 +212,2990 @@ pub struct Request<'a> {
     pub req: AccessRequest,
     pub attribute: &'a str,
     pub value: String<U256>,
+    /// Client chosen identifier echoed in the reply. Defaults to 0.
+    #[serde(default)]
+    pub id: u32,
+    /// Batch phase notification instead of a request, see `Batch`.
+    #[serde(skip)]
+    pub batch: Option<Batch>,
+}
+
+/// Maximum number of requests in a batch.
+pub const MAX_BATCH: usize = 4;
+
+/// Batch phase notifications to the application, see `route_request!()`.
+///
+/// A batch is a JSON array of up to `MAX_BATCH` requests on one line, answered with the array of
+/// the replies. The application is notified of the beginning of a batch. It then stages the
+/// writes of the batch requests instead of applying them. If all requests succeed, the application
+/// is notified to commit the staged writes at once. Otherwise it is notified to discard them and
+/// all replies are errors.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Batch {
+    Begin,
+    Commit,
+    Discard,
+}
+
+// The identifier of a request that failed to parse otherwise.
+#[derive(Deserialize)]
+struct RequestId {
+    #[serde(default)]
+    id: u32,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct IirRequest {
     pub channel: u8,
+    /// Stage index within the cascade. Defaults to the first stage.
+    #[serde(default)]
+    pub stage: u8,
+    pub iir: iir::IIR,
+    /// Number of samples to interpolate from the current to the new
+    /// configuration, see `dsp::iir::Interpolator`. Zero (the default)
+    /// switches immediately with bumpless transfer.
+    #[serde(default)]
+    pub ramp_samples: u32,
+}
+
+/// IIR stage configuration of the channel given by the index of the attribute, see
+/// `IirRequest`.
+#[derive(Serialize, Deserialize)]
+pub struct IirStageRequest {
+    /// Stage index within the cascade. Defaults to the first stage.
+    #[serde(default)]
+    pub stage: u8,
     pub iir: iir::IIR,
+    /// See `IirRequest::ramp_samples`.
+    #[serde(default)]
+    pub ramp_samples: u32,
+}
+
+impl IirStageRequest {
+    /// The request for a channel.
+    pub fn with_channel(self, channel: u8) -> IirRequest {
+        IirRequest {
+            channel,
+            stage: self.stage,
+            iir: self.iir,
+            ramp_samples: self.ramp_samples,
+        }
+    }
+}
+
+/// Biquad design request, see `dsp::iir::design`.
+#[derive(Serialize, Deserialize)]
+pub struct IirDesignRequest {
+    pub channel: u8,
+    /// Stage index within the cascade. Defaults to the first stage.
+    #[serde(default)]
+    pub stage: u8,
+    pub filter: iir::design::Filter,
+    /// Characteristic frequency in Hz.
+    pub f0: f32,
+    /// Quality factor.
+    pub q: f32,
+    /// Shelf gain in dB.
+    #[serde(default)]
+    pub gain: f32,
+}
+
+/// First-order compensator type, see `dsp::iir::FirstOrder`.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TrimKind {
+    Lead,
+    Lag,
+}
+
+/// Position of the first-order trim section relative to the IIR cascade.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TrimPosition {
+    Before,
+    After,
+}
+
+/// First-order lead/lag trim configuration. Writing it clears the trim
+/// state.
+#[derive(Serialize, Deserialize)]
+pub struct TrimRequest {
+    pub channel: u8,
+    /// Apply the trim section. If disabled the other fields are ignored.
+    pub enable: bool,
+    pub kind: TrimKind,
+    pub position: TrimPosition,
+    /// Zero frequency in Hz.
+    pub fz: f32,
+    /// Pole frequency in Hz.
+    pub fp: f32,
+}
+
+/// Signal generator configuration, see `dsp::signal_generator`.
+#[derive(Serialize, Deserialize)]
+pub struct SignalGeneratorRequest {
+    /// DAC channel to sum the generator output into.
+    pub channel: u8,
+    pub waveform: signal_generator::Waveform,
+    /// Frequency in Hz.
+    pub frequency: f32,
+    /// Peak amplitude in volts. Zero disables the generator.
+    pub amplitude: f32,
+    /// DC offset in volts.
+    #[serde(default)]
+    pub offset: f32,
+}
+
+/// Lockin input AGC configuration, see `dsp::agc`.
+#[derive(Serialize, Deserialize)]
+pub struct AgcRequest {
+    /// Apply the AGC. If disabled the gain is unity.
+    pub enable: bool,
+    /// Target output magnitude (mean absolute value) in volts at the ADC.
+    pub target: f32,
+    /// Log2 of the magnitude estimate time constant in samples.
+    pub rate_shift: u32,
+    /// Input magnitude in volts at the ADC below which the gain is frozen.
+    pub squelch: f32,
+    /// Maximum gain.
+    pub gain_max: f32,
+}
+
+/// Lockin reference frequency counter configuration, see
+/// `dsp::freq_counter`.
+#[derive(Serialize, Deserialize)]
+pub struct CounterRequest {
+    /// Gate time in seconds.
+    pub gate: f32,
+}
+
+/// Input histogram configuration, see `dsp::histogram`. Writing it
+/// clears the counts.
+#[derive(Serialize, Deserialize)]
+pub struct HistogramRequest {
+    /// ADC channel.
+    pub channel: u8,
+    /// Center of the bins in volts at the ADC.
+    pub center: f32,
+    /// Log2 of the bin width in the `i32` DSP domain. 16 is one ADC LSB.
+    pub shift: u32,
+}
+
+/// Binary sample stream configuration, see `streaming`. The frames are served on a separate
+/// TCP port.
+#[derive(Serialize, Deserialize)]
+pub struct StreamRequest {
+    /// Bit mask of the streamed channel ids: ADC 0, ADC 1, DAC 0, DAC 1.
+    pub channels: u8,
+    /// Keep one sample out of `decimation`.
+    pub decimation: u16,
 }
 
+/// MQTT client configuration, see `mqtt::Client`.
+///
+/// The configuration is not persisted: it reverts to the defaults at power-on.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MqttRequest {
+    /// IPv4 address of the broker. The client is disabled if it is `[0, 0, 0, 0]`.
+    pub broker: [u8; 4],
+    /// TCP port of the broker.
+    pub port: u16,
+    /// Client identifier, 1 to 23 characters.
+    pub client_id: String<U32>,
+    /// Telemetry interval in milliseconds. Zero disables telemetry.
+    pub interval: u32,
+}
+
+/// UDP status telemetry configuration, see `broadcast::Broadcast`.
+///
+/// The configuration is not persisted: it reverts to the defaults at power-on.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BroadcastRequest {
+    /// IPv4 destination address, e.g. `[255, 255, 255, 255]`. The telemetry is disabled if it
+    /// is `[0, 0, 0, 0]`.
+    pub target: [u8; 4],
+    /// UDP destination port.
+    pub port: u16,
+    /// Send one status every `divider` telemetry periods, see `broadcast::PERIOD_MS`.
+    pub divider: u32,
+}
+
+/// Triggered capture configuration, see `capture`. Writing it disarms the capture and
+/// discards the record.
+#[derive(Serialize, Deserialize)]
+pub struct CaptureRequest {
+    /// ADC channel.
+    pub channel: u8,
+    /// Samples per record.
+    pub depth: u32,
+    /// Samples before the trigger sample.
+    pub pre_trigger: u32,
+    /// Trigger source, level in ADC machine units, and slope.
+    pub trigger: capture::Trigger,
+}
+
+/// Triggered capture state.
+#[derive(Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CaptureStatus {
+    pub state: capture::State,
+    /// Number of completed captures (wrapping).
+    pub count: u32,
+    /// ADC channel.
+    pub channel: u8,
+    /// Samples per record.
+    pub depth: u32,
+    /// Samples before the trigger sample.
+    pub pre_trigger: u32,
+    /// Offset of the next readout chunk.
+    pub offset: u32,
+}
+
+/// A chunk of the captured record.
+#[derive(Serialize)]
+pub struct CaptureData {
+    /// The capture the samples belong to, see `CaptureStatus::count`.
+    pub count: u32,
+    /// Offset of the first sample in the record.
+    pub offset: u32,
+    /// ADC samples in machine units. Empty at the end of the record.
+    pub samples: Vec<i16, CaptureChunk>,
+}
+
+/// Samples per `CaptureData` chunk: the reply value holds up to seven
+/// characters per sample and the framing.
+pub type CaptureChunk = Quot<Diff<ValueSize, U64>, U7>;
+
+/// Input histogram counts since the last read.
+///
+/// The response size limits the number of bins.
 #[derive(Serialize)]
+pub struct HistogramCounts {
+    pub bins: [u32; 16],
+    pub underflow: u32,
+    pub overflow: u32,
+}
+
+/// Swept sine configuration, see `dsp::sweep`. Writing it (re)starts the
+/// sweep.
+#[derive(Serialize, Deserialize)]
+pub struct SweepRequest {
+    /// DAC channel to sum the sweep output into.
+    pub channel: u8,
+    pub law: sweep::SweepLaw,
+    pub mode: sweep::SweepMode,
+    /// Start frequency in Hz.
+    pub start: f32,
+    /// Stop frequency in Hz.
+    pub stop: f32,
+    /// Sweep rate in Hz/s (`Linear`) or octaves/s (`Log`).
+    pub rate: f32,
+    /// Peak amplitude in volts.
+    pub amplitude: f32,
+}
+
+/// Swept sine progress.
+#[derive(Serialize)]
+pub struct SweepStatus {
+    /// A sweep is configured.
+    pub active: bool,
+    /// A single shot sweep has finished.
+    pub finished: bool,
+    /// Current frequency in Hz.
+    pub frequency: f32,
+    /// Progress from start (0) to stop (1).
+    pub progress: f32,
+}
+
+/// PRBS excitation configuration, see `dsp::prbs`. Writing it restarts the
+/// sequence at the seed.
+#[derive(Serialize, Deserialize)]
+pub struct PrbsRequest {
+    /// DAC channel to sum the PRBS output into.
+    pub channel: u8,
+    /// Polynomial order: 7, 15, 23, or 31.
+    pub order: u8,
+    /// Initial register content, must not be zero.
+    pub seed: u32,
+    /// Amplitude in volts. Zero disables the PRBS.
+    pub amplitude: f32,
+}
+
+/// The status of a `Response`.
+///
+/// The numeric values are stable: they are the `code` of the reply document.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ResponseCode {
+    /// The request succeeded.
+    Ok = 200,
+    /// The attribute is unknown, or its index is invalid or out of range.
+    UnknownAttribute = 404,
+    /// The value of the request can not be decoded.
+    DecodeError = 422,
+    /// The value was rejected by the attribute, or the batch was discarded.
+    ValidationFailed = 400,
+    /// The attribute can not be accessed at the moment.
+    Busy = 503,
+    /// The batch holds more than `MAX_BATCH` requests.
+    PayloadTooLarge = 413,
+    /// The request, the batch, or the reply exceeds its buffer.
+    Overflow = 520,
+    /// The request is not valid JSON or not a request.
+    ParseError = 550,
+    /// The attribute failed to be read.
+    Internal = 500,
+}
+
+/// An error of an attribute getter or setter, or of the batch closure of `route_request!`.
+///
+/// A plain message rejects the request with `ResponseCode::ValidationFailed`. Other codes are given
+/// together with the message, e.g. `Err((ResponseCode::Busy, "capture running"))`. A getter
+/// failing without a message replies with `ResponseCode::Internal`.
+pub trait AttributeError {
+    /// The code of the reply.
+    fn code(&self) -> ResponseCode;
+    /// The message of the reply.
+    fn message(&self) -> &str;
+}
+
+impl<'a> AttributeError for &'a str {
+    fn code(&self) -> ResponseCode {
+        ResponseCode::ValidationFailed
+    }
+
+    fn message(&self) -> &str {
+        self
+    }
+}
+
+impl<'a> AttributeError for (ResponseCode, &'a str) {
+    fn code(&self) -> ResponseCode {
+        self.0
+    }
+
+    fn message(&self) -> &str {
+        self.1
+    }
+}
+
+impl AttributeError for () {
+    fn code(&self) -> ResponseCode {
+        ResponseCode::Internal
+    }
+
+    fn message(&self) -> &str {
+        "Failed to read attribute"
+    }
+}
+
+/// Capacity of a reply value. Larger values are read in chunks, see `Response::chunked()`.
+pub type ValueSize = U1024;
+
+/// Capacity of an encoded attribute value, see `Response::chunked()`.
+pub type EncodedSize = U4096;
+
+// Space reserved for the framing of a chunk, see `Response::chunked()`.
+const CHUNK_FRAMING: usize = 48;
+
+/// The value of a read request of a chunk of an attribute value, see `Response::chunked()`.
+#[derive(Deserialize)]
+pub struct ChunkRequest {
+    /// Offset of the chunk in the encoded value.
+    pub offset: u32,
+}
+
+/// A reply to a `Request`.
+///
+/// The value is serialized JSON. It is embedded in the reply document verbatim, see
+/// `Response::write_json()`.
 pub struct Response {
-    code: i32,
+    code: ResponseCode,
     attribute: String<U256>,
-    value: String<U256>,
+    value: String<ValueSize>,
+    id: u32,
+}
+
+// `heapless::String` does not implement `defmt::Format`.
+#[cfg(feature = "defmt")]
+impl<'a> defmt::Format for Request<'a> {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Request(req: {}, attribute: {=str}, value: {=str}, id: {=u32})",
+            self.req,
+            self.attribute,
+            self.value.as_str(),
+            self.id
+        );
+    }
 }
 
+#[cfg(feature = "defmt")]
+impl defmt::Format for Response {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(
+            f,
+            "Response(code: {}, attribute: {=str}, value: {=str}, id: {=u32})",
+            self.code,
+            self.attribute.as_str(),
+            self.value.as_str(),
+            self.id
+        );
+    }
+}
+
+// The server types implement `defmt::Format`. The `defmt` feature enables it
+// for the DSP types they carry as well.
+#[cfg(feature = "defmt")]
+const _: () = {
+    fn format<T: defmt::Format>() {}
+    #[allow(dead_code)]
+    fn check() {
+        format::<Request>();
+        format::<Response>();
+        format::<Status>();
+        format::<LockinStatus>();
+        format::<CounterStatus>();
+        format::<JitterStatus>();
+        format::<AverageStatus>();
+        format::<ThdStatus>();
+        format::<CaptureStatus>();
+        format::<iir::IIR>();
+        format::<dsp::iir_int::Vec5>();
+    }
+};
+
 impl<'a> Request<'a> {
-    pub fn restore_value(&mut self) {
-        let mut new_value: String<U256> = String::new();
-        for byte in self.value.as_str().chars() {
-            if byte == '\'' {
-                new_value.push('"').unwrap();
-            } else {
-                new_value.push(byte).unwrap();
+    /// Decode the value: a JSON value embedded in the request as a JSON string.
+    ///
+    /// Values with backslash escapes are unescaped. Values without escapes are taken to be in
+    /// the deprecated legacy form with all double quotes replaced by single quotes and restored.
+    pub fn restore_value(&mut self) -> Result<(), &'static str> {
+        if self.value.contains('\\') {
+            self.value = unescape(&self.value)
+                .map_err(|_| "Invalid escape sequence in value")?;
+        } else {
+            let mut new_value: String<U256> = String::new();
+            for byte in self.value.as_str().chars() {
+                if byte == '\'' {
+                    new_value.push('"').unwrap();
+                } else {
+                    new_value.push(byte).unwrap();
+                }
             }
+            self.value = new_value;
         }
+        Ok(())
+    }
+}
 
-        self.value = new_value;
+// Append the JSON string escaped form of a string, without the enclosing quotes.
+fn escape(value: &str, out: &mut impl Write) -> core::fmt::Result {
+    for c in value.chars() {
+        match c {
+            '"' => out.write_str("\\\""),
+            '\\' => out.write_str("\\\\"),
+            '\n' => out.write_str("\\n"),
+            '\r' => out.write_str("\\r"),
+            '\t' => out.write_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32),
+            c => out.write_char(c),
+        }?;
     }
+    Ok(())
 }
 
-impl Response {
-    /// Remove all double quotation marks from the `value` field of a response.
-    fn sanitize_value(&mut self) {
-        let mut new_value: String<U256> = String::new();
-        for byte in self.value.as_str().chars() {
-            if byte == '"' {
-                new_value.push('\'').unwrap();
-            } else {
-                new_value.push(byte).unwrap();
+// Decode the escapes of the contents of a JSON string.
+fn unescape<N: ArrayLength<u8>>(value: &str) -> Result<String<N>, ()> {
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        let c = if c == '\\' {
+            match chars.next().ok_or(())? {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let mut code = 0;
+                    for _ in 0..4 {
+                        let digit = chars.next().and_then(|c| c.to_digit(16));
+                        code = (code << 4) | digit.ok_or(())?;
+                    }
+                    core::char::from_u32(code).ok_or(())?
+                }
+                _ => return Err(()),
             }
+        } else {
+            c
+        };
+        out.push(c)?;
+    }
+    Ok(out)
+}
+
+// The attribute names of a `route_request!()` and their access, readable attributes first.
+fn list_entries<'a>(
+    readable: &'a [&'a str],
+    writable: &'a [&'a str],
+) -> impl Iterator<Item = (&'a str, &'static str)> + Clone + 'a {
+    let read = readable.iter().map(move |name| {
+        let access = if writable.contains(name) { "rw" } else { "r" };
+        (*name, access)
+    });
+    let write_only = writable
+        .iter()
+        .filter(move |name| !readable.contains(name))
+        .map(|name| (*name, "w"));
+    read.chain(write_only)
+}
+
+// The page of each attribute of a `LIST_ATTRIBUTE` reply.
+fn list_pages<'a>(
+    entries: impl Iterator<Item = (&'a str, &'static str)> + 'a,
+) -> impl Iterator<Item = usize> + 'a {
+    let (mut page, mut used) = (0, 0);
+    entries.map(move |(name, access)| {
+        // `["name","access"],`
+        let len = name.len() + access.len() + 8;
+        if used > 0 && used + len > LIST_PAGE_BUDGET {
+            page += 1;
+            used = 0;
         }
+        used += len;
+        page
+    })
+}
 
-        self.value = new_value;
+// Serialize a page of a `LIST_ATTRIBUTE` reply.
+fn write_list_page<'a>(
+    value: &mut String<ValueSize>,
+    page: usize,
+    pages: usize,
+    entries: impl Iterator<Item = (&'a str, &'static str)>,
+) -> core::fmt::Result {
+    write!(
+        value,
+        "{{\"page\":{},\"pages\":{},\"attributes\":[",
+        page, pages
+    )?;
+    for (i, (name, access)) in entries.enumerate() {
+        if i > 0 {
+            value.push(',').map_err(|_| core::fmt::Error)?;
+        }
+        write!(value, "[\"{}\",\"{}\"]", name, access)?;
     }
+    write!(value, "]}}")
+}
 
-    /// Remove all double quotation marks from the `value` field of a response and wrap it in single
-    /// quotes.
-    fn wrap_and_sanitize_value(&mut self) {
-        let mut new_value: String<U256> = String::new();
-        new_value.push('\'').unwrap();
-        for byte in self.value.as_str().chars() {
-            if byte == '"' {
-                new_value.push('\'').unwrap();
-            } else {
-                new_value.push(byte).unwrap();
+impl Response {
+    /// Construct a reply with a JSON string value.
+    fn new_message(code: ResponseCode, attribute: &str, message: &str) -> Self {
+        let quote = |value: &mut String<ValueSize>| {
+            write!(value, "\"")?;
+            escape(message, value)?;
+            write!(value, "\"")
+        };
+        let mut value = String::new();
+        let code = match quote(&mut value) {
+            Ok(()) => code,
+            Err(_) => {
+                value = String::from("\"Reply value too long\"");
+                ResponseCode::Overflow
             }
+        };
+        Self {
+            code,
+            attribute: String::from(attribute),
+            value,
+            id: 0,
         }
-        new_value.push('\'').unwrap();
+    }
 
-        self.value = new_value;
+    /// Serialize the reply as a JSON document, the value embedded verbatim
+    /// (escaped as a string with the `legacy_response` feature).
+    ///
+    /// Args:
+    /// * `out` - The buffer to append the document to.
+    pub fn write_json(&self, out: &mut impl Write) -> core::fmt::Result {
+        // The attribute is escaped: CBOR requests and MQTT topics carry it verbatim.
+        write!(
+            out,
+            "{{\"code\":{},\"id\":{},\"attribute\":\"",
+            self.code as i32, self.id
+        )?;
+        escape(&self.attribute, out)?;
+        write!(out, "\",\"value\":")?;
+        if cfg!(feature = "legacy_response") {
+            write!(out, "\"")?;
+            escape(&self.value, out)?;
+            write!(out, "\"}}")
+        } else {
+            write!(out, "{}}}", self.value)
+        }
     }
 
     /// Construct a successful reply.
     ///
-    /// Note: `value` will be sanitized to convert all single quotes to double quotes.
-    ///
     /// Args:
     /// * `attrbute` - The attribute of the success.
-    /// * `value` - The value of the attribute.
+    /// * `value` - The value of the attribute, serialized JSON.
     pub fn success(attribute: &str, value: &str) -> Self {
-        let mut res = Self {
-            code: 200,
+        Self {
+            code: ResponseCode::Ok,
             attribute: String::from(attribute),
             value: String::from(value),
-        };
-        res.sanitize_value();
-        res
+            id: 0,
+        }
     }
 
-    /// Construct an error reply.
+    /// Construct the reply to a read of an attribute value, see `chunked()`.
     ///
-    /// Note: `message` will be sanitized to convert all single quotes to double quotes.
+    /// The value is encoded in the frame of this function, not in that of the caller: the arms
+    /// of `route_request!()` share the buffer.
     ///
     /// Args:
-    /// * `attrbute` - The attribute of the success.
-    /// * `message` - The message denoting the error.
-    pub fn error(attribute: &str, message: &str) -> Self {
-        let mut res = Self {
-            code: 400,
-            attribute: String::from(attribute),
-            value: String::from(message),
+    /// * `attribute` - The attribute of the request.
+    /// * `value` - The value of the request, see `chunked()`.
+    /// * `data` - The attribute value.
+    #[inline(never)]
+    pub fn read(attribute: &str, value: &str, data: &impl Serialize) -> Self {
+        match serde_json_core::to_string::<EncodedSize, _>(data) {
+            Ok(encoded) => Self::chunked(attribute, value, &encoded),
+            Err(_) => {
+                Self::overflow(attribute, "Failed to encode attribute value")
+            }
+        }
+    }
+
+    /// Construct the reply to a read of an encoded attribute value.
+    ///
+    /// A value exceeding `ValueSize` is read in chunks. The reply value is then
+    /// `{"more":M,"offset":N,"data":D}` where `D` is a chunk of the encoded value as a JSON
+    /// string. While `M` is `true`, the next chunk is read with the request value `{"offset":N}`.
+    /// The concatenation of the chunks is the encoded value. Each read encodes the value anew:
+    /// the chunks are consistent if the value does not change in the meantime.
+    ///
+    /// Args:
+    /// * `attribute` - The attribute of the request.
+    /// * `value` - The value of the request, a `ChunkRequest`. Other values are ignored.
+    /// * `encoded` - The encoded attribute value.
+    pub fn chunked(attribute: &str, value: &str, encoded: &str) -> Self {
+        let offset = match serde_json_core::from_str::<ChunkRequest>(value) {
+            Ok((request, _)) => request.offset as usize,
+            Err(_) if encoded.len() <= ValueSize::USIZE => {
+                return Self::success(attribute, encoded)
+            }
+            Err(_) => 0,
         };
-        res.wrap_and_sanitize_value();
-        res
+        if offset > encoded.len() || !encoded.is_char_boundary(offset) {
+            return Self::validation_failed(attribute, "Invalid offset");
+        }
+
+        // The end of the chunk: the escaped data fits next to the framing.
+        let mut len = 0;
+        let mut end = offset;
+        for c in encoded[offset..].chars() {
+            let mut escaped: String<U8> = String::new();
+            // Note(unwrap): A character is escaped to at most six bytes.
+            escape(c.encode_utf8(&mut [0; 4]), &mut escaped).unwrap();
+            if len + escaped.len() > ValueSize::USIZE - CHUNK_FRAMING {
+                break;
+            }
+            len += escaped.len();
+            end += c.len_utf8();
+        }
+        let mut response = Self::success(attribute, "");
+        // Note(unwrap): The framing fits into `CHUNK_FRAMING`, the data into
+        // the rest.
+        write!(
+            response.value,
+            "{{\"more\":{},\"offset\":{},\"data\":\"",
+            end < encoded.len(),
+            end
+        )
+        .unwrap();
+        escape(&encoded[offset..end], &mut response.value).unwrap();
+        response.value.push_str("\"}").unwrap();
+        response
     }
 
-    /// Construct a custom reply.
+    /// Set the identifier of the reply.
     ///
-    /// Note: `message` will be sanitized to convert all single quotes to double quotes.
+    /// Args:
+    /// * `id` - The identifier of the request replied to, see `Request::id`.
+    pub fn with_id(mut self, id: u32) -> Self {
+        self.id = id;
+        self
+    }
+
+    /// The code of the reply.
+    pub fn code(&self) -> ResponseCode {
+        self.code
+    }
+
+    /// The value of the reply, serialized JSON.
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Construct a reply to a `LIST_ATTRIBUTE` read.
     ///
     /// Args:
-    /// * `attrbute` - The attribute of the success.
-    /// * `message` - The message denoting the status.
-    pub fn custom(code: i32, message: &str) -> Self {
-        let mut res = Self {
-            code,
-            attribute: String::from(""),
-            value: String::from(message),
+    /// * `attribute` - The attribute of the request.
+    /// * `value` - The value of the request, the page number.
+    /// * `readable` - The readable attributes.
+    /// * `writable` - The modifiable attributes.
+    pub fn list(
+        attribute: &str,
+        value: &str,
+        readable: &[&str],
+        writable: &[&str],
+    ) -> Self {
+        let page = match value.trim_matches('"') {
+            "" => Ok(0),
+            page => page.parse::<usize>(),
         };
-        res.wrap_and_sanitize_value();
-        res
+        let entries = list_entries(readable, writable);
+        let pages = list_pages(entries.clone()).last().map_or(1, |p| p + 1);
+        let page = match page {
+            Ok(page) if page < pages => page,
+            _ => return Self::validation_failed(attribute, "Invalid page"),
+        };
+
+        let mut response = Self::success(attribute, "");
+        let page_entries = entries
+            .clone()
+            .zip(list_pages(entries))
+            .filter(|(_, p)| *p == page)
+            .map(|(entry, _)| entry);
+        match write_list_page(&mut response.value, page, pages, page_entries) {
+            Ok(()) => response,
+            Err(_) => Self::overflow(attribute, "Attribute name too long"),
+        }
+    }
+
+    /// Construct the reply to a request for an unknown attribute.
+    ///
+    /// Args:
+    /// * `attrbute` - The attribute of the request.
+    pub fn unknown_attribute(attribute: &str) -> Self {
+        Self::new_message(
+            ResponseCode::UnknownAttribute,
+            attribute,
+            "Unknown attribute",
+        )
+    }
+
+    /// Construct a `ResponseCode::DecodeError` reply.
+    ///
+    /// Args:
+    /// * `attrbute` - The attribute of the error.
+    /// * `message` - The message denoting the error. The value is the message as a JSON string.
+    pub fn decode_error(attribute: &str, message: &str) -> Self {
+        Self::new_message(ResponseCode::DecodeError, attribute, message)
+    }
+
+    /// Construct a `ResponseCode::ValidationFailed` reply, see `Response::decode_error()`.
+    pub fn validation_failed(attribute: &str, message: &str) -> Self {
+        Self::new_message(ResponseCode::ValidationFailed, attribute, message)
+    }
+
+    /// Construct a `ResponseCode::Busy` reply, see `Response::decode_error()`.
+    pub fn busy(attribute: &str, message: &str) -> Self {
+        Self::new_message(ResponseCode::Busy, attribute, message)
+    }
+
+    /// Construct a `ResponseCode::PayloadTooLarge` reply, see `Response::decode_error()`.
+    pub fn payload_too_large(attribute: &str, message: &str) -> Self {
+        Self::new_message(ResponseCode::PayloadTooLarge, attribute, message)
+    }
+
+    /// Construct a `ResponseCode::Overflow` reply, see `Response::decode_error()`.
+    pub fn overflow(attribute: &str, message: &str) -> Self {
+        Self::new_message(ResponseCode::Overflow, attribute, message)
+    }
+
+    /// Construct a `ResponseCode::Internal` reply, see `Response::decode_error()`.
+    pub fn internal(attribute: &str, message: &str) -> Self {
+        Self::new_message(ResponseCode::Internal, attribute, message)
+    }
+
+    /// Construct the reply to a line that is not a request.
+    ///
+    /// Args:
+    /// * `message` - The message denoting the error. The value is the message as a JSON string.
+    pub fn parse_error(message: &str) -> Self {
+        Self::new_message(ResponseCode::ParseError, "", message)
+    }
+
+    /// Construct the reply to a failed setter or batch closure.
+    ///
+    /// Args:
+    /// * `attrbute` - The attribute of the error.
+    /// * `error` - The error, with its code and message.
+    pub fn rejected(attribute: &str, error: impl AttributeError) -> Self {
+        Self::new_message(error.code(), attribute, error.message())
     }
 }
 
-#[derive(Serialize)]
+/// The CBOR form of a reply, see `Protocol::Cbor`.
+///
+/// The value is serialized JSON, embedded as a text string.
+impl Serialize for Response {
+    fn serialize<S: serde::Serializer>(
+        &self,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+        let mut response = serializer.serialize_struct("Response", 4)?;
+        response.serialize_field("code", &(self.code as i32))?;
+        response.serialize_field("id", &self.id)?;
+        response.serialize_field("attribute", self.attribute.as_str())?;
+        response.serialize_field("value", self.value.as_str())?;
+        response.end()
+    }
+}
+
+/// The status of a `dual-iir` IIR stage, latched once per telemetry period
+/// (`broadcast::PERIOD_MS`). Repeated reads within a period return the same
+/// status.
+#[derive(Serialize, Copy, Clone)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
 pub struct Status {
     pub t: u32,
+    /// Channel 0 input in volts at the front-end input.
     pub x0: f32,
+    /// Channel 0 output in volts.
     pub y0: f32,
+    /// Channel 1 input in volts at the front-end input.
     pub x1: f32,
+    /// Channel 1 output in volts.
     pub y1: f32,
+    /// Extrema of the channel 0 input in volts at the front-end input over
+    /// the telemetry period. Zero if there were no samples.
+    pub x0_min: f32,
+    pub x0_max: f32,
+    /// Extrema of the channel 0 IIR cascade output in volts over the
+    /// telemetry period.
+    pub y0_min: f32,
+    pub y0_max: f32,
+    /// Extrema of the channel 1 input, see `x0_min`.
+    pub x1_min: f32,
+    pub x1_max: f32,
+    /// Extrema of the channel 1 output, see `y0_min`.
+    pub y1_min: f32,
+    pub y1_max: f32,
+    /// Per channel: the lower output limit was hit during the telemetry period.
+    pub railed_low: [bool; 2],
+    /// Per channel: the upper output limit was hit during the telemetry period.
+    pub railed_high: [bool; 2],
+    /// Per channel: peak input magnitude in volts at the front-end input
+    /// during the telemetry period, with decay.
+    pub peak: [f32; 2],
+    /// Per channel: input RMS in volts at the front-end input over the last
+    /// complete window.
+    pub rms: [f32; 2],
+    /// Sample stream frames dropped since boot (wrapping), see `streaming`.
+    pub stream_dropped: u32,
 }
 
-pub fn json_reply<T: Serialize>(socket: &mut net::socket::TcpSocket, msg: &T) {
-    let mut u: String<U512> = to_string(msg).unwrap();
-    u.push('\n').unwrap();
-    socket.write_str(&u).unwrap();
+#[derive(Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct LockinStatus {
+    pub t: u32,
+    /// Reference frequency in Hz.
+    pub frequency: f32,
+    /// Whether the reference PLL is in holdover.
+    pub holdover: bool,
+    /// Current gain of the input AGC.
+    pub agc_gain: f32,
+    /// Extended (unwrapped) demodulated phase, `1 << 32` per turn.
+    pub phase: i64,
+    /// The lockin output is below the phase meter squelch and the phase is
+    /// held.
+    pub phase_squelched: bool,
 }
 
-pub struct Server {
-    data: Vec<u8, U256>,
-    discard: bool,
+/// Decimated lockin IQ samples drained from the telemetry buffer, see
+/// `dsp::decimate`.
+#[derive(Serialize)]
+pub struct IqTelemetry {
+    /// The oldest buffered samples, removed by the read.
+    pub iq: Vec<[i32; 2], U32>,
+    /// Number of samples still buffered.
+    pub pending: u32,
+    /// Samples lost to buffer overruns since boot (wrapping).
+    pub overruns: u32,
 }
 
-impl Server {
-    /// Construct a new server object for managing requests.
-    pub fn new() -> Self {
-        Self {
-            data: Vec::new(),
-            discard: false,
-        }
-    }
+/// Lockin reference frequency counter result of the last complete gate.
+#[derive(Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct CounterStatus {
+    /// Reference frequency in Hz. Zero if not `valid`.
+    pub frequency: f32,
+    /// Number of reference edges in the gate.
+    pub edges: u32,
+    /// At least two edges were timestamped in the gate.
+    pub valid: bool,
+    /// Gate time in seconds.
+    pub gate: f32,
+}
 
-    /// Poll the server for potential data updates.
+/// Lockin reference period statistics over the last complete window of
+/// edges, see `dsp::jitter`.
+#[derive(Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct JitterStatus {
+    /// Number of reference periods in the window. Zero before the first
+    /// window is complete.
+    pub count: u32,
+    /// Mean reference period in seconds.
+    pub period: f32,
+    /// Standard deviation of the reference period in seconds.
+    pub std: f32,
+    /// Peak-to-peak reference period variation in seconds.
+    pub peak_to_peak: f32,
+}
+
+/// Coherent averages of the lockin input over the reference period, see `dsp::coherent`.
+#[derive(Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AverageStatus {
+    /// Averaging is running.
+    pub enable: bool,
+    /// Number of samples accumulated over all bins.
+    pub samples: u32,
+    /// Average input per reference phase bin (ADC full scale is `1 << 31`).
+    pub bins: [i32; 16],
+}
+
+/// Total harmonic distortion of an ADC input with respect to the signal generator tone of
+/// the channel, see `dsp::thd`.
+#[derive(Serialize)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct ThdStatus {
+    /// Channel of the last measurement.
+    pub channel: u8,
+    /// A measurement is running.
+    pub busy: bool,
+    /// A measurement has completed. The results are zero otherwise.
+    pub valid: bool,
+    /// Fundamental amplitude relative to ADC full scale in dB.
+    pub fundamental_dbfs: f32,
+    /// Total power of the harmonics 2 to 5 relative to the fundamental in dB.
+    pub thd_db: f32,
+    /// Amplitudes of the harmonics 2 to 5 relative to the fundamental in dB.
+    pub per_harmonic_db: [f32; 4],
+}
+
+/// A byte stream, the transport of a control connection.
+///
+/// Implemented by the smoltcp TCP socket, and in memory by the tests.
+pub trait ByteStream {
+    /// Received data is available.
+    fn can_recv(&self) -> bool;
+
+    /// Receive data.
     ///
     /// Args:
-    /// * `socket` - The socket to check contents from.
-    /// * `f` - A closure that can be called if a request has been received on the server.
-    pub fn poll<F>(&mut self, socket: &mut net::socket::TcpSocket, mut f: F)
+    /// * `f` - A closure taking a prefix of the available data. It returns the length of the
+    ///   prefix taken and a result.
+    ///
+    /// Returns:
+    /// The result of the closure, or an error if no data is available.
+    fn recv<R, F>(&mut self, f: F) -> Result<R, ()>
     where
-        F: FnMut(&Request) -> Response,
+        F: FnOnce(&[u8]) -> (usize, R);
+
+    /// Send data.
+    ///
+    /// Returns:
+    /// The number of bytes sent, limited by the free space of the send buffer.
+    fn send(&mut self, data: &[u8]) -> usize;
+}
+
+impl<'a> ByteStream for net::socket::TcpSocket<'a> {
+    fn can_recv(&self) -> bool {
+        net::socket::TcpSocket::can_recv(self)
+    }
+
+    fn recv<R, F>(&mut self, f: F) -> Result<R, ()>
+    where
+        F: FnOnce(&[u8]) -> (usize, R),
     {
-        while socket.can_recv() {
-            let found = socket
-                .recv(|buf| {
-                    let (len, found) =
-                        match buf.iter().position(|&c| c as char == '\n') {
-                            Some(end) => (end + 1, true),
-                            None => (buf.len(), false),
-                        };
-                    if self.data.len() + len >= self.data.capacity() {
-                        self.discard = true;
-                        self.data.clear();
-                    } else if !self.discard && len > 0 {
-                        self.data.extend_from_slice(&buf[..len]).unwrap();
-                    }
-                    (len, found)
-                })
-                .unwrap();
+        net::socket::TcpSocket::recv(self, |buf| f(buf)).map_err(|_| ())
+    }
 
-            if found {
-                if self.discard {
-                    self.discard = false;
-                    json_reply(
-                        socket,
-                        &Response::custom(520, "command buffer overflow"),
-                    );
-                } else {
-                    let r = from_slice::<Request>(
-                        &self.data[..self.data.len() - 1],
-                    );
-                    match r {
-                        Ok((mut res, _)) => {
-                            // Note that serde_json_core doesn't escape quotations within a string.
-                            // To account for this, we manually translate all single quotes to
-                            // double quotes. This occurs because we doubly-serialize this field in
-                            // some cases.
-                            res.restore_value();
-                            let response = f(&res);
-                            json_reply(socket, &response);
-                        }
-                        Err(err) => {
-                            warn!("parse error {:?}", err);
-                            json_reply(
-                                socket,
-                                &Response::custom(550, "parse error"),
-                            );
-                        }
+    fn send(&mut self, data: &[u8]) -> usize {
+        // The socket may be closing.
+        net::socket::TcpSocket::send_slice(self, data).unwrap_or(0)
+    }
+}
+
+/// Serialize a reply line.
+///
+/// Args:
+/// * `out` - The buffer to append the line to.
+/// * `response` - The reply.
+pub fn json_reply(
+    out: &mut impl Write,
+    response: &Response,
+) -> core::fmt::Result {
+    response.write_json(out)?;
+    out.write_char('\n')
+}
+
+/// Serialize the reply line to a batch, the array of the replies, see `json_reply()`.
+pub fn json_reply_batch(
+    out: &mut impl Write,
+    responses: &[Response],
+) -> core::fmt::Result {
+    out.write_char('[')?;
+    for (i, response) in responses.iter().enumerate() {
+        if i > 0 {
+            out.write_char(',')?;
+        }
+        response.write_json(out)?;
+    }
+    out.write_str("]\n")
+}
+
+/// The encoding of the requests and replies of a control connection.
+///
+/// The protocol of a connection is selected by its first byte: a JSON line does not begin with
+/// a control character, a CBOR frame begins with the high byte of its length.
+#[derive(Copy, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Protocol {
+    /// Newline-delimited JSON. A batch is a JSON array of requests.
+    Json,
+    /// CBOR frames, each prefixed by its length as a big endian `u16`. Requests and replies are
+    /// maps with the fields of the JSON documents. A batch is an array of requests. The
+    /// attribute values are serialized JSON, embedded as text strings without escapes.
+    Cbor,
+}
+
+// Size of the length prefix of a CBOR frame.
+const FRAME_HEADER: usize = 2;
+
+impl Protocol {
+    // Select the protocol of a connection by its first byte.
+    fn detect(first: u8) -> Self {
+        if first < b'\t' {
+            Protocol::Cbor
+        } else {
+            Protocol::Json
+        }
+    }
+
+    // The request of a complete frame, without the framing.
+    //
+    // The newline and a trailing carriage return of a JSON line are stripped.
+    fn payload(self, frame: &[u8]) -> &[u8] {
+        match self {
+            Protocol::Json => {
+                let line = &frame[..frame.len() - 1];
+                match line.split_last() {
+                    Some((b'\r', head)) => head,
+                    _ => line,
+                }
+            }
+            Protocol::Cbor => &frame[FRAME_HEADER..],
+        }
+    }
+
+    // Blank requests are not replied to.
+    fn is_blank(self, payload: &[u8]) -> bool {
+        match self {
+            Protocol::Json => payload.iter().all(|c| c.is_ascii_whitespace()),
+            Protocol::Cbor => payload.is_empty(),
+        }
+    }
+
+    fn is_batch(self, payload: &[u8]) -> bool {
+        match self {
+            Protocol::Json => {
+                payload.iter().find(|c| !c.is_ascii_whitespace()) == Some(&b'[')
+            }
+            // An array, CBOR major type 4.
+            Protocol::Cbor => payload.first().map_or(false, |c| c >> 5 == 4),
+        }
+    }
+
+    // Number of requests of a batch, without validating it.
+    fn batch_len(self, payload: &[u8]) -> usize {
+        match self {
+            Protocol::Json => array_len(payload),
+            Protocol::Cbor => cbor_array_len(payload),
+        }
+    }
+
+    fn decode<'a, T: Deserialize<'a>>(
+        self,
+        payload: &'a [u8],
+    ) -> Result<T, ()> {
+        match self {
+            Protocol::Json => from_slice(payload)
+                .map(|(value, _)| value)
+                .map_err(|err| warn!("parse error {:?}", err)),
+            Protocol::Cbor => {
+                serde_cbor::de::from_slice_with_scratch(payload, &mut [0; 0])
+                    .map_err(|err| warn!("parse error {:?}", err))
+            }
+        }
+    }
+
+    // Decode the value of a request.
+    fn restore_value(self, req: &mut Request) -> Result<(), &'static str> {
+        match self {
+            // Note that serde_json_core neither escapes nor unescapes strings.
+            // The request value is a doubly-serialized JSON value: it is unescaped here. The
+            // reply value is embedded verbatim.
+            Protocol::Json => req.restore_value(),
+            Protocol::Cbor => Ok(()),
+        }
+    }
+
+    // Serialize a reply frame.
+    fn encode(
+        self,
+        out: &mut Pending,
+        response: &Response,
+    ) -> core::fmt::Result {
+        match self {
+            Protocol::Json => json_reply(out, response),
+            Protocol::Cbor => cbor_frame(out, response),
+        }
+    }
+
+    // Serialize the reply frame to a batch, the array of the replies.
+    fn encode_batch(
+        self,
+        out: &mut Pending,
+        responses: &[Response],
+    ) -> core::fmt::Result {
+        match self {
+            Protocol::Json => json_reply_batch(out, responses),
+            Protocol::Cbor => cbor_frame(out, responses),
+        }
+    }
+}
+
+// Serialize a CBOR frame, the length prefix and the item.
+fn cbor_frame<T: Serialize + ?Sized>(
+    out: &mut Pending,
+    value: &T,
+) -> core::fmt::Result {
+    let start = out.0.len();
+    if start + FRAME_HEADER > out.0.capacity() {
+        return Err(core::fmt::Error);
+    }
+    // Note(unwrap): The capacity is not exceeded.
+    out.0.resize(out.0.capacity(), 0).unwrap();
+    let mut serializer = serde_cbor::Serializer::new(SliceWrite::new(
+        &mut out.0[start + FRAME_HEADER..],
+    ));
+    let result = value.serialize(&mut serializer);
+    let len = serializer.into_inner().bytes_written();
+    if result.is_err() {
+        out.0.truncate(start);
+        return Err(core::fmt::Error);
+    }
+    out.0[start..start + FRAME_HEADER]
+        .copy_from_slice(&(len as u16).to_be_bytes());
+    out.0.truncate(start + FRAME_HEADER + len);
+    Ok(())
+}
+
+// Number of elements of a CBOR array, without validating it. Indefinite and long arrays count
+// as `usize::MAX`.
+fn cbor_array_len(cbor: &[u8]) -> usize {
+    match (cbor.first().map(|c| c & 0x1f), cbor.get(1)) {
+        (Some(len @ 0..=23), _) => len as usize,
+        (Some(24), Some(&len)) => len as usize,
+        _ => usize::MAX,
+    }
+}
+
+// Length of a CBOR frame from its prefix, without the prefix.
+fn frame_len(data: &[u8]) -> usize {
+    u16::from_be_bytes([data[0], data[1]]) as usize
+}
+
+// Number of elements of a JSON array, without validating it.
+fn array_len(json: &[u8]) -> usize {
+    let (mut depth, mut string, mut escaped) = (0i32, false, false);
+    let (mut commas, mut empty) = (0, true);
+    for &c in json {
+        if string {
+            match c {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            b'[' | b'{' => depth += 1,
+            b']' | b'}' => depth -= 1,
+            b',' if depth == 1 => commas += 1,
+            b'"' => string = true,
+            _ => {}
+        }
+        if depth > 1 || (depth == 1 && c != b'[' && !c.is_ascii_whitespace()) {
+            empty = false;
+        }
+    }
+    if empty {
+        0
+    } else {
+        commas + 1
+    }
+}
+
+/// Maximum number of simultaneous control connections, see `Server`.
+pub const MAX_CLIENTS: usize = 3;
+
+/// Maximum number of subscriptions of a connection, see `AccessRequest::Subscribe`.
+pub const MAX_SUBSCRIPTIONS: usize = 8;
+
+// Attributes subscribed at a time, the bits of a subscription mask.
+const MAX_TOPICS: usize = MAX_CLIENTS * MAX_SUBSCRIPTIONS;
+
+// Keep-alive interval and timeout of the control connections. The connection to a peer that
+// vanished is aborted after the timeout and its slot is free for a new connection.
+const KEEP_ALIVE_MS: u64 = 10_000;
+const TIMEOUT_MS: u64 = 30_000;
+
+/// The control protocol server.
+///
+/// Up to `MAX_CLIENTS` connections are served on the same port. Each has its own socket,
+/// `Protocol` and request frame buffer. Their requests are handled in turn by the same closure.
+/// Subscriptions are handled by the server itself, see `notify()`.
+pub struct Server {
+    port: u16,
+    connections: [Connection; MAX_CLIENTS],
+    subscriptions: Subscriptions,
+}
+
+// A control connection, the buffer of the request frame being received and the replies not
+// yet sent.
+#[derive(Default)]
+struct Connection {
+    // Selected by the first byte received.
+    protocol: Option<Protocol>,
+    // Large enough for a batch.
+    data: Vec<u8, U1024>,
+    discard: bool,
+    // Bytes of a discarded CBOR frame still to be received.
+    skip: usize,
+    pending: Pending,
+}
+
+// Serialized reply frames, large enough for the reply to a batch.
+#[derive(Default)]
+struct Pending(Vec<u8, U4096>);
+
+impl Write for Pending {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.0
+            .extend_from_slice(s.as_bytes())
+            .map_err(|_| core::fmt::Error)
+    }
+}
+
+// The reply to a request line.
+enum Reply {
+    Single(Response),
+    Batch(Vec<Response, U4>),
+}
+
+impl Connection {
+    // Take received data up to the end of the first frame.
+    //
+    // Returns the number of bytes taken and whether the frame is complete.
+    fn receive(&mut self, buf: &[u8]) -> (usize, bool) {
+        if self.protocol.is_none() {
+            match buf.first() {
+                Some(&first) => self.protocol = Some(Protocol::detect(first)),
+                None => return (0, false),
+            }
+        }
+        match self.protocol {
+            Some(Protocol::Cbor) => self.receive_frame(buf),
+            _ => self.receive_line(buf),
+        }
+    }
+
+    // Take received data up to and including the first newline.
+    fn receive_line(&mut self, buf: &[u8]) -> (usize, bool) {
+        let (len, found) = match buf.iter().position(|&c| c as char == '\n') {
+            Some(end) => (end + 1, true),
+            None => (buf.len(), false),
+        };
+        if self.data.len() + len > self.data.capacity() {
+            self.discard = true;
+            self.data.clear();
+        } else if !self.discard && len > 0 {
+            self.data.extend_from_slice(&buf[..len]).unwrap();
+        }
+        (len, found)
+    }
+
+    // Take received data up to the end of the first CBOR frame.
+    //
+    // A frame exceeding the buffer is skipped, the next frame follows its announced length.
+    fn receive_frame(&mut self, buf: &[u8]) -> (usize, bool) {
+        if self.discard {
+            let len = buf.len().min(self.skip);
+            self.skip -= len;
+            return (len, self.skip == 0);
+        }
+        let header = self.data.len() < FRAME_HEADER;
+        let missing = if header {
+            FRAME_HEADER - self.data.len()
+        } else {
+            FRAME_HEADER + frame_len(&self.data) - self.data.len()
+        };
+        let len = buf.len().min(missing);
+        // Note(unwrap): A frame exceeding the buffer is discarded once its length is known.
+        self.data.extend_from_slice(&buf[..len]).unwrap();
+        if !header {
+            (len, len == missing)
+        } else if self.data.len() < FRAME_HEADER {
+            (len, false)
+        } else if FRAME_HEADER + frame_len(&self.data) > self.data.capacity() {
+            self.discard = true;
+            self.skip = frame_len(&self.data);
+            self.data.clear();
+            (len, false)
+        } else {
+            (len, frame_len(&self.data) == 0)
+        }
+    }
+
+    // Handle the complete frame and clear the buffer.
+    //
+    // Blank requests are not replied to.
+    fn reply<F>(&mut self, f: &mut F) -> Option<Reply>
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        let reply = if self.discard {
+            // Only the oversized line is discarded.
+            self.discard = false;
+            Some(Reply::Single(Response::overflow(
+                "",
+                "command buffer overflow",
+            )))
+        } else {
+            let protocol = self.protocol.unwrap_or(Protocol::Json);
+            let payload = protocol.payload(&self.data);
+            if protocol.is_blank(payload) {
+                None
+            } else if protocol.is_batch(payload) {
+                match Server::handle_batch(protocol, payload, f) {
+                    Ok(replies) => Some(Reply::Batch(replies)),
+                    Err(response) => Some(Reply::Single(response)),
+                }
+            } else {
+                Some(Reply::Single(Server::handle(protocol, payload, f)))
+            }
+        };
+        self.data.clear();
+        reply
+    }
+
+    // Queue a reply to be sent.
+    //
+    // A reply exceeding the queue is replaced by an error reply.
+    fn queue(&mut self, reply: &Reply) {
+        let protocol = self.protocol.unwrap_or(Protocol::Json);
+        let len = self.pending.0.len();
+        let result = match reply {
+            Reply::Single(response) => {
+                protocol.encode(&mut self.pending, response)
+            }
+            Reply::Batch(replies) => {
+                protocol.encode_batch(&mut self.pending, replies)
+            }
+        };
+        if result.is_ok() {
+            return;
+        }
+        self.pending.0.truncate(len);
+        let (attribute, id) = match reply {
+            Reply::Single(response) => {
+                (response.attribute.as_str(), response.id)
+            }
+            Reply::Batch(_) => ("", 0),
+        };
+        let response =
+            Response::overflow(attribute, "Reply too long").with_id(id);
+        // Note(unwrap): The queue is empty before a reply is queued, see `Connection::poll()`.
+        protocol.encode(&mut self.pending, &response).unwrap();
+    }
+
+    // Send as much of the pending replies as the socket takes.
+    fn flush(&mut self, socket: &mut impl ByteStream) {
+        let pending = &mut self.pending.0;
+        let (sent, len) = (socket.send(pending), pending.len());
+        pending.copy_within(sent..len, 0);
+        pending.truncate(len - sent);
+    }
+
+    // Queue a notification to be sent, see `Server::notify()`.
+    //
+    // A notification exceeding the queue is dropped.
+    fn push(&mut self, response: &Response) {
+        let protocol = self.protocol.unwrap_or(Protocol::Json);
+        let len = self.pending.0.len();
+        if protocol.encode(&mut self.pending, response).is_err() {
+            self.pending.0.truncate(len);
+            warn!("Notification dropped: {}", response.attribute.as_str());
+        }
+    }
+
+    fn poll<F>(&mut self, socket: &mut impl ByteStream, f: &mut F)
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        self.flush(socket);
+        // Requests are handled once the previous replies are sent: a client not reading its
+        // replies stalls its own requests only.
+        while self.pending.0.is_empty() && socket.can_recv() {
+            // Note(unwrap): Data is available.
+            let found = socket.recv(|buf| self.receive(buf)).unwrap();
+            // Every complete frame is handled in turn.
+            if found {
+                if let Some(reply) = self.reply(f) {
+                    self.queue(&reply);
+                    self.flush(socket);
+                }
+            }
+        }
+    }
+}
+
+// A subscribed attribute.
+#[derive(Default)]
+struct Topic {
+    attribute: String<U64>,
+    // The encoded value last notified or read by a subscription.
+    value: String<ValueSize>,
+    // Per connection: the identifier of the subscription request.
+    ids: [u32; MAX_CLIENTS],
+    // Per connection: the value was read by a later subscription of another connection and has
+    // not been notified to this connection.
+    stale: [bool; MAX_CLIENTS],
+}
+
+// The subscriptions of the connections.
+//
+// Each subscribed attribute occupies a topic. A connection subscribes to the topics of the bits
+// set in its mask. A topic is free when no connection subscribes to it.
+#[derive(Default)]
+struct Subscriptions {
+    // The attributes that can be subscribed to, see `Server::new()`.
+    notified: &'static [&'static str],
+    topics: [Topic; MAX_TOPICS],
+    masks: [u32; MAX_CLIENTS],
+}
+
+impl Subscriptions {
+    // The topics subscribed to by any connection.
+    fn used(&self) -> u32 {
+        self.masks.iter().fold(0, |used, mask| used | mask)
+    }
+
+    // Find the topic of an attribute.
+    fn find(&self, attribute: &str) -> Option<usize> {
+        let used = self.used();
+        (0..MAX_TOPICS).find(|&i| {
+            used & (1 << i) != 0
+                && self.topics[i].attribute.as_str() == attribute
+        })
+    }
+
+    // Handle the subscription requests of a connection. Other requests are passed on.
+    fn handle<F>(&mut self, client: usize, req: &Request, f: &mut F) -> Response
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        match req.req {
+            AccessRequest::Subscribe => self.subscribe(client, req, f),
+            AccessRequest::Unsubscribe => match self.find(req.attribute) {
+                Some(i) if self.masks[client] & (1 << i) != 0 => {
+                    self.masks[client] &= !(1 << i);
+                    Response::success(req.attribute, "null")
+                }
+                _ => Response::new_message(
+                    ResponseCode::UnknownAttribute,
+                    req.attribute,
+                    "Not subscribed",
+                ),
+            },
+            _ => f(req),
+        }
+    }
+
+    // Subscribe a connection to an attribute. The reply is the current value.
+    fn subscribe<F>(
+        &mut self,
+        client: usize,
+        req: &Request,
+        f: &mut F,
+    ) -> Response
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        let topic = self.find(req.attribute);
+        let mask = self.masks[client];
+        if topic.map_or(true, |i| mask & (1 << i) == 0)
+            && mask.count_ones() as usize >= MAX_SUBSCRIPTIONS
+        {
+            return Response::overflow(req.attribute, "Too many subscriptions");
+        }
+        if req.attribute.len() > self.topics[0].attribute.capacity() {
+            return Response::overflow(req.attribute, "Attribute too long");
+        }
+        if !self.notified.contains(&req.attribute) {
+            return Response::validation_failed(
+                req.attribute,
+                "Attribute is not notified",
+            );
+        }
+        let response = f(&Request {
+            req: AccessRequest::Read,
+            attribute: req.attribute,
+            value: String::new(),
+            id: req.id,
+            batch: None,
+        });
+        // Attributes that can not be read can not be subscribed to.
+        if response.code != ResponseCode::Ok {
+            return response;
+        }
+        let i = match topic {
+            Some(i) => i,
+            None => {
+                let used = self.used();
+                // Note(unwrap): Each connection subscribes to at most `MAX_SUBSCRIPTIONS` topics.
+                let i =
+                    (0..MAX_TOPICS).find(|&i| used & (1 << i) == 0).unwrap();
+                self.topics[i] = Topic {
+                    attribute: String::from(req.attribute),
+                    value: response.value.clone(),
+                    ids: [0; MAX_CLIENTS],
+                    stale: [false; MAX_CLIENTS],
+                };
+                i
+            }
+        };
+        let topic = &mut self.topics[i];
+        if topic.value != response.value {
+            // The other subscribers are notified of the value read on the next change or the
+            // next `Server::notify()`.
+            for (other, mask) in self.masks.iter().enumerate() {
+                topic.stale[other] |= mask & (1 << i) != 0;
+            }
+            topic.value = response.value.clone();
+        }
+        self.masks[client] |= 1 << i;
+        topic.ids[client] = req.id;
+        topic.stale[client] = false;
+        response
+    }
+}
+
+impl Server {
+    /// Construct a new server object for managing requests.
+    ///
+    /// Args:
+    /// * `port` - The TCP port to listen on.
+    /// * `notified` - The attributes the application passes to `notify()`. Only these can be
+    ///   subscribed to.
+    pub fn new(port: u16, notified: &'static [&'static str]) -> Self {
+        Self {
+            port,
+            connections: Default::default(),
+            subscriptions: Subscriptions {
+                notified,
+                ..Default::default()
+            },
+        }
+    }
+
+    // Drop the state of a connection and its subscriptions.
+    fn reset(&mut self, client: usize) {
+        self.connections[client] = Connection::default();
+        self.subscriptions.masks[client] = 0;
+    }
+
+    // Serve a connection: send its pending replies and handle its requests.
+    fn serve<F>(
+        &mut self,
+        client: usize,
+        socket: &mut impl ByteStream,
+        f: &mut F,
+    ) where
+        F: FnMut(&Request) -> Response,
+    {
+        let subscriptions = &mut self.subscriptions;
+        self.connections[client].poll(socket, &mut |req: &Request| {
+            subscriptions.handle(client, req, f)
+        });
+    }
+
+    /// Notify the subscribers of an attribute of its value.
+    ///
+    /// The application calls this for each of the attributes given to `new()` when it changes, or
+    /// periodically. The value is pushed to each connection subscribed to the attribute if it
+    /// differs from the value the connection last received, as a reply with the `id` of the
+    /// subscription request. The notifications are sent by the next `poll()`. A notification not
+    /// fitting the queue of a connection is dropped.
+    ///
+    /// Args:
+    /// * `attribute` - The attribute, as subscribed to.
+    /// * `value` - The current value of the attribute, up to `ValueSize` encoded.
+    pub fn notify<T: Serialize + ?Sized>(
+        &mut self,
+        attribute: &str,
+        value: &T,
+    ) {
+        let i = match self.subscriptions.find(attribute) {
+            Some(i) => i,
+            None => return,
+        };
+        let value: String<ValueSize> = match serde_json_core::to_string(value) {
+            Ok(value) => value,
+            Err(_) => {
+                warn!("Notification too long: {}", attribute);
+                return;
+            }
+        };
+        let topic = &mut self.subscriptions.topics[i];
+        let changed = value != topic.value;
+        if !changed && !topic.stale.iter().any(|&stale| stale) {
+            return;
+        }
+        let masks = &self.subscriptions.masks;
+        for (client, connection) in self.connections.iter_mut().enumerate() {
+            if masks[client] & (1 << i) != 0 && (changed || topic.stale[client])
+            {
+                connection.push(
+                    &Response::success(attribute, &value)
+                        .with_id(topic.ids[client]),
+                );
+            }
+            topic.stale[client] = false;
+        }
+        topic.value = value;
+    }
+
+    // Parse and handle a request, without the framing.
+    fn handle<F>(protocol: Protocol, payload: &[u8], f: &mut F) -> Response
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        match protocol.decode::<Request>(payload) {
+            Ok(mut req) => {
+                let response = match protocol.restore_value(&mut req) {
+                    Ok(()) => f(&req),
+                    Err(msg) => Response::decode_error(req.attribute, msg),
+                };
+                response.with_id(req.id)
+            }
+            Err(()) => {
+                let id =
+                    protocol.decode::<RequestId>(payload).map_or(0, |r| r.id);
+                Response::parse_error("parse error").with_id(id)
+            }
+        }
+    }
+
+    // Parse and handle a batch, an array of requests, see `Batch`.
+    //
+    // Returns the replies in order, or a single reply if the batch is rejected as a whole.
+    fn handle_batch<F>(
+        protocol: Protocol,
+        payload: &[u8],
+        f: &mut F,
+    ) -> Result<Vec<Response, U4>, Response>
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        if protocol.batch_len(payload) > MAX_BATCH {
+            return Err(Response::payload_too_large("", "batch too large"));
+        }
+        let mut requests = match protocol.decode::<Vec<Request, U4>>(payload) {
+            Ok(requests) => requests,
+            Err(()) => return Err(Response::parse_error("parse error")),
+        };
+
+        let notify = |f: &mut F, batch| {
+            f(&Request {
+                req: AccessRequest::Write,
+                attribute: "",
+                value: String::new(),
+                id: 0,
+                batch: Some(batch),
+            })
+        };
+        let begin = notify(f, Batch::Begin);
+        if begin.code != ResponseCode::Ok {
+            return Err(begin);
+        }
+
+        let mut replies: Vec<Response, U4> = Vec::new();
+        let mut failed = false;
+        for req in requests.iter_mut() {
+            // Requests after a failure are not handled.
+            let response = if failed {
+                Response::validation_failed(req.attribute, "Batch discarded")
+            } else {
+                match protocol.restore_value(req) {
+                    Ok(()) => f(req),
+                    Err(msg) => Response::decode_error(req.attribute, msg),
+                }
+            };
+            failed |= response.code != ResponseCode::Ok;
+            // Note(unwrap): There are at most as many replies as requests.
+            replies.push(response.with_id(req.id)).ok().unwrap();
+        }
+
+        let end = notify(
+            f,
+            if failed {
+                Batch::Discard
+            } else {
+                Batch::Commit
+            },
+        );
+        if failed || end.code != ResponseCode::Ok {
+            let (code, message) = if failed {
+                (ResponseCode::ValidationFailed, "Batch discarded")
+            } else {
+                (end.code, "Batch commit failed")
+            };
+            for (reply, req) in replies.iter_mut().zip(requests.iter()) {
+                if reply.code == ResponseCode::Ok {
+                    *reply =
+                        Response::new_message(code, req.attribute, message)
+                            .with_id(req.id);
+                }
+            }
+        }
+        Ok(replies)
+    }
+
+    /// Poll the server for potential data updates.
+    ///
+    /// Replies the socket can not take yet are sent before further requests
+    /// of the connection are handled.
+    ///
+    /// Args:
+    /// * `sockets` - The socket set of the connection sockets.
+    /// * `handles` - The sockets of the connections, at most `MAX_CLIENTS`.
+    /// * `f` - A closure that can be called if a request has been received on the server.
+    pub fn poll<F>(
+        &mut self,
+        sockets: &mut net::socket::SocketSet,
+        handles: &[net::socket::SocketHandle],
+        mut f: F,
+    ) where
+        F: FnMut(&Request) -> Response,
+    {
+        for (client, &handle) in handles.iter().enumerate().take(MAX_CLIENTS) {
+            let socket = &mut *sockets.get::<net::socket::TcpSocket>(handle);
+            if socket.state() == net::socket::TcpState::CloseWait {
+                socket.close();
+            } else if !(socket.is_open() || socket.is_listening()) {
+                // Drop a partial line and the subscriptions of the previous connection.
+                self.reset(client);
+                socket.set_keep_alive(Some(net::time::Duration::from_millis(
+                    KEEP_ALIVE_MS,
+                )));
+                socket.set_timeout(Some(net::time::Duration::from_millis(
+                    TIMEOUT_MS,
+                )));
+                socket
+                    .listen(self.port)
+                    .unwrap_or_else(|e| warn!("TCP listen error: {:?}", e));
+            } else {
+                self.serve(client, socket, &mut f);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server;
+
+    fn read(attribute: &str, value: &str) -> Response {
+        let req = Request {
+            req: AccessRequest::Read,
+            attribute,
+            value: String::from(value),
+            id: 0,
+            batch: None,
+        };
+        route(&req)
+    }
+
+    fn route(req: &Request) -> Response {
+        crate::route_request!(req,
+            readable_attributes: [
+                "test/gain": (|| Ok::<u32, ()>(1)),
+                "test/status": (|| Ok::<bool, ()>(true)),
+                "test/iir": (|| Ok::<_, ()>(iir::IIR::new(0.5, -2., 2.)))
+            ],
+            modifiable_attributes: [
+                "test/gain": u32, (|_| Ok::<(), &str>(())),
+                "test/reset": bool, (|_| Ok::<(), &str>(()))
+            ],
+            indexed_readable_attributes: [
+                "test/channel" [2]: (|index| Ok::<usize, ()>(10 * index))
+            ],
+            indexed_modifiable_attributes: [
+                "test/channel" [2]: u32, (|_, gain| {
+                    if gain > 10 {
+                        return Err("gain too large");
                     }
+                    Ok::<(), &str>(())
+                })
+            ]
+        )
+    }
+
+    fn write(attribute: &str, value: &str) -> Response {
+        let req = Request {
+            req: AccessRequest::Write,
+            attribute,
+            value: String::from(value),
+            id: 0,
+            batch: None,
+        };
+        route(&req)
+    }
+
+    #[test]
+    fn list() {
+        let res = read(LIST_ATTRIBUTE, "");
+        assert_eq!(res.code, ResponseCode::Ok);
+        assert_eq!(res.attribute, LIST_ATTRIBUTE);
+        assert_eq!(
+            res.value,
+            "{\"page\":0,\"pages\":1,\"attributes\":[[\"test/gain\",\"rw\"],\
+             [\"test/status\",\"r\"],[\"test/iir\",\"r\"],\
+             [\"test/channel/<index>\",\"rw\"],[\"test/reset\",\"w\"]]}"
+        );
+        assert_eq!(read(LIST_ATTRIBUTE, "0").value, res.value);
+        assert_eq!(
+            read(LIST_ATTRIBUTE, "1").code,
+            ResponseCode::ValidationFailed
+        );
+        assert_eq!(
+            read(LIST_ATTRIBUTE, "x").code,
+            ResponseCode::ValidationFailed
+        );
+        // The other attributes are unaffected
+        assert_eq!(read("test/gain", "").value, "1");
+        assert_eq!(read("test/list", "").code, ResponseCode::UnknownAttribute);
+    }
+
+    #[test]
+    fn indexed() {
+        assert_eq!(read("test/channel/0", "").value, "0");
+        let res = read("test/channel/1", "");
+        assert_eq!((res.code, res.value.as_str()), (ResponseCode::Ok, "10"));
+        assert_eq!(res.attribute, "test/channel/1");
+        // Out of range
+        for &attribute in
+            ["test/channel/2", "test/channel/99999999999999999999"].iter()
+        {
+            let res = read(attribute, "");
+            assert_eq!(res.code, ResponseCode::UnknownAttribute);
+            assert_eq!(res.value, "\"Index out of range\"");
+        }
+        // Not an index
+        for &attribute in
+            ["test/channel/x", "test/channel/", "test/channel/-1"].iter()
+        {
+            let res = read(attribute, "");
+            assert_eq!(res.code, ResponseCode::UnknownAttribute);
+            assert_eq!(res.value, "\"Invalid index\"", "{}", attribute);
+        }
+        for &attribute in ["test/channel", "test/channel/1/x"].iter() {
+            let res = read(attribute, "");
+            assert_eq!(res.code, ResponseCode::UnknownAttribute);
+            assert_eq!(res.value, "\"Unknown attribute\"", "{}", attribute);
+        }
+
+        assert_eq!(write("test/channel/1", "5").code, ResponseCode::Ok);
+        assert_eq!(
+            write("test/channel/1", "20").code,
+            ResponseCode::ValidationFailed
+        );
+        assert_eq!(
+            write("test/channel/1", "x").code,
+            ResponseCode::DecodeError
+        );
+        assert_eq!(
+            write("test/channel/2", "5").code,
+            ResponseCode::UnknownAttribute
+        );
+        assert_eq!(
+            write("test/channel/a", "5").code,
+            ResponseCode::UnknownAttribute
+        );
+        // Plain attributes are unaffected
+        assert_eq!(write("test/gain", "5").code, ResponseCode::Ok);
+        assert_eq!(
+            write("test/gain/0", "5").code,
+            ResponseCode::UnknownAttribute
+        );
+    }
+
+    #[test]
+    fn codes() {
+        let mut route = |req: &Request| {
+            crate::route_request!(req,
+                readable_attributes: [
+                    "test/broken": (|| Err::<u32, ()>(())),
+                    "test/pending": (|| Err::<u32, _>((ResponseCode::Busy, "capture running"))),
+                    "test/invalid": (|| Err::<u32, _>("invalid offset")),
+                    "test/large": (|| Ok::<_, ()>([[iir::IIR::new(0.5, -2., 2.); 32]; 2]))
+                ],
+                modifiable_attributes: [
+                    "test/gain": u32, (|_| Err::<(), _>("gain too large")),
+                    "test/capture": bool, (|_| Err::<(), _>((ResponseCode::Busy, "capture running")))
+                ],
+                batch: (|_| Err::<(), _>((ResponseCode::Internal, "no staging")))
+            )
+        };
+        let code = |req, attribute, value: &str, batch| {
+            route(&Request {
+                req,
+                attribute,
+                value: String::from(value),
+                id: 0,
+                batch,
+            })
+            .code
+        };
+        use AccessRequest::{Read, Write};
+        for &(req, attribute, value, expect) in [
+            (Read, "test/broken", "", ResponseCode::Internal),
+            (Read, "test/pending", "", ResponseCode::Busy),
+            (Read, "test/invalid", "", ResponseCode::ValidationFailed),
+            (Read, "test/large", "", ResponseCode::Overflow),
+            (Read, "test/none", "", ResponseCode::UnknownAttribute),
+            (Read, LIST_ATTRIBUTE, "x", ResponseCode::ValidationFailed),
+            (Write, "test/gain", "x", ResponseCode::DecodeError),
+            (Write, "test/gain", "20", ResponseCode::ValidationFailed),
+            (Write, "test/capture", "true", ResponseCode::Busy),
+            (Write, "test/none", "1", ResponseCode::UnknownAttribute),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                code(req, attribute, value, None),
+                expect,
+                "{}",
+                attribute
+            );
+        }
+        assert_eq!(
+            code(Write, "", "", Some(Batch::Begin)),
+            ResponseCode::Internal
+        );
+        // The message of a getter error is passed on.
+        let res = route(&Request {
+            req: Read,
+            attribute: "test/pending",
+            value: String::new(),
+            id: 0,
+            batch: None,
+        });
+        assert_eq!(res.value, "\"capture running\"");
+        // Not a request, and an invalid value escape
+        let res = Server::handle(Protocol::Json, b"{}", &mut route);
+        assert_eq!(res.code, ResponseCode::ParseError);
+        let res = Server::handle(
+            Protocol::Json,
+            b"{\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"\\x\"}",
+            &mut route,
+        );
+        assert_eq!(res.code, ResponseCode::DecodeError);
+    }
+
+    #[test]
+    fn chunks() {
+        #[derive(Deserialize)]
+        struct Chunk<'a> {
+            more: bool,
+            offset: u32,
+            data: &'a str,
+        }
+
+        let mut large: Vec<iir::IIR, U32> = Vec::new();
+        for i in 0..32 {
+            large.push(iir::IIR::new(i as f32, -2., 2.)).ok().unwrap();
+        }
+        let encoded: String<EncodedSize> =
+            serde_json_core::to_string(&large).unwrap();
+        assert!(encoded.len() > 1024);
+        let route = |req: &Request| {
+            crate::route_request!(req,
+                readable_attributes: [
+                    "test/large": (|| Ok::<_, ()>(&large)),
+                    "test/gain": (|| Ok::<u32, ()>(1))
+                ],
+                modifiable_attributes: [
+                    "test/gain": u32, (|_| Ok::<(), &str>(()))
+                ]
+            )
+        };
+        let read = |attribute, value: &str| {
+            route(&Request {
+                req: AccessRequest::Read,
+                attribute,
+                value: String::from(value),
+                id: 0,
+                batch: None,
+            })
+        };
+
+        let mut reassembled: String<EncodedSize> = String::new();
+        let mut value: String<U256> = String::new();
+        let mut chunks = 0;
+        loop {
+            let res = read("test/large", &value);
+            assert_eq!(res.code, ResponseCode::Ok);
+            let (chunk, _) =
+                serde_json_core::from_str::<Chunk>(&res.value).unwrap();
+            let data = unescape::<ValueSize>(chunk.data).unwrap();
+            reassembled.push_str(&data).unwrap();
+            chunks += 1;
+            if !chunk.more {
+                break;
+            }
+            assert_eq!(chunk.offset as usize, reassembled.len());
+            value.clear();
+            write!(value, "{{\"offset\":{}}}", chunk.offset).unwrap();
+        }
+        assert_eq!(reassembled, encoded);
+        assert!(chunks > encoded.len() / 1024);
+
+        // Small values are not chunked, offsets are checked.
+        assert_eq!(read("test/gain", "").value, "1");
+        assert_eq!(
+            read("test/gain", "{\"offset\":0}").value,
+            "{\"more\":false,\"offset\":1,\"data\":\"1\"}"
+        );
+        assert_eq!(
+            read("test/gain", "{\"offset\":2}").code,
+            ResponseCode::ValidationFailed
+        );
+    }
+
+    #[test]
+    fn pages() {
+        let names: Vec<String<U32>, U64> = (0..60)
+            .map(|i| {
+                let mut name = String::new();
+                write!(name, "stabilizer/attribute/number/{:02}", i).unwrap();
+                name
+            })
+            .collect();
+        let names: Vec<&str, U64> =
+            names.iter().map(|name| name.as_str()).collect();
+        // Read-only, read-write, and write-only
+        let (readable, writable) = (&names[..40], &names[20..]);
+        let mut listed = 0;
+        for &page in ["0", "1", "2"].iter() {
+            let res = Response::list(LIST_ATTRIBUTE, page, readable, writable);
+            assert_eq!(res.code, ResponseCode::Ok);
+            let value = &res.value;
+            assert!(value.contains(",\"pages\":3,"), "{}", value);
+            listed += value.matches("[\"stabilizer/").count();
+        }
+        assert_eq!(listed, names.len());
+        let last = Response::list(LIST_ATTRIBUTE, "2", readable, writable);
+        assert!(last
+            .value
+            .ends_with("[\"stabilizer/attribute/number/59\",\"w\"]]}"));
+        assert_eq!(
+            Response::list(LIST_ATTRIBUTE, "3", readable, writable).code,
+            ResponseCode::ValidationFailed
+        );
+    }
+
+    #[test]
+    fn escaping() {
+        let text = "it's \"quoted\" \\ with\na newline\t\u{1}";
+        let mut escaped: String<U256> = String::new();
+        escape(text, &mut escaped).unwrap();
+        assert_eq!(
+            escaped,
+            "it's \\\"quoted\\\" \\\\ with\\na newline\\t\\u0001"
+        );
+        assert_eq!(unescape::<U256>(&escaped).unwrap(), text);
+        assert_eq!(unescape::<U256>("\\u00e9\\/").unwrap(), "\u{e9}/");
+        for invalid in ["\\", "\\q", "\\u12", "\\ud800"].iter() {
+            assert!(unescape::<U256>(invalid).is_err(), "{}", invalid);
+        }
+
+        // Messages are JSON strings
+        let res = Response::validation_failed("test/label", text);
+        let message = &res.value;
+        assert!(message.starts_with('"') && message.ends_with('"'));
+        assert_eq!(
+            unescape::<U256>(&message[1..message.len() - 1]).unwrap(),
+            text
+        );
+        // The attribute of a reply is escaped.
+        let mut line: String<U256> = String::new();
+        Response::unknown_attribute("test/\"a\"")
+            .write_json(&mut line)
+            .unwrap();
+        assert!(line.starts_with(
+            "{\"code\":404,\"id\":0,\"attribute\":\"test/\\\"a\\\"\","
+        ));
+        let quotes = core::str::from_utf8(&[b'"'; 600]).unwrap();
+        let res = Response::validation_failed("test/label", quotes);
+        assert_eq!(res.code, ResponseCode::Overflow);
+    }
+
+    #[test]
+    fn restore() {
+        let restore = |value: &str| {
+            let mut req = Request {
+                req: AccessRequest::Write,
+                attribute: "test/label",
+                value: String::from(value),
+                id: 0,
+                batch: None,
+            };
+            req.restore_value().map(|_| req.value)
+        };
+        // Escaped
+        assert_eq!(
+            restore(
+                "{\\\"label\\\":\\\"it's \\\\\\\"a\\\\\\\" \\\\\\\\ \\\\n\\\"}"
+            )
+            .unwrap(),
+            "{\"label\":\"it's \\\"a\\\" \\\\ \\n\"}"
+        );
+        // Legacy quote swapped
+        assert_eq!(
+            restore("{'gain': 1, 'label': 'a'}").unwrap(),
+            "{\"gain\": 1, \"label\": \"a\"}"
+        );
+        assert_eq!(restore("1.5").unwrap(), "1.5");
+        assert!(restore("\\x").is_err());
+    }
+
+    #[test]
+    #[cfg(not(feature = "legacy_response"))]
+    fn wire() {
+        #[derive(Deserialize)]
+        struct Reply<'a, T> {
+            code: i32,
+            attribute: &'a str,
+            value: T,
+        }
+
+        let mut json: String<U512> = String::new();
+        read("test/iir", "").write_json(&mut json).unwrap();
+        assert!(json.starts_with(
+            "{\"code\":200,\"id\":0,\"attribute\":\"test/iir\",\"value\":{\"ba\":["
+        ));
+        let (reply, len) =
+            serde_json_core::from_str::<Reply<iir::IIR>>(&json).unwrap();
+        assert_eq!(len, json.len());
+        assert_eq!((reply.code, reply.attribute), (200, "test/iir"));
+        assert_eq!(reply.value.ba.0, [0.5, 0., 0., 0., 0.]);
+        assert_eq!((reply.value.y_min, reply.value.y_max), (-2., 2.));
+
+        // Messages are JSON strings
+        json.clear();
+        read("test/none", "").write_json(&mut json).unwrap();
+        let (reply, _) =
+            serde_json_core::from_str::<Reply<&str>>(&json).unwrap();
+        assert_eq!((reply.code, reply.value), (404, "Unknown attribute"));
+    }
+
+    #[test]
+    #[cfg(feature = "legacy_response")]
+    fn wire_legacy() {
+        let mut json: String<U512> = String::new();
+        Response::success("test/gain", "{\"gain\":1}")
+            .write_json(&mut json)
+            .unwrap();
+        assert_eq!(
+            json,
+            "{\"code\":200,\"id\":0,\"attribute\":\"test/gain\",\"value\":\"{\\\"gain\\\":1}\"}"
+        );
+    }
+
+    #[test]
+    fn id() {
+        let handle = |line: &str| {
+            Server::handle(Protocol::Json, line.as_bytes(), &mut route)
+        };
+        // Absent
+        let res = handle(
+            "{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\"}",
+        );
+        assert_eq!((res.code, res.id), (ResponseCode::Ok, 0));
+        // Present
+        let res = handle(
+            "{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\",\
+             \"id\":7}",
+        );
+        assert_eq!((res.code, res.id), (ResponseCode::Ok, 7));
+        // Errors
+        let res = handle(
+            "{\"req\":\"Read\",\"attribute\":\"test/none\",\"value\":\"\",\
+             \"id\":8}",
+        );
+        assert_eq!((res.code, res.id), (ResponseCode::UnknownAttribute, 8));
+        let res = handle("{\"id\":9}");
+        assert_eq!((res.code, res.id), (ResponseCode::ParseError, 9));
+        let res = handle("{\"req\":\"Read\",\"id\":10");
+        assert_eq!((res.code, res.id), (ResponseCode::ParseError, 0));
+    }
+
+    #[test]
+    fn pipeline() {
+        // The same attribute, replies matched by id
+        let lines = [
+            "{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":3}",
+            "{\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"5\",\"id\":1}",
+            "{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":2}",
+        ];
+        let replies: Vec<Response, U4> = lines
+            .iter()
+            .map(|line| {
+                Server::handle(Protocol::Json, line.as_bytes(), &mut route)
+            })
+            .collect();
+        let reply = |id| {
+            replies
+                .iter()
+                .find(|res| res.id == id)
+                .unwrap()
+                .value
+                .as_str()
+        };
+        assert_eq!(reply(1), "5");
+        assert_eq!(reply(2), "1");
+        assert_eq!(reply(3), "1");
+        assert!(replies.iter().all(|res| res.attribute == "test/gain"));
+    }
+
+    // An application staging the gain writes of a batch. The gain must not exceed 10.
+    #[derive(Default)]
+    struct App {
+        gain: u32,
+        staging: bool,
+        staged: Option<u32>,
+    }
+
+    impl App {
+        fn route(&mut self, req: &Request) -> Response {
+            crate::route_request!(req,
+                readable_attributes: [
+                    "test/gain": (|| Ok::<u32, ()>(self.gain))
+                ],
+                modifiable_attributes: [
+                    "test/gain": u32, (|gain| {
+                        if gain > 10 {
+                            return Err("gain too large");
+                        }
+                        if self.staging {
+                            self.staged = Some(gain);
+                        } else {
+                            self.gain = gain;
+                        }
+                        Ok::<(), &str>(())
+                    })
+                ],
+                batch: (|batch| {
+                    match batch {
+                        Batch::Begin => self.staging = true,
+                        Batch::Commit => {
+                            self.gain = self.staged.take().unwrap_or(self.gain);
+                            self.staging = false;
+                        }
+                        Batch::Discard => {
+                            self.staged = None;
+                            self.staging = false;
+                        }
+                    }
+                    Ok::<(), &str>(())
+                })
+            )
+        }
+
+        fn batch(&mut self, line: &str) -> Result<Vec<Response, U4>, Response> {
+            Server::handle_batch(
+                Protocol::Json,
+                line.as_bytes(),
+                &mut |req: &Request| self.route(req),
+            )
+        }
+    }
+
+    #[test]
+    fn batch() {
+        let mut app = App {
+            gain: 1,
+            ..Default::default()
+        };
+        let replies = app
+            .batch(
+                "[{\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"5\",\"id\":1},\
+                 {\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":2}]",
+            )
+            .unwrap();
+        let replies: Vec<_, U4> = replies
+            .iter()
+            .map(|r| (r.code, r.id, r.value.as_str()))
+            .collect();
+        // Reads see the configuration before the commit
+        assert_eq!(
+            replies[..],
+            [(ResponseCode::Ok, 1, "5"), (ResponseCode::Ok, 2, "1")]
+        );
+        assert_eq!((app.gain, app.staging), (5, false));
+
+        // The second write fails, nothing is applied
+        let replies = app
+            .batch(
+                "[{\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"7\",\"id\":3},\
+                 {\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"20\",\"id\":4},\
+                 {\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"8\",\"id\":5}]",
+            )
+            .unwrap();
+        let replies: Vec<_, U4> = replies
+            .iter()
+            .map(|r| (r.code, r.id, r.value.as_str()))
+            .collect();
+        assert_eq!(
+            replies[..],
+            [
+                (ResponseCode::ValidationFailed, 3, "\"Batch discarded\""),
+                (ResponseCode::ValidationFailed, 4, "\"gain too large\""),
+                (ResponseCode::ValidationFailed, 5, "\"Batch discarded\"")
+            ]
+        );
+        assert_eq!((app.gain, app.staging, app.staged), (5, false, None));
+
+        assert_eq!(app.batch("[]").unwrap().len(), 0);
+        let item =
+            "{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\"}";
+        let mut line: String<U512> = String::from("[");
+        for i in 0..=MAX_BATCH {
+            write!(line, "{}{}", if i > 0 { "," } else { "" }, item).unwrap();
+        }
+        write!(line, "]").unwrap();
+        assert_eq!(
+            app.batch(&line).unwrap_err().code,
+            ResponseCode::PayloadTooLarge
+        );
+        assert_eq!(
+            app.batch("[{\"req\":").unwrap_err().code,
+            ResponseCode::ParseError
+        );
+
+        // Batches are rejected by applications without staging
+        line.clear();
+        write!(line, "[{}]", item).unwrap();
+        let res =
+            Server::handle_batch(Protocol::Json, line.as_bytes(), &mut route);
+        assert_eq!(res.unwrap_err().code, ResponseCode::ValidationFailed);
+    }
+
+    // Feed a segment to a connection. Returns the replies to the complete lines.
+    fn receive(
+        connection: &mut Connection,
+        segment: &[u8],
+    ) -> Vec<Response, U4> {
+        let mut replies = Vec::new();
+        let mut buf = segment;
+        while !buf.is_empty() {
+            let (len, found) = connection.receive(buf);
+            buf = &buf[len..];
+            if found {
+                match connection.reply(&mut route) {
+                    Some(Reply::Single(response)) => {
+                        replies.push(response).ok().unwrap()
+                    }
+                    Some(Reply::Batch(_)) => unreachable!(),
+                    None => {}
                 }
-                self.data.clear();
             }
         }
+        replies
+    }
+
+    #[test]
+    fn clients() {
+        let mut a = Connection::default();
+        let mut b = Connection::default();
+        let line_a =
+            b"{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":1}\n";
+        let line_b =
+            b"{\"req\":\"Write\",\"attribute\":\"test/gain\",\"value\":\"5\",\"id\":2}\n";
+        // Interleaved partial lines
+        assert!(receive(&mut a, &line_a[..20]).is_empty());
+        assert!(receive(&mut b, &line_b[..30]).is_empty());
+        let replies = receive(&mut a, &line_a[20..]);
+        assert_eq!(replies.len(), 1);
+        assert_eq!((replies[0].code, replies[0].id), (ResponseCode::Ok, 1));
+        let replies = receive(&mut b, &line_b[30..]);
+        assert_eq!(replies.len(), 1);
+        assert_eq!(
+            (replies[0].code, replies[0].id, replies[0].value.as_str()),
+            (ResponseCode::Ok, 2, "5")
+        );
+
+        // An overflow of one connection does not affect the other
+        assert!(receive(&mut a, &[b' '; 1100]).is_empty());
+        assert!(receive(&mut b, &line_a[..20]).is_empty());
+        let replies = receive(&mut a, b"\n");
+        assert_eq!(replies[0].code, ResponseCode::Overflow);
+        let replies = receive(&mut b, &line_a[20..]);
+        assert_eq!((replies[0].code, replies[0].id), (ResponseCode::Ok, 1));
+        // Both are ready for the next line
+        let replies = receive(&mut a, line_b);
+        assert_eq!((replies[0].code, replies[0].id), (ResponseCode::Ok, 2));
+        assert!(a.data.is_empty() && b.data.is_empty());
+    }
+
+    // An in-memory `ByteStream`, receiving the segments in order.
+    struct Stream<'a> {
+        segments: &'a [&'a [u8]],
+        // Receive position
+        segment: usize,
+        offset: usize,
+        sent: String<U2048>,
+        // Free space of the send buffer
+        window: usize,
+    }
+
+    impl<'a> Stream<'a> {
+        fn new(segments: &'a [&'a [u8]]) -> Self {
+            Self {
+                segments,
+                segment: 0,
+                offset: 0,
+                sent: String::new(),
+                window: usize::MAX,
+            }
+        }
+    }
+
+    impl<'a> ByteStream for Stream<'a> {
+        fn can_recv(&self) -> bool {
+            self.segment < self.segments.len()
+        }
+
+        fn recv<R, F>(&mut self, f: F) -> Result<R, ()>
+        where
+            F: FnOnce(&[u8]) -> (usize, R),
+        {
+            let segment = self.segments.get(self.segment).ok_or(())?;
+            let (len, result) = f(&segment[self.offset..]);
+            self.offset += len;
+            if self.offset == segment.len() {
+                self.segment += 1;
+                self.offset = 0;
+            }
+            Ok(result)
+        }
+
+        fn send(&mut self, data: &[u8]) -> usize {
+            let len = data.len().min(self.window);
+            let data = core::str::from_utf8(&data[..len]).unwrap();
+            self.sent.push_str(data).unwrap();
+            self.window -= len;
+            len
+        }
+    }
+
+    // Poll a connection with the segments. Returns the data sent.
+    fn poll(segments: &[&[u8]]) -> String<U2048> {
+        let mut stream = Stream::new(segments);
+        Connection::default().poll(&mut stream, &mut route);
+        stream.sent
+    }
+
+    const READ_GAIN: &[u8] =
+        b"{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\"}\n";
+    const GAIN: &str =
+        "{\"code\":200,\"id\":0,\"attribute\":\"test/gain\",\"value\":1}\n";
+    const OVERFLOW: &str = "{\"code\":520,\"id\":0,\"attribute\":\"\",\"value\":\"command buffer overflow\"}\n";
+
+    #[test]
+    fn framing() {
+        assert_eq!(poll(&[READ_GAIN]), GAIN);
+        // Split across receive calls
+        assert_eq!(
+            poll(&[&READ_GAIN[..1], &READ_GAIN[1..30], &READ_GAIN[30..]]),
+            GAIN
+        );
+        // Multiple requests in one segment
+        let mut two: Vec<u8, U256> = Vec::new();
+        two.extend_from_slice(READ_GAIN).unwrap();
+        two.extend_from_slice(READ_GAIN).unwrap();
+        let sent = poll(&[&two]);
+        assert_eq!((&sent[..GAIN.len()], &sent[GAIN.len()..]), (GAIN, GAIN));
+        // CRLF line endings
+        let mut crlf: Vec<u8, U256> = Vec::new();
+        crlf.extend_from_slice(&READ_GAIN[..READ_GAIN.len() - 1])
+            .unwrap();
+        crlf.extend_from_slice(b"\r\n").unwrap();
+        assert_eq!(poll(&[&crlf]), GAIN);
+        // Nothing is sent before the newline
+        assert_eq!(poll(&[&READ_GAIN[..30]]), "");
+    }
+
+    #[test]
+    fn overflow() {
+        // A request exactly filling the buffer, padded with whitespace
+        let mut line = [b' '; 1024];
+        let prefix = &READ_GAIN[..READ_GAIN.len() - 2];
+        line[..prefix.len()].copy_from_slice(prefix);
+        line[1022..].copy_from_slice(b"}\n");
+        assert_eq!(poll(&[&line]), GAIN);
+        // One more byte is discarded, the next line is handled.
+        assert_eq!(poll(&[&line[..1], &line]), OVERFLOW);
+        let sent = poll(&[&[b' '; 2000], b"\n", READ_GAIN]);
+        assert_eq!(
+            (&sent[..OVERFLOW.len()], &sent[OVERFLOW.len()..]),
+            (OVERFLOW, GAIN)
+        );
+    }
+
+    #[test]
+    fn lines() {
+        let crlf = b"{\"req\":\"Read\",\"attribute\":\"test/gain\",\"value\":\"\"}\r\n";
+        let mut three: Vec<u8, U256> = Vec::new();
+        for _ in 0..3 {
+            three.extend_from_slice(crlf).unwrap();
+        }
+        let sent = poll(&[&three]);
+        assert_eq!(sent.matches(GAIN).count(), 3);
+        assert_eq!(sent.len(), 3 * GAIN.len());
+
+        // Blank lines are skipped
+        assert_eq!(poll(&[b"\r\n", b"\n  \n", READ_GAIN]), GAIN);
+
+        // An oversized line followed immediately by a valid one
+        let mut segment: Vec<u8, U2048> = Vec::new();
+        segment.extend_from_slice(&[b'x'; 1500]).unwrap();
+        segment.extend_from_slice(b"\r\n").unwrap();
+        segment.extend_from_slice(crlf).unwrap();
+        let sent = poll(&[&segment]);
+        assert_eq!(
+            (&sent[..OVERFLOW.len()], &sent[OVERFLOW.len()..]),
+            (OVERFLOW, GAIN)
+        );
+
+        // Byte by byte
+        let mut bytes: Vec<&[u8], U256> = Vec::new();
+        for i in 0..crlf.len() {
+            bytes.push(&crlf[i..i + 1]).unwrap();
+        }
+        assert_eq!(poll(&bytes), GAIN);
+    }
+
+    #[test]
+    fn stall() {
+        let mut two: Vec<u8, U256> = Vec::new();
+        two.extend_from_slice(READ_GAIN).unwrap();
+        two.extend_from_slice(READ_GAIN).unwrap();
+        let segments: [&[u8]; 1] = [&two];
+        let mut stream = Stream::new(&segments);
+        let mut connection = Connection::default();
+        // No space: the reply is kept, the next request waits.
+        stream.window = 0;
+        connection.poll(&mut stream, &mut route);
+        assert_eq!(stream.sent, "");
+        assert_eq!(connection.pending.0.len(), GAIN.len());
+        assert!(stream.can_recv());
+        // Partially sent
+        stream.window = 10;
+        connection.poll(&mut stream, &mut route);
+        assert_eq!(stream.sent, &GAIN[..10]);
+        // Eventually delivered in order
+        stream.window = usize::MAX;
+        connection.poll(&mut stream, &mut route);
+        assert_eq!(stream.sent.matches(GAIN).count(), 2);
+        assert_eq!(stream.sent.len(), 2 * GAIN.len());
+        assert!(!stream.can_recv() && connection.pending.0.is_empty());
+    }
+
+    // A CBOR frame.
+    fn cbor<T: Serialize + ?Sized>(value: &T) -> Vec<u8, U512> {
+        let mut buf = [0; 510];
+        let mut serializer =
+            serde_cbor::Serializer::new(SliceWrite::new(&mut buf[..]));
+        value.serialize(&mut serializer).unwrap();
+        let len = serializer.into_inner().bytes_written();
+        let mut frame = Vec::new();
+        frame
+            .extend_from_slice(&(len as u16).to_be_bytes())
+            .unwrap();
+        frame.extend_from_slice(&buf[..len]).unwrap();
+        frame
+    }
+
+    #[derive(Deserialize)]
+    struct CborResponse<'a> {
+        code: i32,
+        id: u32,
+        attribute: &'a str,
+        value: &'a str,
+    }
+
+    #[test]
+    fn cbor_codec() {
+        let iir = iir::IIR::new(0.5, -2., 2.);
+        let value: String<U256> = serde_json_core::to_string(&iir).unwrap();
+        let req = Request {
+            req: AccessRequest::Write,
+            attribute: "test/iir",
+            value: value.clone(),
+            id: 3,
+            batch: None,
+        };
+        // The IIR value is embedded verbatim in CBOR and escaped in JSON.
+        let frame = cbor(&req);
+        let mut line: String<U512> = String::new();
+        line.push_str(
+            "{\"req\":\"Write\",\"attribute\":\"test/iir\",\"value\":\"",
+        )
+        .unwrap();
+        escape(&value, &mut line).unwrap();
+        line.push_str("\",\"id\":3}").unwrap();
+        for &(protocol, payload) in [
+            (Protocol::Cbor, &frame[FRAME_HEADER..]),
+            (Protocol::Json, line.as_bytes()),
+        ]
+        .iter()
+        {
+            let mut decoded: Request = protocol.decode(payload).unwrap();
+            protocol.restore_value(&mut decoded).unwrap();
+            assert_eq!(
+                (decoded.attribute, decoded.value.as_str(), decoded.id),
+                ("test/iir", value.as_str(), 3),
+                "{:?}",
+                protocol
+            );
+            let (restored, _) =
+                serde_json_core::from_str::<iir::IIR>(&decoded.value).unwrap();
+            let restored: String<U256> =
+                serde_json_core::to_string(&restored).unwrap();
+            assert_eq!(restored, value);
+        }
+
+        // A connection beginning with a CBOR frame replies with CBOR frames.
+        let mut connection = Connection::default();
+        let mut replies = receive(
+            &mut connection,
+            &cbor(&Request {
+                req: AccessRequest::Read,
+                attribute: "test/iir",
+                value: String::new(),
+                id: 1,
+                batch: None,
+            }),
+        );
+        assert_eq!(connection.protocol, Some(Protocol::Cbor));
+        connection.queue(&Reply::Single(replies.pop().unwrap()));
+        let pending = &connection.pending.0;
+        assert_eq!(FRAME_HEADER + frame_len(pending), pending.len());
+        let reply: CborResponse =
+            Protocol::Cbor.decode(&pending[FRAME_HEADER..]).unwrap();
+        assert_eq!(
+            (reply.code, reply.id, reply.attribute, reply.value),
+            (200, 1, "test/iir", value.as_str())
+        );
+
+        // Batches are arrays of requests.
+        let mut app = App::default();
+        let requests = [
+            Request {
+                req: AccessRequest::Write,
+                attribute: "test/gain",
+                value: String::from("5"),
+                id: 1,
+                batch: None,
+            },
+            Request {
+                req: AccessRequest::Read,
+                attribute: "test/gain",
+                value: String::new(),
+                id: 2,
+                batch: None,
+            },
+        ];
+        let frame = cbor(&requests[..]);
+        assert!(Protocol::Cbor.is_batch(&frame[FRAME_HEADER..]));
+        assert_eq!(Protocol::Cbor.batch_len(&frame[FRAME_HEADER..]), 2);
+        let replies = Server::handle_batch(
+            Protocol::Cbor,
+            &frame[FRAME_HEADER..],
+            &mut |req: &Request| app.route(req),
+        )
+        .unwrap();
+        let mut out = Pending::default();
+        Protocol::Cbor.encode_batch(&mut out, &replies).unwrap();
+        let replies: Vec<CborResponse, U4> =
+            Protocol::Cbor.decode(&out.0[FRAME_HEADER..]).unwrap();
+        let replies: Vec<_, U4> =
+            replies.iter().map(|r| (r.code, r.id, r.value)).collect();
+        assert_eq!(replies[..], [(200, 1, "5"), (200, 2, "0")]);
+        assert_eq!(app.gain, 5);
+    }
+
+    #[test]
+    fn resync() {
+        let read = cbor(&Request {
+            req: AccessRequest::Read,
+            attribute: "test/gain",
+            value: String::new(),
+            id: 1,
+            batch: None,
+        });
+        let codes = |replies: Vec<Response, U4>| -> Vec<_, U4> {
+            replies.iter().map(|r| (r.code, r.id)).collect()
+        };
+        let mut connection = Connection::default();
+
+        // A corrupted frame is answered, the next frame is handled.
+        let mut corrupted = read.clone();
+        corrupted[FRAME_HEADER] = 0xff;
+        let mut segment: Vec<u8, U2048> = Vec::new();
+        segment.extend_from_slice(&corrupted).unwrap();
+        segment.extend_from_slice(&read).unwrap();
+        assert_eq!(
+            codes(receive(&mut connection, &segment))[..],
+            [(ResponseCode::ParseError, 0), (ResponseCode::Ok, 1)]
+        );
+
+        // A frame exceeding the buffer is skipped by its length.
+        segment.clear();
+        segment.extend_from_slice(&1500u16.to_be_bytes()).unwrap();
+        segment.extend_from_slice(&[b'['; 1500]).unwrap();
+        segment.extend_from_slice(&read).unwrap();
+        assert_eq!(
+            codes(receive(&mut connection, &segment))[..],
+            [(ResponseCode::Overflow, 0), (ResponseCode::Ok, 1)]
+        );
+
+        // Empty frames are skipped, frames may arrive byte by byte.
+        segment.clear();
+        segment.extend_from_slice(&[0, 0]).unwrap();
+        segment.extend_from_slice(&read).unwrap();
+        let mut replies: Vec<_, U4> = Vec::new();
+        for byte in segment.chunks(1) {
+            for reply in receive(&mut connection, byte).iter() {
+                replies.push((reply.code, reply.id)).unwrap();
+            }
+        }
+        assert_eq!(replies[..], [(ResponseCode::Ok, 1)]);
+        assert!(connection.data.is_empty() && !connection.discard);
+
+        // JSON lines resynchronize at the newline.
+        let mut connection = Connection::default();
+        segment.clear();
+        segment.extend_from_slice(b"{\"req\":]\n").unwrap();
+        segment.extend_from_slice(READ_GAIN).unwrap();
+        assert_eq!(connection.protocol, None);
+        assert_eq!(
+            codes(receive(&mut connection, &segment))[..],
+            [(ResponseCode::ParseError, 0), (ResponseCode::Ok, 0)]
+        );
+        assert_eq!(connection.protocol, Some(Protocol::Json));
+    }
+
+    #[test]
+    fn array() {
+        assert_eq!(array_len(b"[]"), 0);
+        assert_eq!(array_len(b" [ ] "), 0);
+        assert_eq!(array_len(b"[1]"), 1);
+        assert_eq!(array_len(b"[[]]"), 1);
+        assert_eq!(array_len(b"[{\"a\":[1,2]},\"x,\\\"]y\",[3,4]]"), 3);
+    }
+
+    // Serve a connection of the server with the segments. Returns the data sent.
+    fn serve<F>(
+        server: &mut Server,
+        client: usize,
+        segments: &[&[u8]],
+        f: &mut F,
+    ) -> String<U2048>
+    where
+        F: FnMut(&Request) -> Response,
+    {
+        let mut stream = Stream::new(segments);
+        server.serve(client, &mut stream, f);
+        stream.sent
+    }
+
+    #[test]
+    fn subscriptions() {
+        let mut server = Server::new(1235, &["test/gain", "test/status"]);
+        let subscribe = b"{\"req\":\"Subscribe\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":7}\n";
+        assert_eq!(
+            serve(&mut server, 0, &[subscribe], &mut route),
+            "{\"code\":200,\"id\":7,\"attribute\":\"test/gain\",\"value\":1}\n"
+        );
+        // The initial value is not notified again.
+        server.notify("test/gain", &1u32);
+        server.notify("test/status", &false);
+        assert_eq!(serve(&mut server, 0, &[], &mut route), "");
+
+        // Changes only
+        server.notify("test/gain", &2u32);
+        server.notify("test/gain", &2u32);
+        assert_eq!(
+            serve(&mut server, 0, &[], &mut route),
+            "{\"code\":200,\"id\":7,\"attribute\":\"test/gain\",\"value\":2}\n"
+        );
+        server.notify("test/gain", &3u32);
+        server.notify("test/gain", &4u32);
+        let sent = serve(&mut server, 0, &[], &mut route);
+        assert_eq!(sent.matches("\"value\":").count(), 2);
+        assert!(sent.ends_with("\"value\":4}\n"));
+
+        // Other connections subscribe with their own identifier. The value they read is
+        // notified to the earlier subscribers.
+        let subscribe_b = b"{\"req\":\"Subscribe\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":9}\n";
+        serve(&mut server, 1, &[subscribe_b], &mut route);
+        server.notify("test/gain", &1u32);
+        assert!(
+            serve(&mut server, 0, &[], &mut route).ends_with("\"value\":1}\n")
+        );
+        assert_eq!(serve(&mut server, 1, &[], &mut route), "");
+        server.notify("test/gain", &1u32);
+        assert_eq!(serve(&mut server, 0, &[], &mut route), "");
+        server.notify("test/gain", &5u32);
+        assert!(serve(&mut server, 0, &[], &mut route).contains("\"id\":7,"));
+        assert!(serve(&mut server, 1, &[], &mut route).contains("\"id\":9,"));
+
+        // Unsubscribing stops the notifications of the connection only.
+        let unsubscribe = b"{\"req\":\"Unsubscribe\",\"attribute\":\"test/gain\",\"value\":\"\",\"id\":8}\n";
+        assert_eq!(
+            serve(&mut server, 0, &[unsubscribe], &mut route),
+            "{\"code\":200,\"id\":8,\"attribute\":\"test/gain\",\"value\":null}\n"
+        );
+        server.notify("test/gain", &6u32);
+        assert_eq!(serve(&mut server, 0, &[], &mut route), "");
+        assert!(serve(&mut server, 1, &[], &mut route).contains("\"value\":6}"));
+
+        // A disconnect ends the subscriptions.
+        server.reset(1);
+        server.notify("test/gain", &7u32);
+        assert_eq!(serve(&mut server, 1, &[], &mut route), "");
+        assert_eq!(server.subscriptions.used(), 0);
+    }
+
+    #[test]
+    fn subscription_limits() {
+        const NOTIFIED: [&str; 11] = [
+            "a/0",
+            "a/1",
+            "a/2",
+            "a/3",
+            "a/4",
+            "a/5",
+            "a/6",
+            "a/7",
+            "b",
+            "test/none",
+            "test/gain",
+        ];
+        let mut server = Server::new(1235, &NOTIFIED);
+        let mut any = |req: &Request| Response::success(req.attribute, "0");
+        let subscribe = |attribute: &str| {
+            let mut line: String<U256> = String::new();
+            writeln!(
+                line,
+                "{{\"req\":\"Subscribe\",\"attribute\":\"{}\",\"value\":\"\"}}",
+                attribute
+            )
+            .unwrap();
+            line
+        };
+        for i in 0..MAX_SUBSCRIPTIONS {
+            let mut attribute: String<U8> = String::new();
+            write!(attribute, "a/{}", i).unwrap();
+            let line = subscribe(&attribute);
+            let sent = serve(&mut server, 0, &[line.as_bytes()], &mut any);
+            assert!(sent.starts_with("{\"code\":200,"));
+        }
+        // Subscribing again is not an additional subscription.
+        let sent =
+            serve(&mut server, 0, &[subscribe("a/0").as_bytes()], &mut any);
+        assert!(sent.starts_with("{\"code\":200,"));
+        let sent =
+            serve(&mut server, 0, &[subscribe("b").as_bytes()], &mut any);
+        assert!(sent.starts_with("{\"code\":520,"));
+        let sent =
+            serve(&mut server, 1, &[subscribe("b").as_bytes()], &mut any);
+        assert!(sent.starts_with("{\"code\":200,"));
+
+        // Attributes that can not be read are not subscribed to.
+        let sent = serve(
+            &mut server,
+            1,
+            &[subscribe("test/none").as_bytes()],
+            &mut route,
+        );
+        assert!(sent.starts_with("{\"code\":404,"));
+        // Neither are the attributes the application does not notify.
+        let sent =
+            serve(&mut server, 1, &[subscribe("c").as_bytes()], &mut any);
+        assert!(sent.starts_with("{\"code\":400,"));
+        assert_eq!(server.subscriptions.masks[1].count_ones(), 1);
+
+        // Subscription requests are handled by the server, not the application.
+        let unsubscribe =
+            b"{\"req\":\"Unsubscribe\",\"attribute\":\"b\",\"value\":\"\"}\n";
+        assert!(serve(&mut server, 1, &[unsubscribe], &mut route)
+            .starts_with("{\"code\":200,"));
+        // Unsubscribing again, or from an attribute subscribed to by another
+        // connection only, fails like reading an unknown attribute.
+        assert!(serve(&mut server, 1, &[unsubscribe], &mut route)
+            .starts_with("{\"code\":404,"));
+        let unsubscribe =
+            b"{\"req\":\"Unsubscribe\",\"attribute\":\"a/0\",\"value\":\"\"}\n";
+        assert!(serve(&mut server, 1, &[unsubscribe], &mut route)
+            .starts_with("{\"code\":404,"));
+        assert_eq!(server.subscriptions.masks[0].count_ones(), 8);
+        let res = route(&Request {
+            req: AccessRequest::Subscribe,
+            attribute: "test/gain",
+            value: String::new(),
+            id: 0,
+            batch: None,
+        });
+        assert_eq!(res.code, ResponseCode::ValidationFailed);
     }
 }
@@ -0,0 +1,426 @@
+///! Binary sample streaming
+///!
+///! The JSON server transports settings, not waveforms. `Stream` collects (decimated) sample
+///! batches from the processing task into fixed size frames and buffers them in a `Ring`. The
+///! idle task drains the frames into a dedicated TCP or UDP socket with `poll_tcp()` or
+///! `poll_udp()`, one frame per lock, without blocking the control server.
+///!
+///! Frame layout, little endian, `FRAME_SIZE` bytes:
+///!
+///! | Offset | Type           | Content                                              |
+///! |--------|----------------|------------------------------------------------------|
+///! | 0      | u16            | `MAGIC`                                              |
+///! | 2      | u8             | `VERSION`                                            |
+///! | 3      | u8             | Channel id: ADC 0, ADC 1, DAC 0, DAC 1 are 0 to 3    |
+///! | 4      | u32            | Sequence number, wrapping, common to all channels    |
+///! | 8      | u32            | Timestamp of the first sample in samples, wrapping   |
+///! | 12     | u16            | Decimation (sample spacing)                          |
+///! | 14     | u16            | Number of valid samples (`SAMPLES`)                  |
+///! | 16     | [i16; SAMPLES] | Samples, machine units                               |
+///!
+///! In Python: `struct.unpack_from("<HBBIIHH", frame)` and
+///! `struct.unpack_from("<128h", frame, 16)`.
+use smoltcp as net;
+
+use super::telemetry::Ring;
+
+/// Frame start marker.
+pub const MAGIC: u16 = 0x57ab;
+/// Frame layout version.
+pub const VERSION: u8 = 1;
+/// Header size in bytes.
+pub const HEADER_SIZE: usize = 16;
+/// Samples per frame.
+pub const SAMPLES: usize = 128;
+/// Frame size in bytes.
+pub const FRAME_SIZE: usize = HEADER_SIZE + 2 * SAMPLES;
+/// Number of channel ids.
+pub const CHANNELS: usize = 4;
+
+/// Frame header, see the module documentation for the layout.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct FrameHeader {
+    pub channel: u8,
+    pub sequence: u32,
+    pub timestamp: u32,
+    pub decimation: u16,
+    pub len: u16,
+}
+
+impl FrameHeader {
+    /// Serialize the header.
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        buf[2] = VERSION;
+        buf[3] = self.channel;
+        buf[4..8].copy_from_slice(&self.sequence.to_le_bytes());
+        buf[8..12].copy_from_slice(&self.timestamp.to_le_bytes());
+        buf[12..14].copy_from_slice(&self.decimation.to_le_bytes());
+        buf[14..16].copy_from_slice(&self.len.to_le_bytes());
+        buf
+    }
+
+    /// Parse a header.
+    ///
+    /// Args:
+    /// * `buf`: At least `HEADER_SIZE` bytes.
+    ///
+    /// Returns:
+    /// The header or an error if the buffer is short or the magic or version don't match.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, &'static str> {
+        if buf.len() < HEADER_SIZE {
+            return Err("Short frame header");
+        }
+        let u16_at = |i: usize| u16::from_le_bytes([buf[i], buf[i + 1]]);
+        let u32_at = |i: usize| {
+            u32::from_le_bytes([buf[i], buf[i + 1], buf[i + 2], buf[i + 3]])
+        };
+        if u16_at(0) != MAGIC || buf[2] != VERSION {
+            return Err("Invalid frame magic or version");
+        }
+        let header = Self {
+            channel: buf[3],
+            sequence: u32_at(4),
+            timestamp: u32_at(8),
+            decimation: u16_at(12),
+            len: u16_at(14),
+        };
+        if header.len as usize > SAMPLES {
+            return Err("Invalid frame length");
+        }
+        Ok(header)
+    }
+}
+
+/// A header and its samples.
+#[derive(Copy, Clone, Debug)]
+pub struct Frame {
+    pub header: FrameHeader,
+    pub samples: [i16; SAMPLES],
+}
+
+impl Default for Frame {
+    fn default() -> Self {
+        Self {
+            header: FrameHeader::default(),
+            samples: [0; SAMPLES],
+        }
+    }
+}
+
+impl Frame {
+    /// Serialize the frame.
+    pub fn to_bytes(&self) -> [u8; FRAME_SIZE] {
+        let mut buf = [0; FRAME_SIZE];
+        buf[..HEADER_SIZE].copy_from_slice(&self.header.to_bytes());
+        for (b, x) in buf[HEADER_SIZE..]
+            .chunks_exact_mut(2)
+            .zip(self.samples.iter())
+        {
+            b.copy_from_slice(&x.to_le_bytes());
+        }
+        buf
+    }
+
+    /// Parse a frame.
+    ///
+    /// Args:
+    /// * `buf`: At least `FRAME_SIZE` bytes.
+    ///
+    /// Returns:
+    /// The frame or an error if the buffer is short or the header invalid.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, &'static str> {
+        let header = FrameHeader::from_bytes(buf)?;
+        if buf.len() < FRAME_SIZE {
+            return Err("Short frame");
+        }
+        let mut samples = [0; SAMPLES];
+        for (x, b) in samples
+            .iter_mut()
+            .zip(buf[HEADER_SIZE..FRAME_SIZE].chunks_exact(2))
+        {
+            *x = i16::from_le_bytes([b[0], b[1]]);
+        }
+        Ok(Self { header, samples })
+    }
+}
+
+/// Frame assembly and buffering of `F` frames.
+pub struct Stream<const F: usize> {
+    frames: Ring<Frame, F>,
+    // Frames being filled, per channel
+    partial: [Frame; CHANNELS],
+    // Samples to skip until the next kept sample, per channel
+    skip: [u16; CHANNELS],
+    channels: u8,
+    decimation: u16,
+    sequence: u32,
+    lost: u32,
+}
+
+impl<const F: usize> Stream<F> {
+    /// Create a new stream with all channels disabled.
+    pub fn new() -> Self {
+        Self {
+            frames: Ring::new(Frame::default()),
+            partial: [Frame::default(); CHANNELS],
+            skip: [0; CHANNELS],
+            channels: 0,
+            decimation: 1,
+            sequence: 0,
+            lost: 0,
+        }
+    }
+
+    /// Enabled channels, bit `i` is channel id `i`.
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Decimation: one sample in `decimation` is kept.
+    pub fn decimation(&self) -> u16 {
+        self.decimation
+    }
+
+    /// Change the channels and the decimation.
+    ///
+    /// Partial frames are discarded. Buffered frames are kept.
+    ///
+    /// Args:
+    /// * `channels`: Bit mask of the enabled channels.
+    /// * `decimation`: Keep one out of `decimation` samples, non-zero.
+    pub fn configure(
+        &mut self,
+        channels: u8,
+        decimation: u16,
+    ) -> Result<(), &'static str> {
+        if channels >> CHANNELS != 0 {
+            return Err("Invalid stream channels");
+        }
+        if decimation == 0 {
+            return Err("Invalid stream decimation");
+        }
+        self.channels = channels;
+        self.decimation = decimation;
+        self.skip = [0; CHANNELS];
+        for frame in self.partial.iter_mut() {
+            frame.header.len = 0;
+        }
+        Ok(())
+    }
+
+    /// Add a batch of samples of one channel.
+    ///
+    /// Samples of disabled or invalid channels are ignored.
+    ///
+    /// Args:
+    /// * `channel`: Channel id.
+    /// * `timestamp`: Timestamp of the first sample in samples.
+    /// * `samples`: Samples.
+    pub fn push(&mut self, channel: u8, timestamp: u32, samples: &[i16]) {
+        if channel as usize >= CHANNELS || self.channels & (1 << channel) == 0 {
+            return;
+        }
+        let ch = channel as usize;
+        let frame = &mut self.partial[ch];
+        for (i, &x) in samples.iter().enumerate() {
+            if self.skip[ch] > 0 {
+                self.skip[ch] -= 1;
+                continue;
+            }
+            self.skip[ch] = self.decimation - 1;
+            let len = frame.header.len as usize;
+            if len == 0 {
+                frame.header.channel = channel;
+                frame.header.decimation = self.decimation;
+                frame.header.timestamp = timestamp.wrapping_add(i as u32);
+            }
+            frame.samples[len] = x;
+            frame.header.len += 1;
+            if len + 1 == SAMPLES {
+                frame.header.sequence = self.sequence;
+                self.sequence = self.sequence.wrapping_add(1);
+                self.frames.push(*frame);
+                frame.header.len = 0;
+            }
+        }
+    }
+
+    /// Remove and return the oldest complete frame.
+    pub fn pop(&mut self) -> Option<Frame> {
+        self.frames.pop()
+    }
+
+    /// Count a frame lost after `pop()`, e.g. when it could not be sent.
+    pub fn count_lost(&mut self) {
+        self.lost = self.lost.wrapping_add(1);
+    }
+
+    /// Number of frames dropped since creation (wrapping): replaced in the buffer before they
+    /// were read or lost after.
+    pub fn dropped(&self) -> u32 {
+        self.frames.overruns().wrapping_add(self.lost)
+    }
+}
+
+impl<const F: usize> Default for Stream<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Drain the buffered frames into a TCP socket.
+///
+/// Frames are only taken from the stream if the socket buffer can take the entire frame: a
+/// slow client causes buffer overruns, never partial frames.
+///
+/// Args:
+/// * `stream`: The shared stream, locked once per frame.
+/// * `socket`: Connected data socket.
+///
+/// Returns:
+/// The number of frames sent.
+pub fn poll_tcp<M, const F: usize>(
+    stream: &mut M,
+    socket: &mut net::socket::TcpSocket,
+) -> usize
+where
+    M: rtic::Mutex<T = Stream<F>>,
+{
+    let mut sent = 0;
+    while socket.may_send()
+        && socket.send_capacity() - socket.send_queue() >= FRAME_SIZE
+    {
+        let frame = match stream.lock(|stream| stream.pop()) {
+            Some(frame) => frame,
+            None => break,
+        };
+        // Note(unwrap): There is space for the entire frame.
+        socket.send_slice(&frame.to_bytes()).unwrap();
+        sent += 1;
+    }
+    sent
+}
+
+/// Drain the buffered frames into a UDP socket, one frame per datagram.
+///
+/// Frames that do not fit into the socket buffer are lost and counted.
+///
+/// Args:
+/// * `stream`: The shared stream, locked once per frame.
+/// * `socket`: Bound data socket.
+/// * `endpoint`: Destination.
+///
+/// Returns:
+/// The number of frames sent.
+pub fn poll_udp<M, const F: usize>(
+    stream: &mut M,
+    socket: &mut net::socket::UdpSocket,
+    endpoint: net::wire::IpEndpoint,
+) -> usize
+where
+    M: rtic::Mutex<T = Stream<F>>,
+{
+    let mut sent = 0;
+    while socket.can_send() {
+        let frame = match stream.lock(|stream| stream.pop()) {
+            Some(frame) => frame,
+            None => break,
+        };
+        if socket.send_slice(&frame.to_bytes(), endpoint).is_err() {
+            stream.lock(|stream| stream.count_lost());
+            break;
+        }
+        sent += 1;
+    }
+    sent
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn header_layout() {
+        let header = FrameHeader {
+            channel: 2,
+            sequence: 0x0403_0201,
+            timestamp: 0x0807_0605,
+            decimation: 0x0a09,
+            len: SAMPLES as u16,
+        };
+        let buf = header.to_bytes();
+        assert_eq!(
+            buf,
+            [0xab, 0x57, 1, 2, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 128, 0]
+        );
+        assert_eq!(FrameHeader::from_bytes(&buf), Ok(header));
+        assert!(FrameHeader::from_bytes(&buf[..HEADER_SIZE - 1]).is_err());
+        let mut bad = buf;
+        bad[2] = VERSION + 1;
+        assert!(FrameHeader::from_bytes(&bad).is_err());
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let mut frame = Frame::default();
+        frame.header.sequence = 7;
+        for (i, x) in frame.samples.iter_mut().enumerate() {
+            *x = (i as i16 - 64) * 0x101;
+        }
+        let buf = frame.to_bytes();
+        assert_eq!(buf.len(), FRAME_SIZE);
+        assert_eq!(
+            &buf[HEADER_SIZE..HEADER_SIZE + 2],
+            &(-64i16 * 0x101).to_le_bytes()
+        );
+        let parsed = Frame::from_bytes(&buf).unwrap();
+        assert_eq!(parsed.header, frame.header);
+        assert_eq!(&parsed.samples[..], &frame.samples[..]);
+        assert!(Frame::from_bytes(&buf[..FRAME_SIZE - 1]).is_err());
+    }
+
+    #[test]
+    fn decimate_and_sequence() {
+        let mut stream = Stream::<4>::new();
+        assert!(stream.configure(1 << CHANNELS, 1).is_err());
+        assert!(stream.configure(0b101, 0).is_err());
+        stream.configure(0b101, 3).unwrap();
+        let batch: [i16; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+        for t in 0..SAMPLES as u32 * 3 / 8 + 1 {
+            for &channel in [0u8, 1, 2].iter() {
+                let samples: [i16; 8] = {
+                    let mut s = batch;
+                    s.iter_mut().for_each(|x| *x += (t * 8) as i16);
+                    s
+                };
+                stream.push(channel, t * 8, &samples);
+            }
+        }
+        let a = stream.pop().unwrap();
+        let b = stream.pop().unwrap();
+        assert!(stream.pop().is_none());
+        assert_eq!((a.header.channel, a.header.sequence), (0, 0));
+        assert_eq!((b.header.channel, b.header.sequence), (2, 1));
+        assert_eq!((a.header.timestamp, a.header.decimation), (0, 3));
+        assert_eq!(a.header.len as usize, SAMPLES);
+        for (i, &x) in a.samples.iter().enumerate() {
+            assert_eq!(x, 3 * i as i16);
+        }
+        assert_eq!(stream.dropped(), 0);
+    }
+
+    #[test]
+    fn overrun() {
+        let mut stream = Stream::<2>::new();
+        stream.configure(1, 1).unwrap();
+        for t in 0..4 {
+            stream.push(0, t * SAMPLES as u32, &[0; SAMPLES]);
+        }
+        assert_eq!(stream.dropped(), 2);
+        assert_eq!(stream.pop().unwrap().header.sequence, 2);
+        stream.count_lost();
+        assert_eq!(stream.dropped(), 3);
+    }
+}
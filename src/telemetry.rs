@@ -0,0 +1,67 @@
+///! Telemetry buffering
+///!
+///! The processing task produces telemetry (e.g. decimated lockin IQ data) at a fixed rate while
+///! the network interface drains it whenever it gets to it. `Ring` is a small fixed capacity
+///! buffer between the two that keeps the most recent samples: when it is full, a new sample
+///! replaces the oldest one and the loss is counted.
+
+/// Fixed capacity ring buffer keeping the most recent `N` samples.
+pub struct Ring<T: Copy, const N: usize> {
+    buffer: [T; N],
+    // Index of the oldest sample
+    read: usize,
+    len: usize,
+    overruns: u32,
+}
+
+impl<T: Copy, const N: usize> Ring<T, N> {
+    /// Create a new, empty ring buffer.
+    ///
+    /// Args:
+    /// * `fill`: Initial value of the storage. It is never returned.
+    pub fn new(fill: T) -> Self {
+        Self {
+            buffer: [fill; N],
+            read: 0,
+            len: 0,
+            overruns: 0,
+        }
+    }
+
+    /// Append a sample, replacing the oldest sample if the buffer is full.
+    pub fn push(&mut self, value: T) {
+        self.buffer[(self.read + self.len) % N] = value;
+        if self.len < N {
+            self.len += 1;
+        } else {
+            self.read = (self.read + 1) % N;
+            self.overruns = self.overruns.wrapping_add(1);
+        }
+    }
+
+    /// Remove and return the oldest sample.
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        let value = self.buffer[self.read];
+        self.read = (self.read + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    /// Number of buffered samples.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether the buffer is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Number of samples lost (replaced before they were read) since creation (wrapping).
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+}
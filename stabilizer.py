@@ -16,12 +16,12 @@ class StabilizerConfig:
     async def connect(self, host, port=1235):
         self.reader, self.writer = await asyncio.open_connection(host, port)
 
-    async def set(self, channel, iir):
-        value = OD([("channel", channel), ("iir", iir.as_dict())])
+    async def set(self, channel, iir, stage=0):
+        value = OD([("stage", stage), ("iir", iir.as_dict())])
         request = {
             "req": "Write",
-            "attribute": "stabilizer/iir{}/state".format(channel),
-            "value": json.dumps(value, separators=[',', ':']).replace('"', "'"),
+            "attribute": "stabilizer/iir/{}".format(channel),
+            "value": json.dumps(value, separators=[',', ':']),
         }
         s = json.dumps(request, separators=[',', ':'])
         assert "\n" not in s
@@ -44,6 +44,8 @@ class IIR:
         self.y_offset = 0.
         self.y_min = -self.full_scale - 1
         self.y_max = self.full_scale
+        # "Clamp" or "BackCalculate"
+        self.anti_windup = "Clamp"
 
     def as_dict(self):
         iir = OD()
@@ -51,6 +53,7 @@ class IIR:
         iir["y_offset"] = self.y_offset
         iir["y_min"] = self.y_min
         iir["y_max"] = self.y_max
+        iir["anti_windup"] = self.anti_windup
         return iir
 
     def configure_pi(self, kp, ki, g=0.):